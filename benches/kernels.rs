@@ -0,0 +1,78 @@
+//! Compares the scalar and SIMD versions of the per-pixel kernels on a 4K
+//! frame. Run with `cargo bench`.
+
+use std::hint::black_box;
+
+use combiner::simd::{self, Kernel};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const WIDTH: usize = 3840;
+const HEIGHT: usize = 2160;
+
+/// Two frames of noise, mostly opaque with some transparent pixels, so
+/// every branch of the blend is taken.
+fn frames() -> (Vec<u8>, Vec<u8>) {
+    let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+    let mut frame = || {
+        let mut pixels: Vec<u8> = (0..WIDTH * HEIGHT * 4)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 24) as u8
+            })
+            .collect();
+        for (index, pixel) in pixels.chunks_exact_mut(4).enumerate() {
+            pixel[3] = if index % 16 == 0 { 0 } else { 255 };
+        }
+        pixels
+    };
+    (frame(), frame())
+}
+
+fn kernels() -> Vec<Kernel> {
+    let mut kernels = vec![Kernel::Scalar];
+    if Kernel::detect() != Kernel::Scalar {
+        kernels.push(Kernel::detect());
+    }
+    kernels
+}
+
+fn blend(c: &mut Criterion) {
+    let (from, to) = frames();
+    let mut group = c.benchmark_group("blend");
+    group.throughput(Throughput::Elements((WIDTH * HEIGHT) as u64));
+    for kernel in kernels() {
+        group.bench_function(BenchmarkId::from_parameter(kernel.name()), |b| {
+            b.iter(|| simd::blend(black_box(&from), black_box(&to), black_box(0.4), kernel))
+        });
+    }
+    group.finish();
+}
+
+fn multiply(c: &mut Criterion) {
+    let (backdrop, source) = frames();
+    let mut group = c.benchmark_group("multiply");
+    group.throughput(Throughput::Elements((WIDTH * HEIGHT) as u64));
+    for kernel in kernels() {
+        group.bench_function(BenchmarkId::from_parameter(kernel.name()), |b| {
+            b.iter(|| simd::multiply(black_box(&backdrop), black_box(&source), black_box(0.8), kernel))
+        });
+    }
+    group.finish();
+}
+
+fn difference(c: &mut Criterion) {
+    let (from, to) = frames();
+    let mut group = c.benchmark_group("difference");
+    group.throughput(Throughput::Elements((WIDTH * HEIGHT) as u64));
+    for kernel in kernels() {
+        group.bench_function(BenchmarkId::from_parameter(kernel.name()), |b| {
+            b.iter(|| simd::difference_magnitudes(black_box(&from), black_box(&to), kernel))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, blend, multiply, difference);
+criterion_main!(benches);
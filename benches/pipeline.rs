@@ -0,0 +1,42 @@
+//! Times each stage of combining synthetic images, on every backend this
+//! build has, at HD and 4K. Run with `cargo bench --bench pipeline`; the
+//! `bench` subcommand reports the same stages in megapixels per second.
+
+use std::hint::black_box;
+
+use combiner::bench::{run_stages, synthetic_pair, STAGES};
+use combiner::gpu::Backend;
+use combiner::options::CombineOptions;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use image::ImageFormat;
+
+fn pipeline(c: &mut Criterion) {
+    let mut backends = vec![Backend::Cpu];
+    if cfg!(feature = "gpu") {
+        backends.push(Backend::Gpu);
+    }
+    let mut group = c.benchmark_group("pipeline");
+    group.sample_size(10);
+    for (width, height) in [(1280, 720), (3840, 2160)] {
+        let (image_1, image_2) = synthetic_pair(width, height, 0);
+        for &backend in &backends {
+            let options = CombineOptions { backend, ..CombineOptions::default() };
+            let id = BenchmarkId::new(backend.name(), format!("{}x{}", width, height));
+            // Criterion times whole runs; how they split between stages is printed after each.
+            group.bench_function(id, |b| {
+                b.iter(|| {
+                    let timings = run_stages(image_1.clone(), image_2.clone(), &options, ImageFormat::Png).unwrap();
+                    black_box(timings)
+                })
+            });
+            let timings = run_stages(image_1.clone(), image_2.clone(), &options, ImageFormat::Png).unwrap();
+            for (stage, timing) in STAGES.iter().zip(timings) {
+                println!("  {} {}x{} {}: {:.1} MP/s", backend.name(), width, height, stage, timing.throughput());
+            }
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, pipeline);
+criterion_main!(benches);
@@ -0,0 +1,7 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    {
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path().expect("vendored protoc is available"));
+        tonic_build::compile_protos("proto/combiner.proto").expect("proto/combiner.proto compiles");
+    }
+}
@@ -0,0 +1,144 @@
+#ifndef IMGCOMBINE_H
+#define IMGCOMBINE_H
+
+/* Generated by cbindgen from src/ffi.rs and src/plugin.rs; do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The ABI version this build loads; plugins built for another are skipped.
+#define IMGCOMBINE_PLUGIN_ABI_VERSION 1
+
+typedef enum ImgcombineStatus {
+  IMGCOMBINE_STATUS_OK = 0,
+  IMGCOMBINE_STATUS_INVALID_ARGUMENT = 1,
+  IMGCOMBINE_STATUS_DECODE_FAILED = 2,
+  IMGCOMBINE_STATUS_ENCODE_FAILED = 3,
+  // A bug inside the library; the call had no effect.
+  IMGCOMBINE_STATUS_PANICKED = 4,
+} ImgcombineStatus;
+
+typedef enum ImgcombineMode {
+  IMGCOMBINE_MODE_ALTERNATE = 0,
+  IMGCOMBINE_MODE_DIFF = 1,
+} ImgcombineMode;
+
+typedef enum ImgcombineDiffStyle {
+  IMGCOMBINE_DIFF_STYLE_HIGHLIGHT = 0,
+  IMGCOMBINE_DIFF_STYLE_HEATMAP = 1,
+} ImgcombineDiffStyle;
+
+typedef struct ImgcombineOptions {
+  enum ImgcombineMode mode;
+  uint8_t threshold;
+  enum ImgcombineDiffStyle diff_style;
+  // Output format as a file extension such as `"png"`, or NULL for the
+  // format of the first image.
+  const char *format;
+} ImgcombineOptions;
+
+// An encoded image owned by the library; release it with `imgcombine_free`.
+typedef struct ImgcombineBuffer {
+  uint8_t *data;
+  size_t len;
+  uint32_t width;
+  uint32_t height;
+} ImgcombineBuffer;
+
+// Blends `pixels` pixels of `first` and `second`, both of one size, into `out`.
+typedef struct ImgcombineBlendMode {
+  const char *name;
+  void (*blend)(const uint8_t *first, const uint8_t *second, uint8_t *out, size_t pixels);
+} ImgcombineBlendMode;
+
+typedef struct ImgcombineSize {
+  uint32_t width;
+  uint32_t height;
+} ImgcombineSize;
+
+// An image of `width * height` RGBA pixels. Inputs are read-only.
+typedef struct ImgcombinePixels {
+  uint8_t *data;
+  uint32_t width;
+  uint32_t height;
+} ImgcombinePixels;
+
+// Arranges both inputs, each at its own size, into an output whose size
+// `size` gives. `layout` returns 0 once `out` is filled, and anything else
+// when it cannot be.
+typedef struct ImgcombineLayout {
+  const char *name;
+  struct ImgcombineSize (*size)(struct ImgcombineSize first, struct ImgcombineSize second);
+  int (*layout)(const struct ImgcombinePixels *first,
+                const struct ImgcombinePixels *second,
+                struct ImgcombinePixels *out);
+} ImgcombineLayout;
+
+// Receives the encoded bytes an encoder writes, in order, passing back the
+// `context` the encoder was given.
+typedef void (*ImgcombineWrite)(void *context, const uint8_t *data, size_t len);
+
+// Encodes outputs whose file extension is `extension`, without the dot and
+// in any case, passing the bytes to `write`. `encode` returns 0 on success.
+typedef struct ImgcombineEncoder {
+  const char *extension;
+  int (*encode)(const struct ImgcombinePixels *image, ImgcombineWrite write, void *context);
+} ImgcombineEncoder;
+
+// What a plugin adds. Each list may be NULL when its count is 0.
+typedef struct ImgcombinePlugin {
+  // [`IMGCOMBINE_PLUGIN_ABI_VERSION`] when the plugin was built.
+  uint32_t abi_version;
+  const char *name;
+  const struct ImgcombineBlendMode *blend_modes;
+  size_t blend_mode_count;
+  const struct ImgcombineLayout *layouts;
+  size_t layout_count;
+  const struct ImgcombineEncoder *encoders;
+  size_t encoder_count;
+} ImgcombinePlugin;
+
+// The type of `imgcombine_plugin`, the function every plugin exports.
+typedef const struct ImgcombinePlugin *(*ImgcombinePluginEntry)(void);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Combines the encoded images `buf_a` and `buf_b` into `out`. `opts` may be
+// NULL for the defaults. On failure `out` is left empty and
+// `imgcombine_last_error` describes what went wrong.
+//
+// # Safety
+//
+// `buf_a` and `buf_b` must point to `len_a` and `len_b` readable bytes,
+// `opts` must be NULL or valid, with a NUL-terminated or NULL `format`, and
+// `out` must point to writable memory for one `ImgcombineBuffer`.
+enum ImgcombineStatus imgcombine_combine(const uint8_t *buf_a,
+                                         size_t len_a,
+                                         const uint8_t *buf_b,
+                                         size_t len_b,
+                                         const struct ImgcombineOptions *opts,
+                                         struct ImgcombineBuffer *out);
+
+// Releases a buffer filled by `imgcombine_combine` and empties it. Passing
+// NULL or an empty buffer does nothing.
+//
+// # Safety
+//
+// `buffer` must be NULL or point to a buffer filled by `imgcombine_combine`
+// that has not been released yet.
+void imgcombine_free(struct ImgcombineBuffer *buffer);
+
+// The message of the last failed call on this thread, or NULL. It stays
+// valid until the next failing call on the same thread.
+const char *imgcombine_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* IMGCOMBINE_H */
@@ -0,0 +1,211 @@
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::error::{EncodingError, ImageFormatHint};
+use image::{Delay, Frame, ImageError, ImageFormat, RgbaImage};
+
+use crate::canvas::blend_over;
+use crate::ImageDataErrors;
+
+/// How an animated comparison moves from one input to the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationStyle {
+    /// A divider sweeps across, revealing the second input over the first,
+    /// then sweeps back.
+    Wipe,
+    /// The first input fades into the second, then back.
+    Crossfade,
+}
+
+impl AnimationStyle {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        match value {
+            "wipe" => Ok(AnimationStyle::Wipe),
+            "crossfade" => Ok(AnimationStyle::Crossfade),
+            _ => Err(ImageDataErrors::InvalidArgument(format!("unknown animation `{}`", value))),
+        }
+    }
+}
+
+/// An animated comparison of the two inputs, encoded as a GIF, an animated
+/// PNG or an animated WebP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Animation {
+    pub style: AnimationStyle,
+    /// The frames of one sweep across; the sweep back reuses them.
+    pub frames: u32,
+    /// How long each frame shows, in milliseconds.
+    pub delay_ms: u32,
+    /// How many times the animation plays, or 0 to loop forever.
+    pub plays: u16,
+}
+
+impl Default for Animation {
+    fn default() -> Self {
+        Animation { style: AnimationStyle::Wipe, frames: 24, delay_ms: 60, plays: 0 }
+    }
+}
+
+impl Animation {
+    /// How far each frame is from the first input to the second, from 0 to 1:
+    /// there and back again, without repeating either end, so the loop is
+    /// seamless.
+    pub(crate) fn positions(&self) -> Vec<f32> {
+        let across: Vec<f32> = (0..self.frames).map(|frame| frame as f32 / (self.frames - 1) as f32).collect();
+        let back = across.iter().rev().skip(1).take(across.len().saturating_sub(2)).copied();
+        across.iter().copied().chain(back).collect()
+    }
+}
+
+/// Draws `image_2` left of a divider at `position` and `image_1` right of
+/// it. Both must be the same size.
+pub(crate) fn wipe_frame(image_1: &RgbaImage, image_2: &RgbaImage, position: f32) -> RgbaImage {
+    let (width, height) = image_1.dimensions();
+    let divider = (position * width as f32).round() as u32;
+    let thickness = (width / 400).max(2);
+    let mut frame = RgbaImage::from_fn(width, height, |x, y| {
+        if x < divider { *image_2.get_pixel(x, y) } else { *image_1.get_pixel(x, y) }
+    });
+    // A white line with a faint dark edge either side, visible on light and dark images alike.
+    let start = divider.saturating_sub(thickness / 2);
+    for x in start.saturating_sub(1)..(start + thickness + 1).min(width) {
+        let edge = x < start || x >= start + thickness;
+        let colour = if edge { [0, 0, 0, 96] } else { [255, 255, 255, 255] };
+        for y in 0..height {
+            blend_over(&mut frame.get_pixel_mut(x, y).0, colour);
+        }
+    }
+    frame
+}
+
+/// Encodes `count` frames of the same size as an animation in `format`,
+/// which must be GIF, PNG or WebP, each frame shown for `animation.delay_ms`.
+pub(crate) fn encode(
+    frames: impl IntoIterator<Item = Result<RgbaImage, ImageDataErrors>>,
+    count: usize,
+    format: ImageFormat,
+    animation: &Animation,
+) -> Result<Vec<u8>, ImageDataErrors> {
+    match format {
+        ImageFormat::Gif => encode_gif(frames, animation),
+        ImageFormat::Png => encode_apng(frames, count, animation),
+        ImageFormat::WebP => encode_webp(frames, animation),
+        _ => Err(ImageDataErrors::InvalidArgument(format!("{:?} outputs cannot be animated", format))),
+    }
+}
+
+fn encoding_error(format: ImageFormat, error: impl std::error::Error + Send + Sync + 'static) -> ImageDataErrors {
+    ImageDataErrors::UnableToSaveImage(ImageError::Encoding(EncodingError::new(ImageFormatHint::Exact(format), error)))
+}
+
+fn encode_gif(
+    frames: impl IntoIterator<Item = Result<RgbaImage, ImageDataErrors>>,
+    animation: &Animation,
+) -> Result<Vec<u8>, ImageDataErrors> {
+    let mut bytes = Vec::new();
+    {
+        // Speed 10 of 30 quantises colours well at a fraction of the slowest setting's cost.
+        let mut encoder = GifEncoder::new_with_speed(&mut bytes, 10);
+        // GIF counts repeats after the first play rather than plays.
+        let repeat = match animation.plays {
+            0 => Repeat::Infinite,
+            plays => Repeat::Finite(plays - 1),
+        };
+        encoder.set_repeat(repeat).map_err(ImageDataErrors::UnableToSaveImage)?;
+        for frame in frames {
+            let frame = Frame::from_parts(frame?, 0, 0, Delay::from_numer_denom_ms(animation.delay_ms, 1));
+            encoder.encode_frame(frame).map_err(ImageDataErrors::UnableToSaveImage)?;
+        }
+    }
+    Ok(bytes)
+}
+
+/// Full-colour animated PNG, which browsers play and older viewers show as
+/// its first frame.
+fn encode_apng(
+    frames: impl IntoIterator<Item = Result<RgbaImage, ImageDataErrors>>,
+    count: usize,
+    animation: &Animation,
+) -> Result<Vec<u8>, ImageDataErrors> {
+    let failed = |error| encoding_error(ImageFormat::Png, error);
+    let mut frames = frames.into_iter();
+    // The header needs the size, so the first frame is made before writing it.
+    let Some(first) = frames.next().transpose()? else { return Ok(Vec::new()) };
+    let (width, height) = first.dimensions();
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(count as u32, animation.plays as u32).map_err(failed)?;
+    // The delay is a fraction of a second with a 16 bit numerator.
+    encoder.set_frame_delay(animation.delay_ms.min(u16::MAX as u32) as u16, 1000).map_err(failed)?;
+    let mut writer = encoder.write_header().map_err(failed)?;
+    writer.write_image_data(first.as_raw()).map_err(failed)?;
+    for frame in frames {
+        writer.write_image_data(frame?.as_raw()).map_err(failed)?;
+    }
+    writer.finish().map_err(failed)?;
+    Ok(bytes)
+}
+
+/// Lossless animated WebP. Each frame is encoded as a still lossless WebP,
+/// whose `VP8L` bitstream is then wrapped in the animation's frame chunks.
+fn encode_webp(
+    frames: impl IntoIterator<Item = Result<RgbaImage, ImageDataErrors>>,
+    animation: &Animation,
+) -> Result<Vec<u8>, ImageDataErrors> {
+    let failed = |error| encoding_error(ImageFormat::WebP, error);
+    let mut body = Vec::new();
+    let (mut width, mut height) = (1, 1);
+    for frame in frames {
+        let frame = frame?;
+        (width, height) = frame.dimensions();
+        let mut still = Vec::new();
+        image_webp::WebPEncoder::new(&mut still)
+            .encode(frame.as_raw(), width, height, image_webp::ColorType::Rgba8)
+            .map_err(failed)?;
+        // RIFF, its size and WEBP, then the lone VP8L chunk, which is kept whole.
+        let bitstream = &still[12..];
+
+        let mut chunk = Vec::with_capacity(16 + bitstream.len());
+        chunk.extend_from_slice(&[0; 6]);
+        chunk.extend_from_slice(&u24(width - 1));
+        chunk.extend_from_slice(&u24(height - 1));
+        chunk.extend_from_slice(&u24(animation.delay_ms.min(0xFF_FFFF)));
+        // Replace the canvas rather than blend over it, and dispose of nothing.
+        chunk.push(0b10);
+        chunk.extend_from_slice(bitstream);
+        write_chunk(&mut body, b"ANMF", &chunk);
+    }
+
+    let mut header = Vec::with_capacity(32);
+    let mut vp8x = vec![0b0001_0010, 0, 0, 0];
+    vp8x.extend_from_slice(&u24(width - 1));
+    vp8x.extend_from_slice(&u24(height - 1));
+    write_chunk(&mut header, b"VP8X", &vp8x);
+    // A transparent background, then the loop count with 0 for forever.
+    let mut anim = vec![0; 4];
+    anim.extend_from_slice(&animation.plays.to_le_bytes());
+    write_chunk(&mut header, b"ANIM", &anim);
+
+    let mut bytes = Vec::with_capacity(12 + header.len() + body.len());
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&((4 + header.len() + body.len()) as u32).to_le_bytes());
+    bytes.extend_from_slice(b"WEBP");
+    bytes.extend_from_slice(&header);
+    bytes.extend_from_slice(&body);
+    Ok(bytes)
+}
+
+fn u24(value: u32) -> [u8; 3] {
+    let [low, middle, high, _] = value.to_le_bytes();
+    [low, middle, high]
+}
+
+/// Appends a RIFF chunk, padded to an even length.
+fn write_chunk(output: &mut Vec<u8>, name: &[u8; 4], data: &[u8]) {
+    output.extend_from_slice(name);
+    output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    output.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        output.push(0);
+    }
+}
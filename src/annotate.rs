@@ -0,0 +1,330 @@
+//! Bounding boxes, labels and masks drawn over an image from COCO or YOLO
+//! annotations, so a dataset can be checked by eye.
+
+use std::collections::HashMap;
+
+use image::RgbaImage;
+use serde::Deserialize;
+
+use crate::canvas::blend_over;
+use crate::text::{draw_text, text_width};
+use crate::ImageDataErrors;
+
+/// Colours given to classes in turn, Tableau's ten, which stay apart on
+/// most photos and for most colour vision.
+const PALETTE: [[u8; 3]; 10] = [
+    [31, 119, 180],
+    [255, 127, 14],
+    [44, 160, 44],
+    [214, 39, 40],
+    [148, 103, 189],
+    [140, 86, 75],
+    [227, 119, 194],
+    [127, 127, 127],
+    [188, 189, 34],
+    [23, 190, 207],
+];
+
+/// How opaque masks are drawn, so the object under them still shows.
+const MASK_ALPHA: u8 = 102;
+
+/// One annotated object, in pixels of the image it belongs to.
+#[derive(Debug, Clone)]
+pub struct Annotation {
+    /// Picks the colour, so every object of a class shares one.
+    pub class: u64,
+    pub label: String,
+    pub bbox: Option<BoundingBox>,
+    pub mask: Option<Mask>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BoundingBox {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+#[derive(Debug, Clone)]
+pub enum Mask {
+    /// Outlines as `(x, y)` points; holes come from overlapping outlines.
+    Polygons(Vec<Vec<(f64, f64)>>),
+    /// Alternating runs of background and object pixels, column by column
+    /// from the top-left, as COCO's run-length encoding stores them.
+    Runs { width: u32, height: u32, counts: Vec<u64> },
+}
+
+/// The parts of a COCO annotation file that are drawn.
+#[derive(Debug, Deserialize)]
+pub struct CocoDataset {
+    #[serde(default)]
+    images: Vec<CocoImage>,
+    annotations: Vec<CocoAnnotation>,
+    #[serde(default)]
+    categories: Vec<CocoCategory>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CocoImage {
+    id: u64,
+    file_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CocoAnnotation {
+    image_id: u64,
+    category_id: u64,
+    bbox: Option<[f64; 4]>,
+    segmentation: Option<Segmentation>,
+    /// Present in detection results rather than ground truth.
+    score: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Segmentation {
+    Polygons(Vec<Vec<f64>>),
+    Runs { counts: RunCounts, size: [u32; 2] },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RunCounts {
+    Plain(Vec<u64>),
+    Compressed(String),
+}
+
+#[derive(Debug, Deserialize)]
+struct CocoCategory {
+    id: u64,
+    name: String,
+}
+
+impl CocoDataset {
+    /// The annotations of image `image_id`, or else of the image whose file
+    /// name matches that of `path`, or else of the file's only image.
+    pub fn annotations_for(&self, path: &str, image_id: Option<u64>) -> Result<Vec<Annotation>, ImageDataErrors> {
+        let file_name = |name: &str| std::path::Path::new(name).file_name().map(|name| name.to_os_string());
+        let image_id = match image_id {
+            Some(id) => id,
+            None => match self.images.iter().find(|image| file_name(&image.file_name) == file_name(path)) {
+                Some(image) => image.id,
+                None if self.images.len() == 1 => self.images[0].id,
+                None => {
+                    return Err(ImageDataErrors::InvalidArgument(format!(
+                        "no image named like {} in the annotations; pick one with `--image-id`",
+                        path
+                    )))
+                }
+            },
+        };
+        let names: HashMap<u64, &str> = self.categories.iter().map(|category| (category.id, category.name.as_str())).collect();
+        self.annotations
+            .iter()
+            .filter(|annotation| annotation.image_id == image_id)
+            .map(|annotation| {
+                let name = names.get(&annotation.category_id).map_or_else(|| annotation.category_id.to_string(), |name| name.to_string());
+                let mask = match &annotation.segmentation {
+                    Some(Segmentation::Polygons(polygons)) => Some(Mask::Polygons(
+                        polygons.iter().map(|points| points.chunks_exact(2).map(|point| (point[0], point[1])).collect()).collect(),
+                    )),
+                    Some(Segmentation::Runs { counts, size: [height, width] }) => {
+                        let counts = match counts {
+                            RunCounts::Plain(counts) => counts.clone(),
+                            RunCounts::Compressed(counts) => decompress_counts(counts)?,
+                        };
+                        Some(Mask::Runs { width: *width, height: *height, counts })
+                    }
+                    None => None,
+                };
+                Ok(Annotation {
+                    class: annotation.category_id,
+                    label: with_score(name, annotation.score),
+                    bbox: annotation.bbox.map(|[x, y, width, height]| BoundingBox { x, y, width, height }),
+                    mask,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Reads YOLO labels for an image of `size`: a line per object holding the
+/// class, then either the box's centre and size or a polygon's points, all
+/// as fractions of the image, with an optional confidence after a box.
+/// Classes are named by `names` when it has them.
+pub fn parse_yolo(text: &str, names: &[String], (width, height): (u32, u32)) -> Result<Vec<Annotation>, ImageDataErrors> {
+    let (width, height) = (width as f64, height as f64);
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            let invalid = || ImageDataErrors::InvalidArgument(format!("line {} of the YOLO labels is not `class x y width height`", index + 1));
+            let mut fields = line.split_whitespace();
+            let class: u64 = fields.next().and_then(|class| class.parse().ok()).ok_or_else(invalid)?;
+            let numbers = fields.map(|field| field.parse::<f64>().map_err(|_| invalid())).collect::<Result<Vec<_>, _>>()?;
+            let name = names.get(class as usize).cloned().unwrap_or_else(|| class.to_string());
+            match numbers[..] {
+                [centre_x, centre_y, box_width, box_height, ref score @ ..] if score.len() <= 1 => Ok(Annotation {
+                    class,
+                    label: with_score(name, score.first().copied()),
+                    bbox: Some(BoundingBox {
+                        x: (centre_x - box_width / 2.0) * width,
+                        y: (centre_y - box_height / 2.0) * height,
+                        width: box_width * width,
+                        height: box_height * height,
+                    }),
+                    mask: None,
+                }),
+                _ if numbers.len() >= 6 && numbers.len() % 2 == 0 => {
+                    let points: Vec<(f64, f64)> = numbers.chunks_exact(2).map(|point| (point[0] * width, point[1] * height)).collect();
+                    let (left, right) = points.iter().fold((f64::MAX, f64::MIN), |(low, high), point| (low.min(point.0), high.max(point.0)));
+                    let (top, bottom) = points.iter().fold((f64::MAX, f64::MIN), |(low, high), point| (low.min(point.1), high.max(point.1)));
+                    Ok(Annotation {
+                        class,
+                        label: name,
+                        bbox: Some(BoundingBox { x: left, y: top, width: right - left, height: bottom - top }),
+                        mask: Some(Mask::Polygons(vec![points])),
+                    })
+                }
+                _ => Err(invalid()),
+            }
+        })
+        .collect()
+}
+
+fn with_score(name: String, score: Option<f64>) -> String {
+    match score {
+        Some(score) => format!("{} {:.2}", name, score),
+        None => name,
+    }
+}
+
+/// Undoes the compression pycocotools applies to run lengths: each is
+/// stored as the change from the run two before, five bits to a character.
+fn decompress_counts(text: &str) -> Result<Vec<u64>, ImageDataErrors> {
+    let invalid = || ImageDataErrors::InvalidArgument("corrupt run-length mask in the annotations".to_string());
+    let mut counts: Vec<i64> = Vec::new();
+    let mut bytes = text.bytes();
+    while let Some(first) = bytes.next() {
+        let (mut value, mut shift, mut byte) = (0i64, 0, first);
+        loop {
+            let chunk = byte.checked_sub(48).filter(|chunk| *chunk < 64).ok_or_else(invalid)? as i64;
+            value |= (chunk & 0x1f) << shift;
+            shift += 5;
+            if chunk & 0x20 == 0 {
+                if chunk & 0x10 != 0 {
+                    value |= -1 << shift;
+                }
+                break;
+            }
+            byte = bytes.next().ok_or_else(invalid)?;
+            if shift > 55 {
+                return Err(invalid());
+            }
+        }
+        if counts.len() > 2 {
+            value += counts[counts.len() - 2];
+        }
+        counts.push(value);
+    }
+    counts.into_iter().map(|count| u64::try_from(count).map_err(|_| invalid())).collect()
+}
+
+/// Draws every annotation over `image`: masks first, then boxes, then the
+/// labels, so no box hides a label.
+pub fn draw(image: &mut RgbaImage, annotations: &[Annotation]) {
+    let colour = |annotation: &Annotation| PALETTE[(annotation.class % PALETTE.len() as u64) as usize];
+    for annotation in annotations {
+        let [red, green, blue] = colour(annotation);
+        match &annotation.mask {
+            Some(Mask::Polygons(polygons)) => fill_polygons(image, polygons, [red, green, blue, MASK_ALPHA]),
+            Some(Mask::Runs { width, height, counts }) => fill_runs(image, (*width, *height), counts, [red, green, blue, MASK_ALPHA]),
+            None => {}
+        }
+    }
+
+    let shorter = image.width().min(image.height());
+    let thickness = (shorter / 300).max(2) as i64;
+    let scale = (shorter / 400).max(1);
+    for annotation in annotations.iter() {
+        if let Some(bbox) = annotation.bbox {
+            let [red, green, blue] = colour(annotation);
+            let (left, top) = (bbox.x.round() as i64, bbox.y.round() as i64);
+            let (right, bottom) = ((bbox.x + bbox.width).round() as i64, (bbox.y + bbox.height).round() as i64);
+            for inset in 0..thickness {
+                outline(image, (left + inset, top + inset, right - inset, bottom - inset), [red, green, blue, 255]);
+            }
+        }
+    }
+    for annotation in annotations.iter() {
+        let Some(bbox) = annotation.bbox else { continue };
+        let [red, green, blue] = colour(annotation);
+        // Above the box, or just inside it when the box touches the top.
+        let (label_width, label_height) = (text_width(&annotation.label, scale) as i64 + 4 * scale as i64, 12 * scale as i64);
+        let left = bbox.x.round() as i64;
+        let top = if bbox.y.round() as i64 >= label_height { bbox.y.round() as i64 - label_height } else { bbox.y.round() as i64 };
+        fill_rect(image, (left, top, left + label_width, top + label_height), [red, green, blue, 255]);
+        // Dark text on light colours, white on the rest.
+        let luma = 0.299 * red as f64 + 0.587 * green as f64 + 0.114 * blue as f64;
+        let ink = if luma > 150.0 { [0, 0, 0, 255] } else { [255, 255, 255, 255] };
+        draw_text(image, &annotation.label, left + 2 * scale as i64, top + 2 * scale as i64, scale, ink);
+    }
+}
+
+/// Fills the pixels whose centres fall inside an odd number of `polygons`.
+fn fill_polygons(image: &mut RgbaImage, polygons: &[Vec<(f64, f64)>], colour: [u8; 4]) {
+    for y in 0..image.height() {
+        let centre = y as f64 + 0.5;
+        let mut crossings: Vec<f64> = polygons
+            .iter()
+            .flat_map(|points| points.iter().zip(points.iter().cycle().skip(1)).take(points.len()))
+            .filter(|((_, y_1), (_, y_2))| (*y_1 <= centre) != (*y_2 <= centre))
+            .map(|((x_1, y_1), (x_2, y_2))| x_1 + (centre - y_1) / (y_2 - y_1) * (x_2 - x_1))
+            .collect();
+        crossings.sort_by(f64::total_cmp);
+        for span in crossings.chunks_exact(2) {
+            let start = (span[0] - 0.5).ceil().max(0.0) as u32;
+            let end = ((span[1] - 0.5).floor() + 1.0).clamp(0.0, image.width() as f64) as u32;
+            for x in start..end {
+                blend_over(&mut image.get_pixel_mut(x, y).0, colour);
+            }
+        }
+    }
+}
+
+/// Fills the object runs of a run-length mask made for an image of `size`.
+fn fill_runs(image: &mut RgbaImage, (width, height): (u32, u32), counts: &[u64], colour: [u8; 4]) {
+    let mut position = 0u64;
+    for (index, &count) in counts.iter().enumerate() {
+        if index % 2 == 1 {
+            for pixel in position..position + count {
+                let (x, y) = ((pixel / height as u64) as u32, (pixel % height as u64) as u32);
+                if x < width.min(image.width()) && y < image.height() {
+                    blend_over(&mut image.get_pixel_mut(x, y).0, colour);
+                }
+            }
+        }
+        position += count;
+    }
+}
+
+fn outline(image: &mut RgbaImage, (left, top, right, bottom): (i64, i64, i64, i64), colour: [u8; 4]) {
+    if right <= left || bottom <= top {
+        return;
+    }
+    fill_rect(image, (left, top, right, top + 1), colour);
+    fill_rect(image, (left, bottom - 1, right, bottom), colour);
+    fill_rect(image, (left, top, left + 1, bottom), colour);
+    fill_rect(image, (right - 1, top, right, bottom), colour);
+}
+
+/// Fills the pixels from `left` and `top` up to, but not including, `right` and `bottom`.
+fn fill_rect(image: &mut RgbaImage, (left, top, right, bottom): (i64, i64, i64, i64), colour: [u8; 4]) {
+    let (width, height) = (image.width() as i64, image.height() as i64);
+    for y in top.max(0)..bottom.min(height) {
+        for x in left.max(0)..right.min(width) {
+            blend_over(&mut image.get_pixel_mut(x as u32, y as u32).0, colour);
+        }
+    }
+}
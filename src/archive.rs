@@ -0,0 +1,66 @@
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, Write};
+
+use zip::write::SimpleFileOptions;
+use zip::{DateTime, ZipArchive, ZipWriter};
+
+use combiner::ImageStore;
+
+use crate::storage::{self, Storage};
+use crate::ImageDataErrors;
+
+/// Splits `archive.zip!photos/a.png` into the archive and the entry inside it.
+pub fn split_entry(path: &str) -> Option<(&str, &str)> {
+    let end = path.to_ascii_lowercase().find(".zip!")? + ".zip".len();
+    Some((&path[..end], &path[end + 1..]))
+}
+
+/// Reads one entry of a zip archive, which may itself live in object storage.
+pub fn read_entry(archive: &str, entry: &str) -> Result<Vec<u8>, ImageDataErrors> {
+    let read = if storage::is_remote(archive) {
+        read_from(Cursor::new(Storage.read(archive)?), entry)
+    } else {
+        read_from(File::open(archive).map_err(ImageDataErrors::UnableToReadImageFromPath)?, entry)
+    };
+    read.map_err(|e| ImageDataErrors::UnableToReadArchive(format!("{}!{}: {}", archive, entry, e)))
+}
+
+fn read_from(reader: impl Read + Seek, entry: &str) -> zip::result::ZipResult<Vec<u8>> {
+    let mut bytes = Vec::new();
+    ZipArchive::new(reader)?.by_name(entry)?.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// `--zip-output`: batch results written as entries of one archive instead of
+/// as files, named by their path relative to `root`.
+pub struct ZipOutput {
+    path: String,
+    root: String,
+    writer: ZipWriter<File>,
+}
+
+impl ZipOutput {
+    pub fn create(path: &str, root: &str) -> Result<Self, ImageDataErrors> {
+        let file = File::create(path).map_err(|e| ImageDataErrors::UnableToWriteArchive(format!("{}: {}", path, e)))?;
+        Ok(ZipOutput { path: path.to_string(), root: root.to_string(), writer: ZipWriter::new(file) })
+    }
+
+    /// Adds the output that would have been written to `output`, returning its entry name.
+    pub fn add(&mut self, output: &str, bytes: &[u8]) -> Result<String, ImageDataErrors> {
+        let name = output.strip_prefix(&self.root).unwrap_or(output).trim_start_matches(['/', '\\']).to_string();
+        let failed = |e: &dyn std::fmt::Display| ImageDataErrors::UnableToWriteArchive(format!("{}!{}: {}", self.path, name, e));
+        // A fixed timestamp, so archives of the same outputs are byte-identical.
+        let options = SimpleFileOptions::default().last_modified_time(DateTime::default());
+        self.writer.start_file(name.as_str(), options).map_err(|e| failed(&e))?;
+        self.writer.write_all(bytes).map_err(|e| failed(&e))?;
+        Ok(format!("{}!{}", self.path, name))
+    }
+
+    /// Writes the archive's central directory; without it the archive is unreadable.
+    pub fn finish(self) -> Result<(), ImageDataErrors> {
+        self.writer
+            .finish()
+            .map(|_| ())
+            .map_err(|e| ImageDataErrors::UnableToWriteArchive(format!("{}: {}", self.path, e)))
+    }
+}
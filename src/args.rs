@@ -1,20 +1,1540 @@
-fn get_nth_arg(n: usize) -> String{
-    std::env::args().nth(n).unwrap()
+pub use combiner::options::{CombineOptions, DiffStyle, Mode};
+use combiner::animate::{Animation, AnimationStyle};
+use combiner::augment::Augmentation;
+use combiner::astro::StackMethod;
+use combiner::caption::{Caption, Placement};
+use combiner::displace::Displacement;
+use combiner::fit::Fit;
+use combiner::frame::Device;
+use combiner::geometry::{CropRegion, Geometry, Gravity};
+use combiner::lut::Lut;
+use combiner::mask::{self, MaskSource};
+use combiner::gpu::Backend;
+use combiner::icc::Profile;
+use combiner::noise::{Grain, Noise};
+use combiner::npy::NpyDtype;
+use combiner::pipeline::Pipeline;
+use combiner::preset::SizePreset;
+use combiner::print::PrintLayout;
+use combiner::qr::QrErrorCorrection;
+use combiner::stamp::Stamp;
+use combiner::raw::RawLayout;
+use combiner::svg::SvgOptions;
+use combiner::tone::{Curve, Levels};
+use combiner::transform::Flip;
+use combiner::warp::Quad;
+use image::ImageFormat;
+
+use crate::cache;
+use crate::checksum::ChecksumAlgorithm;
+use crate::incremental;
+use crate::{capture, clipboard, memory};
+use crate::preview::Protocol;
+use crate::template::{FramePattern, NameBy, NameTemplate, TilePattern};
+use crate::ImageDataErrors;
+
+/// `--sequence`: a crossfade written as numbered frames instead of one output.
+#[derive(Debug)]
+pub struct Sequence {
+    pub frames: usize,
+    pub pattern: FramePattern,
+}
+
+/// Where the images of every combination come from.
+#[derive(Debug)]
+pub enum Inputs {
+    /// A single `image_1 image_2 output` combination.
+    Single { image_1: String, image_2: String, output: String },
+    /// Every file in `input_dir` combined with `image_2`, written under `output_dir`.
+    Directory { input_dir: String, output_dir: String, image_2: String },
+    /// A CSV or JSON file listing one combination per row.
+    Manifest { path: String },
+    /// Combinations requested while running, on stdin or on a unix `socket`.
+    Daemon { socket: Option<String> },
+}
+
+/// Which files of an input directory take part in a batch. Patterns are
+/// matched against paths relative to the input directory.
+#[derive(Debug, Default)]
+pub struct Selection {
+    pub recursive: bool,
+    pub include: Vec<glob::Pattern>,
+    pub exclude: Vec<glob::Pattern>,
+}
+
+impl Selection {
+    pub fn accepts(&self, input_dir: &str, path: &std::path::Path) -> bool {
+        let relative = path.strip_prefix(input_dir).unwrap_or(path);
+        let included = self.include.is_empty() || self.include.iter().any(|pattern| pattern.matches_path(relative));
+        included && !self.exclude.iter().any(|pattern| pattern.matches_path(relative))
+    }
+}
+
+/// `--lut-1`, `--lut-2` and `--lut`: `.cube` grades applied to each input
+/// once decoded, and to the output before it is encoded.
+#[derive(Debug, Default)]
+pub struct Luts {
+    pub inputs: [Option<Lut>; 2],
+    pub output: Option<Lut>,
+    /// The hashes of the `.cube` files, so `--incremental` rebuilds when one changes.
+    pub digests: Vec<String>,
 }
 
 #[derive(Debug)]
 pub struct Args {
-    pub image_1: String,
-    pub image_2: String,
+    pub inputs: Inputs,
+    pub options: CombineOptions,
+    pub diff_report: Option<String>,
+    pub strict: bool,
+    pub watch: bool,
+    pub selection: Selection,
+    pub name_template: Option<NameTemplate>,
+    /// What batch outputs are named after instead of a template.
+    pub name_by: Option<NameBy>,
+    pub cache_size: usize,
+    /// The state file to skip up-to-date outputs with, when `--incremental` is set.
+    pub incremental: Option<String>,
+    /// The archive batch results are written into instead of as files.
+    pub zip_output: Option<String>,
+    /// The layout every input is read with, as a headerless buffer, when `--raw` is set.
+    pub raw: Option<RawLayout>,
+    /// The maxval PNM outputs are written with.
+    pub pnm_maxval: u16,
+    /// The element type of `.npy` outputs.
+    pub npy_dtype: NpyDtype,
+    /// How large SVG inputs are rasterised.
+    pub svg: SvgOptions,
+    /// The DPI PDF pages are rendered at.
+    pub pdf_dpi: f32,
+    /// The colour transparent outputs are flattened onto when their format has no alpha.
+    pub background: Option<[u8; 3]>,
+    /// Whether outputs must be byte-identical on every run: nothing is
+    /// carried over from the inputs' metadata and nothing may depend on the
+    /// clock.
+    pub deterministic: bool,
+    /// The hash printed for each output, when `--checksum` is set.
+    pub checksum: Option<ChecksumAlgorithm>,
+    /// Whether checksums go to a `.sha256` file beside each output instead of stdout.
+    pub checksum_sidecar: bool,
+    /// The crossfade written instead of combining, when `--sequence` is set.
+    pub sequence: Option<Sequence>,
+    /// The animated comparison written instead of combining, when `--animate` is set.
+    pub animate: Option<Animation>,
+    /// The directory a Deep Zoom pyramid of each output is written to.
+    pub output_dzi: Option<String>,
+    /// How many bytes the run may allocate, when `--max-memory` is set.
+    pub max_memory: Option<u64>,
+    /// Whether JPEG inputs are always decoded whole, even when a smaller
+    /// scale would cover the output.
+    pub full_decode: bool,
+    /// Whether jobs are only listed, with their sizes read from the inputs'
+    /// headers, and nothing is decoded or written.
+    pub dry_run: bool,
+    /// How the result is previewed in the terminal before it is written,
+    /// when `--preview` is set.
+    pub preview: Option<Protocol>,
+    /// Whether the output is also, or with no output path only, placed on
+    /// the clipboard.
+    pub to_clipboard: bool,
+    /// The screen or window captured as the first input.
+    pub capture: Option<capture::Source>,
+    /// Where a summary of each finished batch is POSTed.
+    pub notify_url: Option<String>,
+    /// The Rhai script combining pixels or naming outputs.
+    pub script: Option<String>,
+    /// The plugin blend mode or layout used in place of the mode.
+    pub plugin: Option<String>,
+    /// Where plugins are loaded from, in place of the user's plugin directory.
+    pub plugins_dir: Option<String>,
+    /// The colour grades applied to the inputs and the output.
+    pub luts: Luts,
+    /// The map the second input is displaced by once graded, and the hash of
+    /// the map and its amount, so `--incremental` rebuilds when either changes.
+    pub displace: Option<(Displacement, String)>,
+    /// The output profile a soft proof is written beside the output for.
+    pub proof: Option<Profile>,
+    /// The output profile whose out-of-gamut pixels are marked on the output.
+    pub gamut_warn: Option<Profile>,
+    /// The colour out-of-gamut pixels are marked in.
+    pub gamut_colour: [u8; 3],
+    /// Whether a sidecar recording how each output was made is written beside it.
+    pub record: bool,
+    /// The flags given that shape outputs beyond the options, as given, for
+    /// the sidecar to replay.
+    pub arguments: Vec<String>,
+}
+
+/// The parsed command line. The logging level it sets is shared by every
+/// command and applied to the logger as soon as it is known.
+#[derive(Debug)]
+pub struct Cli {
+    pub command: Command,
+}
+
+impl Cli {
+    /// Parses the command line and sets the logging level it asks for. The
+    /// logger starts first so parsing itself can report through it, letting
+    /// only warnings through until the flags are known.
+    pub fn new() -> Result<Self, ImageDataErrors> {
+        // `RUST_LOG` overrides the flags, so it is left alone when set.
+        let overridden = std::env::var_os("RUST_LOG").is_some();
+        env_logger::Builder::new()
+            .filter_level(if overridden { log::LevelFilter::Warn } else { log::LevelFilter::Trace })
+            .parse_default_env()
+            .init();
+        if !overridden {
+            log::set_max_level(log::LevelFilter::Warn);
+        }
+        let mut verbosity = Verbosity::default();
+        let command = Command::parse(std::env::args().skip(1), &mut verbosity)?;
+        if !overridden {
+            log::set_max_level(verbosity.level());
+        }
+        Ok(Cli { command })
+    }
+}
+
+/// `-q`, `-v`, `-vv` and `-vvv`, or `--quiet` and `--verbose`, as counted
+/// by each parser where a flag can stand. They are left alone as another
+/// flag's value, as in `--message -v`, and after `--`.
+#[derive(Debug, Default)]
+struct Verbosity(i8);
+
+impl Verbosity {
+    /// Counts `arg` if it is a verbosity flag, returning whether it was.
+    fn count(&mut self, arg: &str) -> bool {
+        let step = match arg {
+            "-q" | "--quiet" => -1,
+            "-v" | "--verbose" => 1,
+            "-vv" => 2,
+            "-vvv" => 3,
+            _ => return false,
+        };
+        self.0 = self.0.saturating_add(step);
+        true
+    }
+
+    /// The next argument of `raw` that is not a verbosity flag, counting
+    /// those before it.
+    fn next(&mut self, raw: &mut impl Iterator<Item = String>) -> Option<String> {
+        raw.find(|arg| !self.count(arg))
+    }
+
+    fn level(&self) -> log::LevelFilter {
+        match self.0 {
+            i8::MIN..=-1 => log::LevelFilter::Error,
+            0 => log::LevelFilter::Warn,
+            1 => log::LevelFilter::Info,
+            2 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace,
+        }
+    }
+}
+
+/// What the program was asked to do, decided by the first argument.
+#[derive(Debug)]
+pub enum Command {
+    Combine(Box<Args>),
+    Info(Vec<String>),
+    Serve(ServeArgs),
+    Grpc(ServeArgs),
+    Stego(StegoArgs),
+    Idwm(IdwmArgs),
+    Lenticular(LenticularArgs),
+    Augment(AugmentArgs),
+    Annotate(AnnotateArgs),
+    Caption(CaptionArgs),
+    MosaicAssemble(MosaicArgs),
+    Bench(Box<BenchArgs>),
+    Gui(GuiArgs),
+    Recipe(Box<RecipeArgs>),
+    Replay(ReplayArgs),
+}
+
+/// Options of the `stego` subcommand.
+#[derive(Debug)]
+pub enum StegoArgs {
+    /// Hides `message` in `image`, writing the result to `output`.
+    Embed { image: String, output: String, message: Vec<u8> },
+    /// Recovers the message hidden in `image`, into `output` or to stdout.
+    Extract { image: String, output: Option<String> },
+}
+
+/// Options of the `idwm` subcommand, for invisible watermarks.
+#[derive(Debug)]
+pub enum IdwmArgs {
+    /// Marks `image` with the watermark of `key`, writing the result to `output`.
+    Embed { image: String, output: String, key: String, strength: f32 },
+    /// Reports whether `image` carries the watermark of `key`.
+    Verify { image: String, key: String },
+}
+
+/// Options of the `lenticular` subcommand, for lens sheet prints.
+#[derive(Debug)]
+pub enum LenticularArgs {
+    /// Interlaces `images` into `output` for a sheet of `lpi` lenses per inch, printed at `dpi`.
+    Interlace { images: Vec<String>, output: String, lpi: f64, dpi: f64 },
+    /// Writes a pitch test for every LPI from `lpi - spread` to `lpi + spread` in `step`s.
+    Calibrate { output: String, lpi: f64, dpi: f64, spread: f64, step: f64 },
+}
+
+/// Options of the `augment` subcommand, which writes randomly transformed
+/// variants of every image in a directory.
+#[derive(Debug)]
+pub struct AugmentArgs {
+    pub input_dir: String,
+    pub output_dir: String,
+    pub recursive: bool,
+    /// How many variants are made of each image.
+    pub variants: usize,
+    pub seed: u64,
+    pub augmentation: Augmentation,
+    /// Where the transforms applied to each variant are recorded.
+    pub manifest: String,
+}
+
+impl AugmentArgs {
+    fn parse(mut raw: impl Iterator<Item = String>, verbosity: &mut Verbosity) -> Result<Self, ImageDataErrors> {
+        let mut positional = Vec::new();
+        let mut recursive = false;
+        let mut variants = 4;
+        let mut seed = 0;
+        let mut augmentation = Augmentation::default();
+        let mut manifest = None;
+        while let Some(arg) = raw.next() {
+            let (flag, inline_value) = split_flag(&arg);
+            let mut value = || next_value(flag, inline_value.clone(), &mut raw);
+            match flag {
+                "--recursive" => recursive = true,
+                "--variants" => variants = parse_number(flag, &value()?)?,
+                "--seed" => seed = parse_number(flag, &value()?)?,
+                "--flip" => augmentation.flip = true,
+                "--crop" => augmentation.crop = Some(parse_number(flag, &value()?)?),
+                "--jitter" => augmentation.jitter = Some(parse_number(flag, &value()?)?),
+                "--blend" => augmentation.blend = Some(parse_number(flag, &value()?)?),
+                "--manifest" => manifest = Some(value()?),
+                "--" => {
+                    positional.extend(raw.by_ref());
+                    break;
+                }
+                _ if verbosity.count(flag) => {}
+                _ if flag.starts_with("--") => {
+                    return Err(ImageDataErrors::InvalidArgument(format!("unknown flag `{}`", flag)))
+                }
+                _ => positional.push(arg),
+            }
+        }
+        let mut positional = positional.into_iter();
+        let input_dir = positional.next().ok_or(ImageDataErrors::MissingArgument("input_dir"))?;
+        let output_dir = positional.next().ok_or(ImageDataErrors::MissingArgument("output_dir"))?;
+
+        if augmentation.is_empty() {
+            return Err(ImageDataErrors::MissingArgument("--flip, --crop, --jitter or --blend"));
+        }
+        if variants == 0 {
+            return Err(ImageDataErrors::InvalidArgument("`--variants` must be at least 1".to_string()));
+        }
+        if augmentation.crop.is_some_and(|crop| !(crop > 0.0 && crop <= 1.0)) {
+            return Err(ImageDataErrors::InvalidArgument("`--crop` must be above 0 and at most 1".to_string()));
+        }
+        for (flag, fraction) in [("--jitter", augmentation.jitter), ("--blend", augmentation.blend)] {
+            if fraction.is_some_and(|fraction| !(0.0..=1.0).contains(&fraction)) {
+                return Err(ImageDataErrors::InvalidArgument(format!("`{}` must be between 0 and 1", flag)));
+            }
+        }
+        let manifest = manifest.unwrap_or_else(|| {
+            std::path::Path::new(&output_dir).join("augment.json").to_string_lossy().into_owned()
+        });
+        Ok(AugmentArgs { input_dir, output_dir, recursive, variants, seed, augmentation, manifest })
+    }
+}
+
+/// Options of the `annotate` subcommand, which draws a dataset's labels over
+/// one of its images.
+#[derive(Debug)]
+pub struct AnnotateArgs {
+    pub image: String,
+    /// A COCO `.json` file or YOLO `.txt` labels.
+    pub annotations: String,
     pub output: String,
+    /// A file naming YOLO classes, one per line in class order.
+    pub names: Option<String>,
+    /// The COCO image drawn, when the file names do not match.
+    pub image_id: Option<u64>,
+}
+
+impl AnnotateArgs {
+    fn parse(mut raw: impl Iterator<Item = String>, verbosity: &mut Verbosity) -> Result<Self, ImageDataErrors> {
+        let mut positional = Vec::new();
+        let mut names = None;
+        let mut image_id = None;
+        while let Some(arg) = raw.next() {
+            let (flag, inline_value) = split_flag(&arg);
+            let mut value = || next_value(flag, inline_value.clone(), &mut raw);
+            match flag {
+                "--names" => names = Some(value()?),
+                "--image-id" => image_id = Some(parse_number(flag, &value()?)?),
+                "--" => {
+                    positional.extend(raw.by_ref());
+                    break;
+                }
+                _ if verbosity.count(flag) => {}
+                _ if flag.starts_with("--") => {
+                    return Err(ImageDataErrors::InvalidArgument(format!("unknown flag `{}`", flag)))
+                }
+                _ => positional.push(arg),
+            }
+        }
+        let mut positional = positional.into_iter();
+        let image = positional.next().ok_or(ImageDataErrors::MissingArgument("image"))?;
+        let annotations = positional.next().ok_or(ImageDataErrors::MissingArgument("annotations"))?;
+        let output = positional.next().ok_or(ImageDataErrors::MissingArgument("output"))?;
+        Ok(AnnotateArgs { image, annotations, output, names, image_id })
+    }
+}
+
+/// Options of the `caption` subcommand, which sets top and bottom text over
+/// an image.
+#[derive(Debug)]
+pub struct CaptionArgs {
+    pub image: String,
+    pub output: String,
+    pub caption: Caption,
+}
+
+impl CaptionArgs {
+    fn parse(mut raw: impl Iterator<Item = String>, verbosity: &mut Verbosity) -> Result<Self, ImageDataErrors> {
+        let mut positional = Vec::new();
+        let mut caption = Caption::default();
+        while let Some(arg) = raw.next() {
+            let (flag, inline_value) = split_flag(&arg);
+            let mut value = || next_value(flag, inline_value.clone(), &mut raw);
+            match flag {
+                "--top" => caption.top = Some(value()?),
+                "--bottom" => caption.bottom = Some(value()?),
+                "--placement" => caption.placement = Placement::parse(&value()?)?,
+                "--size" => caption.size = Some(parse_number(flag, &value()?)?),
+                "--stroke" => caption.stroke = Some(parse_number(flag, &value()?)?),
+                "--fill" => caption.fill = Some(parse_color(flag, &value()?)?),
+                "--stroke-colour" => caption.stroke_colour = Some(parse_color(flag, &value()?)?),
+                "--" => {
+                    positional.extend(raw.by_ref());
+                    break;
+                }
+                _ if verbosity.count(flag) => {}
+                _ if flag.starts_with("--") => {
+                    return Err(ImageDataErrors::InvalidArgument(format!("unknown flag `{}`", flag)))
+                }
+                _ => positional.push(arg),
+            }
+        }
+        let mut positional = positional.into_iter();
+        let image = positional.next().ok_or(ImageDataErrors::MissingArgument("image"))?;
+        let output = positional.next().ok_or(ImageDataErrors::MissingArgument("output"))?;
+
+        if caption.top.is_none() && caption.bottom.is_none() {
+            return Err(ImageDataErrors::MissingArgument("--top or --bottom"));
+        }
+        if caption.size == Some(0) {
+            return Err(ImageDataErrors::InvalidArgument("`--size` must be at least 1".to_string()));
+        }
+        Ok(CaptionArgs { image, output, caption })
+    }
+}
+
+/// Options of the `mosaic-assemble` subcommand, which stitches a directory
+/// of tiles into one image.
+#[derive(Debug)]
+pub struct MosaicArgs {
+    pub tile_dir: String,
+    pub output: String,
+    /// How tile files are named, `tile_{x}_{y}.png` by default.
+    pub pattern: TilePattern,
+    /// How many pixels neighbouring tiles share.
+    pub overlap: u32,
+}
+
+/// Options of the `bench` subcommand, which times the pipeline's stages
+/// on synthetic images.
+#[derive(Debug)]
+pub struct BenchArgs {
+    /// The output sizes benchmarked, smallest first.
+    pub sizes: Vec<(u32, u32)>,
+    /// The mode, fit and backend benchmarked.
+    pub options: CombineOptions,
+    pub format: ImageFormat,
+    /// How many pipelines run at once, as batch jobs or server workers do.
+    pub threads: usize,
+    /// How many times each thread runs every size.
+    pub runs: usize,
+}
+
+impl BenchArgs {
+    fn parse(mut raw: impl Iterator<Item = String>, verbosity: &mut Verbosity) -> Result<Self, ImageDataErrors> {
+        let mut sizes = vec![(1280, 720), (3840, 2160), (7680, 4320)];
+        let mut options = CombineOptions::default();
+        let mut format = ImageFormat::Png;
+        let mut threads = 1;
+        let mut runs = 3;
+        while let Some(arg) = raw.next() {
+            let (flag, inline_value) = split_flag(&arg);
+            let mut value = || next_value(flag, inline_value.clone(), &mut raw);
+            match flag {
+                "--sizes" => {
+                    sizes = value()?.split(',').map(|size| parse_size(flag, size.trim())).collect::<Result<_, _>>()?;
+                }
+                "--mode" => options.mode = Mode::parse(&value()?)?,
+                "--fit" => options.fit = Fit::parse(&value()?)?,
+                "--backend" => options.backend = Backend::parse(&value()?)?,
+                "--format" => {
+                    let value = value()?;
+                    format = ImageFormat::from_extension(&value)
+                        .ok_or_else(|| ImageDataErrors::InvalidArgument(format!("unknown format `{}`", value)))?;
+                }
+                "--threads" => threads = parse_number(flag, &value()?)?,
+                "--runs" => runs = parse_number(flag, &value()?)?,
+                _ if verbosity.count(flag) => {}
+                _ => return Err(ImageDataErrors::InvalidArgument(format!("unknown flag `{}`", flag))),
+            }
+        }
+        if matches!(options.mode, Mode::Warp | Mode::Photomosaic) {
+            return Err(ImageDataErrors::InvalidArgument(format!("{} mode cannot be benchmarked", options.mode.name())));
+        }
+        if threads == 0 || runs == 0 {
+            return Err(ImageDataErrors::InvalidArgument("`--threads` and `--runs` must be at least 1".to_string()));
+        }
+        sizes.sort_by_key(|&(width, height)| width as u64 * height as u64);
+        Ok(BenchArgs { sizes, options, format, threads, runs })
+    }
+}
+
+/// Options of the `gui` subcommand.
+#[derive(Debug)]
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub struct GuiArgs {
+    /// The inputs shown at first; more can be dropped on the window.
+    pub images: Vec<String>,
+    /// Where the result is saved, until changed in the window.
+    pub output: String,
+    pub mode: Mode,
+}
+
+impl GuiArgs {
+    fn parse(mut raw: impl Iterator<Item = String>, verbosity: &mut Verbosity) -> Result<Self, ImageDataErrors> {
+        let mut positional = Vec::new();
+        let mut mode = Mode::Alternate;
+        while let Some(arg) = raw.next() {
+            let (flag, inline_value) = split_flag(&arg);
+            let mut value = || next_value(flag, inline_value.clone(), &mut raw);
+            match flag {
+                "--mode" => mode = Mode::parse(&value()?)?,
+                "--" => {
+                    positional.extend(raw.by_ref());
+                    break;
+                }
+                _ if verbosity.count(flag) => {}
+                _ if flag.starts_with("--") => {
+                    return Err(ImageDataErrors::InvalidArgument(format!("unknown flag `{}`", flag)))
+                }
+                _ => positional.push(arg),
+            }
+        }
+        if positional.len() > 3 {
+            return Err(ImageDataErrors::InvalidArgument("`gui` takes at most two images and an output".to_string()));
+        }
+        let output = if positional.len() == 3 { positional.pop().expect("three arguments") } else { "output.png".to_string() };
+        Ok(GuiArgs { images: positional, output, mode })
+    }
+}
+
+/// Options of the `recipe` subcommand.
+#[derive(Debug)]
+pub struct RecipeArgs {
+    /// The JSON recipe, whose paths are relative to its directory.
+    pub path: String,
+    /// Used by `combine` steps, and to finish every output.
+    pub options: CombineOptions,
+    /// The colour transparent outputs are flattened onto when their format has no alpha.
+    pub background: Option<[u8; 3]>,
+}
+
+impl RecipeArgs {
+    fn parse(mut raw: impl Iterator<Item = String>, verbosity: &mut Verbosity) -> Result<Self, ImageDataErrors> {
+        let mut positional = Vec::new();
+        let mut options = CombineOptions::default();
+        let mut background = None;
+        while let Some(arg) = raw.next() {
+            let (flag, inline_value) = split_flag(&arg);
+            let mut value = || next_value(flag, inline_value.clone(), &mut raw);
+            match flag {
+                "--options" => options = load_options(&value()?)?,
+                "--mode" => options.mode = Mode::parse(&value()?)?,
+                "--background" => background = Some(parse_color(flag, &value()?)?),
+                "--" => {
+                    positional.extend(raw.by_ref());
+                    break;
+                }
+                _ if verbosity.count(flag) => {}
+                _ if flag.starts_with("--") => {
+                    return Err(ImageDataErrors::InvalidArgument(format!("unknown flag `{}`", flag)))
+                }
+                _ => positional.push(arg),
+            }
+        }
+        let path = positional.into_iter().next().ok_or(ImageDataErrors::MissingArgument("recipe"))?;
+        Ok(RecipeArgs { path, options, background })
+    }
+}
+
+/// Options of the `replay` subcommand.
+#[derive(Debug)]
+pub struct ReplayArgs {
+    /// The `.imgcombine.json` sidecar to replay.
+    pub sidecar: String,
+    /// Where the output is written, in place of beside the sidecar.
+    pub output: Option<String>,
+    /// Whether inputs that changed since the output was made are used anyway.
+    pub allow_changed: bool,
+}
+
+impl ReplayArgs {
+    fn parse(mut raw: impl Iterator<Item = String>, verbosity: &mut Verbosity) -> Result<Self, ImageDataErrors> {
+        let mut positional = Vec::new();
+        let mut allow_changed = false;
+        while let Some(arg) = raw.next() {
+            match arg.as_str() {
+                "--allow-changed" => allow_changed = true,
+                "--" => {
+                    positional.extend(raw.by_ref());
+                    break;
+                }
+                flag if verbosity.count(flag) => {}
+                flag if flag.starts_with("--") => {
+                    return Err(ImageDataErrors::InvalidArgument(format!("unknown flag `{}`", flag)))
+                }
+                _ => positional.push(arg),
+            }
+        }
+        if positional.len() > 2 {
+            return Err(ImageDataErrors::InvalidArgument("`replay` takes a sidecar and at most an output".to_string()));
+        }
+        let mut positional = positional.into_iter();
+        let sidecar = positional.next().ok_or(ImageDataErrors::MissingArgument("sidecar"))?;
+        Ok(ReplayArgs { sidecar, output: positional.next(), allow_changed })
+    }
+}
+
+impl MosaicArgs {
+    fn parse(mut raw: impl Iterator<Item = String>, verbosity: &mut Verbosity) -> Result<Self, ImageDataErrors> {
+        let mut positional = Vec::new();
+        let mut pattern = None;
+        let mut overlap = 0;
+        while let Some(arg) = raw.next() {
+            let (flag, inline_value) = split_flag(&arg);
+            let mut value = || next_value(flag, inline_value.clone(), &mut raw);
+            match flag {
+                "--pattern" => pattern = Some(TilePattern::parse(&value()?)?),
+                "--overlap" => overlap = parse_number(flag, &value()?)?,
+                "--" => {
+                    positional.extend(raw.by_ref());
+                    break;
+                }
+                _ if verbosity.count(flag) => {}
+                _ if flag.starts_with("--") => {
+                    return Err(ImageDataErrors::InvalidArgument(format!("unknown flag `{}`", flag)))
+                }
+                _ => positional.push(arg),
+            }
+        }
+        let mut positional = positional.into_iter();
+        let tile_dir = positional.next().ok_or(ImageDataErrors::MissingArgument("tile_dir"))?;
+        let output = positional.next().ok_or(ImageDataErrors::MissingArgument("output"))?;
+        let pattern = match pattern {
+            Some(pattern) => pattern,
+            None => TilePattern::parse("tile_{x}_{y}.png")?,
+        };
+        Ok(MosaicArgs { tile_dir, output, pattern, overlap })
+    }
 }
 
+impl LenticularArgs {
+    fn parse(mut raw: impl Iterator<Item = String>, verbosity: &mut Verbosity) -> Result<Self, ImageDataErrors> {
+        let action = verbosity.next(&mut raw).ok_or(ImageDataErrors::MissingArgument("interlace|calibrate"))?;
+        let mut positional = Vec::new();
+        let mut lpi = None;
+        let mut dpi = 600.0;
+        let mut spread = 1.0;
+        let mut step = 0.1;
+        while let Some(arg) = raw.next() {
+            let (flag, inline_value) = split_flag(&arg);
+            let mut value = || next_value(flag, inline_value.clone(), &mut raw);
+            match flag {
+                "--lpi" => lpi = Some(parse_number(flag, &value()?)?),
+                "--dpi" => dpi = parse_number(flag, &value()?)?,
+                "--spread" => spread = parse_number(flag, &value()?)?,
+                "--step" => step = parse_number(flag, &value()?)?,
+                "--" => {
+                    positional.extend(raw.by_ref());
+                    break;
+                }
+                _ if verbosity.count(flag) => {}
+                _ if flag.starts_with("--") => {
+                    return Err(ImageDataErrors::InvalidArgument(format!("unknown flag `{}`", flag)))
+                }
+                _ => positional.push(arg),
+            }
+        }
+        let lpi: f64 = lpi.ok_or(ImageDataErrors::MissingArgument("--lpi"))?;
+        for (flag, number) in [("--lpi", lpi), ("--dpi", dpi), ("--step", step)] {
+            if !(number > 0.0 && number.is_finite()) {
+                return Err(ImageDataErrors::InvalidArgument(format!("`{}` must be positive", flag)));
+            }
+        }
+        if !(0.0..lpi).contains(&spread) {
+            return Err(ImageDataErrors::InvalidArgument("`--spread` must be between 0 and `--lpi`".to_string()));
+        }
+
+        match action.as_str() {
+            "interlace" => {
+                let output = positional.pop().ok_or(ImageDataErrors::MissingArgument("output"))?;
+                if positional.len() < 2 {
+                    return Err(ImageDataErrors::MissingArgument("image_2"));
+                }
+                Ok(LenticularArgs::Interlace { images: positional, output, lpi, dpi })
+            }
+            "calibrate" => {
+                let output = positional.into_iter().next().ok_or(ImageDataErrors::MissingArgument("output"))?;
+                Ok(LenticularArgs::Calibrate { output, lpi, dpi, spread, step })
+            }
+            _ => Err(ImageDataErrors::InvalidArgument(format!("unknown lenticular action `{}`", action))),
+        }
+    }
+}
+
+impl IdwmArgs {
+    fn parse(mut raw: impl Iterator<Item = String>, verbosity: &mut Verbosity) -> Result<Self, ImageDataErrors> {
+        let action = verbosity.next(&mut raw).ok_or(ImageDataErrors::MissingArgument("embed|verify"))?;
+        let mut positional = Vec::new();
+        let mut key = None;
+        let mut strength = combiner::watermark::DEFAULT_STRENGTH;
+        while let Some(arg) = raw.next() {
+            let (flag, inline_value) = split_flag(&arg);
+            let mut value = || next_value(flag, inline_value.clone(), &mut raw);
+            match flag {
+                "--key" => key = Some(value()?),
+                "--strength" => strength = parse_number(flag, &value()?)?,
+                "--" => {
+                    positional.extend(raw.by_ref());
+                    break;
+                }
+                _ if verbosity.count(flag) => {}
+                _ if flag.starts_with("--") => {
+                    return Err(ImageDataErrors::InvalidArgument(format!("unknown flag `{}`", flag)))
+                }
+                _ => positional.push(arg),
+            }
+        }
+        if !(strength > 0.0 && strength.is_finite()) {
+            return Err(ImageDataErrors::InvalidArgument("`--strength` must be positive".to_string()));
+        }
+
+        let mut positional = positional.into_iter();
+        let mut next = |name| positional.next().ok_or(ImageDataErrors::MissingArgument(name));
+        let key = key.ok_or(ImageDataErrors::MissingArgument("--key"))?;
+        match action.as_str() {
+            "embed" => Ok(IdwmArgs::Embed { image: next("image")?, output: next("output")?, key, strength }),
+            "verify" => Ok(IdwmArgs::Verify { image: next("image")?, key }),
+            _ => Err(ImageDataErrors::InvalidArgument(format!("unknown idwm action `{}`", action))),
+        }
+    }
+}
+
+impl StegoArgs {
+    fn parse(mut raw: impl Iterator<Item = String>, verbosity: &mut Verbosity) -> Result<Self, ImageDataErrors> {
+        let action = verbosity.next(&mut raw).ok_or(ImageDataErrors::MissingArgument("embed|extract"))?;
+        let mut positional = Vec::new();
+        let mut message = None;
+        let mut output = None;
+        while let Some(arg) = raw.next() {
+            let (flag, inline_value) = split_flag(&arg);
+            let mut value = || next_value(flag, inline_value.clone(), &mut raw);
+            match flag {
+                "--message" => message = Some(value()?.into_bytes()),
+                "--file" => {
+                    let path = value()?;
+                    let bytes = std::fs::read(&path)
+                        .map_err(|e| ImageDataErrors::InvalidArgument(format!("`--file` cannot read {}: {}", path, e)))?;
+                    message = Some(bytes);
+                }
+                "--output" => output = Some(value()?),
+                "--" => {
+                    positional.extend(raw.by_ref());
+                    break;
+                }
+                _ if verbosity.count(flag) => {}
+                _ if flag.starts_with("--") => {
+                    return Err(ImageDataErrors::InvalidArgument(format!("unknown flag `{}`", flag)))
+                }
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        let mut next = |name| positional.next().ok_or(ImageDataErrors::MissingArgument(name));
+        match action.as_str() {
+            "embed" => Ok(StegoArgs::Embed {
+                image: next("image")?,
+                output: next("output")?,
+                message: message.ok_or(ImageDataErrors::MissingArgument("--message"))?,
+            }),
+            "extract" => Ok(StegoArgs::Extract { image: next("image")?, output }),
+            _ => Err(ImageDataErrors::InvalidArgument(format!("unknown stego action `{}`", action))),
+        }
+    }
+}
+
+/// Options of the `serve` and `grpc` subcommands.
+#[derive(Debug)]
+pub struct ServeArgs {
+    pub addr: String,
+    pub threads: usize,
+    /// The hosts `serve` may fetch `image_1_url` and `image_2_url` from,
+    /// given with `--allow-fetch`. None unless given, so clients cannot have
+    /// the server reach addresses only it can.
+    pub fetch_hosts: Vec<String>,
+}
+
+impl ServeArgs {
+    fn parse(mut raw: impl Iterator<Item = String>, default_addr: &str, verbosity: &mut Verbosity) -> Result<Self, ImageDataErrors> {
+        let mut addr = default_addr.to_string();
+        let mut threads = std::thread::available_parallelism().map_or(4, |threads| threads.get());
+        let mut fetch_hosts = Vec::new();
+
+        while let Some(arg) = raw.next() {
+            let (flag, inline_value) = split_flag(&arg);
+            let mut value = || next_value(flag, inline_value.clone(), &mut raw);
+            match flag {
+                "--addr" => addr = value()?,
+                "--threads" => threads = parse_number(flag, &value()?)?,
+                "--allow-fetch" => {
+                    fetch_hosts.extend(value()?.split(',').map(|host| host.trim().to_ascii_lowercase()).filter(|host| !host.is_empty()));
+                }
+                _ if verbosity.count(flag) => {}
+                _ => return Err(ImageDataErrors::InvalidArgument(format!("unknown flag `{}`", flag))),
+            }
+        }
+        Ok(ServeArgs { addr, threads, fetch_hosts })
+    }
+}
+
+impl Command {
+    fn parse(raw: impl Iterator<Item = String>, verbosity: &mut Verbosity) -> Result<Self, ImageDataErrors> {
+        let mut raw = raw.peekable();
+        while raw.next_if(|arg| verbosity.count(arg)).is_some() {}
+        match raw.peek().map(String::as_str) {
+            Some("info") => {
+                let mut paths = Vec::new();
+                let mut raw = raw.skip(1);
+                while let Some(arg) = raw.next() {
+                    match arg.as_str() {
+                        "--" => {
+                            paths.extend(raw.by_ref());
+                            break;
+                        }
+                        flag if verbosity.count(flag) => {}
+                        _ => paths.push(arg),
+                    }
+                }
+                if paths.is_empty() {
+                    return Err(ImageDataErrors::MissingArgument("image"));
+                }
+                Ok(Command::Info(paths))
+            }
+            Some("serve") => ServeArgs::parse(raw.skip(1), "127.0.0.1:8080", verbosity).map(Command::Serve),
+            Some("grpc") => match ServeArgs::parse(raw.skip(1), "127.0.0.1:50051", verbosity)? {
+                args if !args.fetch_hosts.is_empty() => {
+                    Err(ImageDataErrors::InvalidArgument("`grpc` fetches nothing, so takes no `--allow-fetch`".to_string()))
+                }
+                args => Ok(Command::Grpc(args)),
+            },
+            Some("stego") => StegoArgs::parse(raw.skip(1), verbosity).map(Command::Stego),
+            Some("idwm") => IdwmArgs::parse(raw.skip(1), verbosity).map(Command::Idwm),
+            Some("lenticular") => LenticularArgs::parse(raw.skip(1), verbosity).map(Command::Lenticular),
+            Some("augment") => AugmentArgs::parse(raw.skip(1), verbosity).map(Command::Augment),
+            Some("annotate") => AnnotateArgs::parse(raw.skip(1), verbosity).map(Command::Annotate),
+            Some("caption") => CaptionArgs::parse(raw.skip(1), verbosity).map(Command::Caption),
+            Some("mosaic-assemble") => MosaicArgs::parse(raw.skip(1), verbosity).map(Command::MosaicAssemble),
+            Some("bench") => BenchArgs::parse(raw.skip(1), verbosity).map(|args| Command::Bench(Box::new(args))),
+            Some("gui") => GuiArgs::parse(raw.skip(1), verbosity).map(Command::Gui),
+            Some("recipe") => RecipeArgs::parse(raw.skip(1), verbosity).map(|args| Command::Recipe(Box::new(args))),
+            Some("replay") => ReplayArgs::parse(raw.skip(1), verbosity).map(Command::Replay),
+            _ => Args::parse(raw, verbosity).map(|args| Command::Combine(Box::new(args))),
+        }
+    }
+}
+
+/// The flags a sidecar keeps besides the options, since they also shape the output.
+const RECORDED_FLAGS: [&str; 18] = [
+    "--raw",
+    "--pnm-maxval",
+    "--npy-dtype",
+    "--svg-dpi",
+    "--svg-size",
+    "--pdf-dpi",
+    "--background",
+    "--deterministic",
+    "--full-decode",
+    "--script",
+    "--plugin",
+    "--plugins-dir",
+    "--lut",
+    "--lut-1",
+    "--lut-2",
+    "--displace",
+    "--gamut-warn",
+    "--gamut-colour",
+];
+
 impl Args {
-    pub fn new() -> Self {
-        Args {
-            image_1: get_nth_arg(1),
-            image_2: get_nth_arg(2),
-            output: get_nth_arg(3),
+    /// The single combination a sidecar recorded, made with `options` and
+    /// the recorded `arguments`.
+    pub fn replayed(
+        arguments: Vec<String>,
+        options: CombineOptions,
+        image_1: String,
+        image_2: String,
+        output: String,
+    ) -> Result<Self, ImageDataErrors> {
+        if let Some(flag) = arguments.iter().find(|arg| arg.starts_with("--") && !RECORDED_FLAGS.contains(&split_flag(arg).0)) {
+            return Err(ImageDataErrors::InvalidArgument(format!("`{}` cannot be replayed", flag)));
         }
+        let mut args = Args::parse(arguments.into_iter().chain([image_1, image_2, output]), &mut Verbosity::default())?;
+        args.options = options;
+        Ok(args)
     }
-}
\ No newline at end of file
+
+    fn parse(mut raw: impl Iterator<Item = String>, verbosity: &mut Verbosity) -> Result<Self, ImageDataErrors> {
+        let mut positional = Vec::new();
+        let mut options = CombineOptions::default();
+        let mut diff_report = None;
+        let mut input_dir = None;
+        let mut output_dir = None;
+        let mut strict = false;
+        let mut watch = false;
+        let mut selection = Selection::default();
+        let mut name_template = None;
+        let mut name_by = None;
+        let mut manifest = None;
+        let mut cache_size = cache::DEFAULT_CAPACITY;
+        let mut incremental = false;
+        let mut state_file = None;
+        let mut zip_output = None;
+        let mut raw_layout = None;
+        let mut pnm_maxval = 255;
+        let mut npy_dtype = NpyDtype::default();
+        let mut svg = SvgOptions::default();
+        let mut pdf_dpi = combiner::pdf::DEFAULT_DPI;
+        let mut background = None;
+        let mut deterministic = false;
+        let mut checksum = None;
+        let mut checksum_sidecar = false;
+        let mut frames = None;
+        let mut out_pattern = None;
+        let mut animate = None;
+        let mut animation = Animation::default();
+        let mut output_dzi = None;
+        let mut max_memory = None;
+        let mut full_decode = false;
+        let mut dry_run = false;
+        let mut preview = None;
+        let mut from_clipboard = false;
+        let mut to_clipboard = false;
+        let mut capture = None;
+        let mut notify_url = None;
+        let mut script = None;
+        let mut plugin = None;
+        let mut plugins_dir = None;
+        let mut daemon = false;
+        let mut socket = None;
+        let mut record = false;
+        let mut arguments = Vec::new();
+        let mut luts = Luts::default();
+        let mut displace = None;
+        let mut proof = None;
+        let mut gamut_warn = None;
+        let mut gamut_colour = [128, 128, 128];
+
+        while let Some(arg) = raw.next() {
+            let (flag, inline_value) = split_flag(&arg);
+            let recorded = RECORDED_FLAGS.contains(&flag).then(|| flag.to_string());
+            let mut taken = None;
+            let mut value = || {
+                let value = next_value(flag, inline_value.clone(), &mut raw)?;
+                taken = Some(value.clone());
+                Ok::<_, ImageDataErrors>(value)
+            };
+            match flag {
+                "--options" => options = load_options(&value()?)?,
+                "--mode" => options.mode = Mode::parse(&value()?)?,
+                "--threshold" => options.threshold = parse_number(flag, &value()?)?,
+                "--diff-report" => diff_report = Some(value()?),
+                "--diff-style" => options.diff_style = DiffStyle::parse(&value()?)?,
+                "--input-dir" => input_dir = Some(value()?),
+                "--output-dir" => output_dir = Some(value()?),
+                "--trim" => options.trim = true,
+                "--make-tileable" => options.make_tileable = true,
+                "--preset-size" => options.preset_size = Some(SizePreset::parse(&value()?)?),
+                "--print" => options.print = Some(PrintLayout::parse(&value()?)?),
+                "--crop-marks" => options.crop_marks = true,
+                "--noise" => options.noise = Some(Noise::parse(&value()?)?),
+                "--grain" => options.grain = Some(Grain::parse(&value()?)?),
+                "--seed" => options.seed = parse_number(flag, &value()?)?,
+                "--lambda" => options.lambda = Some(parse_number(flag, &value()?)?),
+                "--mosaic-tile" => options.mosaic_tile = parse_number(flag, &value()?)?,
+                "--mosaic-scale" => options.mosaic_scale = parse_number(flag, &value()?)?,
+                "--mosaic-correct" => options.mosaic_correction = parse_number(flag, &value()?)?,
+                "--qr" => options.qr = Some(value()?),
+                "--position" => options.qr_position = Gravity::parse(&value()?)?,
+                "--qr-size" => options.qr_size = Some(parse_number(flag, &value()?)?),
+                "--qr-margin" => options.qr_margin = parse_number(flag, &value()?)?,
+                "--qr-ec" => options.qr_error_correction = QrErrorCorrection::parse(&value()?)?,
+                "--stamp" => options.stamp.push(Stamp::parse(&value()?)?),
+                "--stamp-format" => options.stamp_format = value()?,
+                "--stamp-position" => options.stamp_position = Gravity::parse(&value()?)?,
+                "--dpi" => options.dpi = Some(parse_number(flag, &value()?)?),
+                "--frame-device" => options.frame = Some(Device::parse(&value()?)?),
+                "--resize" | "-resize" => options.resize = Some(Geometry::parse(&value()?)?),
+                "--crop" | "-crop" => options.crop = Some(Geometry::parse(&value()?)?),
+                "--warp-2" => {
+                    options.mode = Mode::Warp;
+                    options.warp = Some(Quad::parse(&value()?)?);
+                }
+                "--rotate-1" | "--rotate-2" => {
+                    options.rotations[usize::from(flag == "--rotate-2")] = parse_number(flag, &value()?)?
+                }
+                "--pipeline" => options.pipeline = Some(Pipeline::parse(&value()?)?),
+                "--flip-1" | "--flip-2" => options.flips[usize::from(flag == "--flip-2")] = Some(Flip::parse(&value()?)?),
+                "--levels-1" | "--levels-2" => options.levels[usize::from(flag == "--levels-2")] = Some(Levels::parse(&value()?)?),
+                "--curve-1" | "--curve-2" => options.curves[usize::from(flag == "--curve-2")] = Some(Curve::parse(&value()?)?),
+                "--crop-1" | "--crop-2" => {
+                    options.input_crops[usize::from(flag == "--crop-2")] = Some(CropRegion::parse(&value()?)?)
+                }
+                "--roi" => options.roi = Some(CropRegion::parse(&value()?)?),
+                "--mask-from" => options.mask_from = Some(MaskSource::parse(&value()?)?),
+                "--mask-range" => options.mask_range = mask::parse_range(&value()?)?,
+                "--matte-model" => options.matte_model = Some(value()?),
+                "--fit" => options.fit = Fit::parse(&value()?)?,
+                "--backend" => options.backend = Backend::parse(&value()?)?,
+                "--smart-crop" => {
+                    options.fit = Fit::Crop;
+                    options.smart_crop = true;
+                }
+                "--smart-position" => options.smart_position = true,
+                "--face-model" => options.face_model = Some(value()?),
+                "--gravity" | "-gravity" => options.gravity = Gravity::parse(&value()?)?,
+                "--canvas" => {
+                    options.mode = Mode::Canvas;
+                    options.canvas = Some(parse_size(flag, &value()?)?);
+                }
+                "--size" => options.canvas = Some(parse_size(flag, &value()?)?),
+                "--tile-size" => options.tile_size = Some(parse_number(flag, &value()?)?),
+                "--tile-offset" => options.tile_offset = parse_position(flag, &value()?)?,
+                "--mirror-repeat" => options.tile_mirror = true,
+                "--folds" => options.mirror_folds = parse_number(flag, &value()?)?,
+                "--mirror-blend" => options.mirror_blend = Some(parse_number(flag, &value()?)?),
+                "--sky-feather" => options.sky_feather = Some(parse_number(flag, &value()?)?),
+                "--sky-harmonize" => options.sky_harmonize = parse_number(flag, &value()?)?,
+                "--motion-cleanup" => options.motion_cleanup = parse_number(flag, &value()?)?,
+                "--motion-background" => options.motion_background = Some(parse_color(flag, &value()?)?),
+                "--subtract-softness" => options.subtract_softness = parse_number(flag, &value()?)?,
+                "--dark-frame" => options.dark_frame = Some(value()?),
+                "--stack-method" => options.stack_method = StackMethod::parse(&value()?)?,
+                "--depth-map" => options.depth_map = Some(value()?),
+                "--focus-near" => options.focus_near = parse_number(flag, &value()?)?,
+                "--focus-far" => options.focus_far = parse_number(flag, &value()?)?,
+                "--depth-blur" => options.depth_blur = Some(parse_number(flag, &value()?)?),
+                "--pos-1" | "--pos-2" => {
+                    options.mode = Mode::Canvas;
+                    options.positions[usize::from(flag == "--pos-2")] = parse_position(flag, &value()?)?;
+                }
+                "--strict" => strict = true,
+                "--watch" => watch = true,
+                "--recursive" => selection.recursive = true,
+                "--include" => selection.include.push(parse_pattern(flag, &value()?)?),
+                "--exclude" => selection.exclude.push(parse_pattern(flag, &value()?)?),
+                "--name-template" => name_template = Some(NameTemplate::parse(&value()?)?),
+                "--name-by" => name_by = Some(NameBy::parse(&value()?)?),
+                "--jobs" => manifest = Some(value()?),
+                "--cache-size" => cache_size = parse_number(flag, &value()?)?,
+                "--incremental" => incremental = true,
+                "--state-file" => state_file = Some(value()?),
+                "--zip-output" => zip_output = Some(value()?),
+                "--raw" => raw_layout = Some(RawLayout::parse(&value()?)?),
+                "--pnm-maxval" => pnm_maxval = parse_number(flag, &value()?)?,
+                "--npy-dtype" => npy_dtype = NpyDtype::parse(&value()?)?,
+                "--svg-dpi" => svg.dpi = parse_number(flag, &value()?)?,
+                "--svg-size" => svg.fit = Some(parse_size(flag, &value()?)?),
+                "--pdf-dpi" => pdf_dpi = parse_number(flag, &value()?)?,
+                "--background" => background = Some(parse_color(flag, &value()?)?),
+                "--deterministic" => deterministic = true,
+                "--checksum" => checksum = Some(ChecksumAlgorithm::parse(&value()?)?),
+                "--checksum-sidecar" => checksum_sidecar = true,
+                "--sequence" => frames = Some(parse_number(flag, &value()?)?),
+                "--animate" => animate = Some(AnimationStyle::parse(&value()?)?),
+                "--animate-frames" => animation.frames = parse_number(flag, &value()?)?,
+                "--animate-delay" => animation.delay_ms = parse_number(flag, &value()?)?,
+                "--animate-loops" => animation.plays = parse_number(flag, &value()?)?,
+                "--out-pattern" => out_pattern = Some(FramePattern::parse(&value()?)?),
+                "--output-dzi" => output_dzi = Some(value()?),
+                "--max-memory" => max_memory = Some(memory::parse_bytes(&value()?)?),
+                "--full-decode" => full_decode = true,
+                "--dry-run" => dry_run = true,
+                "--preview" => preview = Some(preview.unwrap_or_else(Protocol::detect)),
+                "--preview-protocol" => preview = Some(Protocol::parse(&value()?)?),
+                "--from-clipboard" => from_clipboard = true,
+                "--to-clipboard" => to_clipboard = true,
+                "--capture" => capture = Some(capture::Source::parse(&value()?)?),
+                "--notify-url" => notify_url = Some(value()?),
+                "--script" => script = Some(value()?),
+                "--plugin" => plugin = Some(value()?),
+                "--plugins-dir" => plugins_dir = Some(value()?),
+                "--daemon" => daemon = true,
+                "--socket" => socket = Some(value()?),
+                "--record" => record = true,
+                "--lut" | "--lut-1" | "--lut-2" => {
+                    let (lut, digest) = load_lut(flag, &value()?)?;
+                    match flag {
+                        "--lut" => luts.output = Some(lut),
+                        _ => luts.inputs[usize::from(flag == "--lut-2")] = Some(lut),
+                    }
+                    luts.digests.push(digest);
+                }
+                "--displace" => displace = Some(load_displacement(flag, &value()?)?),
+                "--proof" => proof = Some(load_profile(flag, &value()?)?),
+                "--gamut-warn" => gamut_warn = Some(load_profile(flag, &value()?)?),
+                "--gamut-colour" => gamut_colour = parse_color(flag, &value()?)?,
+                "--" => {
+                    positional.extend(raw.by_ref());
+                    break;
+                }
+                _ if verbosity.count(flag) => {}
+                _ if flag.starts_with("--") => {
+                    return Err(ImageDataErrors::InvalidArgument(format!("unknown flag `{}`", flag)))
+                }
+                _ => positional.push(arg),
+            }
+            if let Some(flag) = recorded {
+                arguments.push(flag);
+                arguments.extend(taken);
+            }
+        }
+
+        let sequence = match (frames, out_pattern) {
+            (Some(frames), Some(pattern)) => Some(Sequence { frames, pattern }),
+            (Some(_), None) => return Err(ImageDataErrors::MissingArgument("--out-pattern")),
+            (None, Some(_)) => return Err(ImageDataErrors::MissingArgument("--sequence")),
+            (None, None) => None,
+        };
+        let mut positional = positional.into_iter();
+        let mut next = |name| positional.next().ok_or(ImageDataErrors::MissingArgument(name));
+        if socket.is_some() && !daemon {
+            return Err(ImageDataErrors::MissingArgument("--daemon"));
+        }
+        let inputs = match (manifest, input_dir, output_dir) {
+            (None, None, None) if daemon => Inputs::Daemon { socket },
+            _ if daemon => {
+                return Err(ImageDataErrors::InvalidArgument(
+                    "`--daemon` reads its jobs from requests, not `--input-dir` or `--jobs`".to_string(),
+                ))
+            }
+            (Some(path), None, None) => Inputs::Manifest { path },
+            (Some(_), _, _) => {
+                return Err(ImageDataErrors::InvalidArgument(
+                    "`--jobs` cannot be combined with `--input-dir` or `--output-dir`".to_string(),
+                ))
+            }
+            (None, Some(input_dir), Some(output_dir)) => Inputs::Directory { input_dir, output_dir, image_2: next("image_2")? },
+            (None, Some(_), None) => return Err(ImageDataErrors::MissingArgument("--output-dir")),
+            (None, None, Some(_)) => return Err(ImageDataErrors::MissingArgument("--input-dir")),
+            (None, None, None) => {
+                let image_1 = match &capture {
+                    Some(source) => source.to_string(),
+                    None if from_clipboard => clipboard::PATH.to_string(),
+                    None => next("image_1")?,
+                };
+                let image_2 = next("image_2")?;
+                // Frames are named by the pattern, so there is no single output to name.
+                let output = match &sequence {
+                    Some(sequence) => sequence.pattern.render(1),
+                    None if to_clipboard => next("output").unwrap_or_else(|_| clipboard::PATH.to_string()),
+                    None => next("output")?,
+                };
+                Inputs::Single { image_1, image_2, output }
+            }
+        };
+        let animate = animate.map(|style| Animation { style, ..animation });
+        if daemon {
+            if sequence.is_some() || animate.is_some() || preview.is_some() || capture.is_some() || from_clipboard || to_clipboard {
+                return Err(ImageDataErrors::InvalidArgument(
+                    "`--daemon` takes each job's images from its request, which `--sequence`, `--animate`, `--preview`, \
+                     `--capture` and the clipboard flags do not"
+                        .to_string(),
+                ));
+            }
+            // Answers to requests are what is written to stdout.
+            if dry_run || zip_output.is_some() || (checksum.is_some() && !checksum_sidecar) {
+                return Err(ImageDataErrors::InvalidArgument(
+                    "`--daemon` cannot be combined with `--dry-run`, `--zip-output` or `--checksum` without `--checksum-sidecar`"
+                        .to_string(),
+                ));
+            }
+        }
+        if record && (sequence.is_some() || animate.is_some() || capture.is_some() || from_clipboard || to_clipboard || zip_output.is_some()) {
+            return Err(ImageDataErrors::InvalidArgument(
+                "`--record` writes a sidecar beside an output file, which `--sequence`, `--animate`, `--capture`, \
+                 `--zip-output` and the clipboard flags do not leave to replay"
+                    .to_string(),
+            ));
+        }
+        if animate.is_some() {
+            if !matches!(inputs, Inputs::Single { .. }) {
+                return Err(ImageDataErrors::InvalidArgument(
+                    "`--animate` compares a single pair, not `--input-dir` or `--jobs`".to_string(),
+                ));
+            }
+            if sequence.is_some() {
+                return Err(ImageDataErrors::InvalidArgument("`--animate` cannot be combined with `--sequence`".to_string()));
+            }
+            if animation.frames < 2 {
+                return Err(ImageDataErrors::InvalidArgument("`--animate-frames` must be at least 2".to_string()));
+            }
+        }
+        if preview.is_some() {
+            if !matches!(inputs, Inputs::Single { .. }) {
+                return Err(ImageDataErrors::InvalidArgument(
+                    "`--preview` shows a single pair, not `--input-dir` or `--jobs`".to_string(),
+                ));
+            }
+            if sequence.is_some() || animate.is_some() {
+                return Err(ImageDataErrors::InvalidArgument(
+                    "`--preview` cannot be combined with `--sequence` or `--animate`".to_string(),
+                ));
+            }
+        }
+        if capture.is_some() {
+            if !matches!(inputs, Inputs::Single { .. }) {
+                return Err(ImageDataErrors::InvalidArgument(
+                    "`--capture` takes a single pair, not `--input-dir` or `--jobs`".to_string(),
+                ));
+            }
+            if from_clipboard {
+                return Err(ImageDataErrors::InvalidArgument(
+                    "`--capture` and `--from-clipboard` both give the first input".to_string(),
+                ));
+            }
+        }
+        if from_clipboard || to_clipboard {
+            if !matches!(inputs, Inputs::Single { .. }) {
+                return Err(ImageDataErrors::InvalidArgument(
+                    "`--from-clipboard` and `--to-clipboard` take a single pair, not `--input-dir` or `--jobs`".to_string(),
+                ));
+            }
+            if to_clipboard && (sequence.is_some() || animate.is_some() || output_dzi.is_some()) {
+                return Err(ImageDataErrors::InvalidArgument(
+                    "`--to-clipboard` holds one image, not `--sequence`, `--animate` or `--output-dzi`".to_string(),
+                ));
+            }
+        }
+        if let Some(sequence) = &sequence {
+            if !matches!(inputs, Inputs::Single { .. }) {
+                return Err(ImageDataErrors::InvalidArgument(
+                    "`--sequence` crossfades a single pair, not `--input-dir` or `--jobs`".to_string(),
+                ));
+            }
+            if sequence.frames < 2 {
+                return Err(ImageDataErrors::InvalidArgument("`--sequence` needs at least 2 frames".to_string()));
+            }
+        }
+        if notify_url.is_some() && !matches!(inputs, Inputs::Directory { .. } | Inputs::Manifest { .. }) {
+            return Err(ImageDataErrors::InvalidArgument(
+                "`--notify-url` reports on `--input-dir` and `--jobs` batches".to_string(),
+            ));
+        }
+        if (watch || name_template.is_some() || name_by.is_some()) && !matches!(inputs, Inputs::Directory { .. }) {
+            return Err(ImageDataErrors::MissingArgument("--input-dir"));
+        }
+        if name_template.is_some() && name_by.is_some() {
+            return Err(ImageDataErrors::InvalidArgument("`--name-template` and `--name-by` both name outputs".to_string()));
+        }
+        if !(svg.dpi > 0.0 && svg.dpi.is_finite()) {
+            return Err(ImageDataErrors::InvalidArgument("`--svg-dpi` must be positive".to_string()));
+        }
+        if !(pdf_dpi > 0.0 && pdf_dpi.is_finite()) {
+            return Err(ImageDataErrors::InvalidArgument("`--pdf-dpi` must be positive".to_string()));
+        }
+        if options.dpi.is_some_and(|dpi| !(dpi > 0.0 && dpi.is_finite())) {
+            return Err(ImageDataErrors::InvalidArgument("`--dpi` must be positive".to_string()));
+        }
+        if options.lambda.is_some_and(|lambda| !(0.0..=1.0).contains(&lambda)) {
+            return Err(ImageDataErrors::InvalidArgument("`--lambda` must be between 0 and 1".to_string()));
+        }
+        if options.mosaic_scale == 0 || options.mosaic_scale > options.mosaic_tile {
+            return Err(ImageDataErrors::InvalidArgument("`--mosaic-scale` must be between 1 and `--mosaic-tile`".to_string()));
+        }
+        if !(0.0..=1.0).contains(&options.mosaic_correction) {
+            return Err(ImageDataErrors::InvalidArgument("`--mosaic-correct` must be between 0 and 1".to_string()));
+        }
+        if !matches!(options.mirror_folds, 2 | 4) {
+            return Err(ImageDataErrors::InvalidArgument("`--folds` must be 2 or 4".to_string()));
+        }
+        if options.mirror_blend.is_some_and(|weight| !(0.0..=1.0).contains(&weight)) {
+            return Err(ImageDataErrors::InvalidArgument("`--mirror-blend` must be between 0 and 1".to_string()));
+        }
+        if matches!(options.mask_from, Some(MaskSource::Matte(_))) && options.matte_model.is_none() {
+            return Err(ImageDataErrors::MissingArgument("--matte-model"));
+        }
+        if options.mode == Mode::DepthBlend && options.depth_map.is_none() {
+            return Err(ImageDataErrors::MissingArgument("--depth-map"));
+        }
+        if !(0.0..=1.0).contains(&options.focus_near) || !(0.0..=1.0).contains(&options.focus_far) || options.focus_near > options.focus_far {
+            return Err(ImageDataErrors::InvalidArgument("`--focus-near` and `--focus-far` must be between 0 and 1, near first".to_string()));
+        }
+        if options.depth_blur.is_some_and(|sigma| !sigma.is_finite() || sigma <= 0.0) {
+            return Err(ImageDataErrors::InvalidArgument("`--depth-blur` must be above 0".to_string()));
+        }
+        if !(0.0..=1.0).contains(&options.sky_harmonize) {
+            return Err(ImageDataErrors::InvalidArgument("`--sky-harmonize` must be between 0 and 1".to_string()));
+        }
+        if options.tile_size == Some(0) {
+            return Err(ImageDataErrors::InvalidArgument("`--tile-size` must be at least 1".to_string()));
+        }
+        if !options.rotations.iter().all(|degrees| degrees.is_finite()) {
+            return Err(ImageDataErrors::InvalidArgument("`--rotate-1` and `--rotate-2` take a number of degrees".to_string()));
+        }
+        if pnm_maxval == 0 {
+            return Err(ImageDataErrors::InvalidArgument("`--pnm-maxval` must be between 1 and 65535".to_string()));
+        }
+        if deterministic && name_template.as_ref().is_some_and(NameTemplate::uses_date) {
+            return Err(ImageDataErrors::InvalidArgument(
+                "`{date}` in `--name-template` changes daily, which `--deterministic` does not allow".to_string(),
+            ));
+        }
+        if checksum_sidecar && checksum.is_none() {
+            return Err(ImageDataErrors::MissingArgument("--checksum"));
+        }
+        if zip_output.is_some() {
+            if matches!(inputs, Inputs::Single { .. }) {
+                return Err(ImageDataErrors::MissingArgument("--input-dir"));
+            }
+            if watch || incremental {
+                return Err(ImageDataErrors::InvalidArgument(
+                    "`--zip-output` cannot be combined with `--watch` or `--incremental`".to_string(),
+                ));
+            }
+        }
+
+        Ok(Args {
+            inputs,
+            options,
+            diff_report,
+            strict,
+            watch,
+            selection,
+            name_template,
+            name_by,
+            cache_size,
+            incremental: incremental
+                .then(|| state_file.unwrap_or_else(|| incremental::DEFAULT_STATE_FILE.to_string())),
+            zip_output,
+            raw: raw_layout,
+            pnm_maxval,
+            npy_dtype,
+            svg,
+            pdf_dpi,
+            background,
+            deterministic,
+            checksum,
+            checksum_sidecar,
+            sequence,
+            animate,
+            output_dzi,
+            max_memory,
+            full_decode,
+            dry_run,
+            preview,
+            to_clipboard,
+            capture,
+            notify_url,
+            script,
+            plugin,
+            plugins_dir,
+            luts,
+            displace,
+            proof,
+            gamut_warn,
+            gamut_colour,
+            record,
+            arguments,
+        })
+    }
+}
+
+/// Splits `--flag=value` into its parts; anything else is returned untouched.
+fn split_flag(arg: &str) -> (&str, Option<String>) {
+    match arg.split_once('=') {
+        Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+        _ => (arg, None),
+    }
+}
+
+fn next_value(
+    flag: &str,
+    inline_value: Option<String>,
+    raw: &mut impl Iterator<Item = String>,
+) -> Result<String, ImageDataErrors> {
+    inline_value
+        .or_else(|| raw.next())
+        .ok_or_else(|| ImageDataErrors::InvalidArgument(format!("`{}` expects a value", flag)))
+}
+
+pub fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, ImageDataErrors> {
+    value
+        .parse()
+        .map_err(|_| ImageDataErrors::InvalidArgument(format!("`{}` got an invalid number `{}`", flag, value)))
+}
+
+/// Parses `white`, `black`, `#RRGGBB` or `R,G,B`.
+fn parse_color(flag: &str, value: &str) -> Result<[u8; 3], ImageDataErrors> {
+    let invalid = || ImageDataErrors::InvalidArgument(format!("`{}` got an invalid colour `{}`", flag, value));
+    match value {
+        "white" => return Ok([255, 255, 255]),
+        "black" => return Ok([0, 0, 0]),
+        _ => {}
+    }
+    if let Some(hex) = value.strip_prefix('#') {
+        let channel = |at: usize| hex.get(at..at + 2).and_then(|digits| u8::from_str_radix(digits, 16).ok());
+        return match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(red), Some(green), Some(blue)) => Ok([red, green, blue]),
+            _ => Err(invalid()),
+        };
+    }
+    let channels: Vec<u8> = value.split(',').map(|channel| channel.trim().parse()).collect::<Result<_, _>>().map_err(|_| invalid())?;
+    channels.try_into().map_err(|_| invalid())
+}
+
+/// Parses `WIDTHxHEIGHT`, both above zero.
+fn parse_size(flag: &str, value: &str) -> Result<(u32, u32), ImageDataErrors> {
+    match value.split_once('x').map(|(width, height)| (width.parse(), height.parse())) {
+        Some((Ok(width), Ok(height))) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(ImageDataErrors::InvalidArgument(format!("`{}` expects WIDTHxHEIGHT, got `{}`", flag, value))),
+    }
+}
+
+/// Parses `X,Y`, either of which may be negative.
+fn parse_position(flag: &str, value: &str) -> Result<(i32, i32), ImageDataErrors> {
+    match value.split_once(',').map(|(x, y)| (x.trim().parse(), y.trim().parse())) {
+        Some((Ok(x), Ok(y))) => Ok((x, y)),
+        _ => Err(ImageDataErrors::InvalidArgument(format!("`{}` expects X,Y, got `{}`", flag, value))),
+    }
+}
+
+/// Reads `CombineOptions` saved as JSON, such as a preset shared between runs.
+fn load_options(path: &str) -> Result<CombineOptions, ImageDataErrors> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| ImageDataErrors::InvalidArgument(format!("`--options` cannot read {}: {}", path, e)))?;
+    serde_json::from_str(&contents)
+        .map_err(|e| ImageDataErrors::InvalidArgument(format!("`--options` got invalid options in {}: {}", path, e)))
+}
+
+/// The LUT in the `.cube` file at `path`, with the file's hash.
+fn load_lut(flag: &str, path: &str) -> Result<(Lut, String), ImageDataErrors> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| ImageDataErrors::InvalidArgument(format!("`{}` cannot read {}: {}", flag, path, e)))?;
+    let lut = Lut::parse(&contents).map_err(|e| match e {
+        ImageDataErrors::InvalidArgument(message) => ImageDataErrors::InvalidArgument(format!("`{}` cannot use {}: {}", flag, path, message)),
+        e => e,
+    })?;
+    Ok((lut, ChecksumAlgorithm::Sha256.digest(contents.as_bytes())))
+}
+
+/// Loads `--displace`'s `map.png:20`: a displacement map and the offset in
+/// pixels between its channels' ends.
+fn load_displacement(flag: &str, value: &str) -> Result<(Displacement, String), ImageDataErrors> {
+    let (path, amount) = value
+        .rsplit_once(':')
+        .ok_or_else(|| ImageDataErrors::InvalidArgument(format!("`{}` expects a map and an amount, as map.png:20, not `{}`", flag, value)))?;
+    let amount: f32 = parse_number(flag, amount)?;
+    if !amount.is_finite() {
+        return Err(ImageDataErrors::InvalidArgument(format!("`{}` got an invalid amount `{}`", flag, amount)));
+    }
+    let contents = std::fs::read(path).map_err(|e| ImageDataErrors::InvalidArgument(format!("`{}` cannot read {}: {}", flag, path, e)))?;
+    let (map, _) = combiner::decode_image_bytes(&contents)?;
+    let digest = ChecksumAlgorithm::Sha256.digest(&[contents, amount.to_le_bytes().to_vec()].concat());
+    Ok((Displacement::new(&map, amount), digest))
+}
+
+fn load_profile(flag: &str, path: &str) -> Result<Profile, ImageDataErrors> {
+    let contents = std::fs::read(path).map_err(|e| ImageDataErrors::InvalidArgument(format!("`{}` cannot read {}: {}", flag, path, e)))?;
+    Profile::parse(&contents).map_err(|e| match e {
+        ImageDataErrors::InvalidArgument(message) => ImageDataErrors::InvalidArgument(format!("`{}` cannot use {}: {}", flag, path, message)),
+        e => e,
+    })
+}
+
+fn parse_pattern(flag: &str, value: &str) -> Result<glob::Pattern, ImageDataErrors> {
+    glob::Pattern::new(value)
+        .map_err(|e| ImageDataErrors::InvalidArgument(format!("`{}` got an invalid pattern `{}`: {}", flag, value, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(raw: &[&str]) -> Result<Args, ImageDataErrors> {
+        Args::parse(raw.iter().map(|arg| arg.to_string()), &mut Verbosity::default())
+    }
+
+    fn parsed(raw: &[&str]) -> (Command, i8) {
+        let mut verbosity = Verbosity::default();
+        let command = Command::parse(raw.iter().map(|arg| arg.to_string()), &mut verbosity).unwrap();
+        (command, verbosity.0)
+    }
+
+    #[test]
+    fn verbosity_only_where_a_flag_can_stand() {
+        let (command, verbosity) = parsed(&["-v", "stego", "-q", "embed", "a.png", "--message", "-v", "b.png", "-vv"]);
+        assert_eq!(verbosity, 2);
+        assert!(matches!(
+            command,
+            Command::Stego(StegoArgs::Embed { image, output, message })
+                if (image.as_str(), output.as_str(), message.as_slice()) == ("a.png", "b.png", b"-v".as_slice())
+        ));
+
+        let (command, verbosity) = parsed(&["info", "--verbose", "a.png", "--", "-q", "--quiet"]);
+        assert_eq!(verbosity, 1);
+        assert!(matches!(command, Command::Info(paths) if paths == ["a.png", "-q", "--quiet"]));
+    }
+
+    #[test]
+    fn records_flags_shaping_the_output() {
+        let args = args(&["a.png", "b.png", "out.png", "--gamut-colour", "#ff0000", "--trim", "--background=white"]).unwrap();
+        assert_eq!(args.arguments, ["--gamut-colour", "#ff0000", "--background", "white"]);
+
+        let options = CombineOptions::default();
+        let replayed = Args::replayed(args.arguments, options, "a.png".into(), "b.png".into(), "out.png".into()).unwrap();
+        assert_eq!(replayed.gamut_colour, [255, 0, 0]);
+        assert_eq!(replayed.background, Some([255, 255, 255]));
+    }
+
+    #[test]
+    fn imagemagick_spellings() {
+        let args = args(&["a.png", "-resize", "800x600^", "-gravity", "center", "-crop", "800x600+0+0", "b.png", "out.png"]).unwrap();
+        assert_eq!(args.options.resize, Some(Geometry::parse("800x600^").unwrap()));
+        assert_eq!(args.options.crop, Some(Geometry::parse("800x600+0+0").unwrap()));
+        assert_eq!(args.options.gravity, Gravity::Center);
+        assert!(matches!(
+            args.inputs,
+            Inputs::Single { image_1, image_2, output } if (image_1.as_str(), image_2.as_str(), output.as_str()) == ("a.png", "b.png", "out.png")
+        ));
+    }
+}
@@ -0,0 +1,332 @@
+//! Stacking astrophotos: frames aligned to a reference by their stars, then
+//! averaged so the noise falls away and faint detail stays.
+
+use image::{DynamicImage, GenericImageView, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::hooks::Hooks;
+use crate::ImageDataErrors;
+
+/// How many of the brightest stars of each frame are matched.
+const STARS: usize = 40;
+/// How many of those the guesses at a frame's alignment are drawn from.
+const GUESS_STARS: usize = 12;
+/// How close, in pixels, a star must land to one of the reference's to match.
+const TOLERANCE: f32 = 2.0;
+/// How many stars must match before a frame is trusted to be aligned.
+const MIN_MATCHES: usize = 4;
+
+/// How the aligned frames are stacked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StackMethod {
+    /// The mean of each pixel, kept as running sums so frames stream through.
+    #[default]
+    Mean,
+    /// The median of each pixel, which drops satellite trails and hot pixels
+    /// but keeps every aligned frame in memory until the end.
+    Median,
+}
+
+impl StackMethod {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        match value {
+            "mean" => Ok(StackMethod::Mean),
+            "median" => Ok(StackMethod::Median),
+            _ => Err(ImageDataErrors::InvalidArgument(format!("unknown stack method `{}`, expected mean or median", value))),
+        }
+    }
+}
+
+/// A star's centroid, and how much light it has above the sky.
+#[derive(Debug, Clone, Copy)]
+struct Star {
+    x: f32,
+    y: f32,
+    flux: f32,
+}
+
+/// A similarity transform taking `(x, y)` to `a (x + iy) + b` in complex
+/// terms: a rotation and scale by `a`, then a shift by `b`.
+#[derive(Debug, Clone, Copy)]
+struct Similarity {
+    a: (f32, f32),
+    b: (f32, f32),
+}
+
+impl Similarity {
+    fn apply(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        let (ar, ai) = self.a;
+        (ar * x - ai * y + self.b.0, ai * x + ar * y + self.b.1)
+    }
+
+    fn inverse(&self) -> Similarity {
+        let (ar, ai) = self.a;
+        let norm = ar * ar + ai * ai;
+        let a = (ar / norm, -ai / norm);
+        let inverse = Similarity { a, b: (0.0, 0.0) };
+        let (bx, by) = inverse.apply(self.b);
+        Similarity { a, b: (-bx, -by) }
+    }
+}
+
+/// Frames stacked so far onto a reference frame's pixel grid.
+pub struct Stack {
+    width: u32,
+    height: u32,
+    method: StackMethod,
+    stars: Vec<Star>,
+    /// Running sums of each channel, at 32 bits so no number of frames clips.
+    sums: Vec<f32>,
+    counts: Vec<u32>,
+    /// The aligned frames for a median, NaN where a frame does not reach.
+    aligned: Vec<Vec<f32>>,
+    stacked: usize,
+}
+
+impl Stack {
+    /// A stack holding only `reference`, which later frames are aligned to.
+    pub fn new(reference: &DynamicImage, method: StackMethod) -> Self {
+        let (width, height) = reference.dimensions();
+        let samples = samples(reference);
+        let stars = find_stars(&samples, width, height);
+        log::info!("found {} stars in the reference frame", stars.len());
+        let mut stack = Stack {
+            width,
+            height,
+            method,
+            stars,
+            sums: vec![0.0; samples.len()],
+            counts: vec![0; samples.len() / 3],
+            aligned: Vec::new(),
+            stacked: 0,
+        };
+        stack.accumulate(samples);
+        stack
+    }
+
+    /// Aligns `frame` to the reference by its stars and stacks it, or
+    /// leaves it out and returns `false` when too few of them match.
+    pub fn add(&mut self, frame: &DynamicImage) -> bool {
+        let (width, height) = frame.dimensions();
+        let samples = samples(frame);
+        let stars = find_stars(&samples, width, height);
+        let Some(transform) = align(&stars, &self.stars) else {
+            return false;
+        };
+        let (ar, ai) = transform.a;
+        log::debug!(
+            "aligned {} stars: shifted {:.1},{:.1}, turned {:.3} degrees, scaled {:.4}",
+            stars.len(),
+            transform.b.0,
+            transform.b.1,
+            ai.atan2(ar).to_degrees(),
+            ar.hypot(ai)
+        );
+
+        // Each reference pixel takes the frame's colour where the inverse
+        // transform lands, between its four nearest pixels.
+        let back = transform.inverse();
+        let mut aligned = vec![f32::NAN; self.sums.len()];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let (fx, fy) = back.apply((x as f32, y as f32));
+                if fx < 0.0 || fy < 0.0 || fx > (width - 1) as f32 || fy > (height - 1) as f32 {
+                    continue;
+                }
+                let (x0, y0) = (fx.floor() as u32, fy.floor() as u32);
+                let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+                let (tx, ty) = (fx - x0 as f32, fy - y0 as f32);
+                let at = |x: u32, y: u32, channel: usize| samples[(y * width + x) as usize * 3 + channel];
+                for channel in 0..3 {
+                    let top = at(x0, y0, channel) * (1.0 - tx) + at(x1, y0, channel) * tx;
+                    let bottom = at(x0, y1, channel) * (1.0 - tx) + at(x1, y1, channel) * tx;
+                    aligned[(y * self.width + x) as usize * 3 + channel] = top * (1.0 - ty) + bottom * ty;
+                }
+            }
+        }
+        self.accumulate(aligned);
+        true
+    }
+
+    fn accumulate(&mut self, aligned: Vec<f32>) {
+        for ((sum, count), pixel) in self.sums.chunks_exact_mut(3).zip(&mut self.counts).zip(aligned.chunks_exact(3)) {
+            if !pixel[0].is_nan() {
+                sum.iter_mut().zip(pixel).for_each(|(sum, value)| *sum += value);
+                *count += 1;
+            }
+        }
+        if self.method == StackMethod::Median {
+            self.aligned.push(aligned);
+        }
+        self.stacked += 1;
+    }
+
+    /// How many frames have been stacked, the reference among them.
+    pub fn frames(&self) -> usize {
+        self.stacked
+    }
+
+    /// The stacked image, on the reference's grid.
+    pub fn finish(self) -> RgbaImage {
+        self.finish_with_hooks(Hooks::NONE).expect("only a cancel token stops stacking")
+    }
+
+    /// [`Stack::finish`], reporting each row to `hooks`.
+    pub(crate) fn finish_with_hooks(self, hooks: Hooks) -> Result<RgbaImage, ImageDataErrors> {
+        let mut values = Vec::with_capacity(self.aligned.len());
+        let mut data = Vec::with_capacity(self.sums.len());
+        let row = self.width as usize * 3;
+        for y in 0..self.height as usize {
+            hooks.step("combining", y, self.height as usize)?;
+            data.extend((y * row..(y + 1) * row).map(|index| {
+                let value = match self.method {
+                    StackMethod::Mean => self.sums[index] / self.counts[index / 3].max(1) as f32,
+                    StackMethod::Median => {
+                        values.clear();
+                        values.extend(self.aligned.iter().map(|frame| frame[index]).filter(|value| !value.is_nan()));
+                        values.sort_by(f32::total_cmp);
+                        match values.len() {
+                            0 => 0.0,
+                            len if len % 2 == 1 => values[len / 2],
+                            len => (values[len / 2 - 1] + values[len / 2]) / 2.0,
+                        }
+                    }
+                };
+                // Frames are read at 16 bits.
+                (value / 257.0).round().clamp(0.0, 255.0) as u8
+            }));
+        }
+        hooks.step("combining", self.height as usize, self.height as usize)?;
+        Ok(RgbaImage::from_fn(self.width, self.height, |x, y| {
+            let at = (y * self.width + x) as usize * 3;
+            image::Rgba([data[at], data[at + 1], data[at + 2], 255])
+        }))
+    }
+}
+
+/// The RGB of `image` at 16 bits, as 32-bit floats.
+fn samples(image: &DynamicImage) -> Vec<f32> {
+    image.to_rgb16().into_raw().into_iter().map(f32::from).collect()
+}
+
+/// The brightest stars in `samples`: peaks of brightness five standard
+/// deviations above the mean that are the brightest within two pixels,
+/// placed at the centroid of the light around them.
+fn find_stars(samples: &[f32], width: u32, height: u32) -> Vec<Star> {
+    let (width, height) = (width as usize, height as usize);
+    let luma: Vec<f32> = samples.chunks_exact(3).map(|pixel| 0.2126 * pixel[0] + 0.7152 * pixel[1] + 0.0722 * pixel[2]).collect();
+    let count = luma.len().max(1) as f32;
+    let mean = luma.iter().sum::<f32>() / count;
+    let deviation = (luma.iter().map(|value| (value - mean).powi(2)).sum::<f32>() / count).sqrt();
+    let threshold = mean + 5.0 * deviation;
+
+    let mut stars = Vec::new();
+    for y in 2..height.saturating_sub(2) {
+        for x in 2..width.saturating_sub(2) {
+            let value = luma[y * width + x];
+            if value <= threshold {
+                continue;
+            }
+            // Ties go to the first pixel, so flat-topped stars count once.
+            let peak = (y - 2..=y + 2).all(|ny| {
+                (x - 2..=x + 2).all(|nx| {
+                    let other = luma[ny * width + nx];
+                    other < value || (other == value && (ny, nx) >= (y, x))
+                })
+            });
+            if !peak {
+                continue;
+            }
+            let (mut flux, mut sum_x, mut sum_y) = (0.0, 0.0, 0.0);
+            for ny in y - 2..=y + 2 {
+                for nx in x - 2..=x + 2 {
+                    let weight = (luma[ny * width + nx] - mean).max(0.0);
+                    flux += weight;
+                    sum_x += weight * nx as f32;
+                    sum_y += weight * ny as f32;
+                }
+            }
+            stars.push(Star { x: sum_x / flux, y: sum_y / flux, flux });
+        }
+    }
+    stars.sort_by(|a, b| b.flux.total_cmp(&a.flux));
+    stars.truncate(STARS);
+    stars
+}
+
+/// The similarity taking `stars` onto `reference` that matches the most of
+/// them, guessed from every pairing of two bright stars with two of the
+/// reference's and refined by least squares over the stars it matches.
+fn align(stars: &[Star], reference: &[Star]) -> Option<Similarity> {
+    let guesses = |stars: &[Star]| -> Vec<(f32, f32)> { stars.iter().take(GUESS_STARS).map(|star| (star.x, star.y)).collect() };
+    let (from, to) = (guesses(stars), guesses(reference));
+    let matches = |transform: &Similarity| -> Vec<((f32, f32), (f32, f32))> {
+        stars
+            .iter()
+            .filter_map(|star| {
+                let (x, y) = transform.apply((star.x, star.y));
+                let nearest = reference.iter().min_by(|a, b| (a.x - x).hypot(a.y - y).total_cmp(&(b.x - x).hypot(b.y - y)))?;
+                ((nearest.x - x).hypot(nearest.y - y) <= TOLERANCE).then_some(((star.x, star.y), (nearest.x, nearest.y)))
+            })
+            .collect()
+    };
+
+    let mut best: Option<(usize, Similarity)> = None;
+    for (i, &f1) in from.iter().enumerate() {
+        for &f2 in &from[i + 1..] {
+            let (dx, dy) = (f2.0 - f1.0, f2.1 - f1.1);
+            let length = dx * dx + dy * dy;
+            if length < 1.0 {
+                continue;
+            }
+            for (k, &r1) in to.iter().enumerate() {
+                for (l, &r2) in to.iter().enumerate() {
+                    if k == l {
+                        continue;
+                    }
+                    // a = (r2 - r1) / (f2 - f1), as complex numbers.
+                    let (rx, ry) = (r2.0 - r1.0, r2.1 - r1.1);
+                    let a = ((rx * dx + ry * dy) / length, (ry * dx - rx * dy) / length);
+                    // Frames of one sky from one camera barely change scale.
+                    if !(0.8..=1.25).contains(&a.0.hypot(a.1)) {
+                        continue;
+                    }
+                    let b = (r1.0 - (a.0 * f1.0 - a.1 * f1.1), r1.1 - (a.1 * f1.0 + a.0 * f1.1));
+                    let transform = Similarity { a, b };
+                    let matched = matches(&transform).len();
+                    if best.is_none_or(|(most, _)| matched > most) {
+                        best = Some((matched, transform));
+                    }
+                }
+            }
+        }
+    }
+    let (_, guess) = best?;
+    let pairs = matches(&guess);
+    if pairs.len() < MIN_MATCHES {
+        return None;
+    }
+
+    // The least-squares similarity: a = Σ (r - r̄)(f - f̄)* / Σ |f - f̄|².
+    let count = pairs.len() as f32;
+    let centre = |points: &mut dyn Iterator<Item = (f32, f32)>| {
+        let (x, y) = points.fold((0.0, 0.0), |(x, y), point| (x + point.0, y + point.1));
+        (x / count, y / count)
+    };
+    let (fx, fy) = centre(&mut pairs.iter().map(|pair| pair.0));
+    let (rx, ry) = centre(&mut pairs.iter().map(|pair| pair.1));
+    let (mut real, mut imaginary, mut norm) = (0.0, 0.0, 0.0);
+    for &((x, y), (u, v)) in &pairs {
+        let (x, y, u, v) = (x - fx, y - fy, u - rx, v - ry);
+        real += u * x + v * y;
+        imaginary += v * x - u * y;
+        norm += x * x + y * y;
+    }
+    if norm <= 0.0 {
+        return Some(guess);
+    }
+    let a = (real / norm, imaginary / norm);
+    let b = (rx - (a.0 * fx - a.1 * fy), ry - (a.1 * fx + a.0 * fy));
+    Some(Similarity { a, b })
+}
@@ -0,0 +1,41 @@
+use image::{DynamicImage, ImageError, ImageFormat};
+
+use crate::diff::DiffReport;
+use crate::{decode_image_bytes, encode_image_bytes, Combiner, FloatingImage, ImageDataErrors};
+
+/// Reads the image at `path` with tokio's fs and decodes it on the blocking pool.
+pub async fn load_image_async(path: &str) -> Result<(DynamicImage, ImageFormat), ImageDataErrors> {
+    let bytes = tokio::fs::read(path).await.map_err(ImageDataErrors::UnableToReadImageFromPath)?;
+    blocking(move || decode_image_bytes(&bytes)).await
+}
+
+/// Encodes `output` on the blocking pool and writes it to `path` with tokio's fs.
+pub async fn save_image_async(path: &str, output: FloatingImage, format: ImageFormat) -> Result<(), ImageDataErrors> {
+    let bytes = blocking(move || encode_image_bytes(output, format)).await?;
+    tokio::fs::write(path, bytes)
+        .await
+        .map_err(|e| ImageDataErrors::UnableToSaveImage(ImageError::IoError(e)))
+}
+
+impl Combiner {
+    /// [`Combiner::run`] on tokio's blocking pool, so resizing and combining
+    /// large images does not stall the executor.
+    pub async fn run_async(
+        &self,
+        image_1: DynamicImage,
+        image_2: DynamicImage,
+    ) -> Result<(FloatingImage, Option<DiffReport>), ImageDataErrors> {
+        let combiner = self.clone();
+        blocking(move || combiner.run(image_1, image_2)).await
+    }
+}
+
+/// Runs CPU-heavy work off the executor, passing its panics on to the caller.
+async fn blocking<T: Send + 'static>(
+    run: impl FnOnce() -> Result<T, ImageDataErrors> + Send + 'static,
+) -> Result<T, ImageDataErrors> {
+    match tokio::task::spawn_blocking(run).await {
+        Ok(result) => result,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
@@ -0,0 +1,145 @@
+use image::{imageops, DynamicImage, GenericImageView, RgbaImage};
+use serde::Serialize;
+
+use crate::crossfade;
+use crate::fit::{fit_to, Fit};
+use crate::gpu::Backend;
+use crate::noise::Random;
+
+/// Which random transforms make the variants of a dataset, and how strong
+/// they may be.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Augmentation {
+    /// Whether half of the variants, at random, are mirrored left to right.
+    pub flip: bool,
+    /// The smallest share of each side a random crop keeps, or `None` to
+    /// keep the whole image.
+    pub crop: Option<f64>,
+    /// How far brightness, contrast and saturation may each move either
+    /// way, as a fraction: 0.2 scales them by 0.8 to 1.2.
+    pub jitter: Option<f64>,
+    /// The largest share of another image of the dataset blended in, or
+    /// `None` to blend nothing.
+    pub blend: Option<f64>,
+}
+
+impl Augmentation {
+    pub fn is_empty(&self) -> bool {
+        *self == Augmentation::default()
+    }
+}
+
+/// The transforms drawn for one variant, in the order they are applied,
+/// recorded so a variant can be traced back or made again.
+#[derive(Debug, Clone, Serialize)]
+pub struct AugmentPlan {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crop: Option<RelativeCrop>,
+    pub flip: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jitter: Option<Jitter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blend: Option<Blend>,
+}
+
+/// A crop as fractions of the source's width and height, so it does not
+/// depend on the source's size.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct RelativeCrop {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+/// Factors applied to brightness, contrast and saturation, 1 leaving each as it was.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Jitter {
+    pub brightness: f64,
+    pub contrast: f64,
+    pub saturation: f64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Blend {
+    /// The index of the other image among the dataset's `sources`.
+    #[serde(skip)]
+    pub partner: usize,
+    /// The share of the other image in the result.
+    pub weight: f64,
+}
+
+/// Draws the transforms of the variant named `name`, of image `source` out
+/// of `sources` images, from `seed`. The same seed and name always draw the
+/// same plan, and the plan alone decides what [`apply`] does.
+pub fn plan(augmentation: &Augmentation, seed: u64, name: &str, source: usize, sources: usize) -> AugmentPlan {
+    let mut random = Random::for_name(seed, name);
+    let mut between = |low: f64, high: f64| low + random.next_f64() * (high - low);
+    let crop = augmentation.crop.map(|smallest| {
+        let (width, height) = (between(smallest, 1.0), between(smallest, 1.0));
+        RelativeCrop { x: between(0.0, 1.0 - width), y: between(0.0, 1.0 - height), width, height }
+    });
+    let flip = augmentation.flip && between(0.0, 1.0) < 0.5;
+    let jitter = augmentation.jitter.map(|strength| Jitter {
+        brightness: between(1.0 - strength, 1.0 + strength),
+        contrast: between(1.0 - strength, 1.0 + strength),
+        saturation: between(1.0 - strength, 1.0 + strength),
+    });
+    // Any image but the source itself.
+    let blend = augmentation.blend.filter(|_| sources > 1).map(|largest| {
+        let partner = (between(0.0, (sources - 1) as f64) as usize).min(sources - 2);
+        Blend { partner: if partner >= source { partner + 1 } else { partner }, weight: between(0.0, largest) }
+    });
+    AugmentPlan { crop, flip, jitter, blend }
+}
+
+/// Applies `plan` to `image`. `partner` is the image the plan blends in,
+/// resized to the result, and is only needed when it blends one.
+pub fn apply(image: DynamicImage, plan: &AugmentPlan, partner: Option<DynamicImage>) -> RgbaImage {
+    let mut image = match plan.crop {
+        Some(crop) => {
+            let (width, height) = image.dimensions();
+            let scale = |fraction: f64, length: u32| ((fraction * length as f64).round() as u32).min(length);
+            let (x, y) = (scale(crop.x, width), scale(crop.y, height));
+            let (crop_width, crop_height) = (scale(crop.width, width).max(1), scale(crop.height, height).max(1));
+            image.crop_imm(x, y, crop_width.min(width - x), crop_height.min(height - y)).to_rgba8()
+        }
+        None => image.to_rgba8(),
+    };
+    if plan.flip {
+        imageops::flip_horizontal_in_place(&mut image);
+    }
+    if let Some(jitter) = plan.jitter {
+        jitter_colours(&mut image, jitter);
+    }
+    match (plan.blend, partner) {
+        (Some(blend), Some(partner)) => {
+            let partner = fit_to(partner, image.dimensions(), Fit::Crop, false, &[], Backend::Cpu).to_rgba8();
+            let (width, height) = image.dimensions();
+            let data = crossfade::blend(&image, &partner, blend.weight as f32);
+            RgbaImage::from_raw(width, height, data).expect("blending keeps the size")
+        }
+        _ => image,
+    }
+}
+
+/// Scales brightness, then contrast around the mean grey, then saturation
+/// away from each pixel's own grey, as torchvision's `ColorJitter` does.
+fn jitter_colours(image: &mut RgbaImage, jitter: Jitter) {
+    let grey = |pixel: [f64; 3]| 0.299 * pixel[0] + 0.587 * pixel[1] + 0.114 * pixel[2];
+    let mut pixels: Vec<[f64; 3]> = image
+        .pixels()
+        .map(|pixel| [0, 1, 2].map(|channel| pixel.0[channel] as f64 * jitter.brightness).map(|value| value.clamp(0.0, 255.0)))
+        .collect();
+    let mean = pixels.iter().map(|&pixel| grey(pixel)).sum::<f64>() / pixels.len().max(1) as f64;
+    for pixel in &mut pixels {
+        *pixel = pixel.map(|value| (mean + (value - mean) * jitter.contrast).clamp(0.0, 255.0));
+        let own_grey = grey(*pixel);
+        *pixel = pixel.map(|value| (own_grey + (value - own_grey) * jitter.saturation).clamp(0.0, 255.0));
+    }
+    for (pixel, jittered) in image.pixels_mut().zip(pixels) {
+        for (channel, value) in pixel.0.iter_mut().zip(jittered) {
+            *channel = value.round() as u8;
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use combiner::exif::DateTime;
+
+use crate::args::{Args, Inputs, Mode, Selection};
+use crate::manifest;
+use crate::template::{NameTemplate, TemplateValues};
+use crate::webhook::{self, Finished};
+use crate::ImageDataErrors;
+
+/// One combination of two images into an output file.
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub image_1: String,
+    pub image_2: String,
+    pub output: String,
+    /// Position of the job within its batch, starting at 1.
+    pub index: usize,
+    /// Overrides the command line's `--mode`, as manifest rows may.
+    pub mode: Option<Mode>,
+}
+
+pub fn jobs(args: &Args) -> Result<Vec<Job>, ImageDataErrors> {
+    match &args.inputs {
+        Inputs::Single { image_1, image_2, output } => Ok(vec![Job {
+            image_1: image_1.clone(),
+            image_2: image_2.clone(),
+            output: output.clone(),
+            index: 1,
+            mode: None,
+        }]),
+        Inputs::Directory { input_dir, output_dir, image_2 } => {
+            directory_jobs(input_dir, output_dir, &args.selection, image_2)
+        }
+        Inputs::Manifest { path } => manifest::manifest_jobs(path),
+        // Requested one at a time once running.
+        Inputs::Daemon { .. } => Ok(Vec::new()),
+    }
+}
+
+/// Pairs every selected file under `input_dir` with `image_2`. Outputs keep
+/// the file's path relative to `input_dir`, so recursive runs mirror the
+/// input tree under `output_dir`. Entries are sorted so runs are reproducible.
+pub fn directory_jobs(
+    input_dir: &str,
+    output_dir: &str,
+    selection: &Selection,
+    image_2: &str,
+) -> Result<Vec<Job>, ImageDataErrors> {
+    let skip = Path::new(output_dir).canonicalize().ok();
+
+    let mut paths = Vec::new();
+    collect_files(Path::new(input_dir), selection.recursive, skip.as_deref(), &mut paths)?;
+    paths.retain(|path| selection.accepts(input_dir, path));
+    paths.sort();
+
+    Ok(paths
+        .iter()
+        .enumerate()
+        .map(|(i, path)| file_job(path, input_dir, output_dir, image_2, i + 1))
+        .collect())
+}
+
+fn collect_files(
+    dir: &Path,
+    recursive: bool,
+    skip: Option<&Path>,
+    paths: &mut Vec<PathBuf>,
+) -> Result<(), ImageDataErrors> {
+    for entry in std::fs::read_dir(dir).map_err(ImageDataErrors::UnableToReadDirectory)? {
+        let path = entry.map_err(ImageDataErrors::UnableToReadDirectory)?.path();
+        if path.is_dir() {
+            // The output directory may live inside the input tree; its results are not inputs.
+            if recursive && !is_hidden(&path) && path.canonicalize().ok().as_deref() != skip {
+                collect_files(&path, recursive, skip, paths)?;
+            }
+        } else if is_input_file(&path) {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name().is_none_or(|name| name.to_string_lossy().starts_with('.'))
+}
+
+/// Regular files are inputs, except hidden ones such as `.DS_Store`.
+pub fn is_input_file(path: &Path) -> bool {
+    path.is_file() && !is_hidden(path)
+}
+
+/// The job combining one file of the input tree with `image_2`.
+pub fn file_job(
+    path: &Path,
+    input_dir: &str,
+    output_dir: &str,
+    image_2: &str,
+    index: usize,
+) -> Job {
+    let relative = path.strip_prefix(input_dir).unwrap_or(path);
+    let output = Path::new(output_dir).join(relative);
+
+    Job {
+        image_1: path.to_string_lossy().into_owned(),
+        image_2: image_2.to_string(),
+        output: output.to_string_lossy().into_owned(),
+        index,
+        mode: None,
+    }
+}
+
+/// The output paths rendered so far, and the inputs of the job each was
+/// given to, so that no two jobs are given the same one.
+pub type Named = HashMap<String, (String, String)>;
+
+/// Where a job's result goes: its own output path, or the same directory with
+/// the file name rendered from `template` once the output size is known,
+/// made unique among `named`.
+pub fn output_path(job: &Job, template: Option<&NameTemplate>, mode: Mode, (width, height): (u32, u32), named: &mut Named) -> String {
+    match template {
+        Some(template) => {
+            let name = template.render(&TemplateValues {
+                image_1: &job.image_1,
+                image_2: &job.image_2,
+                mode: mode.name(),
+                width,
+                height,
+                index: job.index,
+            });
+            unique_path(&Path::new(&job.output).with_file_name(name).to_string_lossy(), job, named)
+        }
+        None => job.output.clone(),
+    }
+}
+
+/// Where a job's result goes when named after `taken`, its capture time:
+/// the same directory, as `YYYY-MM-DD_HH-MM-SS` with the output's extension,
+/// so names sort in the order the photos were taken.
+pub fn dated_path(job: &Job, taken: DateTime, named: &mut Named) -> String {
+    let output = Path::new(&job.output);
+    let extension = output.extension().map(|extension| format!(".{}", extension.to_string_lossy())).unwrap_or_default();
+    let name = format!("{}{}", taken.format("%Y-%m-%d_%H-%M-%S"), extension);
+    unique_path(&output.with_file_name(name).to_string_lossy(), job, named)
+}
+
+/// `path`, unless another job of `named` was given it first: then the same
+/// with `_2`, `_3` and so on before the extension, whichever is free. A job
+/// run again keeps the path it was given.
+pub fn unique_path(path: &str, job: &Job, named: &mut Named) -> String {
+    let inputs = (job.image_1.clone(), job.image_2.clone());
+    let path = Path::new(path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().map(|extension| format!(".{}", extension.to_string_lossy())).unwrap_or_default();
+    let unique = (1..)
+        .map(|count| match count {
+            1 => path.to_string_lossy().into_owned(),
+            _ => path.with_file_name(format!("{}_{}{}", stem, count, extension)).to_string_lossy().into_owned(),
+        })
+        .find(|candidate| named.entry(candidate.clone()).or_insert_with(|| inputs.clone()) == &inputs)
+        .expect("some count is free");
+    if unique != path.to_string_lossy() {
+        log::warn!("{} is already written by another job, so {} goes to {}", path.display(), job.image_1, unique);
+    }
+    unique
+}
+
+/// What happened to a job that did not fail.
+#[derive(Debug)]
+pub enum Outcome {
+    Written(String),
+    /// `--incremental` found the output already up to date.
+    UpToDate(String),
+}
+
+/// Runs every job even when some of them fail, then prints a summary table
+/// and, given a `notify_url`, POSTs it there. The batch only counts as failed
+/// when nothing succeeded, or when `strict` is set and anything failed.
+pub fn run(
+    jobs: &[Job],
+    strict: bool,
+    notify_url: Option<&str>,
+    mut combine: impl FnMut(&Job) -> Result<Outcome, ImageDataErrors>,
+) -> Result<(), ImageDataErrors> {
+    let started = Instant::now();
+    let (results, durations): (Vec<Result<Outcome, ImageDataErrors>>, Vec<Duration>) = jobs
+        .iter()
+        .map(|job| {
+            let job_started = Instant::now();
+            let result = combine(job);
+            if let Err(e) = &result {
+                log::warn!("failed {}: {}", job.image_1, e);
+            }
+            (result, job_started.elapsed())
+        })
+        .unzip();
+
+    print_summary(jobs, &results);
+    if let Some(url) = notify_url {
+        let finished: Vec<Finished> = jobs
+            .iter()
+            .zip(&results)
+            .zip(durations)
+            .map(|((job, result), duration)| Finished { job, result, duration })
+            .collect();
+        webhook::notify(url, &finished, started.elapsed());
+    }
+
+    let failed = results.iter().filter(|result| result.is_err()).count();
+    if (failed > 0 && strict) || (failed == jobs.len() && !jobs.is_empty()) {
+        Err(ImageDataErrors::BatchFailed { failed, total: jobs.len() })
+    } else {
+        Ok(())
+    }
+}
+
+fn print_summary(jobs: &[Job], results: &[Result<Outcome, ImageDataErrors>]) {
+    let width = jobs.iter().map(|job| job.image_1.len()).max().unwrap_or(0).max("INPUT".len());
+    println!("{:<7} {:<width$} DETAIL", "STATUS", "INPUT", width = width);
+    for (job, result) in jobs.iter().zip(results) {
+        match result {
+            Ok(Outcome::Written(output)) => println!("{:<7} {:<width$} {}", "ok", job.image_1, output, width = width),
+            Ok(Outcome::UpToDate(output)) => println!("{:<7} {:<width$} {}", "skipped", job.image_1, output, width = width),
+            Err(e) => println!("{:<7} {:<width$} {}", "failed", job.image_1, e, width = width),
+        }
+    }
+
+    let failed = results.iter().filter(|result| result.is_err()).count();
+    let skipped = results.iter().filter(|result| matches!(result, Ok(Outcome::UpToDate(_)))).count();
+    println!("{} succeeded, {} skipped, {} failed", results.len() - failed - skipped, skipped, failed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(image_1: &str, image_2: &str, index: usize) -> Job {
+        Job { image_1: image_1.to_string(), image_2: image_2.to_string(), output: format!("out/{}", image_1), index, mode: None }
+    }
+
+    #[test]
+    fn templated_outputs_never_collide() {
+        let template = NameTemplate::parse("{stem2}_{mode}.png").unwrap();
+        let mut named = Named::new();
+        let mut path = |job: &Job| output_path(job, Some(&template), Mode::Diff, (4, 3), &mut named);
+        let jobs = [job("a.jpg", "logo.png", 1), job("b.jpg", "logo.png", 2), job("c.jpg", "logo.png", 3)];
+        assert_eq!(jobs.each_ref().map(&mut path), ["out/logo_diff.png", "out/logo_diff_2.png", "out/logo_diff_3.png"]);
+        // Jobs run again, as `--watch` does, keep their names.
+        assert_eq!(path(&jobs[1]), "out/logo_diff_2.png");
+        // As the same input combined with another does not.
+        assert_eq!(path(&job("a.jpg", "other/logo.png", 4)), "out/logo_diff_4.png");
+    }
+
+    #[test]
+    fn untemplated_outputs_are_kept() {
+        let mut named = Named::new();
+        assert_eq!(output_path(&job("a.jpg", "b.png", 1), None, Mode::Diff, (4, 3), &mut named), "out/a.jpg");
+        assert!(named.is_empty());
+    }
+}
@@ -0,0 +1,94 @@
+//! Synthetic benchmarks of the combining pipeline, timing its stages one
+//! by one, so backends, fits and thread counts can be compared on the
+//! hardware at hand.
+
+use std::time::Instant;
+
+use image::{DynamicImage, GenericImageView, ImageFormat, RgbaImage};
+
+use crate::hooks::Hooks;
+use crate::noise::Random;
+use crate::options::CombineOptions;
+use crate::{combine_prepared, encode_image_bytes, prepare_inputs, FloatingImage, ImageDataErrors};
+
+/// The stages [`run_stages`] times, in order.
+pub const STAGES: [&str; 3] = ["resize", "combine", "encode"];
+
+/// How many megapixels a stage produced and how long that took.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StageTiming {
+    pub megapixels: f64,
+    pub seconds: f64,
+}
+
+impl StageTiming {
+    /// Megapixels per second.
+    pub fn throughput(&self) -> f64 {
+        if self.seconds > 0.0 {
+            self.megapixels / self.seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Two timings together, as if run one after the other.
+impl std::ops::Add for StageTiming {
+    type Output = StageTiming;
+
+    fn add(self, other: StageTiming) -> StageTiming {
+        StageTiming { megapixels: self.megapixels + other.megapixels, seconds: self.seconds + other.seconds }
+    }
+}
+
+/// Two inputs for a `width`x`height` output: gradients under noise, so
+/// encoders cannot make light work of them. The second is half as large
+/// again, so combining has to resize it.
+pub fn synthetic_pair(width: u32, height: u32, seed: u64) -> (DynamicImage, DynamicImage) {
+    let mut random = Random::new(seed);
+    let mut synthetic = |width: u32, height: u32| {
+        let mut image = RgbaImage::new(width.max(1), height.max(1));
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            let noise = random.next_u64().to_le_bytes();
+            let gradient = [x * 255 / width.max(1), y * 255 / height.max(1), (x + y) * 127 / (width + height).max(1)];
+            for channel in 0..3 {
+                pixel.0[channel] = (gradient[channel] as u8).wrapping_add(noise[channel] % 32);
+            }
+            pixel.0[3] = 255;
+        }
+        DynamicImage::ImageRgba8(image)
+    };
+    let image_1 = synthetic(width, height);
+    let image_2 = synthetic(width + width / 2, height + height / 2);
+    (image_1, image_2)
+}
+
+/// Runs the stages of combining `image_1` and `image_2` as `options` say,
+/// then encodes the result as `format`, and times each stage. Finishing
+/// options such as framing and noise are left out; they cost the same on
+/// every backend.
+pub fn run_stages(
+    image_1: DynamicImage,
+    image_2: DynamicImage,
+    options: &CombineOptions,
+    format: ImageFormat,
+) -> Result<[StageTiming; 3], ImageDataErrors> {
+    let megapixels = |(width, height): (u32, u32)| width as f64 * height as f64 / 1e6;
+    let timing = |start: Instant, size| StageTiming { megapixels: megapixels(size), seconds: start.elapsed().as_secs_f64() };
+
+    let start = Instant::now();
+    let (image_1, image_2) = prepare_inputs(image_1, image_2, options, options.mode.resizes_inputs(), Hooks::NONE)?;
+    let resize = timing(start, (image_1.width(), image_1.height()));
+
+    let start = Instant::now();
+    let combined = combine_prepared(image_1, image_2, options, "bench", Hooks::NONE)?;
+    let size = (combined.width, combined.height);
+    let combine = timing(start, size);
+
+    let mut output = FloatingImage::new(combined.width, combined.height, format!("bench.{}", format.extensions_str()[0]));
+    output.set_data(combined.data)?;
+    let start = Instant::now();
+    encode_image_bytes(output, format)?;
+    let encode = timing(start, size);
+    Ok([resize, combine, encode])
+}
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use image::{DynamicImage, ImageFormat};
+
+use crate::ImageDataErrors;
+
+/// How many decoded images are kept when `--cache-size` is not given.
+pub const DEFAULT_CAPACITY: usize = 16;
+
+struct Entry {
+    modified: Option<SystemTime>,
+    image: DynamicImage,
+    format: ImageFormat,
+    last_used: u64,
+}
+
+/// Decoded images shared between jobs, so an input that appears in many jobs
+/// (a watermark, a template) is only decoded once. Entries are keyed by path
+/// and modification time, so a file that changes on disk is decoded again,
+/// and the least recently used entry is evicted once `capacity` is reached.
+pub struct DecodeCache {
+    capacity: usize,
+    entries: HashMap<String, Entry>,
+    clock: u64,
+}
+
+impl DecodeCache {
+    pub fn new(capacity: usize) -> Self {
+        DecodeCache { capacity, entries: HashMap::new(), clock: 0 }
+    }
+
+    /// Returns a copy of the decoded image at `path`, decoding it when it is
+    /// not cached or has been modified since it was.
+    pub fn get_or_decode(
+        &mut self,
+        path: &str,
+        decode: impl FnOnce(&str) -> Result<(DynamicImage, ImageFormat), ImageDataErrors>,
+    ) -> Result<(DynamicImage, ImageFormat), ImageDataErrors> {
+        self.clock += 1;
+        let modified = std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+
+        if let Some(entry) = self.entries.get_mut(path) {
+            if entry.modified.is_some() && entry.modified == modified {
+                log::debug!("reusing decoded {}", path);
+                entry.last_used = self.clock;
+                return Ok((entry.image.clone(), entry.format));
+            }
+        }
+
+        let (image, format) = decode(path)?;
+        if self.capacity == 0 {
+            return Ok((image, format));
+        }
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(path) {
+            self.evict_least_recently_used();
+        }
+        self.entries.insert(
+            path.to_string(),
+            Entry { modified, image: image.clone(), format, last_used: self.clock },
+        );
+        Ok((image, format))
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let oldest = self.entries.iter().min_by_key(|(_, entry)| entry.last_used).map(|(path, _)| path.clone());
+        if let Some(path) = oldest {
+            log::debug!("evicting decoded {}", path);
+            self.entries.remove(&path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_each_unchanged_file_once() {
+        let dir = std::env::temp_dir().join(format!("combiner-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let paths: Vec<String> = ["a.png", "b.png", "c.png"]
+            .iter()
+            .map(|name| {
+                let path = dir.join(name).to_string_lossy().into_owned();
+                std::fs::write(&path, name).unwrap();
+                path
+            })
+            .collect();
+        let mut decoded = Vec::new();
+        let mut cache = DecodeCache::new(2);
+        let mut get = |cache: &mut DecodeCache, path: &str| {
+            cache
+                .get_or_decode(path, |path| {
+                    decoded.push(path.to_string());
+                    Ok((DynamicImage::new_rgba8(1, 1), ImageFormat::Png))
+                })
+                .unwrap();
+        };
+        get(&mut cache, &paths[0]);
+        get(&mut cache, &paths[1]);
+        get(&mut cache, &paths[0]);
+        // Evicts b.png, the least recently used.
+        get(&mut cache, &paths[2]);
+        get(&mut cache, &paths[0]);
+        get(&mut cache, &paths[1]);
+        assert_eq!(decoded, [&paths[0], &paths[1], &paths[2], &paths[1]].map(String::as_str));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn never_caches_at_zero_capacity() {
+        let mut cache = DecodeCache::new(0);
+        let path = std::env::current_exe().unwrap().to_string_lossy().into_owned();
+        let mut decodes = 0;
+        for _ in 0..2 {
+            cache
+                .get_or_decode(&path, |_| {
+                    decodes += 1;
+                    Ok((DynamicImage::new_rgba8(1, 1), ImageFormat::Png))
+                })
+                .unwrap();
+        }
+        assert_eq!(decodes, 2);
+    }
+}
@@ -0,0 +1,47 @@
+//! Camera RAW files, decoded with rawloader and developed by imagepipe's
+//! default pipeline (demosaic, camera white balance, sRGB) behind the
+//! `camera-raw` feature.
+
+use image::DynamicImage;
+
+use crate::ImageDataErrors;
+
+/// Extensions of the RAW formats rawloader reads.
+const EXTENSIONS: &[&str] = &[
+    "3fr", "ari", "arw", "cr2", "crw", "dcr", "dcs", "dng", "erf", "kdc", "mef", "mos", "mrw", "nef", "nrw", "orf",
+    "pef", "raf", "rw2", "srf", "sr2", "srw",
+];
+
+/// Whether a path names a camera RAW file.
+pub fn is_camera_raw(path: &str) -> bool {
+    let extension = std::path::Path::new(path).extension().and_then(|extension| extension.to_str());
+    extension.is_some_and(|extension| EXTENSIONS.iter().any(|raw| extension.eq_ignore_ascii_case(raw)))
+}
+
+#[cfg(all(feature = "camera-raw", not(target_arch = "wasm32")))]
+pub fn decode(bytes: &[u8]) -> Result<DynamicImage, ImageDataErrors> {
+    use image::error::{DecodingError, ImageFormatHint};
+    use image::{ImageError, RgbImage};
+    use imagepipe::{ImageSource, Pipeline};
+
+    let invalid = |message: String| {
+        ImageDataErrors::UnableToDecodeImage(ImageError::Decoding(DecodingError::new(
+            ImageFormatHint::Name("camera RAW".to_string()),
+            message,
+        )))
+    };
+
+    let raw = rawloader::decode(&mut std::io::Cursor::new(bytes)).map_err(|e| invalid(e.to_string()))?;
+    let developed = Pipeline::new_from_source(ImageSource::Raw(raw))
+        .and_then(|mut pipeline| pipeline.output_8bit(None))
+        .map_err(invalid)?;
+    let (width, height) = (developed.width as u32, developed.height as u32);
+    RgbImage::from_raw(width, height, developed.data)
+        .map(DynamicImage::ImageRgb8)
+        .ok_or_else(|| invalid("the developed image does not match its size".to_string()))
+}
+
+#[cfg(not(all(feature = "camera-raw", not(target_arch = "wasm32"))))]
+pub fn decode(_bytes: &[u8]) -> Result<DynamicImage, ImageDataErrors> {
+    Err(ImageDataErrors::InvalidArgument("camera RAW inputs need a build with the `camera-raw` feature".to_string()))
+}
@@ -0,0 +1,60 @@
+use image::DynamicImage;
+
+use crate::hooks::Hooks;
+use crate::ImageDataErrors;
+
+/// Places both inputs at their own size on a transparent canvas, `image_2`
+/// over `image_1`. Without an explicit `canvas` size, the canvas is just large
+/// enough to hold both. Parts placed outside the canvas are cut off.
+pub(crate) fn place_images(
+    image_1: &DynamicImage,
+    image_2: &DynamicImage,
+    canvas: Option<(u32, u32)>,
+    positions: [(i32, i32); 2],
+    hooks: Hooks,
+) -> Result<(u32, u32, Vec<u8>), ImageDataErrors> {
+    let layers = [(image_1.to_rgba8(), positions[0]), (image_2.to_rgba8(), positions[1])];
+    let (width, height) = canvas.unwrap_or_else(|| {
+        layers.iter().fold((0, 0), |(width, height), (layer, (x, y))| {
+            let right = (*x as i64 + layer.width() as i64).max(0) as u32;
+            let bottom = (*y as i64 + layer.height() as i64).max(0) as u32;
+            (width.max(right), height.max(bottom))
+        })
+    });
+    log::debug!("placing both images on a {}x{} canvas", width, height);
+
+    let mut data = vec![0u8; width as usize * height as usize * 4];
+    for y in 0..height {
+        hooks.step("combining", y as usize, height as usize)?;
+        for (layer, (left, top)) in &layers {
+            let source_y = y as i64 - *top as i64;
+            if source_y < 0 || source_y >= layer.height() as i64 {
+                continue;
+            }
+            let first = (*left as i64).clamp(0, width as i64) as u32;
+            let last = (*left as i64 + layer.width() as i64).clamp(0, width as i64) as u32;
+            for x in first..last {
+                let source = layer.get_pixel((x as i64 - *left as i64) as u32, source_y as u32).0;
+                let at = (y as usize * width as usize + x as usize) * 4;
+                blend_over(&mut data[at..at + 4], source);
+            }
+        }
+    }
+    hooks.step("combining", height as usize, height as usize)?;
+    Ok((width, height, data))
+}
+
+/// Composites `source` over the pixel in `destination` with straight alpha.
+pub(crate) fn blend_over(destination: &mut [u8], source: [u8; 4]) {
+    let source_alpha = source[3] as u32;
+    let destination_alpha = destination[3] as u32 * (255 - source_alpha) / 255;
+    let alpha = source_alpha + destination_alpha;
+    if alpha == 0 {
+        return;
+    }
+    for channel in 0..3 {
+        let mixed = source[channel] as u32 * source_alpha + destination[channel] as u32 * destination_alpha;
+        destination[channel] = ((mixed + alpha / 2) / alpha) as u8;
+    }
+    destination[3] = alpha as u8;
+}
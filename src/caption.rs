@@ -0,0 +1,244 @@
+//! Captions: top and bottom text set in the bitmap font, wrapped to the
+//! image and outlined so it reads over any photo, as memes and captioned
+//! photos have it.
+
+use image::{imageops, GrayImage, Luma, Rgba, RgbaImage};
+
+use crate::canvas::blend_over;
+use crate::text::{draw_text, text_width};
+use crate::ImageDataErrors;
+
+/// Where captions go and how they look unless told otherwise.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Placement {
+    /// Large white capitals with a black outline over the top and bottom
+    /// of the image.
+    #[default]
+    Meme,
+    /// Black text in white bars added above and below the image, which is
+    /// left uncovered.
+    Bars,
+    /// Smaller white text with a thin outline, in the case it was written.
+    Subtitle,
+}
+
+impl Placement {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        match value {
+            "meme" => Ok(Placement::Meme),
+            "bars" => Ok(Placement::Bars),
+            "subtitle" => Ok(Placement::Subtitle),
+            _ => Err(ImageDataErrors::InvalidArgument(format!(
+                "invalid placement `{}`, expected meme, bars or subtitle",
+                value
+            ))),
+        }
+    }
+
+    /// How tall a line is by default, as a share of the image's height.
+    fn line_share(self) -> u32 {
+        match self {
+            Placement::Meme => 8,
+            Placement::Bars => 12,
+            Placement::Subtitle => 20,
+        }
+    }
+}
+
+/// The text of a caption and how it is set.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Caption {
+    pub top: Option<String>,
+    pub bottom: Option<String>,
+    pub placement: Placement,
+    /// The height of a letter in pixels, rounded down to a multiple of the
+    /// font's 8, or `None` for the largest that fits, up to the placement's
+    /// own.
+    pub size: Option<u32>,
+    /// The width of the outline in pixels, or `None` for the placement's own.
+    pub stroke: Option<u32>,
+    /// The colour of the letters, or `None` for the placement's own.
+    pub fill: Option<[u8; 3]>,
+    /// The colour of the outline, or `None` for the placement's own.
+    pub stroke_colour: Option<[u8; 3]>,
+}
+
+/// A block of wrapped lines, each font pixel `scale` wide.
+struct Block {
+    lines: Vec<String>,
+    scale: u32,
+}
+
+impl Block {
+    fn height(&self) -> u32 {
+        // A font pixel of space between lines, as tight as Impact sets them.
+        self.lines.len() as u32 * 9 * self.scale - self.scale
+    }
+}
+
+/// `image` with `caption` set over it, or in bars around it.
+pub fn draw(image: &RgbaImage, caption: &Caption) -> Result<RgbaImage, ImageDataErrors> {
+    let texts = [&caption.top, &caption.bottom].map(|text| {
+        let text = text.as_deref().filter(|text| !text.trim().is_empty())?;
+        Some(if caption.placement == Placement::Meme { text.to_uppercase() } else { text.to_string() })
+    });
+    if texts.iter().all(Option::is_none) {
+        return Err(ImageDataErrors::MissingArgument("--top or --bottom"));
+    }
+    let (width, height) = image.dimensions();
+    let placement = caption.placement;
+    let default_scale = (height / placement.line_share() / 8).max(1);
+    let stroke = |scale: u32| {
+        caption.stroke.unwrap_or(match placement {
+            Placement::Meme => scale.div_ceil(2),
+            Placement::Subtitle => scale.div_ceil(3),
+            Placement::Bars => 0,
+        })
+    };
+    let margin = |scale: u32| width / 40 + stroke(scale);
+    let available = |scale: u32| width.saturating_sub(2 * margin(scale)).max(8 * scale);
+
+    // The largest scale at which no word is broken and neither caption takes
+    // more than a third of the image, the same for both so they match.
+    let scale = match caption.size {
+        Some(size) => (size / 8).max(1),
+        None => (1..=default_scale)
+            .rev()
+            .find(|&scale| {
+                texts.iter().flatten().all(|text| {
+                    let words_fit = text.split_whitespace().all(|word| text_width(word, scale) <= available(scale));
+                    words_fit && wrap(text, scale, available(scale)).height() <= height / 3
+                })
+            })
+            .unwrap_or(1),
+    };
+    let blocks = texts.map(|text| text.map(|text| wrap(&text, scale, available(scale))));
+    log::info!("captioning with {} pixel letters", 8 * scale);
+
+    let (fill, outline) = match placement {
+        Placement::Bars => ([0, 0, 0], [255, 255, 255]),
+        Placement::Meme | Placement::Subtitle => ([255, 255, 255], [0, 0, 0]),
+    };
+    let (fill, outline) = (caption.fill.unwrap_or(fill), caption.stroke_colour.unwrap_or(outline));
+    let stroke = stroke(scale);
+    let margin = margin(scale);
+
+    let bar = |block: &Option<Block>| block.as_ref().map_or(0, |block| block.height() + 2 * margin);
+    let (mut output, tops) = match placement {
+        Placement::Bars => {
+            let (above, below) = (bar(&blocks[0]), bar(&blocks[1]));
+            let mut output = RgbaImage::from_pixel(width, above + height + below, Rgba([255, 255, 255, 255]));
+            imageops::replace(&mut output, image, 0, above);
+            (output, [margin, above + height + margin])
+        }
+        Placement::Meme | Placement::Subtitle => {
+            let bottom = blocks[1].as_ref().map_or(0, |block| height.saturating_sub(block.height() + margin));
+            (image.clone(), [margin, bottom])
+        }
+    };
+
+    // The letters are drawn into a mask first, so the outline can be grown
+    // around all of them at once.
+    let mut letters = RgbaImage::new(output.width(), output.height());
+    for (block, top) in blocks.iter().zip(tops) {
+        let Some(block) = block else { continue };
+        for (index, line) in block.lines.iter().enumerate() {
+            let left = (i64::from(width) - i64::from(text_width(line, scale))) / 2;
+            let top = i64::from(top + index as u32 * 9 * scale);
+            draw_text(&mut letters, line, left, top, scale, [255, 255, 255, 255]);
+        }
+    }
+    let mask = GrayImage::from_fn(letters.width(), letters.height(), |x, y| Luma([letters.get_pixel(x, y).0[3]]));
+    if stroke > 0 {
+        let distance = distance_to(&mask);
+        // Chamfer distances are in thirds of a pixel.
+        let reach = stroke * 3;
+        for (pixel, &distance) in output.pixels_mut().zip(&distance) {
+            if distance > 0 && distance <= reach {
+                blend_over(&mut pixel.0, [outline[0], outline[1], outline[2], 255]);
+            }
+        }
+    }
+    for (pixel, coverage) in output.pixels_mut().zip(mask.pixels()) {
+        if coverage.0[0] > 0 {
+            blend_over(&mut pixel.0, [fill[0], fill[1], fill[2], coverage.0[0]]);
+        }
+    }
+    Ok(output)
+}
+
+/// `text` broken into lines no wider than `available` at `scale`, at spaces
+/// where it can be and mid-word where a word is too long on its own.
+fn wrap(text: &str, scale: u32, available: u32) -> Block {
+    let per_line = (available / (8 * scale)).max(1) as usize;
+    let mut lines: Vec<String> = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let mut word: Vec<char> = word.chars().collect();
+            while word.len() > per_line {
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                lines.push(word.drain(..per_line).collect());
+            }
+            let word: String = word.into_iter().collect();
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > per_line {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&word);
+        }
+        if !line.is_empty() {
+            lines.push(line);
+        }
+    }
+    Block { lines, scale }
+}
+
+/// How far each pixel is from the nearest covered pixel of `mask`, by the
+/// 3-4 chamfer that two passes give, in thirds of a pixel; 0 where covered.
+fn distance_to(mask: &GrayImage) -> Vec<u32> {
+    let (width, height) = (mask.width() as usize, mask.height() as usize);
+    let far = u32::MAX / 2;
+    let mut distance: Vec<u32> = mask.pixels().map(|coverage| if coverage.0[0] > 0 { 0 } else { far }).collect();
+    for y in 0..height {
+        for x in 0..width {
+            let mut best = distance[y * width + x];
+            if x > 0 {
+                best = best.min(distance[y * width + x - 1] + 3);
+            }
+            if y > 0 {
+                best = best.min(distance[(y - 1) * width + x] + 3);
+                if x > 0 {
+                    best = best.min(distance[(y - 1) * width + x - 1] + 4);
+                }
+                if x + 1 < width {
+                    best = best.min(distance[(y - 1) * width + x + 1] + 4);
+                }
+            }
+            distance[y * width + x] = best;
+        }
+    }
+    for y in (0..height).rev() {
+        for x in (0..width).rev() {
+            let mut best = distance[y * width + x];
+            if x + 1 < width {
+                best = best.min(distance[y * width + x + 1] + 3);
+            }
+            if y + 1 < height {
+                best = best.min(distance[(y + 1) * width + x] + 3);
+                if x + 1 < width {
+                    best = best.min(distance[(y + 1) * width + x + 1] + 4);
+                }
+                if x > 0 {
+                    best = best.min(distance[(y + 1) * width + x - 1] + 4);
+                }
+            }
+            distance[y * width + x] = best;
+        }
+    }
+    distance
+}
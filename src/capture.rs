@@ -0,0 +1,172 @@
+//! Screenshots as the first input, taken with `--capture screen:1` or
+//! `--capture window:"App Name"` behind the `capture` feature, so a screen
+//! can be annotated or diffed in one command. Linux and the BSDs capture
+//! through X11, including XWayland; Windows and macOS through their own APIs.
+
+use std::fmt;
+
+use image::DynamicImage;
+
+use crate::ImageDataErrors;
+
+/// What `--capture` takes a screenshot of.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// A monitor, numbered from 1 in the order the system lists them.
+    Screen(usize),
+    /// The first window whose title or application name contains this,
+    /// ignoring case.
+    Window(String),
+}
+
+impl Source {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        let invalid = || ImageDataErrors::InvalidArgument(format!("`{}` is not screen:<number> or window:<name>", value));
+        match value.split_once(':') {
+            Some(("screen", number)) => match number.parse() {
+                Ok(number) if number > 0 => Ok(Source::Screen(number)),
+                _ => Err(invalid()),
+            },
+            Some(("window", name)) if !name.is_empty() => Ok(Source::Window(name.to_string())),
+            _ => Err(invalid()),
+        }
+    }
+
+    #[cfg(feature = "capture")]
+    fn matches_window(&self, title: &str, app_name: &str) -> bool {
+        let Source::Window(name) = self else { return false };
+        let name = name.to_lowercase();
+        title.to_lowercase().contains(&name) || app_name.to_lowercase().contains(&name)
+    }
+}
+
+/// Written the way `--capture` takes it, which also names the input in logs.
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Screen(number) => write!(f, "screen:{}", number),
+            Source::Window(name) => write!(f, "window:{}", name),
+        }
+    }
+}
+
+fn failed(message: impl fmt::Display) -> ImageDataErrors {
+    ImageDataErrors::UnableToCapture(message.to_string())
+}
+
+/// Takes the screenshot `source` asks for.
+#[cfg(all(feature = "capture", unix, not(target_os = "macos")))]
+pub fn grab(source: &Source) -> Result<DynamicImage, ImageDataErrors> {
+    use x11rb::connection::Connection;
+    use x11rb::protocol::randr::ConnectionExt as _;
+    use x11rb::protocol::xproto::{ConnectionExt as _, ImageFormat};
+
+    let (connection, screen) = x11rb::connect(None).map_err(failed)?;
+    let root = connection.setup().roots[screen].root;
+    let (x, y, width, height) = match source {
+        Source::Screen(number) => {
+            let monitors = connection.randr_get_monitors(root, true).map_err(failed)?.reply().map_err(failed)?.monitors;
+            let monitor = monitors
+                .get(number - 1)
+                .ok_or_else(|| failed(format!("there is no screen {}, only {}", number, monitors.len())))?;
+            (monitor.x, monitor.y, monitor.width, monitor.height)
+        }
+        Source::Window(_) => {
+            let window = x11::find_window(&connection, root, source)?;
+            let geometry = connection.get_geometry(window).map_err(failed)?.reply().map_err(failed)?;
+            let origin = connection.translate_coordinates(window, root, 0, 0).map_err(failed)?.reply().map_err(failed)?;
+            // Clipped to the screen, since only what is on it can be read.
+            let screen = connection.get_geometry(root).map_err(failed)?.reply().map_err(failed)?;
+            let (left, top) = (origin.dst_x.max(0), origin.dst_y.max(0));
+            let right = (origin.dst_x as i32 + geometry.width as i32).min(screen.width as i32);
+            let bottom = (origin.dst_y as i32 + geometry.height as i32).min(screen.height as i32);
+            if right <= left as i32 || bottom <= top as i32 {
+                return Err(failed(format!("{} is off the screen", source)));
+            }
+            (left, top, (right - left as i32) as u16, (bottom - top as i32) as u16)
+        }
+    };
+    // Taken from the root window, so it shows what is on screen there.
+    let image = connection
+        .get_image(ImageFormat::Z_PIXMAP, root, x, y, width, height, !0)
+        .map_err(failed)?
+        .reply()
+        .map_err(failed)?;
+    if !matches!(image.depth, 24 | 32) || image.data.len() != width as usize * height as usize * 4 {
+        return Err(failed(format!("{}-bit displays are not supported", image.depth)));
+    }
+    // Pixels come as blue, green, red and padding.
+    let mut pixels = image.data;
+    for pixel in pixels.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+        pixel[3] = 255;
+    }
+    image::RgbaImage::from_raw(width as u32, height as u32, pixels).map(DynamicImage::ImageRgba8).ok_or(ImageDataErrors::BufferTooSmall)
+}
+
+#[cfg(all(feature = "capture", unix, not(target_os = "macos")))]
+mod x11 {
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::{AtomEnum, ConnectionExt as _, Window};
+
+    use super::{failed, Source};
+    use crate::ImageDataErrors;
+
+    /// The first top-level window, in the window manager's list, whose title
+    /// or `WM_CLASS` matches `source`.
+    pub fn find_window(connection: &impl Connection, root: Window, source: &Source) -> Result<Window, ImageDataErrors> {
+        let atom = |name: &[u8]| -> Result<u32, ImageDataErrors> {
+            Ok(connection.intern_atom(false, name).map_err(failed)?.reply().map_err(failed)?.atom)
+        };
+        let property = |window: Window, property: u32, kind: u32| -> Result<Vec<u8>, ImageDataErrors> {
+            let reply = connection.get_property(false, window, property, kind, 0, u32::MAX / 4).map_err(failed)?.reply().map_err(failed)?;
+            Ok(reply.value)
+        };
+        let (client_list, net_wm_name, utf8) = (atom(b"_NET_CLIENT_LIST")?, atom(b"_NET_WM_NAME")?, atom(b"UTF8_STRING")?);
+        let windows = property(root, client_list, AtomEnum::WINDOW.into())?;
+        for window in windows.chunks_exact(4).map(|id| u32::from_ne_bytes([id[0], id[1], id[2], id[3]])) {
+            let mut title = property(window, net_wm_name, utf8)?;
+            if title.is_empty() {
+                title = property(window, AtomEnum::WM_NAME.into(), AtomEnum::STRING.into())?;
+            }
+            // The instance and class names, each ending in a nul.
+            let class = property(window, AtomEnum::WM_CLASS.into(), AtomEnum::STRING.into())?;
+            let class = String::from_utf8_lossy(&class).replace('\0', " ");
+            if source.matches_window(&String::from_utf8_lossy(&title), &class) {
+                return Ok(window);
+            }
+        }
+        Err(failed(format!("no window matches {}", source)))
+    }
+}
+
+/// Takes the screenshot `source` asks for.
+#[cfg(all(feature = "capture", any(windows, target_os = "macos")))]
+pub fn grab(source: &Source) -> Result<DynamicImage, ImageDataErrors> {
+    let captured = match source {
+        Source::Screen(number) => {
+            let monitors = xcap::Monitor::all().map_err(failed)?;
+            let monitor = monitors
+                .get(number - 1)
+                .ok_or_else(|| failed(format!("there is no screen {}, only {}", number, monitors.len())))?;
+            monitor.capture_image().map_err(failed)?
+        }
+        Source::Window(_) => {
+            let windows = xcap::Window::all().map_err(failed)?;
+            let window = windows
+                .iter()
+                .filter(|window| !window.is_minimized().unwrap_or(true))
+                .find(|window| source.matches_window(&window.title().unwrap_or_default(), &window.app_name().unwrap_or_default()))
+                .ok_or_else(|| failed(format!("no window matches {}", source)))?;
+            window.capture_image().map_err(failed)?
+        }
+    };
+    // xcap hands back its own version of `image`'s buffers.
+    let (width, height) = captured.dimensions();
+    image::RgbaImage::from_raw(width, height, captured.into_raw()).map(DynamicImage::ImageRgba8).ok_or(ImageDataErrors::BufferTooSmall)
+}
+
+#[cfg(not(all(feature = "capture", any(unix, windows))))]
+pub fn grab(_source: &Source) -> Result<DynamicImage, ImageDataErrors> {
+    Err(failed("this build lacks the `capture` feature"))
+}
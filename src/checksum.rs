@@ -0,0 +1,53 @@
+use sha2::{Digest, Sha256};
+
+use crate::ImageDataErrors;
+
+/// The hash `--checksum` reports for each output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        match value.to_ascii_lowercase().as_str() {
+            "sha256" => Ok(ChecksumAlgorithm::Sha256),
+            _ => Err(ImageDataErrors::InvalidArgument(format!("unknown checksum `{}`, expected sha256", value))),
+        }
+    }
+
+    /// The extension of sidecar files, as `sha256sum` users expect.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    /// The lowercase hex digest of `bytes`.
+    pub fn digest(self, bytes: &[u8]) -> String {
+        let digest = match self {
+            ChecksumAlgorithm::Sha256 => Sha256::digest(bytes),
+        };
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+/// A line `sha256sum --check` accepts for the file `name`.
+pub fn line(digest: &str, name: &str) -> String {
+    format!("{}  {}\n", digest, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digests_as_sha256sum_does() {
+        let algorithm = ChecksumAlgorithm::parse("SHA256").unwrap();
+        let digest = algorithm.digest(b"abc");
+        assert_eq!(digest, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(line(&digest, "out.png"), format!("{}  out.png\n", digest));
+        assert_eq!(algorithm.extension(), "sha256");
+        assert!(ChecksumAlgorithm::parse("md5").is_err());
+    }
+}
@@ -0,0 +1,100 @@
+//! `--from-clipboard` and `--to-clipboard`: the system clipboard as the first
+//! input and as the output, so screenshots can be combined without going
+//! through files. The clipboard holds pixels in no file format.
+
+use std::borrow::Cow;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Command, Stdio};
+
+use arboard::{Clipboard, ImageData};
+use image::{DynamicImage, RgbaImage};
+
+use combiner::FloatingImage;
+
+use crate::ImageDataErrors;
+
+/// The path standing for the clipboard, in place of an input or output file.
+pub const PATH: &str = "clipboard:";
+
+/// Set in the environment of the copy of this program that keeps serving an
+/// output placed on the clipboard, see [`write`].
+pub const SERVE: &str = "COMBINER_SERVE_CLIPBOARD";
+
+pub fn is_clipboard(path: &str) -> bool {
+    path == PATH
+}
+
+fn unable(e: impl std::fmt::Display) -> ImageDataErrors {
+    ImageDataErrors::UnableToUseClipboard(e.to_string())
+}
+
+/// The image on the clipboard.
+pub fn read() -> Result<DynamicImage, ImageDataErrors> {
+    let image = Clipboard::new().and_then(|mut clipboard| clipboard.get_image()).map_err(unable)?;
+    let pixels = RgbaImage::from_raw(image.width as u32, image.height as u32, image.bytes.into_owned())
+        .ok_or(ImageDataErrors::BufferTooSmall)?;
+    Ok(DynamicImage::ImageRgba8(pixels))
+}
+
+/// Places `output` on the clipboard.
+///
+/// On X11 and Wayland, the clipboard is only a promise: pasting asks the
+/// program that copied for the pixels, so they would be gone once this one
+/// exits. A copy of the program started in the background holds them
+/// instead, until something else is copied.
+pub fn write(output: &FloatingImage) -> Result<(), ImageDataErrors> {
+    if cfg!(all(unix, not(target_os = "macos"))) {
+        let mut server = Command::new(std::env::current_exe().map_err(unable)?)
+            .env(SERVE, "1")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(unable)?;
+        let mut stdin = server.stdin.take().expect("stdin is piped");
+        stdin.write_all(&output.width.to_le_bytes()).map_err(unable)?;
+        stdin.write_all(&output.height.to_le_bytes()).map_err(unable)?;
+        stdin.write_all(&output.data).map_err(unable)?;
+        drop(stdin);
+        // The server replies once it reaches the display, or says why it could not.
+        let mut reply = String::new();
+        BufReader::new(server.stdout.take().expect("stdout is piped")).read_line(&mut reply).map_err(unable)?;
+        match reply.trim_end() {
+            "ok" => {}
+            "" => return Err(unable("the background copy holding it stopped")),
+            reason => return Err(unable(reason)),
+        }
+    } else {
+        let image = ImageData { width: output.width as usize, height: output.height as usize, bytes: Cow::Borrowed(&output.data) };
+        Clipboard::new().and_then(|mut clipboard| clipboard.set_image(image)).map_err(unable)?;
+    }
+    log::info!("copied the {}x{} output to the clipboard", output.width, output.height);
+    Ok(())
+}
+
+/// Runs in the background copy [`write`] starts, when [`SERVE`] is set:
+/// reads the output's size and pixels from stdin, then holds the clipboard
+/// with them until something else is copied.
+pub fn serve() -> Result<(), ImageDataErrors> {
+    let mut stdin = std::io::stdin().lock();
+    let mut size = [0u8; 8];
+    let mut data = Vec::new();
+    stdin.read_exact(&mut size).and_then(|_| stdin.read_to_end(&mut data)).map_err(unable)?;
+    let width = u32::from_le_bytes([size[0], size[1], size[2], size[3]]) as usize;
+    let height = u32::from_le_bytes([size[4], size[5], size[6], size[7]]) as usize;
+    let image = ImageData { width, height, bytes: Cow::Owned(data) };
+
+    let mut stdout = std::io::stdout();
+    let mut clipboard = match Clipboard::new() {
+        Ok(clipboard) => clipboard,
+        Err(e) => return writeln!(stdout, "{}", e).map_err(unable),
+    };
+    writeln!(stdout, "ok").and_then(|_| stdout.flush()).map_err(unable)?;
+    #[cfg(all(unix, not(target_os = "macos")))]
+    {
+        use arboard::SetExtLinux;
+        clipboard.set().wait().image(image).map_err(unable)
+    }
+    #[cfg(not(all(unix, not(target_os = "macos"))))]
+    clipboard.set_image(image).map_err(unable)
+}
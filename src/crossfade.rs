@@ -0,0 +1,10 @@
+use image::RgbaImage;
+
+use crate::simd::{self, Kernel};
+
+/// Mixes two images of the same size, `weight` of the way from `from` to
+/// `to`. Colours are mixed premultiplied by alpha, so the colour hidden in
+/// transparent pixels never bleeds into the fade.
+pub(crate) fn blend(from: &RgbaImage, to: &RgbaImage, weight: f32) -> Vec<u8> {
+    simd::blend(from.as_raw(), to.as_raw(), weight, Kernel::detect())
+}
@@ -0,0 +1,164 @@
+//! `--daemon`: stays running and combines the jobs it is sent, one JSON
+//! request per line on stdin or on a unix socket, answering each with a JSON
+//! event line. Startup is paid once, and decoded inputs stay in the cache
+//! between requests, so thousands of jobs sharing an overlay decode it once.
+//!
+//! A request is a job manifest row with an optional `id`, echoed in its
+//! answer, and optional `options`, replacing the command line's:
+//!
+//! ```text
+//! {"id": 1, "image_1": "a.png", "image_2": "b.png", "output": "out.png", "mode": "diff"}
+//! ```
+//!
+//! The answers are `{"event": "ready"}` once requests are read, then for
+//! each request `written` with the output and the time taken, `up_to_date`
+//! under `--incremental`, or `failed` with the error.
+
+use std::io::{BufRead, BufReader, Write};
+use std::time::Instant;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::args::{CombineOptions, Mode};
+use crate::batch::{Job, Outcome};
+use crate::ImageDataErrors;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    image_1: String,
+    image_2: String,
+    output: String,
+    #[serde(default)]
+    mode: Option<String>,
+    #[serde(default)]
+    options: Option<CombineOptions>,
+}
+
+/// Answers requests until stdin ends, or for as long as the process runs
+/// when listening on `socket`. Jobs run one at a time, in the order their
+/// requests arrive, through `combine`.
+pub fn run(
+    socket: Option<&str>,
+    defaults: &CombineOptions,
+    mut combine: impl FnMut(&Job, CombineOptions) -> Result<Outcome, ImageDataErrors>,
+) -> Result<(), ImageDataErrors> {
+    let mut index = 0;
+    let mut handle = |line: &str| {
+        index += 1;
+        answer(line, index, defaults, &mut combine)
+    };
+    match socket {
+        None => {
+            let mut stdout = std::io::stdout();
+            send(&mut stdout, &json!({ "event": "ready" })).map_err(unable)?;
+            for line in std::io::stdin().lock().lines() {
+                let line = line.map_err(unable)?;
+                if !line.trim().is_empty() {
+                    send(&mut stdout, &handle(&line)).map_err(unable)?;
+                }
+            }
+            Ok(())
+        }
+        Some(path) => listen(path, handle),
+    }
+}
+
+fn unable(e: impl std::fmt::Display) -> ImageDataErrors {
+    ImageDataErrors::UnableToServe(e.to_string())
+}
+
+fn send(out: &mut impl Write, event: &Value) -> std::io::Result<()> {
+    writeln!(out, "{}", event)?;
+    out.flush()
+}
+
+/// Runs the job `line` requests and describes how it went.
+fn answer(
+    line: &str,
+    index: usize,
+    defaults: &CombineOptions,
+    combine: &mut impl FnMut(&Job, CombineOptions) -> Result<Outcome, ImageDataErrors>,
+) -> Value {
+    let value: Value = match serde_json::from_str(line) {
+        Ok(value) => value,
+        Err(e) => return json!({ "id": null, "event": "failed", "error": format!("invalid request: {}", e) }),
+    };
+    let id = value.get("id").cloned().unwrap_or(Value::Null);
+    let failed = |error: String| json!({ "id": id, "event": "failed", "error": error });
+    let request: Request = match serde_json::from_value(value) {
+        Ok(request) => request,
+        Err(e) => return failed(format!("invalid request: {}", e)),
+    };
+    let mode = match request.mode.as_deref().map(Mode::parse).transpose() {
+        Ok(mode) => mode,
+        Err(e) => return failed(e.to_string()),
+    };
+    let mut options = request.options.unwrap_or_else(|| defaults.clone());
+    options.mode = mode.unwrap_or(options.mode);
+    let job = Job { image_1: request.image_1, image_2: request.image_2, output: request.output, index, mode };
+
+    let started = Instant::now();
+    match combine(&job, options) {
+        Ok(Outcome::Written(output)) => {
+            let millis = started.elapsed().as_secs_f64() * 1000.0;
+            json!({ "id": request.id, "event": "written", "output": output, "millis": (millis * 10.0).round() / 10.0 })
+        }
+        Ok(Outcome::UpToDate(output)) => json!({ "id": request.id, "event": "up_to_date", "output": output }),
+        Err(e) => failed(e.to_string()),
+    }
+}
+
+/// Accepts any number of clients on a unix socket at `path`. Each is read on
+/// a thread of its own, and its requests are queued for this one to run, so
+/// the cache is shared; answers go back to the client that asked.
+#[cfg(unix)]
+fn listen(path: &str, mut handle: impl FnMut(&str) -> Value) -> Result<(), ImageDataErrors> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::sync::mpsc;
+
+    // A socket left behind by an earlier daemon would stop the bind.
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) && UnixStream::connect(path).is_err() {
+        std::fs::remove_file(path).map_err(unable)?;
+    }
+    let listener = UnixListener::bind(path).map_err(|e| unable(format!("{}: {}", path, e)))?;
+    log::info!("listening on {}", path);
+
+    let (requests, queue) = mpsc::channel::<(String, UnixStream)>();
+    std::thread::spawn(move || {
+        for client in listener.incoming() {
+            let Ok(client) = client else { continue };
+            let requests = requests.clone();
+            std::thread::spawn(move || {
+                let Ok(mut reply) = client.try_clone() else { return };
+                if send(&mut reply, &json!({ "event": "ready" })).is_err() {
+                    return;
+                }
+                for line in BufReader::new(client).lines() {
+                    let Ok(line) = line else { return };
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let Ok(answer) = reply.try_clone() else { return };
+                    if requests.send((line, answer)).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    for (line, mut reply) in queue {
+        // A client that left before its answer only misses the answer.
+        let _ = send(&mut reply, &handle(&line));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn listen(path: &str, _handle: impl FnMut(&str) -> Value) -> Result<(), ImageDataErrors> {
+    Err(unable(format!("cannot listen on {}, unix sockets need a unix system", path)))
+}
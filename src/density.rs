@@ -0,0 +1,98 @@
+use image::ImageFormat;
+
+const INCHES_PER_METRE: f64 = 39.3701;
+
+/// Records `dpi` in encoded PNG (`pHYs`) and JPEG (JFIF) bytes, so print
+/// software knows the physical size. Other formats are returned untouched.
+pub fn set_dpi(bytes: Vec<u8>, format: ImageFormat, dpi: f32) -> Vec<u8> {
+    match format {
+        ImageFormat::Png => set_png_dpi(bytes, dpi),
+        ImageFormat::Jpeg => set_jpeg_dpi(bytes, dpi),
+        _ => {
+            log::debug!("{:?} outputs cannot record their DPI", format);
+            bytes
+        }
+    }
+}
+
+/// The DPI recorded in the start of encoded PNG or JPEG bytes, if any. Only
+/// physical units count; a bare pixel aspect ratio says nothing about size.
+pub fn read_dpi(bytes: &[u8]) -> Option<f32> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        let mut at = 8;
+        while at + 8 <= bytes.len() {
+            let length = u32::from_be_bytes(bytes[at..at + 4].try_into().ok()?) as usize;
+            match &bytes[at + 4..at + 8] {
+                b"pHYs" if length == 9 && bytes.len() >= at + 17 && bytes[at + 16] == 1 => {
+                    let pixels_per_metre = u32::from_be_bytes(bytes[at + 8..at + 12].try_into().ok()?);
+                    return Some((pixels_per_metre as f64 / INCHES_PER_METRE) as f32);
+                }
+                // `pHYs` must come before the image data.
+                b"IDAT" => return None,
+                _ => at += 12 + length,
+            }
+        }
+        return None;
+    }
+    if bytes.len() >= 18 && bytes[..4] == [0xFF, 0xD8, 0xFF, 0xE0] && &bytes[6..11] == b"JFIF\0" {
+        let density = u16::from_be_bytes([bytes[14], bytes[15]]) as f32;
+        return match bytes[13] {
+            1 => Some(density),
+            2 => Some(density * 2.54),
+            _ => None,
+        }
+        .filter(|dpi| *dpi > 0.0);
+    }
+    None
+}
+
+/// Inserts a `pHYs` chunk after `IHDR`, replacing any already there.
+fn set_png_dpi(bytes: Vec<u8>, dpi: f32) -> Vec<u8> {
+    let pixels_per_metre = (dpi as f64 * INCHES_PER_METRE).round() as u32;
+    let mut chunk_data = Vec::with_capacity(13);
+    chunk_data.extend_from_slice(b"pHYs");
+    chunk_data.extend_from_slice(&pixels_per_metre.to_be_bytes());
+    chunk_data.extend_from_slice(&pixels_per_metre.to_be_bytes());
+    chunk_data.push(1);
+
+    let mut output = Vec::with_capacity(bytes.len() + 21);
+    // The 8 byte signature, then chunks of length, type, data and CRC.
+    output.extend_from_slice(&bytes[..8.min(bytes.len())]);
+    let mut at = 8;
+    while at + 8 <= bytes.len() {
+        let length = u32::from_be_bytes(bytes[at..at + 4].try_into().expect("four bytes")) as usize;
+        let end = (at + 12 + length).min(bytes.len());
+        let kind = &bytes[at + 4..at + 8];
+        if kind != b"pHYs" {
+            output.extend_from_slice(&bytes[at..end]);
+        }
+        if kind == b"IHDR" {
+            output.extend_from_slice(&9u32.to_be_bytes());
+            output.extend_from_slice(&chunk_data);
+            output.extend_from_slice(&crc32fast::hash(&chunk_data).to_be_bytes());
+        }
+        at = end;
+    }
+    output
+}
+
+/// Sets the density of the JFIF `APP0` segment, adding one if the encoder
+/// did not write it.
+fn set_jpeg_dpi(mut bytes: Vec<u8>, dpi: f32) -> Vec<u8> {
+    let dpi = (dpi.round() as u16).max(1).to_be_bytes();
+    // SOI, then the APP0 marker, its length and the JFIF identifier.
+    if bytes.len() >= 18 && bytes[2..4] == [0xFF, 0xE0] && &bytes[6..11] == b"JFIF\0" {
+        bytes[13] = 1;
+        bytes[14..16].copy_from_slice(&dpi);
+        bytes[16..18].copy_from_slice(&dpi);
+        return bytes;
+    }
+    let mut segment = vec![0xFF, 0xE0, 0, 16];
+    segment.extend_from_slice(b"JFIF\0");
+    segment.extend_from_slice(&[1, 2, 1]);
+    segment.extend_from_slice(&dpi);
+    segment.extend_from_slice(&dpi);
+    segment.extend_from_slice(&[0, 0]);
+    bytes.splice(2..2, segment);
+    bytes
+}
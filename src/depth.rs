@@ -0,0 +1,35 @@
+use image::{GrayImage, RgbaImage};
+
+use crate::hooks::Hooks;
+use crate::ImageDataErrors;
+
+/// How far past the focused range, as a share of the depth map's range, the
+/// second input takes over completely.
+const FALLOFF: f32 = 0.2;
+
+/// Mixes `sharp` into `soft`, the same size, by `depth`, where black is
+/// nearest and white farthest: pixels whose depth lies within `focus`, from
+/// 0 to 1, are all `sharp`, and beyond it `soft` takes over across
+/// [`FALLOFF`] on a smoothstep, as a lens's depth of field falls away.
+pub(crate) fn blend(sharp: &RgbaImage, soft: &RgbaImage, depth: &GrayImage, (near, far): (f32, f32), hooks: Hooks) -> Result<Vec<u8>, ImageDataErrors> {
+    let table: [f32; 256] = std::array::from_fn(|value| {
+        let depth = value as f32 / 255.0;
+        let outside = (near - depth).max(depth - far).max(0.0);
+        let t = (outside / FALLOFF).min(1.0);
+        t * t * (3.0 - 2.0 * t)
+    });
+    let mut data = sharp.as_raw().clone();
+    let (row, height) = (sharp.width() as usize, sharp.height() as usize);
+    let rows = data.chunks_exact_mut(row * 4).zip(soft.as_raw().chunks_exact(row * 4)).zip(depth.as_raw().chunks_exact(row));
+    for (y, ((pixels, soft), depth)) in rows.enumerate() {
+        hooks.step("combining", y, height)?;
+        for ((pixel, soft), &depth) in pixels.chunks_exact_mut(4).zip(soft.chunks_exact(4)).zip(depth) {
+            let weight = table[usize::from(depth)];
+            for (channel, &soft) in pixel.iter_mut().zip(soft) {
+                *channel = (f32::from(*channel) * (1.0 - weight) + f32::from(soft) * weight).round() as u8;
+            }
+        }
+    }
+    hooks.step("combining", height, height)?;
+    Ok(data)
+}
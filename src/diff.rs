@@ -0,0 +1,182 @@
+use image::{DynamicImage, GenericImageView};
+use serde::Serialize;
+use std::collections::VecDeque;
+
+use crate::options::DiffStyle;
+use crate::hooks::Hooks;
+use crate::simd::{self, Kernel};
+use crate::ImageDataErrors;
+
+/// Colour painted over pixels that differ between the two inputs.
+const HIGHLIGHT: [u8; 4] = [255, 0, 0, 255];
+
+#[derive(Debug, Serialize)]
+pub struct DiffReport {
+    pub width: u32,
+    pub height: u32,
+    pub changed_pixels: u64,
+    pub change_percentage: f64,
+    pub regions: Vec<Region>,
+}
+
+/// A connected group of changed pixels and the box that encloses it.
+#[derive(Debug, Serialize)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub changed_pixels: u64,
+    pub change_percentage: f64,
+}
+
+/// Compares two images of equal dimensions. Pixels whose largest channel
+/// difference exceeds `threshold` are drawn over a dimmed copy of `image_1`
+/// according to `style`, and are grouped into 8-connected regions for the report.
+pub(crate) fn diff_images(
+    image_1: &DynamicImage,
+    image_2: &DynamicImage,
+    threshold: u8,
+    style: DiffStyle,
+    hooks: Hooks,
+) -> Result<(Vec<u8>, DiffReport), ImageDataErrors> {
+    let (width, height) = image_1.dimensions();
+    let vec_1 = image_1.to_rgba8().into_vec();
+    let vec_2 = image_2.to_rgba8().into_vec();
+
+    let magnitudes = simd::difference_magnitudes(&vec_1, &vec_2, Kernel::detect());
+    let mask: Vec<bool> = magnitudes.iter().map(|magnitude| *magnitude > threshold).collect();
+    let regions = find_regions(&mask, width, height, hooks)?;
+
+    let mut output = vec![0u8; vec_1.len()];
+    for (i, magnitude) in magnitudes.iter().enumerate() {
+        hooks.step("combining", i, magnitudes.len())?;
+        let base = [vec_1[i * 4] / 3, vec_1[i * 4 + 1] / 3, vec_1[i * 4 + 2] / 3, 255];
+        let pixel = match style {
+            _ if !mask[i] => base,
+            DiffStyle::Highlight => HIGHLIGHT,
+            DiffStyle::Heatmap => heat_colour(base, *magnitude),
+        };
+        output[i * 4..i * 4 + 4].copy_from_slice(&pixel);
+    }
+    hooks.step("combining", magnitudes.len(), magnitudes.len())?;
+
+    let changed_pixels = mask.iter().filter(|changed| **changed).count() as u64;
+    let report = DiffReport {
+        width,
+        height,
+        changed_pixels,
+        change_percentage: percentage(changed_pixels, width as u64 * height as u64),
+        regions,
+    };
+    Ok((output, report))
+}
+
+/// Maps a difference magnitude onto a blue → cyan → green → yellow → red
+/// gradient and blends it over `base`, letting larger differences cover more
+/// of the underlying image.
+fn heat_colour(base: [u8; 4], magnitude: u8) -> [u8; 4] {
+    let t = magnitude as f32 / 255.0;
+    let (r, g, b) = match t {
+        t if t < 0.25 => (0.0, t / 0.25, 1.0),
+        t if t < 0.5 => (0.0, 1.0, 1.0 - (t - 0.25) / 0.25),
+        t if t < 0.75 => ((t - 0.5) / 0.25, 1.0, 0.0),
+        t => (1.0, 1.0 - (t - 0.75) / 0.25, 0.0),
+    };
+    let opacity = 0.5 + 0.5 * t;
+    let blend = |base: u8, heat: f32| (base as f32 * (1.0 - opacity) + heat * 255.0 * opacity).round() as u8;
+    [blend(base[0], r), blend(base[1], g), blend(base[2], b), 255]
+}
+
+fn find_regions(mask: &[bool], width: u32, height: u32, hooks: Hooks) -> Result<Vec<Region>, ImageDataErrors> {
+    let (w, h) = (width as usize, height as usize);
+    let mut visited = vec![false; mask.len()];
+    let mut regions = Vec::new();
+    let mut queue = VecDeque::new();
+
+    for start in 0..mask.len() {
+        if start % w == 0 {
+            hooks.check()?;
+        }
+        if !mask[start] || visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        queue.push_back(start);
+
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (w, h, 0, 0);
+        let mut changed_pixels = 0u64;
+        while let Some(index) = queue.pop_front() {
+            let (x, y) = (index % w, index / w);
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+            changed_pixels += 1;
+
+            for ny in y.saturating_sub(1)..=(y + 1).min(h - 1) {
+                for nx in x.saturating_sub(1)..=(x + 1).min(w - 1) {
+                    let neighbour = ny * w + nx;
+                    if mask[neighbour] && !visited[neighbour] {
+                        visited[neighbour] = true;
+                        queue.push_back(neighbour);
+                    }
+                }
+            }
+        }
+
+        let (region_width, region_height) = ((max_x - min_x + 1) as u32, (max_y - min_y + 1) as u32);
+        regions.push(Region {
+            x: min_x as u32,
+            y: min_y as u32,
+            width: region_width,
+            height: region_height,
+            changed_pixels,
+            change_percentage: percentage(changed_pixels, region_width as u64 * region_height as u64),
+        });
+    }
+    Ok(regions)
+}
+
+fn percentage(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn groups_changes_into_regions() {
+        let before = RgbaImage::from_pixel(10, 8, Rgba([100, 100, 100, 255]));
+        let mut after = before.clone();
+        // A diagonal pair, joined by 8-connectivity, and a lone pixel.
+        after.put_pixel(1, 1, Rgba([200, 100, 100, 255]));
+        after.put_pixel(2, 2, Rgba([100, 200, 100, 255]));
+        after.put_pixel(8, 6, Rgba([100, 100, 0, 255]));
+        // Below the threshold, so unchanged.
+        after.put_pixel(5, 5, Rgba([105, 100, 100, 255]));
+
+        let (before, after) = (DynamicImage::ImageRgba8(before), DynamicImage::ImageRgba8(after));
+        let (output, report) = diff_images(&before, &after, 10, DiffStyle::Highlight, Hooks::NONE).unwrap();
+        assert_eq!(report.changed_pixels, 3);
+        assert_eq!(report.change_percentage, 3.0 * 100.0 / 80.0);
+        let boxes: Vec<_> = report.regions.iter().map(|r| (r.x, r.y, r.width, r.height, r.changed_pixels)).collect();
+        assert_eq!(boxes, [(1, 1, 2, 2, 2), (8, 6, 1, 1, 1)]);
+        assert_eq!(report.regions[0].change_percentage, 50.0);
+        assert_eq!(output[(10 + 1) * 4..(10 + 1) * 4 + 4], HIGHLIGHT);
+        assert_eq!(output[..4], [33, 33, 33, 255]);
+    }
+
+    #[test]
+    fn identical_images_have_no_regions() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, Rgba([9, 8, 7, 255])));
+        let (_, report) = diff_images(&image, &image, 0, DiffStyle::Heatmap, Hooks::NONE).unwrap();
+        assert_eq!((report.changed_pixels, report.regions.len()), (0, 0));
+    }
+}
@@ -0,0 +1,50 @@
+//! Displacement maps: a layer sampled through offsets an image's red and
+//! green channels give, as through glass, water or folds of cloth.
+
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GenericImageView, RgbImage, RgbaImage};
+
+/// A map of offsets and how far it reaches.
+#[derive(Debug, Clone)]
+pub struct Displacement {
+    map: RgbImage,
+    /// The offset in pixels between the channels' ends, so that 0 moves a
+    /// pixel `amount / 2` one way, 255 the other, and 128 barely at all.
+    amount: f32,
+}
+
+impl Displacement {
+    pub fn new(map: &DynamicImage, amount: f32) -> Self {
+        Displacement { map: map.to_rgb8(), amount }
+    }
+
+    /// `image` with each pixel taken from where the map, stretched over it,
+    /// sends it, as SVG's `feDisplacementMap` does: right by red and down by
+    /// green, from between the four nearest pixels and clamped to the edges.
+    pub fn apply(&self, image: &DynamicImage) -> DynamicImage {
+        let (width, height) = image.dimensions();
+        let source = image.to_rgba8();
+        let map = if self.map.dimensions() == (width, height) {
+            self.map.clone()
+        } else {
+            imageops::resize(&self.map, width, height, FilterType::Triangle)
+        };
+        let offset = |value: u8| self.amount * (f32::from(value) / 255.0 - 0.5);
+        let (max_x, max_y) = ((width - 1) as f32, (height - 1) as f32);
+        let displaced = RgbaImage::from_fn(width, height, |x, y| {
+            let [red, green, _] = map.get_pixel(x, y).0;
+            let fx = (x as f32 + offset(red)).clamp(0.0, max_x);
+            let fy = (y as f32 + offset(green)).clamp(0.0, max_y);
+            let (x0, y0) = (fx.floor() as u32, fy.floor() as u32);
+            let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+            let (tx, ty) = (fx - x0 as f32, fy - y0 as f32);
+            let at = |x: u32, y: u32, channel: usize| f32::from(source.get_pixel(x, y).0[channel]);
+            image::Rgba(std::array::from_fn(|channel| {
+                let top = at(x0, y0, channel) * (1.0 - tx) + at(x1, y0, channel) * tx;
+                let bottom = at(x0, y1, channel) * (1.0 - tx) + at(x1, y1, channel) * tx;
+                (top * (1.0 - ty) + bottom * ty).round() as u8
+            }))
+        });
+        DynamicImage::ImageRgba8(displaced)
+    }
+}
@@ -0,0 +1,53 @@
+//! Deep Zoom (DZI) tile pyramids, which OpenSeadragon and similar viewers
+//! stream a tile at a time, so composites too large to open whole can be
+//! panned and zoomed in a browser.
+
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+
+/// The side of every tile, before overlap, as most viewers expect.
+pub const TILE_SIZE: u32 = 256;
+/// How many pixels each tile repeats of its neighbours, hiding seams when
+/// viewers scale tiles independently.
+pub const OVERLAP: u32 = 1;
+
+/// The `.dzi` descriptor of a `width` by `height` pyramid whose tiles have
+/// the file extension `format`.
+pub fn descriptor(width: u32, height: u32, format: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <Image xmlns=\"http://schemas.microsoft.com/deepzoom/2008\" Format=\"{}\" Overlap=\"{}\" TileSize=\"{}\">\n  \
+         <Size Width=\"{}\" Height=\"{}\"/>\n\
+         </Image>\n",
+        format, OVERLAP, TILE_SIZE, width, height
+    )
+}
+
+/// How many levels the pyramid of an image of that size has: level 0 is a
+/// single pixel and each level doubles the last, up to the full size.
+pub fn level_count(width: u32, height: u32) -> u32 {
+    u32::BITS - (width.max(height).max(1) - 1).leading_zeros() + 1
+}
+
+/// Calls `tile` with the level, column, row and pixels of every tile of the
+/// pyramid of `image`, from the full-size level down to level 0. Each level
+/// is halved from the one above, rounding up, as the format requires.
+pub fn tiles<E>(image: RgbaImage, mut tile: impl FnMut(u32, u32, u32, RgbaImage) -> Result<(), E>) -> Result<(), E> {
+    let mut level_image = image;
+    for level in (0..level_count(level_image.width(), level_image.height())).rev() {
+        let (width, height) = level_image.dimensions();
+        for row in 0..height.div_ceil(TILE_SIZE) {
+            for column in 0..width.div_ceil(TILE_SIZE) {
+                let left = (column * TILE_SIZE).saturating_sub(OVERLAP);
+                let top = (row * TILE_SIZE).saturating_sub(OVERLAP);
+                let right = ((column + 1) * TILE_SIZE + OVERLAP).min(width);
+                let bottom = ((row + 1) * TILE_SIZE + OVERLAP).min(height);
+                tile(level, column, row, imageops::crop_imm(&level_image, left, top, right - left, bottom - top).to_image())?;
+            }
+        }
+        if level > 0 {
+            level_image = imageops::resize(&level_image, width.div_ceil(2), height.div_ceil(2), FilterType::Triangle);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,242 @@
+//! The capture time and place an image's Exif metadata records, read from
+//! the bytes of a JPEG, PNG, WebP or TIFF file.
+
+use std::fmt;
+
+/// The Exif tags read, from IFD0 and the Exif and GPS IFDs it points to.
+const DATE_TIME: u16 = 0x0132;
+const EXIF_IFD: u16 = 0x8769;
+const GPS_IFD: u16 = 0x8825;
+const DATE_TIME_ORIGINAL: u16 = 0x9003;
+const DATE_TIME_DIGITIZED: u16 = 0x9004;
+const GPS_LATITUDE_REF: u16 = 1;
+const GPS_LATITUDE: u16 = 2;
+const GPS_LONGITUDE_REF: u16 = 3;
+const GPS_LONGITUDE: u16 = 4;
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// What an image's Exif metadata says about when and where it was taken.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Exif {
+    /// When the picture was taken, or failing that digitised or last saved.
+    pub taken: Option<DateTime>,
+    /// Latitude and longitude in degrees, negative to the south and west.
+    pub gps: Option<(f64, f64)>,
+}
+
+/// A time as Exif records it, in the camera's local time without a zone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Parses Exif's `YYYY:MM:DD HH:MM:SS`. Cameras without a set clock
+    /// write zeroes or spaces, which give `None`.
+    fn parse(value: &str) -> Option<Self> {
+        let parts: Vec<u16> = value.trim_end_matches('\0').trim().split([':', ' ']).map(|part| part.parse().ok()).collect::<Option<_>>()?;
+        let [year, month, day, hour, minute, second] = parts[..] else {
+            return None;
+        };
+        let part = |value: u16| u8::try_from(value).ok();
+        let taken = DateTime { year, month: part(month)?, day: part(day)?, hour: part(hour)?, minute: part(minute)?, second: part(second)? };
+        let valid = year > 0 && (1..=12).contains(&month) && (1..=31).contains(&day) && hour < 24 && minute < 60;
+        valid.then_some(taken)
+    }
+
+    /// The time written out by `pattern`, where `%Y`, `%y`, `%m`, `%d`,
+    /// `%e`, `%b`, `%H`, `%M` and `%S` stand for its parts as `strftime` has
+    /// them and `%%` for a percent sign. Anything else is kept as it is.
+    pub fn format(&self, pattern: &str) -> String {
+        let mut formatted = String::new();
+        let mut characters = pattern.chars();
+        while let Some(character) = characters.next() {
+            if character != '%' {
+                formatted.push(character);
+                continue;
+            }
+            match characters.next() {
+                Some('Y') => formatted.push_str(&format!("{:04}", self.year)),
+                Some('y') => formatted.push_str(&format!("{:02}", self.year % 100)),
+                Some('m') => formatted.push_str(&format!("{:02}", self.month)),
+                Some('d') => formatted.push_str(&format!("{:02}", self.day)),
+                Some('e') => formatted.push_str(&format!("{:2}", self.day)),
+                Some('b') => formatted.push_str(MONTHS[usize::from(self.month - 1)]),
+                Some('H') => formatted.push_str(&format!("{:02}", self.hour)),
+                Some('M') => formatted.push_str(&format!("{:02}", self.minute)),
+                Some('S') => formatted.push_str(&format!("{:02}", self.second)),
+                Some('%') => formatted.push('%'),
+                Some(other) => {
+                    formatted.push('%');
+                    formatted.push(other);
+                }
+                None => formatted.push('%'),
+            }
+        }
+        formatted
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.format("%Y-%m-%d %H:%M:%S"))
+    }
+}
+
+/// The Exif metadata of the image file in `bytes`, or `None` when it has
+/// none or it cannot be read.
+pub fn read(bytes: &[u8]) -> Option<Exif> {
+    let tiff = find_tiff(bytes)?;
+    let tiff = Tiff::new(tiff)?;
+    let ifd0 = tiff.ifd(tiff.u32(4)? as usize)?;
+    let exif_ifd = ifd0.iter().find(|entry| entry.tag == EXIF_IFD).and_then(|entry| tiff.ifd(entry.offset as usize));
+    let gps_ifd = ifd0.iter().find(|entry| entry.tag == GPS_IFD).and_then(|entry| tiff.ifd(entry.offset as usize));
+
+    let date = |ifd: &[Entry], tag: u16| ifd.iter().find(|entry| entry.tag == tag).and_then(|entry| DateTime::parse(&tiff.ascii(entry)?));
+    let exif_ifd = exif_ifd.unwrap_or_default();
+    let taken = date(&exif_ifd, DATE_TIME_ORIGINAL).or_else(|| date(&exif_ifd, DATE_TIME_DIGITIZED)).or_else(|| date(&ifd0, DATE_TIME));
+
+    let gps = gps_ifd.and_then(|ifd| {
+        let find = |tag: u16| ifd.iter().find(|entry| entry.tag == tag);
+        let coordinate = |value: u16, reference: u16, negative: &str| {
+            let [degrees, minutes, seconds] = tiff.rationals::<3>(find(value)?)?;
+            let sign = if tiff.ascii(find(reference)?)?.starts_with(negative) { -1.0 } else { 1.0 };
+            Some(sign * (degrees + minutes / 60.0 + seconds / 3600.0))
+        };
+        Some((coordinate(GPS_LATITUDE, GPS_LATITUDE_REF, "S")?, coordinate(GPS_LONGITUDE, GPS_LONGITUDE_REF, "W")?))
+    });
+    Some(Exif { taken, gps })
+}
+
+/// The TIFF structure holding the Exif metadata of the file in `bytes`: the
+/// JPEG APP1 segment, the PNG `eXIf` chunk, the WebP `EXIF` chunk, or a
+/// TIFF file itself.
+fn find_tiff(bytes: &[u8]) -> Option<&[u8]> {
+    // PNG and WebP files sometimes keep JPEG's header in front of the TIFF.
+    fn strip(payload: &[u8]) -> &[u8] {
+        payload.strip_prefix(b"Exif\0\0").unwrap_or(payload)
+    }
+    if bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*") {
+        return Some(bytes);
+    }
+    if bytes.starts_with(&[0xFF, 0xD8]) {
+        let mut at = 2;
+        while at + 4 <= bytes.len() && bytes[at] == 0xFF {
+            let marker = bytes[at + 1];
+            if marker == 0xDA || marker == 0xD9 {
+                break;
+            }
+            let length = usize::from(u16::from_be_bytes([bytes[at + 2], bytes[at + 3]]));
+            let payload = bytes.get(at + 4..at + 2 + length)?;
+            if marker == 0xE1 && payload.starts_with(b"Exif\0\0") {
+                return Some(strip(payload));
+            }
+            at += 2 + length;
+        }
+        return None;
+    }
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        let mut at = 8;
+        while let (Some(length), Some(kind)) = (bytes.get(at..at + 4), bytes.get(at + 4..at + 8)) {
+            let length = u32::from_be_bytes(length.try_into().ok()?) as usize;
+            match kind {
+                b"eXIf" => return bytes.get(at + 8..at + 8 + length).map(strip),
+                b"IEND" => break,
+                _ => {}
+            }
+            at += 12 + length;
+        }
+        return None;
+    }
+    if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        let mut at = 12;
+        while let (Some(kind), Some(length)) = (bytes.get(at..at + 4), bytes.get(at + 4..at + 8)) {
+            let length = u32::from_le_bytes(length.try_into().ok()?) as usize;
+            if kind == b"EXIF" {
+                return bytes.get(at + 8..at + 8 + length).map(strip);
+            }
+            at += 8 + length + (length & 1);
+        }
+    }
+    None
+}
+
+/// One IFD entry: its tag, type, count of values, and the offset they sit
+/// at, or the values themselves when they fit in four bytes.
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    offset: u32,
+    /// Where the entry's last four bytes are, for values held inline.
+    at: usize,
+}
+
+struct Tiff<'a> {
+    bytes: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(bytes: &'a [u8]) -> Option<Self> {
+        let little_endian = match bytes.get(..2)? {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+        Some(Tiff { bytes, little_endian })
+    }
+
+    fn u16(&self, at: usize) -> Option<u16> {
+        let bytes = self.bytes.get(at..at + 2)?.try_into().ok()?;
+        Some(if self.little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    }
+
+    fn u32(&self, at: usize) -> Option<u32> {
+        let bytes = self.bytes.get(at..at + 4)?.try_into().ok()?;
+        Some(if self.little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    }
+
+    fn ifd(&self, at: usize) -> Option<Vec<Entry>> {
+        let count = usize::from(self.u16(at)?);
+        (0..count)
+            .map(|index| {
+                let at = at + 2 + index * 12;
+                Some(Entry { tag: self.u16(at)?, kind: self.u16(at + 2)?, count: self.u32(at + 4)?, offset: self.u32(at + 8)?, at: at + 8 })
+            })
+            .collect()
+    }
+
+    /// The bytes of an entry with `size` bytes per value.
+    fn values(&self, entry: &Entry, size: usize) -> Option<&'a [u8]> {
+        let length = size.checked_mul(entry.count as usize)?;
+        let at = if length <= 4 { entry.at } else { entry.offset as usize };
+        self.bytes.get(at..at.checked_add(length)?)
+    }
+
+    fn ascii(&self, entry: &Entry) -> Option<String> {
+        const ASCII: u16 = 2;
+        (entry.kind == ASCII).then_some(())?;
+        Some(String::from_utf8_lossy(self.values(entry, 1)?).trim_end_matches('\0').to_string())
+    }
+
+    fn rationals<const N: usize>(&self, entry: &Entry) -> Option<[f64; N]> {
+        const RATIONAL: u16 = 5;
+        (entry.kind == RATIONAL && entry.count as usize >= N).then_some(())?;
+        let at = entry.offset as usize;
+        let values: Option<Vec<f64>> = (0..N)
+            .map(|index| {
+                let (numerator, denominator) = (self.u32(at + index * 8)?, self.u32(at + index * 8 + 4)?);
+                (denominator != 0).then(|| f64::from(numerator) / f64::from(denominator))
+            })
+            .collect();
+        values?.try_into().ok()
+    }
+}
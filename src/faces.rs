@@ -0,0 +1,122 @@
+//! Faces, found by an UltraFace-style ONNX detector behind the `ml`
+//! feature, so that smart crops keep them and smart placement leaves them
+//! uncovered.
+
+use image::{imageops, DynamicImage, RgbaImage};
+
+use crate::fit::edge_energy;
+use crate::ImageDataErrors;
+
+/// How sure the detector must be of a face.
+#[cfg(feature = "ml")]
+const CONFIDENCE: f32 = 0.7;
+/// How much two detections may overlap, as intersection over union, before
+/// the less confident is dropped as the same face.
+#[cfg(feature = "ml")]
+const OVERLAP: f32 = 0.3;
+
+/// The box around a face, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Face {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Face {
+    /// How many pixels of the face lie within `width`x`height` at `(x, y)`.
+    pub fn overlap(&self, (x, y): (u32, u32), (width, height): (u32, u32)) -> u64 {
+        let across = (self.x + self.width).min(x + width).saturating_sub(self.x.max(x));
+        let down = (self.y + self.height).min(y + height).saturating_sub(self.y.max(y));
+        u64::from(across) * u64::from(down)
+    }
+}
+
+/// The faces in `image`, found by the detector at `model`, or none without one.
+/// The detector takes RGB scaled to -1..1, channels first, at its own size or
+/// 320x240, and gives a score for each of its anchors, background then face,
+/// and a box of corners from 0 to 1.
+pub(crate) fn detect(model: Option<&str>, image: &DynamicImage) -> Result<Vec<Face>, ImageDataErrors> {
+    let Some(model) = model else {
+        return Ok(Vec::new());
+    };
+    #[cfg(feature = "ml")]
+    {
+        let faces = crate::timed("finding faces", || find(model, image))?;
+        log::info!("found {} faces", faces.len());
+        Ok(faces)
+    }
+    #[cfg(not(feature = "ml"))]
+    {
+        let _ = (model, image);
+        Err(ImageDataErrors::InvalidArgument("`--face-model` needs combiner built with the `ml` feature".to_string()))
+    }
+}
+
+#[cfg(feature = "ml")]
+fn find(model: &str, image: &DynamicImage) -> Result<Vec<Face>, ImageDataErrors> {
+    use image::GenericImageView;
+
+    let model = crate::onnx::Model::load(model)?;
+    let size = match model.input_shape()?[..] {
+        [_, 3, height, width] if height > 0 && width > 0 => (width as u32, height as u32),
+        _ => (320, 240),
+    };
+    let outputs = model.run_image(image, size)?;
+    let output = |columns: i64| {
+        outputs
+            .iter()
+            .find(|output| output.shape.last() == Some(&columns))
+            .ok_or_else(|| ImageDataErrors::UnableToRunModel(format!("expected an output of {} columns for each anchor", columns)))
+    };
+    let (scores, boxes) = (output(2)?, output(4)?);
+    let (width, height) = image.dimensions();
+    let mut found: Vec<(f32, [f32; 4])> = scores
+        .data
+        .chunks_exact(2)
+        .zip(boxes.data.chunks_exact(4))
+        .filter(|(score, _)| score[1] >= CONFIDENCE)
+        .map(|(score, corners)| (score[1], [corners[0], corners[1], corners[2], corners[3]]))
+        .collect();
+    found.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let iou = |a: &[f32; 4], b: &[f32; 4]| {
+        let area = |[left, top, right, bottom]: [f32; 4]| (right - left).max(0.0) * (bottom - top).max(0.0);
+        let shared = area([a[0].max(b[0]), a[1].max(b[1]), a[2].min(b[2]), a[3].min(b[3])]);
+        shared / (area(*a) + area(*b) - shared).max(f32::EPSILON)
+    };
+    let mut kept: Vec<[f32; 4]> = Vec::new();
+    for (_, corners) in found {
+        if kept.iter().all(|other| iou(other, &corners) <= OVERLAP) {
+            kept.push(corners);
+        }
+    }
+    Ok(kept
+        .into_iter()
+        .filter_map(|[left, top, right, bottom]| {
+            let x = |value: f32| (value.clamp(0.0, 1.0) * width as f32).round() as u32;
+            let y = |value: f32| (value.clamp(0.0, 1.0) * height as f32).round() as u32;
+            let face = Face { x: x(left), y: y(top), width: x(right).saturating_sub(x(left)), height: y(bottom).saturating_sub(y(top)) };
+            (face.width > 0 && face.height > 0).then_some(face)
+        })
+        .collect())
+}
+
+/// The top-left corner for a `width`x`height` overlay, `margin` pixels in
+/// from whichever corner of `image` has it covering the least of `faces`,
+/// then the least detail. `None` when it does not fit.
+pub(crate) fn quietest_corner(image: &RgbaImage, faces: &[Face], (width, height): (u32, u32), margin: u32) -> Option<(u32, u32)> {
+    let (image_width, image_height) = image.dimensions();
+    let right = image_width.checked_sub(width + margin)?;
+    let bottom = image_height.checked_sub(height + margin)?;
+    if right < margin || bottom < margin {
+        return None;
+    }
+    let energy = edge_energy(&imageops::grayscale(image));
+    let detail = |(x, y): (u32, u32)| -> u64 {
+        (y..y + height).map(|row| energy[(row * image_width + x) as usize..(row * image_width + x + width) as usize].iter().map(|&e| u64::from(e)).sum::<u64>()).sum()
+    };
+    [(right, bottom), (margin, bottom), (right, margin), (margin, margin)]
+        .into_iter()
+        .min_by_key(|&corner| (faces.iter().map(|face| face.overlap(corner, (width, height))).sum::<u64>(), detail(corner)))
+}
@@ -0,0 +1,153 @@
+use image::{imageops, DynamicImage, GenericImageView, GrayImage, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::faces::Face;
+use crate::gpu::{resize_exact, Backend};
+use crate::hooks::Hooks;
+use crate::seam;
+use crate::ImageDataErrors;
+
+/// How the larger input is brought to the size of the smaller one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Fit {
+    /// Resize to exactly that size, distorting the aspect ratio if it differs.
+    #[default]
+    Stretch,
+    /// Scale to fit inside that size, leaving transparent bars around it.
+    Contain,
+    /// Scale to cover that size, then crop away what overflows.
+    Crop,
+    /// Scale to cover that size, then remove the least detailed seams of
+    /// pixels from what overflows, keeping subjects in proportion.
+    SeamCarve,
+}
+
+impl Fit {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        match value {
+            "stretch" => Ok(Fit::Stretch),
+            "contain" => Ok(Fit::Contain),
+            "crop" => Ok(Fit::Crop),
+            "seam-carve" => Ok(Fit::SeamCarve),
+            _ => Err(ImageDataErrors::InvalidArgument(format!("unknown fit `{}`", value))),
+        }
+    }
+}
+
+/// Brings `image` to exactly `width`x`height` the way `fit` says. Crops keep
+/// the centre, or with `smart_crop` the window with the most of `faces`,
+/// found in `image`, then the most edge energy. Resizes run on `backend`.
+pub(crate) fn fit_to(image: DynamicImage, size: (u32, u32), fit: Fit, smart_crop: bool, faces: &[Face], backend: Backend) -> DynamicImage {
+    fit_to_with_hooks(image, size, fit, smart_crop, faces, backend, Hooks::NONE).expect("only a cancel token stops a resize")
+}
+
+/// [`fit_to`], reporting the resize to `hooks` and stopping it when they
+/// say the run was cancelled.
+pub(crate) fn fit_to_with_hooks(
+    image: DynamicImage,
+    (width, height): (u32, u32),
+    fit: Fit,
+    smart_crop: bool,
+    faces: &[Face],
+    backend: Backend,
+    hooks: Hooks,
+) -> Result<DynamicImage, ImageDataErrors> {
+    let (image_width, image_height) = image.dimensions();
+    match fit {
+        Fit::Stretch => return resize_exact(image, width, height, backend, hooks),
+        Fit::Contain => return contain(image, width, height, backend, hooks),
+        Fit::Crop | Fit::SeamCarve => {}
+    }
+    let scale = (width as f64 / image_width as f64).max(height as f64 / image_height as f64);
+    let scaled_width = ((image_width as f64 * scale).round() as u32).max(width);
+    let scaled_height = ((image_height as f64 * scale).round() as u32).max(height);
+    let scaled = resize_exact(image, scaled_width, scaled_height, backend, hooks)?;
+
+    if fit == Fit::SeamCarve {
+        log::debug!("carving {}x{} down to {}x{}", scaled_width, scaled_height, width, height);
+        return Ok(seam::carve(scaled, width, height));
+    }
+    let (x, y) = if smart_crop {
+        let scale = |value: u32, size: u32, scaled: u32| (u64::from(value) * u64::from(scaled) / u64::from(size)) as u32;
+        let faces: Vec<Face> = faces
+            .iter()
+            .map(|face| Face {
+                x: scale(face.x, image_width, scaled_width),
+                y: scale(face.y, image_height, scaled_height),
+                width: scale(face.width, image_width, scaled_width),
+                height: scale(face.height, image_height, scaled_height),
+            })
+            .collect();
+        salient_window(&scaled.to_luma8(), &faces, width, height)
+    } else {
+        ((scaled_width - width) / 2, (scaled_height - height) / 2)
+    };
+    log::debug!("cropping {}x{} at {},{} out of {}x{}", width, height, x, y, scaled_width, scaled_height);
+    Ok(scaled.crop_imm(x, y, width, height))
+}
+
+/// Scales `image` to fit inside `width`x`height` and centres it on a
+/// transparent canvas of exactly that size.
+fn contain(image: DynamicImage, width: u32, height: u32, backend: Backend, hooks: Hooks) -> Result<DynamicImage, ImageDataErrors> {
+    let (image_width, image_height) = image.dimensions();
+    let scale = (width as f64 / image_width as f64).min(height as f64 / image_height as f64);
+    let scaled_width = ((image_width as f64 * scale).round() as u32).clamp(1, width);
+    let scaled_height = ((image_height as f64 * scale).round() as u32).clamp(1, height);
+    let scaled = resize_exact(image, scaled_width, scaled_height, backend, hooks)?.to_rgba8();
+
+    let mut canvas = RgbaImage::new(width, height);
+    imageops::replace(&mut canvas, &scaled, (width - scaled_width) / 2, (height - scaled_height) / 2);
+    Ok(DynamicImage::ImageRgba8(canvas))
+}
+
+/// The top-left corner of the `width`x`height` window of `luma` holding the
+/// most of `faces`, then the most edge energy. Covering scales leave at most
+/// one axis to slide along.
+fn salient_window(luma: &GrayImage, faces: &[Face], width: u32, height: u32) -> (u32, u32) {
+    let energy = edge_energy(luma);
+    let (luma_width, luma_height) = luma.dimensions();
+    let horizontal = luma_width > width;
+    let lines = if horizontal { luma_width } else { luma_height };
+    let window = if horizontal { width } else { height } as usize;
+
+    // Face pixels and edge energy along each line, compared in that order.
+    let mut totals = vec![(0u64, 0u64); lines as usize];
+    for (i, energy) in energy.iter().enumerate() {
+        let (x, y) = (i as u32 % luma_width, i as u32 / luma_width);
+        totals[if horizontal { x } else { y } as usize].1 += *energy as u64;
+    }
+    for face in faces {
+        for (line, total) in totals.iter_mut().enumerate() {
+            let line = line as u32;
+            total.0 += if horizontal { face.overlap((line, 0), (1, luma_height)) } else { face.overlap((0, line), (luma_width, 1)) };
+        }
+    }
+    let add = |a: (u64, u64), b: (u64, u64)| (a.0 + b.0, a.1 + b.1);
+    let mut sum = totals[..window].iter().fold((0, 0), |sum, &total| add(sum, total));
+    let (mut best, mut best_sum) = (0, sum);
+    for start in 1..=totals.len() - window {
+        let (enter, leave) = (totals[start + window - 1], totals[start - 1]);
+        sum = (sum.0 + enter.0 - leave.0, sum.1 + enter.1 - leave.1);
+        if sum > best_sum {
+            (best, best_sum) = (start, sum);
+        }
+    }
+    if horizontal { (best as u32, 0) } else { (0, best as u32) }
+}
+
+/// The gradient magnitude of every pixel, as the sum of the absolute
+/// horizontal and vertical differences to its neighbours.
+pub(crate) fn edge_energy(luma: &GrayImage) -> Vec<u32> {
+    let (width, height) = luma.dimensions();
+    let at = |x: u32, y: u32| luma.get_pixel(x, y).0[0] as i32;
+    let mut energy = Vec::with_capacity(width as usize * height as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let dx = at((x + 1).min(width - 1), y) - at(x.saturating_sub(1), y);
+            let dy = at(x, (y + 1).min(height - 1)) - at(x, y.saturating_sub(1));
+            energy.push(dx.unsigned_abs() + dy.unsigned_abs());
+        }
+    }
+    energy
+}
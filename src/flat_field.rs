@@ -0,0 +1,88 @@
+//! Flat-field correction for microscope and telescope frames: dividing out
+//! the vignetting and dust a flat frame records, after taking away the
+//! sensor's own signal a dark frame records, at 16 bits throughout.
+
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::codecs::tiff::TiffEncoder;
+use image::{ColorType, DynamicImage, GenericImageView, ImageFormat};
+
+use crate::ImageDataErrors;
+
+/// A corrected frame, gray when the light frame is.
+pub struct Corrected {
+    pub width: u32,
+    pub height: u32,
+    pub color: ColorType,
+    pub samples: Vec<u16>,
+}
+
+/// `light` with `dark` subtracted, then divided by `flat` normalised to a
+/// mean of 1 in each channel, so the frame keeps its brightness while its
+/// corners and dust shadows are lifted to match its centre. All three
+/// frames must come from the same sensor at the same size.
+pub fn correct(light: &DynamicImage, flat: &DynamicImage, dark: Option<&DynamicImage>) -> Result<Corrected, ImageDataErrors> {
+    let (width, height) = light.dimensions();
+    for (name, frame) in [("flat", Some(flat)), ("dark", dark)] {
+        if let Some(frame) = frame.filter(|frame| frame.dimensions() != (width, height)) {
+            let (frame_width, frame_height) = frame.dimensions();
+            return Err(ImageDataErrors::InvalidArgument(format!(
+                "the {} frame is {}x{} but the light frame {}x{}; calibration frames must match it",
+                name, frame_width, frame_height, width, height
+            )));
+        }
+    }
+    let (color, channels) = if light.color().has_color() { (ColorType::Rgb16, 3) } else { (ColorType::L16, 1) };
+    let read = |frame: &DynamicImage| -> Vec<f32> {
+        match channels {
+            3 => frame.to_rgb16().into_raw().into_iter().map(f32::from).collect(),
+            _ => frame.to_luma16().into_raw().into_iter().map(f32::from).collect(),
+        }
+    };
+    let mut values = read(light);
+    if let Some(dark) = dark {
+        for (value, dark) in values.iter_mut().zip(read(dark)) {
+            *value = (*value - dark).max(0.0);
+        }
+    }
+    let flat = read(flat);
+    let mut means = [0.0f64; 3];
+    for (index, &value) in flat.iter().enumerate() {
+        means[index % channels] += f64::from(value);
+    }
+    let pixels = f64::from(width) * f64::from(height);
+    let means = means.map(|sum| (sum / pixels.max(1.0)) as f32);
+    log::info!("flat frame means {:?}", &means[..channels]);
+
+    let samples = values
+        .iter()
+        .zip(&flat)
+        .enumerate()
+        .map(|(index, (&value, &flat))| {
+            // Pixels the flat records as black have nothing to divide by.
+            let gain = if flat > 0.0 { means[index % channels] / flat } else { 1.0 };
+            (value * gain).round().clamp(0.0, 65535.0) as u16
+        })
+        .collect();
+    Ok(Corrected { width, height, color, samples })
+}
+
+impl Corrected {
+    /// The frame encoded as a 16-bit PNG or TIFF.
+    pub fn encode(&self, format: ImageFormat) -> Result<Vec<u8>, ImageDataErrors> {
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        let written = match format {
+            // PNG stores samples big-endian, TIFF as the encoder's machine does.
+            ImageFormat::Png => {
+                let data: Vec<u8> = self.samples.iter().flat_map(|sample| sample.to_be_bytes()).collect();
+                PngEncoder::new_with_quality(&mut bytes, CompressionType::Fast, FilterType::Sub).encode(&data, self.width, self.height, self.color)
+            }
+            ImageFormat::Tiff => {
+                let data: Vec<u8> = self.samples.iter().flat_map(|sample| sample.to_ne_bytes()).collect();
+                TiffEncoder::new(&mut bytes).encode(&data, self.width, self.height, self.color)
+            }
+            _ => return Err(ImageDataErrors::InvalidArgument("flat-field outputs are 16-bit, so must be PNG or TIFF".to_string())),
+        };
+        written.map_err(ImageDataErrors::UnableToSaveImage)?;
+        Ok(bytes.into_inner())
+    }
+}
@@ -0,0 +1,154 @@
+use image::{imageops, GrayImage, Luma, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::canvas::blend_over;
+use crate::ImageDataErrors;
+
+/// The built-in device mockups an output can be framed in. Frames are drawn
+/// around the screenshot at its own size, so they fit any aspect ratio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Device {
+    Iphone,
+    Ipad,
+    Browser,
+}
+
+impl Device {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        match value {
+            "iphone" | "phone" => Ok(Device::Iphone),
+            "ipad" | "tablet" => Ok(Device::Ipad),
+            "browser" => Ok(Device::Browser),
+            _ => Err(ImageDataErrors::InvalidArgument(format!("unknown device `{}`", value))),
+        }
+    }
+
+    fn style(self, screen_width: f64) -> FrameStyle {
+        match self {
+            Device::Iphone => FrameStyle {
+                bezel: [0.045 * screen_width; 4],
+                screen_radius: 0.11 * screen_width,
+                body_radius: 0.155 * screen_width,
+                colour: [28, 28, 30],
+            },
+            Device::Ipad => FrameStyle {
+                bezel: [0.06 * screen_width; 4],
+                screen_radius: 0.025 * screen_width,
+                body_radius: 0.085 * screen_width,
+                colour: [44, 44, 46],
+            },
+            Device::Browser => {
+                let title_bar = (0.05 * screen_width).max(28.0);
+                FrameStyle { bezel: [title_bar, 1.0, 1.0, 1.0], screen_radius: 0.0, body_radius: 0.012 * screen_width, colour: [232, 232, 232] }
+            }
+        }
+    }
+}
+
+struct FrameStyle {
+    /// The body's width around the screen: top, right, bottom and left.
+    bezel: [f64; 4],
+    screen_radius: f64,
+    body_radius: f64,
+    colour: [u8; 3],
+}
+
+/// Draws the `device` around `screen` with a soft drop shadow below it, on a
+/// transparent canvas. The screen's corners are masked to the device's.
+pub fn frame(screen: &RgbaImage, device: Device) -> RgbaImage {
+    let (screen_width, screen_height) = (screen.width() as f64, screen.height() as f64);
+    let style = device.style(screen_width);
+    let [top, right, bottom, left] = style.bezel;
+    let (body_width, body_height) = (screen_width + left + right, screen_height + top + bottom);
+    let margin = (0.08 * body_width.max(body_height)).ceil();
+    let width = (body_width + 2.0 * margin).ceil() as u32;
+    let height = (body_height + 2.0 * margin).ceil() as u32;
+    log::debug!("framing {}x{} as {:?} on {}x{}", screen.width(), screen.height(), device, width, height);
+
+    let body = Rect { x: margin, y: margin, width: body_width, height: body_height, radius: style.body_radius };
+    let screen_rect = Rect { x: margin + left, y: margin + top, width: screen_width, height: screen_height, radius: style.screen_radius };
+
+    let mut output = shadow(&body, width, height, margin);
+    for (x, y, pixel) in output.enumerate_pixels_mut() {
+        let (centre_x, centre_y) = (x as f64 + 0.5, y as f64 + 0.5);
+        let body_coverage = body.coverage(centre_x, centre_y);
+        if body_coverage > 0.0 {
+            let [red, green, blue] = style.colour;
+            blend_over(&mut pixel.0, [red, green, blue, (body_coverage * 255.0).round() as u8]);
+        }
+        // The browser's square screen must not poke out of the window's round corners.
+        let screen_coverage = screen_rect.coverage(centre_x, centre_y).min(body_coverage);
+        if screen_coverage > 0.0 {
+            let source_x = (x as f64 - screen_rect.x).clamp(0.0, screen_width - 1.0) as u32;
+            let source_y = (y as f64 - screen_rect.y).clamp(0.0, screen_height - 1.0) as u32;
+            let mut source = screen.get_pixel(source_x, source_y).0;
+            source[3] = (source[3] as f64 * screen_coverage).round() as u8;
+            blend_over(&mut pixel.0, source);
+        }
+    }
+    if device == Device::Browser {
+        draw_window_buttons(&mut output, margin, margin, top);
+    }
+    output
+}
+
+/// A rounded rectangle.
+struct Rect {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+    radius: f64,
+}
+
+impl Rect {
+    /// How much of the pixel centred on a point lies inside, from 0.0 to 1.0,
+    /// so edges and corners are antialiased.
+    fn coverage(&self, x: f64, y: f64) -> f64 {
+        let radius = self.radius.min(self.width / 2.0).min(self.height / 2.0);
+        let dx = (x - (self.x + self.width / 2.0)).abs() - (self.width / 2.0 - radius);
+        let dy = (y - (self.y + self.height / 2.0)).abs() - (self.height / 2.0 - radius);
+        let outside = dx.max(0.0).hypot(dy.max(0.0)) + dx.max(dy).min(0.0) - radius;
+        (0.5 - outside).clamp(0.0, 1.0)
+    }
+}
+
+/// A blurred silhouette of `body`, dropped slightly below it. It is blurred
+/// at a quarter of the size, as the blur is the slowest part of framing.
+fn shadow(body: &Rect, width: u32, height: u32, margin: f64) -> RgbaImage {
+    const SCALE: f64 = 4.0;
+    let small_width = (width as f64 / SCALE).ceil() as u32;
+    let small_height = (height as f64 / SCALE).ceil() as u32;
+    let offset = margin / 3.0;
+    let mask = GrayImage::from_fn(small_width, small_height, |x, y| {
+        let coverage = body.coverage((x as f64 + 0.5) * SCALE, (y as f64 + 0.5) * SCALE - offset);
+        Luma([(coverage * 255.0).round() as u8])
+    });
+    let blurred = imageops::blur(&mask, (margin / SCALE / 2.5).max(0.5) as f32);
+    let mask = imageops::resize(&blurred, width, height, imageops::FilterType::Triangle);
+    RgbaImage::from_fn(width, height, |x, y| Rgba([0, 0, 0, (mask.get_pixel(x, y).0[0] as f64 * 0.35).round() as u8]))
+}
+
+/// The close, minimise and maximise buttons of a browser window's title bar.
+fn draw_window_buttons(output: &mut RgbaImage, left: f64, top: f64, bar: f64) {
+    const COLOURS: [[u8; 3]; 3] = [[255, 95, 86], [255, 189, 46], [39, 201, 63]];
+    let radius = bar * 0.18;
+    for (i, [red, green, blue]) in COLOURS.into_iter().enumerate() {
+        let button = Rect {
+            x: left + bar * (0.5 + 0.55 * i as f64) - radius,
+            y: top + bar / 2.0 - radius,
+            width: radius * 2.0,
+            height: radius * 2.0,
+            radius,
+        };
+        let (first_x, first_y) = (button.x.floor() as u32, button.y.floor() as u32);
+        let (last_x, last_y) = ((button.x + button.width).ceil() as u32, (button.y + button.height).ceil() as u32);
+        for y in first_y..last_y.min(output.height()) {
+            for x in first_x..last_x.min(output.width()) {
+                let coverage = button.coverage(x as f64 + 0.5, y as f64 + 0.5);
+                blend_over(&mut output.get_pixel_mut(x, y).0, [red, green, blue, (coverage * 255.0).round() as u8]);
+            }
+        }
+    }
+}
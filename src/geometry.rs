@@ -0,0 +1,332 @@
+use std::fmt;
+
+use image::{imageops::Triangle, DynamicImage, GenericImageView};
+use serde::{Deserialize, Serialize};
+
+use crate::ImageDataErrors;
+
+/// How a [`Geometry`] used for resizing treats the aspect ratio, from the
+/// flag following its size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeometryFlag {
+    /// `^`: fill the box, overflowing it in one dimension.
+    Fill,
+    /// `!`: take exactly the size given, ignoring the aspect ratio.
+    Exact,
+    /// `>`: only shrink images larger than the box.
+    ShrinkOnly,
+    /// `<`: only enlarge images smaller than the box.
+    EnlargeOnly,
+}
+
+/// A size and offset written the way ImageMagick does, such as `800x600^`,
+/// `50%`, `x600` or `800x600+10-20`, so scripts can be migrated as they are.
+/// It is kept in that form in option files.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Geometry {
+    pub width: Option<f64>,
+    pub height: Option<f64>,
+    /// Whether `width` and `height` are percentages of the image's size.
+    pub percent: bool,
+    pub flag: Option<GeometryFlag>,
+    pub offset: (i32, i32),
+}
+
+impl Geometry {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        let invalid = || ImageDataErrors::InvalidArgument(format!("invalid geometry `{}`", value));
+        let offset_at = value.find(['+', '-']).unwrap_or(value.len());
+        let (size, offset) = value.split_at(offset_at);
+
+        // Flags may follow either dimension, as in `50%x25%`.
+        let percent = size.contains('%');
+        let flag = size.chars().rev().find_map(|c| match c {
+            '^' => Some(GeometryFlag::Fill),
+            '!' => Some(GeometryFlag::Exact),
+            '>' => Some(GeometryFlag::ShrinkOnly),
+            '<' => Some(GeometryFlag::EnlargeOnly),
+            _ => None,
+        });
+        let size: String = size.chars().filter(|c| !"%^!<>".contains(*c)).collect();
+
+        let dimension = |digits: &str| match digits {
+            "" => Ok(None),
+            _ => digits.parse::<f64>().ok().filter(|n| *n > 0.0 && n.is_finite()).map(Some).ok_or_else(invalid),
+        };
+        let (width, height) = match size.split_once('x') {
+            Some((width, height)) => (dimension(width)?, dimension(height)?),
+            None => (dimension(&size)?, None),
+        };
+        if width.is_none() && height.is_none() {
+            return Err(invalid());
+        }
+        // Percentages without a height scale both dimensions alike.
+        let height = if percent && size.split_once('x').is_none() { width } else { height };
+
+        let offset = match offset {
+            "" => (0, 0),
+            _ => {
+                let split = offset[1..].find(['+', '-']).map(|at| at + 1).ok_or_else(invalid)?;
+                let (x, y) = offset.split_at(split);
+                (x.parse().map_err(|_| invalid())?, y.parse().map_err(|_| invalid())?)
+            }
+        };
+        Ok(Geometry { width, height, percent, flag, offset })
+    }
+
+    /// The width and height the geometry names for an image of `size`:
+    /// percentages are resolved against it and missing dimensions taken from it.
+    fn resolve(&self, (width, height): (u32, u32)) -> (f64, f64) {
+        let scale = |value: Option<f64>, of: u32| match value {
+            Some(value) if self.percent => of as f64 * value / 100.0,
+            Some(value) => value,
+            None => of as f64,
+        };
+        (scale(self.width, width), scale(self.height, height))
+    }
+
+    /// The size an image of `size` is resized to, with ImageMagick's
+    /// `-resize` semantics.
+    pub fn resized_size(&self, size: (u32, u32)) -> (u32, u32) {
+        let (width, height) = (size.0 as f64, size.1 as f64);
+        let (box_width, box_height) = self.resolve(size);
+        let (new_width, new_height) = if self.percent || self.flag == Some(GeometryFlag::Exact) {
+            (box_width, box_height)
+        } else {
+            let ratio = match (self.width, self.height) {
+                (Some(_), None) => box_width / width,
+                (None, Some(_)) => box_height / height,
+                _ if self.flag == Some(GeometryFlag::Fill) => (box_width / width).max(box_height / height),
+                _ => (box_width / width).min(box_height / height),
+            };
+            (width * ratio, height * ratio)
+        };
+        let larger = new_width > width || new_height > height;
+        let smaller = new_width < width || new_height < height;
+        match self.flag {
+            Some(GeometryFlag::ShrinkOnly) if !smaller => size,
+            Some(GeometryFlag::EnlargeOnly) if !larger => size,
+            _ => ((new_width.round() as u32).max(1), (new_height.round() as u32).max(1)),
+        }
+    }
+
+    /// The region `(x, y, width, height)` an image of `size` is cropped to,
+    /// with ImageMagick's `-crop` semantics: the offset is measured inwards
+    /// from the edge `gravity` names, and the region is clipped to the image.
+    pub fn crop_region(&self, size: (u32, u32), gravity: Gravity) -> Option<(u32, u32, u32, u32)> {
+        let (width, height) = self.resolve(size);
+        let (width, height) = (width.round() as i64, height.round() as i64);
+        let (horizontal, vertical) = gravity.anchor();
+        let place = |anchor: i8, outer: u32, inner: i64, offset: i32| match anchor {
+            -1 => offset as i64,
+            0 => (outer as i64 - inner) / 2 + offset as i64,
+            _ => outer as i64 - inner - offset as i64,
+        };
+        let x = place(horizontal, size.0, width, self.offset.0);
+        let y = place(vertical, size.1, height, self.offset.1);
+
+        let (left, top) = (x.max(0), y.max(0));
+        let (right, bottom) = ((x + width).min(size.0 as i64), (y + height).min(size.1 as i64));
+        (right > left && bottom > top).then(|| (left as u32, top as u32, (right - left) as u32, (bottom - top) as u32))
+    }
+}
+
+impl fmt::Display for Geometry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(width) = self.width {
+            write!(f, "{}", width)?;
+        }
+        if let Some(height) = self.height.filter(|_| !self.percent || self.height != self.width) {
+            write!(f, "x{}", height)?;
+        }
+        if self.percent {
+            write!(f, "%")?;
+        }
+        match self.flag {
+            Some(GeometryFlag::Fill) => write!(f, "^")?,
+            Some(GeometryFlag::Exact) => write!(f, "!")?,
+            Some(GeometryFlag::ShrinkOnly) => write!(f, ">")?,
+            Some(GeometryFlag::EnlargeOnly) => write!(f, "<")?,
+            None => {}
+        }
+        if self.offset != (0, 0) {
+            write!(f, "{:+}{:+}", self.offset.0, self.offset.1)?;
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<String> for Geometry {
+    type Error = ImageDataErrors;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Geometry::parse(&value)
+    }
+}
+
+impl From<Geometry> for String {
+    fn from(geometry: Geometry) -> Self {
+        geometry.to_string()
+    }
+}
+
+/// Which edge or corner crop offsets are measured from, as ImageMagick's `-gravity`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Gravity {
+    #[default]
+    NorthWest,
+    North,
+    NorthEast,
+    West,
+    Center,
+    East,
+    SouthWest,
+    South,
+    SouthEast,
+}
+
+impl Gravity {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        match value.to_ascii_lowercase().as_str() {
+            "northwest" | "top-left" => Ok(Gravity::NorthWest),
+            "north" | "top" => Ok(Gravity::North),
+            "northeast" | "top-right" => Ok(Gravity::NorthEast),
+            "west" | "left" => Ok(Gravity::West),
+            "center" | "centre" => Ok(Gravity::Center),
+            "east" | "right" => Ok(Gravity::East),
+            "southwest" | "bottom-left" => Ok(Gravity::SouthWest),
+            "south" | "bottom" => Ok(Gravity::South),
+            "southeast" | "bottom-right" => Ok(Gravity::SouthEast),
+            _ => Err(ImageDataErrors::InvalidArgument(format!("unknown gravity `{}`", value))),
+        }
+    }
+
+    /// -1, 0 or 1 for the left, middle or right, and the top, middle or bottom.
+    pub(crate) fn anchor(self) -> (i8, i8) {
+        match self {
+            Gravity::NorthWest => (-1, -1),
+            Gravity::North => (0, -1),
+            Gravity::NorthEast => (1, -1),
+            Gravity::West => (-1, 0),
+            Gravity::Center => (0, 0),
+            Gravity::East => (1, 0),
+            Gravity::SouthWest => (-1, 1),
+            Gravity::South => (0, 1),
+            Gravity::SouthEast => (1, 1),
+        }
+    }
+}
+
+/// One of the numbers of a [`CropRegion`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Length {
+    Pixels(u32),
+    Percent(f64),
+}
+
+impl Length {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().strip_suffix('%') {
+            Some(percent) => percent.parse().ok().filter(|n: &f64| (0.0..=100.0).contains(n)).map(Length::Percent),
+            None => value.trim().parse().ok().map(Length::Pixels),
+        }
+    }
+
+    fn resolve(self, of: u32) -> u32 {
+        match self {
+            Length::Pixels(pixels) => pixels,
+            Length::Percent(percent) => (of as f64 * percent / 100.0).round() as u32,
+        }
+    }
+}
+
+impl fmt::Display for Length {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Length::Pixels(pixels) => write!(f, "{}", pixels),
+            Length::Percent(percent) => write!(f, "{}%", percent),
+        }
+    }
+}
+
+/// A region of one input written as `x,y,width,height`, each in pixels or
+/// as a percentage of the input's size, such as `10%,0,50%,100%`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CropRegion {
+    pub x: Length,
+    pub y: Length,
+    pub width: Length,
+    pub height: Length,
+}
+
+impl CropRegion {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        let lengths: Option<Vec<Length>> = value.split(',').map(Length::parse).collect();
+        match lengths.as_deref() {
+            Some(&[x, y, width, height]) => Ok(CropRegion { x, y, width, height }),
+            _ => Err(ImageDataErrors::InvalidArgument(format!("invalid crop region `{}`, expected x,y,width,height", value))),
+        }
+    }
+
+    /// The region `(x, y, width, height)` of an image of `size`, clipped to it.
+    pub fn region(&self, (width, height): (u32, u32)) -> Option<(u32, u32, u32, u32)> {
+        let (x, y) = (self.x.resolve(width), self.y.resolve(height));
+        let right = x.saturating_add(self.width.resolve(width)).min(width);
+        let bottom = y.saturating_add(self.height.resolve(height)).min(height);
+        (right > x && bottom > y).then(|| (x, y, right - x, bottom - y))
+    }
+
+    /// Takes the region out of `image`.
+    pub fn apply(&self, image: DynamicImage) -> Result<DynamicImage, ImageDataErrors> {
+        let (x, y, width, height) = self.region(image.dimensions()).ok_or_else(|| {
+            ImageDataErrors::InvalidArgument(format!("crop region `{}` lies outside the {}x{} image", self, image.width(), image.height()))
+        })?;
+        Ok(image.crop_imm(x, y, width, height))
+    }
+}
+
+impl fmt::Display for CropRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{},{},{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+impl TryFrom<String> for CropRegion {
+    type Error = ImageDataErrors;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        CropRegion::parse(&value)
+    }
+}
+
+impl From<CropRegion> for String {
+    fn from(region: CropRegion) -> Self {
+        region.to_string()
+    }
+}
+
+/// Resizes and then crops one input by the given geometries.
+pub(crate) fn reshape(
+    image: DynamicImage,
+    resize: Option<&Geometry>,
+    crop: Option<&Geometry>,
+    gravity: Gravity,
+) -> Result<DynamicImage, ImageDataErrors> {
+    let mut image = image;
+    if let Some(resize) = resize {
+        let (width, height) = resize.resized_size(image.dimensions());
+        if (width, height) != image.dimensions() {
+            image = image.resize_exact(width, height, Triangle);
+        }
+    }
+    if let Some(crop) = crop {
+        let (x, y, width, height) = crop.crop_region(image.dimensions(), gravity).ok_or_else(|| {
+            ImageDataErrors::InvalidArgument(format!("`{}` crops a {}x{} image to nothing", crop, image.width(), image.height()))
+        })?;
+        image = image.crop_imm(x, y, width, height);
+    }
+    Ok(image)
+}
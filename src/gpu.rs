@@ -0,0 +1,399 @@
+//! Where the heaviest per-pixel work runs: resizing the inputs to match and
+//! blending them. Builds with the `gpu` feature can run both as wgpu compute
+//! shaders; whenever no GPU can be used, the same work runs on the CPU.
+
+use image::imageops::{self, Triangle};
+use image::{DynamicImage, ImageBuffer, Pixel, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::crossfade;
+use crate::hooks::Hooks;
+use crate::ImageDataErrors;
+
+/// How many lines the CPU resizes between checks of the run's hooks.
+const RESIZE_BAND: u32 = 64;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Cpu,
+    /// Falls back to the CPU, with a warning, in builds without the `gpu`
+    /// feature or on machines without a usable GPU.
+    Gpu,
+}
+
+impl Backend {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        match value {
+            "cpu" => Ok(Backend::Cpu),
+            "gpu" => Ok(Backend::Gpu),
+            _ => Err(ImageDataErrors::InvalidArgument(format!("unknown backend `{}`", value))),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::Cpu => "cpu",
+            Backend::Gpu => "gpu",
+        }
+    }
+}
+
+/// Resizes `image` to exactly `width`x`height` with a triangle filter,
+/// reporting to `hooks` as it goes. On the CPU it can be cancelled between
+/// bands of lines; the GPU resizes in one dispatch, checked before and after.
+pub(crate) fn resize_exact(image: DynamicImage, width: u32, height: u32, backend: Backend, hooks: Hooks) -> Result<DynamicImage, ImageDataErrors> {
+    if backend == Backend::Gpu {
+        hooks.check()?;
+        if let Some(resized) = device::resize(&image.to_rgba8(), width, height) {
+            hooks.check()?;
+            return Ok(DynamicImage::ImageRgba8(resized));
+        }
+    }
+    let size = (width, height);
+    Ok(match image {
+        DynamicImage::ImageLuma8(image) => DynamicImage::ImageLuma8(resize_in_bands(&image, size, hooks)?),
+        DynamicImage::ImageLumaA8(image) => DynamicImage::ImageLumaA8(resize_in_bands(&image, size, hooks)?),
+        DynamicImage::ImageRgb8(image) => DynamicImage::ImageRgb8(resize_in_bands(&image, size, hooks)?),
+        DynamicImage::ImageRgba8(image) => DynamicImage::ImageRgba8(resize_in_bands(&image, size, hooks)?),
+        DynamicImage::ImageBgr8(image) => DynamicImage::ImageBgr8(resize_in_bands(&image, size, hooks)?),
+        DynamicImage::ImageBgra8(image) => DynamicImage::ImageBgra8(resize_in_bands(&image, size, hooks)?),
+        DynamicImage::ImageLuma16(image) => DynamicImage::ImageLuma16(resize_in_bands(&image, size, hooks)?),
+        DynamicImage::ImageLumaA16(image) => DynamicImage::ImageLumaA16(resize_in_bands(&image, size, hooks)?),
+        DynamicImage::ImageRgb16(image) => DynamicImage::ImageRgb16(resize_in_bands(&image, size, hooks)?),
+        DynamicImage::ImageRgba16(image) => DynamicImage::ImageRgba16(resize_in_bands(&image, size, hooks)?),
+    })
+}
+
+/// `image` resized by `image`'s triangle filter along the rows, then down
+/// the columns, each pass [`RESIZE_BAND`] lines at a time. The filter is
+/// separable, so the passes together resize it as one call would, up to
+/// rounding between them.
+fn resize_in_bands<P>(image: &ImageBuffer<P, Vec<P::Subpixel>>, (width, height): (u32, u32), hooks: Hooks) -> Result<ImageBuffer<P, Vec<P::Subpixel>>, ImageDataErrors>
+where
+    P: Pixel + 'static,
+    P::Subpixel: 'static,
+{
+    let bands = |lines: u32| lines.div_ceil(RESIZE_BAND) as usize;
+    let total = bands(image.height()) + bands(height);
+    let mut done = 0;
+    // Resizing a band to the same number of rows leaves its columns as they are.
+    let mut along_rows = |image: &ImageBuffer<P, Vec<P::Subpixel>>, length: u32| {
+        let mut resized = ImageBuffer::new(length, image.height());
+        for top in (0..image.height()).step_by(RESIZE_BAND as usize) {
+            hooks.step("resizing", done, total)?;
+            let rows = RESIZE_BAND.min(image.height() - top);
+            let band = imageops::resize(&imageops::crop_imm(image, 0, top, image.width(), rows), length, rows, Triangle);
+            imageops::replace(&mut resized, &band, 0, top);
+            done += 1;
+        }
+        Ok::<_, ImageDataErrors>(resized)
+    };
+    let rows = along_rows(image, width)?;
+    let columns = along_rows(&imageops::rotate90(&rows), height)?;
+    hooks.step("resizing", total, total)?;
+    Ok(imageops::rotate270(&columns))
+}
+
+/// Mixes two images of the same size, as [`crossfade::blend`] does.
+pub(crate) fn blend(from: &RgbaImage, to: &RgbaImage, weight: f32, backend: Backend) -> Vec<u8> {
+    if backend == Backend::Gpu {
+        if let Some(blended) = device::blend(from, to, weight) {
+            return blended;
+        }
+    }
+    crossfade::blend(from, to, weight)
+}
+
+#[cfg(all(feature = "gpu", not(target_arch = "wasm32")))]
+mod device {
+    use std::sync::{mpsc, OnceLock};
+
+    use image::RgbaImage;
+    use wgpu::util::DeviceExt;
+
+    /// Each output pixel is the triangle-filtered average of the source
+    /// pixels around it, weighted as `image`'s own triangle filter does.
+    const RESIZE_SHADER: &str = r"
+struct Sizes { source_width: u32, source_height: u32, width: u32, height: u32 }
+
+@group(0) @binding(0) var<uniform> sizes: Sizes;
+@group(0) @binding(1) var<storage, read> source: array<u32>;
+@group(0) @binding(2) var<storage, read_write> output: array<u32>;
+
+struct Window { start: u32, end: u32, centre: f32, scale: f32 }
+
+fn window(position: u32, source_size: u32, size: u32) -> Window {
+    let ratio = f32(source_size) / f32(size);
+    let scale = max(ratio, 1.0);
+    let centre = (f32(position) + 0.5) * ratio;
+    let start = u32(clamp(floor(centre - scale), 0.0, f32(source_size - 1u)));
+    let end = u32(clamp(ceil(centre + scale), f32(start + 1u), f32(source_size)));
+    return Window(start, end, centre, scale);
+}
+
+fn weight(index: u32, around: Window) -> f32 {
+    return max(0.0, 1.0 - abs((f32(index) - around.centre + 0.5) / around.scale));
+}
+
+@compute @workgroup_size(16, 16)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= sizes.width || id.y >= sizes.height) {
+        return;
+    }
+    let columns = window(id.x, sizes.source_width, sizes.width);
+    let rows = window(id.y, sizes.source_height, sizes.height);
+    var sum = vec4<f32>(0.0);
+    var total = 0.0;
+    for (var y = rows.start; y < rows.end; y++) {
+        let row_weight = weight(y, rows);
+        for (var x = columns.start; x < columns.end; x++) {
+            let pixel_weight = row_weight * weight(x, columns);
+            sum += unpack4x8unorm(source[y * sizes.source_width + x]) * pixel_weight;
+            total += pixel_weight;
+        }
+    }
+    output[id.y * sizes.width + id.x] = pack4x8unorm(sum / max(total, 1e-6));
+}
+";
+
+    /// The same premultiplied mix as `crossfade::blend`.
+    const BLEND_SHADER: &str = r"
+struct Mix { count: u32, stride: u32, weight: f32, padding: u32 }
+
+@group(0) @binding(0) var<uniform> mix_by: Mix;
+@group(0) @binding(1) var<storage, read> first: array<u32>;
+@group(0) @binding(2) var<storage, read> second: array<u32>;
+@group(0) @binding(3) var<storage, read_write> output: array<u32>;
+
+@compute @workgroup_size(256)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.y * mix_by.stride + id.x;
+    if (index >= mix_by.count) {
+        return;
+    }
+    let a = unpack4x8unorm(first[index]);
+    let b = unpack4x8unorm(second[index]);
+    let alpha = mix(a.a, b.a, mix_by.weight);
+    var colour = vec3<f32>(0.0);
+    if (alpha > 0.0) {
+        colour = mix(a.rgb * a.a, b.rgb * b.a, mix_by.weight) / alpha;
+    }
+    output[index] = pack4x8unorm(vec4<f32>(colour, alpha));
+}
+";
+
+    /// The most workgroups a dispatch may have along one axis.
+    const MAX_GROUPS: u32 = 65535;
+
+    struct Gpu {
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        resize: wgpu::ComputePipeline,
+        blend: wgpu::ComputePipeline,
+        max_binding: u64,
+    }
+
+    /// The GPU, set up on first use and shared by every later call, or
+    /// `None` once setting it up has failed.
+    fn gpu() -> Option<&'static Gpu> {
+        static GPU: OnceLock<Option<Gpu>> = OnceLock::new();
+        GPU.get_or_init(|| match pollster::block_on(Gpu::new()) {
+            Ok(gpu) => Some(gpu),
+            Err(e) => {
+                log::warn!("no usable GPU ({}), falling back to the CPU", e);
+                None
+            }
+        })
+        .as_ref()
+    }
+
+    impl Gpu {
+        async fn new() -> Result<Self, String> {
+            let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::new_without_display_handle_from_env());
+            let adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::HighPerformance,
+                    ..Default::default()
+                })
+                .await
+                .map_err(|e| e.to_string())?;
+            let info = adapter.get_info();
+            // The adapter's own limits, since 8K inputs outgrow the defaults' buffer sizes.
+            let limits = adapter.limits();
+            let (device, queue) = adapter
+                .request_device(&wgpu::DeviceDescriptor {
+                    label: Some("combiner"),
+                    required_limits: limits.clone(),
+                    ..Default::default()
+                })
+                .await
+                .map_err(|e| e.to_string())?;
+            log::info!("using {} ({:?}) for resizing and blending", info.name, info.backend);
+
+            let pipeline = |label, source: &str| {
+                let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some(label),
+                    source: wgpu::ShaderSource::Wgsl(source.into()),
+                });
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some(label),
+                    layout: None,
+                    module: &module,
+                    entry_point: Some("main"),
+                    compilation_options: Default::default(),
+                    cache: None,
+                })
+            };
+            let resize = pipeline("resize", RESIZE_SHADER);
+            let blend = pipeline("blend", BLEND_SHADER);
+            let max_binding = (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size);
+            Ok(Gpu { device, queue, resize, blend, max_binding })
+        }
+
+        /// Runs `pipeline` once over `groups` workgroups, with `uniform` at
+        /// binding 0, `inputs` after it and an output of `output_size` bytes
+        /// last, and reads the output back. `None` if a buffer is too large
+        /// for the GPU.
+        fn run(
+            &self,
+            pipeline: &wgpu::ComputePipeline,
+            uniform: &[u8],
+            inputs: &[&[u8]],
+            output_size: u64,
+            groups: (u32, u32),
+        ) -> Option<Vec<u8>> {
+            if inputs.iter().map(|input| input.len() as u64).chain([output_size]).any(|size| size > self.max_binding) {
+                log::warn!("the image is too large for the GPU, falling back to the CPU");
+                return None;
+            }
+            let buffer = |contents: &[u8], usage| {
+                self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor { label: None, contents, usage })
+            };
+            let uniform = buffer(uniform, wgpu::BufferUsages::UNIFORM);
+            let inputs: Vec<_> = inputs.iter().map(|input| buffer(input, wgpu::BufferUsages::STORAGE)).collect();
+            let output = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size: output_size,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            });
+            let readback = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size: output_size,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+
+            let entries: Vec<_> = [&uniform]
+                .into_iter()
+                .chain(&inputs)
+                .chain([&output])
+                .enumerate()
+                .map(|(binding, buffer)| wgpu::BindGroupEntry { binding: binding as u32, resource: buffer.as_entire_binding() })
+                .collect();
+            let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &pipeline.get_bind_group_layout(0),
+                entries: &entries,
+            });
+            let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+            {
+                let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+                pass.set_pipeline(pipeline);
+                pass.set_bind_group(0, &bind_group, &[]);
+                pass.dispatch_workgroups(groups.0, groups.1, 1);
+            }
+            encoder.copy_buffer_to_buffer(&output, 0, &readback, 0, output_size);
+            self.queue.submit([encoder.finish()]);
+
+            let (sender, receiver) = mpsc::channel();
+            readback.map_async(wgpu::MapMode::Read, .., move |result| {
+                let _ = sender.send(result);
+            });
+            if let Err(e) = self.device.poll(wgpu::PollType::wait_indefinitely()) {
+                log::warn!("the GPU failed ({}), falling back to the CPU", e);
+                return None;
+            }
+            receiver.recv().ok()?.ok()?;
+            let data = readback.get_mapped_range(..).ok()?.to_vec();
+            readback.unmap();
+            Some(data)
+        }
+    }
+
+    fn words(values: [u32; 4]) -> Vec<u8> {
+        values.iter().flat_map(|value| value.to_le_bytes()).collect()
+    }
+
+    pub(super) fn resize(image: &RgbaImage, width: u32, height: u32) -> Option<RgbaImage> {
+        let gpu = gpu()?;
+        let sizes = words([image.width(), image.height(), width, height]);
+        let output_size = width as u64 * height as u64 * 4;
+        let data = gpu.run(&gpu.resize, &sizes, &[image.as_raw()], output_size, (width.div_ceil(16), height.div_ceil(16)))?;
+        RgbaImage::from_raw(width, height, data)
+    }
+
+    pub(super) fn blend(from: &RgbaImage, to: &RgbaImage, weight: f32) -> Option<Vec<u8>> {
+        let gpu = gpu()?;
+        let count = from.width() * from.height();
+        // Large images need a second axis of workgroups.
+        let groups = count.div_ceil(256);
+        let columns = groups.min(MAX_GROUPS);
+        let mix = words([count, columns * 256, weight.to_bits(), 0]);
+        gpu.run(&gpu.blend, &mix, &[from.as_raw(), to.as_raw()], count as u64 * 4, (columns, groups.div_ceil(columns)))
+    }
+}
+
+#[cfg(not(all(feature = "gpu", not(target_arch = "wasm32"))))]
+mod device {
+    use std::sync::Once;
+
+    use image::RgbaImage;
+
+    fn unavailable() {
+        static WARNING: Once = Once::new();
+        WARNING.call_once(|| log::warn!("the GPU backend needs a build with the `gpu` feature, falling back to the CPU"));
+    }
+
+    pub(super) fn resize(_image: &RgbaImage, _width: u32, _height: u32) -> Option<RgbaImage> {
+        unavailable();
+        None
+    }
+
+    pub(super) fn blend(_from: &RgbaImage, _to: &RgbaImage, _weight: f32) -> Option<Vec<u8>> {
+        unavailable();
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CancelToken;
+
+    fn gradient(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| image::Rgba([(x * 7 % 256) as u8, (y * 5 % 256) as u8, ((x * y) % 256) as u8, 255]))
+    }
+
+    #[test]
+    fn banded_resize_matches_one_call() {
+        let image = gradient(301, 157);
+        for (width, height) in [(120, 90), (400, 200), (301, 64)] {
+            let banded = resize_exact(DynamicImage::ImageRgba8(image.clone()), width, height, Backend::Cpu, Hooks::NONE).unwrap().to_rgba8();
+            let whole = imageops::resize(&image, width, height, Triangle);
+            assert_eq!(banded.dimensions(), (width, height));
+            let furthest = banded.as_raw().iter().zip(whole.as_raw()).map(|(a, b)| a.abs_diff(*b)).max().unwrap();
+            assert!(furthest <= 1, "{}x{} differs by {}", width, height, furthest);
+        }
+    }
+
+    #[test]
+    fn cancelled_resize_stops() {
+        let token = CancelToken::new();
+        token.cancel();
+        let resized = resize_exact(DynamicImage::ImageRgba8(gradient(300, 300)), 100, 100, Backend::Cpu, Hooks::new(None, Some(&token)));
+        assert!(matches!(resized, Err(ImageDataErrors::Cancelled)));
+    }
+}
@@ -0,0 +1,137 @@
+//! Edits as a graph of operations, each node an input or a [`Step`] applied
+//! to the nodes before it. Adding an operation that is already in the graph,
+//! on the same nodes, hands back the existing node, so branches that start
+//! the same way share those steps; evaluating runs every node once, however
+//! many branches use it, and drops intermediate images once nothing needs
+//! them any more.
+
+use std::collections::HashMap;
+
+use image::DynamicImage;
+
+use crate::geometry::Gravity;
+use crate::options::Mode;
+use crate::pipeline::{Pipeline, Step};
+use crate::ImageDataErrors;
+
+/// A node of a [`Graph`], valid only in the graph that made it.
+pub type NodeId = usize;
+
+#[derive(Debug)]
+enum Op {
+    /// An image the caller loads by name when evaluating.
+    Input(String),
+    /// A step on the first input node, with the second for steps using one.
+    Step(Step),
+}
+
+#[derive(Debug)]
+struct Node {
+    op: Op,
+    inputs: Vec<NodeId>,
+}
+
+#[derive(Debug, Default)]
+pub struct Graph {
+    /// In the order added, so every node comes after its inputs.
+    nodes: Vec<Node>,
+    /// Each node by its operation, in written form, and inputs.
+    known: HashMap<(String, Vec<NodeId>), NodeId>,
+}
+
+impl Graph {
+    pub fn new() -> Self {
+        Graph::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// The node for the image named `name`, loaded once however often it is used.
+    pub fn input(&mut self, name: &str) -> NodeId {
+        self.add(format!("input {}", name), Op::Input(name.to_string()), Vec::new())
+    }
+
+    /// The node running `step` on `input`, blending or combining `second`
+    /// into it for steps that use a second image.
+    pub fn step(&mut self, step: Step, input: NodeId, second: Option<NodeId>) -> Result<NodeId, ImageDataErrors> {
+        let inputs = match (step.uses_second(), second) {
+            (true, Some(second)) => vec![input, second],
+            (true, None) => {
+                return Err(ImageDataErrors::InvalidArgument(format!("`{}` needs a second image to use", step)));
+            }
+            (false, _) => vec![input],
+        };
+        Ok(self.add(step.to_string(), Op::Step(step), inputs))
+    }
+
+    /// The node at the end of `pipeline`'s steps, starting from `input`.
+    pub fn pipeline(&mut self, pipeline: &Pipeline, input: NodeId, second: Option<NodeId>) -> Result<NodeId, ImageDataErrors> {
+        pipeline.steps.iter().try_fold(input, |node, step| self.step(step.clone(), node, second))
+    }
+
+    fn add(&mut self, key: String, op: Op, inputs: Vec<NodeId>) -> NodeId {
+        let key = (key, inputs);
+        if let Some(&node) = self.known.get(&key) {
+            return node;
+        }
+        self.nodes.push(Node { op, inputs: key.1.clone() });
+        self.known.insert(key, self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    /// The images of the `wanted` nodes, in order, loading inputs with `load`
+    /// and running `combine` steps with `combine`. Only the nodes the wanted
+    /// ones depend on run.
+    pub(crate) fn evaluate(
+        &self,
+        wanted: &[NodeId],
+        gravity: Gravity,
+        default_mode: Mode,
+        mut load: impl FnMut(&str) -> Result<DynamicImage, ImageDataErrors>,
+        mut combine: impl FnMut(DynamicImage, DynamicImage, Mode) -> Result<DynamicImage, ImageDataErrors>,
+    ) -> Result<Vec<DynamicImage>, ImageDataErrors> {
+        // How many times each node's image is still to be used, by later nodes or as a result.
+        let mut uses = vec![0usize; self.nodes.len()];
+        for &node in wanted {
+            uses[node] += 1;
+        }
+        for node in (0..self.nodes.len()).rev() {
+            if uses[node] > 0 {
+                for &input in &self.nodes[node].inputs {
+                    uses[input] += 1;
+                }
+            }
+        }
+        let needed = uses.iter().filter(|&&count| count > 0).count();
+        log::debug!("evaluating {} of {} nodes for {} results", needed, self.nodes.len(), wanted.len());
+
+        let mut images: Vec<Option<DynamicImage>> = (0..self.nodes.len()).map(|_| None).collect();
+        // The last use of an image takes it; earlier ones copy it.
+        let take = |images: &mut Vec<Option<DynamicImage>>, uses: &mut Vec<usize>, node: NodeId| {
+            uses[node] -= 1;
+            let image = if uses[node] == 0 { images[node].take() } else { images[node].clone() };
+            image.expect("nodes are evaluated before the nodes using them")
+        };
+        for (id, node) in self.nodes.iter().enumerate() {
+            if uses[id] == 0 {
+                continue;
+            }
+            let image = match &node.op {
+                Op::Input(name) => load(name)?,
+                Op::Step(step) => {
+                    let image = take(&mut images, &mut uses, node.inputs[0]);
+                    let second = node.inputs.get(1).map(|&second| take(&mut images, &mut uses, second));
+                    step.apply(image, second, gravity, default_mode, &mut combine)?
+                }
+            };
+            images[id] = Some(image);
+        }
+        Ok(wanted.iter().map(|&node| take(&mut images, &mut uses, node)).collect())
+    }
+}
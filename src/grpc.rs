@@ -0,0 +1,155 @@
+use combiner::combine_bytes;
+use combiner::diff::DiffReport;
+use image::ImageFormat;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::args::{parse_number, CombineOptions, DiffStyle, Mode, ServeArgs};
+use crate::ImageDataErrors;
+
+mod proto {
+    tonic::include_proto!("combiner");
+}
+
+use proto::combiner_server::{Combiner, CombinerServer};
+use proto::image_upload::Payload;
+use proto::{CombinedImage, DiffResult, ImageUpload, Region};
+
+/// Largest total upload accepted per call, matching the HTTP server.
+const MAX_UPLOAD_BYTES: usize = 64 * 1024 * 1024;
+
+/// Serves the `combiner.Combiner` gRPC service until the process is stopped.
+pub fn serve(args: &ServeArgs) -> Result<(), ImageDataErrors> {
+    let addr = args.addr.parse().map_err(|e| ImageDataErrors::UnableToServe(format!("{}: {}", args.addr, e)))?;
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(args.threads.max(1))
+        .enable_all()
+        .build()
+        .map_err(|e| ImageDataErrors::UnableToServe(e.to_string()))?;
+
+    log::warn!("gRPC listening on {}", args.addr);
+    runtime
+        .block_on(Server::builder().add_service(CombinerServer::new(Service)).serve(addr))
+        .map_err(|e| ImageDataErrors::UnableToServe(e.to_string()))
+}
+
+struct Service;
+
+#[tonic::async_trait]
+impl Combiner for Service {
+    async fn combine(&self, request: Request<Streaming<ImageUpload>>) -> Result<Response<CombinedImage>, Status> {
+        let upload = Upload::receive(request.into_inner()).await?;
+        let (image, _) = run_blocking(upload, None).await?;
+        Ok(Response::new(image))
+    }
+
+    async fn diff(&self, request: Request<Streaming<ImageUpload>>) -> Result<Response<DiffResult>, Status> {
+        let upload = Upload::receive(request.into_inner()).await?;
+        let (image, report) = run_blocking(upload, Some(Mode::Diff)).await?;
+        let report = report.ok_or_else(|| Status::internal("diff produced no report"))?;
+
+        Ok(Response::new(DiffResult {
+            image: Some(image),
+            changed_pixels: report.changed_pixels,
+            change_percentage: report.change_percentage,
+            regions: report
+                .regions
+                .into_iter()
+                .map(|region| Region {
+                    x: region.x,
+                    y: region.y,
+                    width: region.width,
+                    height: region.height,
+                    changed_pixels: region.changed_pixels,
+                    change_percentage: region.change_percentage,
+                })
+                .collect(),
+        }))
+    }
+}
+
+#[derive(Default)]
+struct Upload {
+    options: proto::Options,
+    image_1: Vec<u8>,
+    image_2: Vec<u8>,
+}
+
+impl Upload {
+    async fn receive(mut stream: Streaming<ImageUpload>) -> Result<Self, Status> {
+        let mut upload = Upload::default();
+        while let Some(message) = stream.message().await? {
+            match message.payload {
+                Some(Payload::Options(options)) => upload.options = options,
+                Some(Payload::Image1Chunk(chunk)) => upload.image_1.extend_from_slice(&chunk),
+                Some(Payload::Image2Chunk(chunk)) => upload.image_2.extend_from_slice(&chunk),
+                None => {}
+            }
+            if upload.image_1.len() + upload.image_2.len() > MAX_UPLOAD_BYTES {
+                return Err(Status::resource_exhausted("upload is too large"));
+            }
+        }
+        Ok(upload)
+    }
+}
+
+/// Decoding and combining are CPU bound, so they run off the async workers.
+async fn run_blocking(
+    upload: Upload,
+    mode: Option<Mode>,
+) -> Result<(CombinedImage, Option<DiffReport>), Status> {
+    tokio::task::spawn_blocking(move || combine_upload(upload, mode))
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?
+        .map_err(status)
+}
+
+fn combine_upload(
+    upload: Upload,
+    mode: Option<Mode>,
+) -> Result<(CombinedImage, Option<DiffReport>), ImageDataErrors> {
+    if upload.image_1.is_empty() {
+        return Err(ImageDataErrors::MissingArgument("image_1"));
+    }
+    if upload.image_2.is_empty() {
+        return Err(ImageDataErrors::MissingArgument("image_2"));
+    }
+
+    let mut options = options_from(&upload.options)?;
+    if let Some(mode) = mode {
+        options.mode = mode;
+    }
+    let format = match upload.options.format.as_str() {
+        "" => None,
+        format => Some(
+            ImageFormat::from_extension(format)
+                .ok_or_else(|| ImageDataErrors::InvalidArgument(format!("unknown format `{}`", format)))?,
+        ),
+    };
+
+    crate::server::check_pixels(&upload.image_1, &upload.image_2, &options)?;
+    let combined = combine_bytes(&upload.image_1, &upload.image_2, &options, format)?;
+    let format = combined.format.extensions_str().first().copied().unwrap_or_default().to_string();
+    let image = CombinedImage { data: combined.bytes, format, width: combined.width, height: combined.height };
+    Ok((image, combined.report))
+}
+
+/// Empty strings leave the defaults in place, as proto3 cannot tell them apart from unset fields.
+fn options_from(options: &proto::Options) -> Result<CombineOptions, ImageDataErrors> {
+    let mut combine_options = CombineOptions::default();
+    if !options.mode.is_empty() {
+        combine_options.mode = Mode::parse(&options.mode)?;
+    }
+    combine_options.threshold = parse_number("threshold", &options.threshold.to_string())?;
+    if !options.diff_style.is_empty() {
+        combine_options.diff_style = DiffStyle::parse(&options.diff_style)?;
+    }
+    Ok(combine_options)
+}
+
+fn status(e: ImageDataErrors) -> Status {
+    match e {
+        ImageDataErrors::UnableToSaveImage(_) => Status::internal(e.to_string()),
+        _ => Status::invalid_argument(e.to_string()),
+    }
+}
@@ -0,0 +1,189 @@
+//! `combiner gui`: a window to drop two images on, see them combined while
+//! the mode, the second input's opacity and a before/after split are
+//! adjusted, and save the result. The preview is made like `--preview` makes
+//! it, and saving runs the library's pipeline on the inputs at full size.
+
+use std::path::Path;
+
+use eframe::egui;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+
+use combiner::options::CombineOptions;
+
+use crate::args::GuiArgs;
+use crate::preview::{self, Tweaks};
+use crate::ImageDataErrors;
+
+/// The largest preview made, in pixels, so it follows the sliders at once.
+const PREVIEW_SIZE: u32 = 1024;
+
+/// An input: its encoded bytes, combined at full size when saving, and a
+/// copy shrunk for the preview.
+struct Input {
+    name: String,
+    bytes: Vec<u8>,
+    small: DynamicImage,
+}
+
+impl Input {
+    fn load(name: String, bytes: Vec<u8>) -> Result<Self, ImageDataErrors> {
+        let (image, _) = combiner::decode_image_bytes_covering(&bytes, (PREVIEW_SIZE, PREVIEW_SIZE))?;
+        let small = image.resize(PREVIEW_SIZE, PREVIEW_SIZE, FilterType::Triangle);
+        Ok(Input { name, bytes, small })
+    }
+}
+
+struct Gui {
+    inputs: [Option<Input>; 2],
+    output: String,
+    tweaks: Tweaks,
+    preview: Option<egui::TextureHandle>,
+    /// Whether the inputs or tweaks changed since the preview was made.
+    stale: bool,
+    /// What the last action reported, shown under the controls.
+    status: String,
+}
+
+/// Opens the window and returns once it is closed.
+pub fn run(args: &GuiArgs) -> Result<(), ImageDataErrors> {
+    let mut gui = Gui {
+        inputs: [None, None],
+        output: args.output.clone(),
+        tweaks: Tweaks { options: CombineOptions { mode: args.mode, ..CombineOptions::default() }, split: 0.0 },
+        preview: None,
+        stale: true,
+        status: String::new(),
+    };
+    for (slot, path) in args.images.iter().enumerate() {
+        let bytes = std::fs::read(path).map_err(ImageDataErrors::UnableToReadImageFromPath)?;
+        gui.inputs[slot] = Some(Input::load(path.clone(), bytes)?);
+    }
+
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default().with_inner_size([960.0, 720.0]).with_drag_and_drop(true),
+        ..eframe::NativeOptions::default()
+    };
+    eframe::run_native("combiner", options, Box::new(|_| Ok(Box::new(gui))))
+        .map_err(|e| ImageDataErrors::UnableToOpenWindow(e.to_string()))
+}
+
+impl eframe::App for Gui {
+    fn ui(&mut self, ui: &mut egui::Ui, _frame: &mut eframe::Frame) {
+        self.receive_dropped(ui.ctx());
+
+        egui::Panel::top("inputs").show(ui, |ui| {
+            ui.horizontal(|ui| {
+                for (slot, input) in self.inputs.iter().enumerate() {
+                    let name = input.as_ref().map_or("drop an image here", |input| input.name.as_str());
+                    ui.label(format!("Image {}: {}", slot + 1, name));
+                    ui.separator();
+                }
+            });
+        });
+
+        egui::Panel::bottom("controls").show(ui, |ui| self.controls(ui));
+
+        if self.stale {
+            self.stale = false;
+            self.refresh_preview(ui.ctx());
+        }
+        egui::CentralPanel::default().show(ui, |ui| match &self.preview {
+            Some(preview) => {
+                ui.centered_and_justified(|ui| ui.add(egui::Image::new(preview).shrink_to_fit()));
+            }
+            None => {
+                ui.centered_and_justified(|ui| {
+                    ui.label("Drop two images on the window: on its left half for the first, its right half for the second")
+                });
+            }
+        });
+    }
+}
+
+impl Gui {
+    /// Takes in dropped files. Two dropped at once become both inputs; one
+    /// replaces the input of the half of the window it was dropped on, or
+    /// fills the first missing input when the drop position is unknown.
+    fn receive_dropped(&mut self, ctx: &egui::Context) {
+        let (dropped, position, width) =
+            ctx.input(|input| (input.raw.dropped_files.clone(), input.pointer.hover_pos(), input.content_rect().width()));
+        let slots: Vec<usize> = match dropped.len() {
+            0 => return,
+            1 => vec![match position {
+                Some(position) => usize::from(position.x > width / 2.0),
+                None => usize::from(self.inputs[0].is_some()),
+            }],
+            _ => vec![0, 1],
+        };
+        for (slot, file) in slots.into_iter().zip(dropped) {
+            let name = file.path().display().to_string();
+            let loaded = file
+                .bytes()
+                .map_err(|e| ImageDataErrors::UnableToReadImageFromPath(std::io::Error::other(e)))
+                .and_then(|bytes| Input::load(name.clone(), bytes));
+            match loaded {
+                Ok(input) => {
+                    self.inputs[slot] = Some(input);
+                    self.status = String::new();
+                }
+                Err(e) => self.status = format!("{}: {}", name, e),
+            }
+        }
+        self.stale = true;
+    }
+
+    fn controls(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_label("mode").selected_text(self.tweaks.options.mode.name()).show_ui(ui, |ui| {
+                for mode in preview::MODES {
+                    self.stale |= ui.selectable_value(&mut self.tweaks.options.mode, mode, mode.name()).changed();
+                }
+            });
+            if let Some(mut opacity) = self.tweaks.opacity() {
+                if ui.add(egui::Slider::new(&mut opacity, 0.0..=1.0).text("opacity")).changed() {
+                    self.tweaks.set_opacity(opacity);
+                    self.stale = true;
+                }
+            }
+            self.stale |= ui.add(egui::Slider::new(&mut self.tweaks.split, 0.0..=1.0).text("split")).changed();
+        });
+        ui.horizontal(|ui| {
+            ui.label("output");
+            ui.text_edit_singleline(&mut self.output);
+            if ui.button("Save").clicked() {
+                self.status = match self.save() {
+                    Ok(()) => format!("saved {}", self.output),
+                    Err(e) => e.to_string(),
+                };
+            }
+        });
+        ui.label(&self.status);
+    }
+
+    fn refresh_preview(&mut self, ctx: &egui::Context) {
+        let (Some(input_1), Some(input_2)) = (&self.inputs[0], &self.inputs[1]) else {
+            self.preview = None;
+            return;
+        };
+        match preview::compose(&input_1.small, &input_2.small, &self.tweaks, (PREVIEW_SIZE, PREVIEW_SIZE)) {
+            Ok(pixels) => {
+                let size = [pixels.width() as usize, pixels.height() as usize];
+                let image = egui::ColorImage::from_rgba_unmultiplied(size, pixels.as_raw());
+                self.preview = Some(ctx.load_texture("preview", image, egui::TextureOptions::LINEAR));
+            }
+            Err(e) => self.status = e.to_string(),
+        }
+    }
+
+    /// Combines the inputs at full size and writes the result, in the format
+    /// its extension names, or else in the first input's format.
+    fn save(&self) -> Result<(), ImageDataErrors> {
+        let (Some(input_1), Some(input_2)) = (&self.inputs[0], &self.inputs[1]) else {
+            return Err(ImageDataErrors::MissingArgument("image_1 and image_2"));
+        };
+        let format = ImageFormat::from_path(Path::new(&self.output)).ok();
+        let combined = combiner::combine_bytes(&input_1.bytes, &input_2.bytes, &self.tweaks.options, format)?;
+        std::fs::write(&self.output, combined.bytes).map_err(|e| ImageDataErrors::UnableToSaveImage(image::ImageError::IoError(e)))
+    }
+}
@@ -0,0 +1,63 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::ImageDataErrors;
+
+/// Called with a pipeline stage, such as `"resizing"` or `"combining"`, and
+/// the fraction of it done so far, from 0.0 to 1.0.
+pub type ProgressCallback = Arc<dyn Fn(&str, f32) + Send + Sync>;
+
+/// Aborts a run from another thread. Clones share one flag, so the embedder
+/// keeps a clone and calls [`CancelToken::cancel`] when the user gives up.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// What the embedder hooked into one run. Per-pixel loops call [`Hooks::step`],
+/// which only does anything once per percent so the loops stay cheap.
+#[derive(Clone, Copy)]
+pub(crate) struct Hooks<'a> {
+    progress: Option<&'a ProgressCallback>,
+    cancel: Option<&'a CancelToken>,
+}
+
+impl<'a> Hooks<'a> {
+    pub(crate) const NONE: Hooks<'static> = Hooks { progress: None, cancel: None };
+
+    pub(crate) fn new(progress: Option<&'a ProgressCallback>, cancel: Option<&'a CancelToken>) -> Self {
+        Hooks { progress, cancel }
+    }
+
+    /// Reports that `done` of `total` units of `stage` are finished, and stops
+    /// the run with [`ImageDataErrors::Cancelled`] if it has been cancelled.
+    pub(crate) fn step(&self, stage: &str, done: usize, total: usize) -> Result<(), ImageDataErrors> {
+        if done != total && !done.is_multiple_of((total / 100).max(1)) {
+            return Ok(());
+        }
+        self.check()?;
+        if let Some(progress) = self.progress {
+            progress(stage, if total == 0 { 1.0 } else { done as f32 / total as f32 });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check(&self) -> Result<(), ImageDataErrors> {
+        match self.cancel {
+            Some(cancel) if cancel.is_cancelled() => Err(ImageDataErrors::Cancelled),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Soft proofs: how an output will look once printed, found by converting
+//! it into an ICC output profile's colours and back, with the `icc` feature.
+//! Colours the profile cannot reproduce come back clipped to what it can,
+//! which is also how gamut warnings find them.
+
+use std::fmt;
+
+use image::{imageops, RgbaImage};
+
+use crate::ImageDataErrors;
+
+/// How far a colour may move through a profile and back, in CIE76 ΔE,
+/// before it counts as out of the profile's gamut. Colours inside it move a
+/// little too, rounded through the profile's tables.
+const GAMUT_TOLERANCE: f32 = 4.0;
+
+/// An output profile, such as a press's CMYK, ready to convert sRGB into
+/// its colours and back.
+pub struct Profile {
+    #[cfg(feature = "icc")]
+    to_device: std::sync::Arc<moxcms::Transform8BitExecutor>,
+    #[cfg(feature = "icc")]
+    from_device: std::sync::Arc<moxcms::Transform8BitExecutor>,
+    /// How many channels the profile's colours have.
+    channels: usize,
+}
+
+impl fmt::Debug for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Profile").field("channels", &self.channels).finish_non_exhaustive()
+    }
+}
+
+impl Profile {
+    /// Reads an ICC profile for a gray, RGB or CMYK device, converting to
+    /// and from it by relative colorimetric rendering, as proofs without
+    /// paper simulation do.
+    #[cfg(feature = "icc")]
+    pub fn parse(bytes: &[u8]) -> Result<Self, ImageDataErrors> {
+        use moxcms::{ColorProfile, DataColorSpace, Layout, RenderingIntent, TransformOptions};
+
+        let invalid = |message: String| ImageDataErrors::InvalidArgument(format!("invalid ICC profile: {}", message));
+        let device = ColorProfile::new_from_slice(bytes).map_err(|e| invalid(e.to_string()))?;
+        let (layout, channels) = match device.color_space {
+            DataColorSpace::Gray => (Layout::Gray, 1),
+            DataColorSpace::Rgb => (Layout::Rgb, 3),
+            // Four channels, whatever they stand for, travel as RGBA.
+            DataColorSpace::Cmyk => (Layout::Rgba, 4),
+            other => return Err(invalid(format!("{:?} devices are not supported", other))),
+        };
+        let options = TransformOptions { rendering_intent: RenderingIntent::RelativeColorimetric, ..TransformOptions::default() };
+        let srgb = ColorProfile::new_srgb();
+        let to_device = srgb.create_transform_8bit(Layout::Rgb, &device, layout, options).map_err(|e| invalid(e.to_string()))?;
+        let from_device = device.create_transform_8bit(layout, &srgb, Layout::Rgb, options).map_err(|e| invalid(e.to_string()))?;
+        Ok(Profile { to_device, from_device, channels })
+    }
+
+    #[cfg(not(feature = "icc"))]
+    pub fn parse(_bytes: &[u8]) -> Result<Self, ImageDataErrors> {
+        Err(ImageDataErrors::InvalidArgument("ICC profiles need combiner built with the `icc` feature".to_string()))
+    }
+
+    /// `image` as the profile's device reproduces it, back in sRGB, with
+    /// alpha kept as it was.
+    pub fn soft_proof(&self, image: &RgbaImage) -> RgbaImage {
+        let rgb: Vec<u8> = image.pixels().flat_map(|pixel| [pixel.0[0], pixel.0[1], pixel.0[2]]).collect();
+        let proofed = self.round_trip(&rgb);
+        let mut proof = image.clone();
+        for (pixel, colour) in proof.pixels_mut().zip(proofed.chunks_exact(3)) {
+            pixel.0[..3].copy_from_slice(colour);
+        }
+        proof
+    }
+
+    #[cfg(feature = "icc")]
+    fn round_trip(&self, rgb: &[u8]) -> Vec<u8> {
+        let mut device = vec![0; rgb.len() / 3 * self.channels];
+        let mut proofed = vec![0; rgb.len()];
+        // Both buffers are sized to the transforms' layouts, which is all they check.
+        self.to_device.transform(rgb, &mut device).expect("buffers match the layouts");
+        self.from_device.transform(&device, &mut proofed).expect("buffers match the layouts");
+        proofed
+    }
+
+    #[cfg(not(feature = "icc"))]
+    fn round_trip(&self, _rgb: &[u8]) -> Vec<u8> {
+        unreachable!("profiles cannot be made without the `icc` feature")
+    }
+}
+
+/// `image` and its soft proof in `profile`, side by side, so what shifts in
+/// print can be seen at a glance.
+pub fn proof_sheet(image: &RgbaImage, profile: &Profile) -> RgbaImage {
+    let proof = profile.soft_proof(image);
+    let mut sheet = RgbaImage::new(image.width() * 2, image.height());
+    imageops::replace(&mut sheet, image, 0, 0);
+    imageops::replace(&mut sheet, &proof, image.width(), 0);
+    sheet
+}
+
+/// Paints the pixels of `image` that `profile` cannot reproduce in `colour`,
+/// as editors' gamut warnings do, returning how many there were. A pixel is
+/// out of gamut when its soft proof lands more than [`GAMUT_TOLERANCE`] away.
+pub fn mark_out_of_gamut(image: &mut RgbaImage, profile: &Profile, colour: [u8; 3]) -> usize {
+    let proof = profile.soft_proof(image);
+    let mut marked = 0;
+    for (pixel, proofed) in image.pixels_mut().zip(proof.pixels()) {
+        let [l1, a1, b1] = lab(pixel.0);
+        let [l2, a2, b2] = lab(proofed.0);
+        if ((l1 - l2).powi(2) + (a1 - a2).powi(2) + (b1 - b2).powi(2)).sqrt() > GAMUT_TOLERANCE {
+            pixel.0[..3].copy_from_slice(&colour);
+            marked += 1;
+        }
+    }
+    marked
+}
+
+/// The CIE L*a*b* of an sRGB pixel, relative to D65 white.
+fn lab([r, g, b, _]: [u8; 4]) -> [f32; 3] {
+    let linear = |value: u8| {
+        let value = f32::from(value) / 255.0;
+        if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+    };
+    let (r, g, b) = (linear(r), linear(g), linear(b));
+    // The sRGB primaries' XYZ, each divided by the white point's.
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.9505;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.0890;
+    let f = |t: f32| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+#[cfg(all(test, feature = "icc"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marks_only_colours_outside_the_gamut() {
+        let gray = moxcms::ColorProfile::new_gray_with_gamma(2.2).encode().unwrap();
+        let profile = Profile::parse(&gray).unwrap();
+        let mut image = RgbaImage::from_fn(4, 1, |x, _| match x {
+            0 => image::Rgba([255, 0, 0, 255]),
+            1 => image::Rgba([0, 200, 255, 128]),
+            _ => image::Rgba([120, 120, 120, 255]),
+        });
+        assert_eq!(mark_out_of_gamut(&mut image, &profile, [0, 255, 0]), 2);
+        assert_eq!(image.get_pixel(0, 0).0, [0, 255, 0, 255]);
+        assert_eq!(image.get_pixel(1, 0).0, [0, 255, 0, 128]);
+        assert_eq!(image.get_pixel(2, 0).0, [120, 120, 120, 255]);
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use crate::batch::Job;
+use crate::ImageDataErrors;
+
+/// Where `--incremental` records what each output was built from, unless `--state-file` says otherwise.
+pub const DEFAULT_STATE_FILE: &str = ".combiner-state.json";
+
+/// What an input looked like when an output was last built from it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Fingerprint {
+    size: u64,
+    modified: u64,
+    hash: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    image_1: Fingerprint,
+    image_2: Fingerprint,
+    settings: String,
+}
+
+/// Make-style bookkeeping for `--incremental`: a job is skipped when its
+/// output exists, is newer than both inputs, and was built from inputs with
+/// the same content and with the same settings.
+pub struct IncrementalState {
+    path: PathBuf,
+    records: BTreeMap<String, Record>,
+}
+
+impl IncrementalState {
+    pub fn load(path: &str) -> Result<Self, ImageDataErrors> {
+        let records = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                log::warn!("ignoring unreadable state file {}: {}", path, e);
+                BTreeMap::new()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(ImageDataErrors::UnableToReadState(e)),
+        };
+        Ok(IncrementalState { path: PathBuf::from(path), records })
+    }
+
+    pub fn is_up_to_date(&self, job: &Job, output: &str, settings: &str) -> bool {
+        let Some(record) = self.records.get(output) else { return false };
+        let Some(output_modified) = modified_secs(output) else { return false };
+
+        record.settings == settings
+            && unchanged(&job.image_1, &record.image_1, output_modified)
+            && unchanged(&job.image_2, &record.image_2, output_modified)
+    }
+
+    /// Remembers what `output` was just built from and saves the state file,
+    /// so an interrupted run keeps the work it finished.
+    pub fn record(&mut self, job: &Job, output: &str, settings: &str) -> Result<(), ImageDataErrors> {
+        let (Some(image_1), Some(image_2)) = (fingerprint(&job.image_1), fingerprint(&job.image_2)) else {
+            return Ok(());
+        };
+        self.records.insert(output.to_string(), Record { image_1, image_2, settings: settings.to_string() });
+
+        let json = serde_json::to_string_pretty(&self.records).expect("state records are always serialisable");
+        std::fs::write(&self.path, json).map_err(ImageDataErrors::UnableToWriteState)
+    }
+}
+
+/// An input older than the output with its recorded size and modification
+/// time is unchanged. Otherwise its content hash decides, so a file that was
+/// only touched still counts as unchanged.
+fn unchanged(path: &str, recorded: &Fingerprint, output_modified: u64) -> bool {
+    let Ok(metadata) = std::fs::metadata(path) else { return false };
+    if metadata.len() != recorded.size {
+        return false;
+    }
+    let modified = modified_secs(path);
+    if modified == Some(recorded.modified) && modified.is_some_and(|modified| modified <= output_modified) {
+        return true;
+    }
+    std::fs::read(path).is_ok_and(|bytes| fnv1a(&bytes) == recorded.hash)
+}
+
+fn fingerprint(path: &str) -> Option<Fingerprint> {
+    let bytes = std::fs::read(path).ok()?;
+    Some(Fingerprint { size: bytes.len() as u64, modified: modified_secs(path)?, hash: fnv1a(&bytes) })
+}
+
+fn modified_secs(path: &str) -> Option<u64> {
+    let modified = std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()?;
+    modified.duration_since(UNIX_EPOCH).ok().map(|elapsed| elapsed.as_secs())
+}
+
+/// 64-bit FNV-1a: stable across platforms and Rust versions, unlike `DefaultHasher`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A job of two fresh inputs and an output, in a directory of its own.
+    fn job(name: &str) -> (Job, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("combiner-incremental-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |file: &str| dir.join(file).to_string_lossy().into_owned();
+        std::fs::write(path("a.png"), b"first").unwrap();
+        std::fs::write(path("b.png"), b"second").unwrap();
+        std::fs::write(path("out.png"), b"output").unwrap();
+        let job = Job { image_1: path("a.png"), image_2: path("b.png"), output: path("out.png"), index: 1, mode: None };
+        (job, dir)
+    }
+
+    #[test]
+    fn skips_only_what_is_up_to_date() {
+        let (job, dir) = job("settings");
+        let state_file = dir.join("state.json").to_string_lossy().into_owned();
+        let mut state = IncrementalState::load(&state_file).unwrap();
+        assert!(!state.is_up_to_date(&job, &job.output, "settings"));
+        state.record(&job, &job.output, "settings").unwrap();
+        assert!(state.is_up_to_date(&job, &job.output, "settings"));
+
+        // The state file carries over to the next run.
+        let state = IncrementalState::load(&state_file).unwrap();
+        assert!(state.is_up_to_date(&job, &job.output, "settings"));
+        assert!(!state.is_up_to_date(&job, &job.output, r#"settings flags:["--background","white"]"#));
+
+        // A changed input is rebuilt, though one only rewritten as it was is not.
+        std::fs::write(&job.image_2, b"second").unwrap();
+        assert!(state.is_up_to_date(&job, &job.output, "settings"));
+        std::fs::write(&job.image_2, b"another second").unwrap();
+        assert!(!state.is_up_to_date(&job, &job.output, "settings"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rebuilds_missing_outputs() {
+        let (job, dir) = job("missing");
+        let mut state = IncrementalState::load(&dir.join("state.json").to_string_lossy()).unwrap();
+        state.record(&job, &job.output, "settings").unwrap();
+        std::fs::remove_file(&job.output).unwrap();
+        assert!(!state.is_up_to_date(&job, &job.output, "settings"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
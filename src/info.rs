@@ -0,0 +1,21 @@
+use combiner::probe::ImageInfo;
+
+pub fn print_info(info: &ImageInfo) {
+    println!("{}", info.path);
+    println!("  format:      {:?}", info.format);
+    println!("  dimensions:  {}x{}", info.width, info.height);
+    println!("  color type:  {:?}", info.color_type);
+    println!("  bit depth:   {}", info.bit_depth());
+    println!("  frames:      {}", info.frames);
+    println!("  icc profile: {}", yes_no(info.has_icc_profile));
+    println!("  exif:        {}", yes_no(info.has_exif));
+    match info.dpi {
+        Some(dpi) => println!("  dpi:         {:.0}", dpi),
+        None => println!("  dpi:         unknown"),
+    }
+    println!("  memory:      {:.2} MiB decoded", info.decoded_bytes() as f64 / (1024.0 * 1024.0));
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value { "yes" } else { "no" }
+}
@@ -0,0 +1,205 @@
+//! CMYK and YCCK JPEGs. `image` refuses CMYK files without an Adobe marker
+//! and inverts the colours of YCCK ones, so four-component JPEGs are decoded
+//! and converted to RGB here instead.
+
+use image::error::{DecodingError, ImageFormatHint};
+use image::{DynamicImage, GrayImage, ImageError, ImageFormat, RgbImage};
+
+use crate::ImageDataErrors;
+
+/// The Adobe APP14 colour transform meaning the channels are stored as CMYK.
+const TRANSFORM_NONE: u8 = 0;
+
+struct Markers {
+    components: Option<u8>,
+    adobe_transform: Option<u8>,
+}
+
+/// Whether a JPEG holds four components, CMYK or YCCK.
+pub(crate) fn is_cmyk(bytes: &[u8]) -> bool {
+    scan(bytes).components == Some(4)
+}
+
+pub(crate) fn decode_cmyk(bytes: &[u8]) -> Result<DynamicImage, ImageDataErrors> {
+    let markers = scan(bytes);
+    // The decoder demands an Adobe marker, so files without one are given
+    // one declaring no transform.
+    let bytes = match markers.adobe_transform {
+        Some(_) => bytes.to_vec(),
+        None => with_adobe_marker(bytes),
+    };
+
+    let mut decoder = jpeg_decoder::Decoder::new(bytes.as_slice());
+    let mut data = decoder.decode().map_err(|e| invalid(e.to_string()))?;
+    let info = decoder.info().expect("decoded JPEGs have their info read");
+    if info.pixel_format != jpeg_decoder::PixelFormat::CMYK32 {
+        return Err(invalid("expected four components".to_string()));
+    }
+
+    // Bring every pixel to straight CMYK ink amounts.
+    match markers.adobe_transform {
+        // Adobe files store inverted ink, which the decoder undoes; other
+        // files store it straight, so that undoing is undone again.
+        None => data.iter_mut().for_each(|channel| *channel = 255 - *channel),
+        Some(TRANSFORM_NONE) => {}
+        // The decoder turns YCCK into inverted CMY with a straight K.
+        Some(_) => data.chunks_exact_mut(4).for_each(|pixel| pixel[..3].iter_mut().for_each(|channel| *channel = 255 - *channel)),
+    }
+
+    let rgb = data
+        .chunks_exact(4)
+        .flat_map(|pixel| {
+            let white = 255 - pixel[3] as u32;
+            [pixel[0], pixel[1], pixel[2]].map(|ink| ((255 - ink as u32) * white / 255) as u8)
+        })
+        .collect();
+    RgbImage::from_raw(info.width as u32, info.height as u32, rgb)
+        .map(DynamicImage::ImageRgb8)
+        .ok_or_else(|| invalid("image data is truncated".to_string()))
+}
+
+/// The size a `width`x`height` JPEG decodes to at the smallest of its 1/8,
+/// 1/4, 1/2 and full scales that still covers `covering` in both axes.
+pub(crate) fn scaled_size((width, height): (u32, u32), covering: (u32, u32)) -> (u32, u32) {
+    let scaled = |length: u32, eighths: u32| (length * eighths).div_ceil(8);
+    let eighths = [1, 2, 4].into_iter().find(|&eighths| scaled(width, eighths) >= covering.0 && scaled(height, eighths) >= covering.1);
+    let eighths = eighths.unwrap_or(8);
+    (scaled(width, eighths), scaled(height, eighths))
+}
+
+/// Decodes a greyscale or colour JPEG at [`scaled_size`], which takes up to
+/// 64 times less memory than decoding it whole. `None` for CMYK files.
+pub(crate) fn decode_covering(bytes: &[u8], covering: (u32, u32)) -> Result<Option<DynamicImage>, ImageDataErrors> {
+    let mut decoder = jpeg_decoder::Decoder::new(bytes);
+    decoder.read_info().map_err(|e| invalid(e.to_string()))?;
+    let info = decoder.info().expect("the info was just read");
+    let (width, height) = scaled_size((info.width as u32, info.height as u32), covering);
+    decoder.scale(width as u16, height as u16).map_err(|e| invalid(e.to_string()))?;
+    let truncated = || invalid("image data is truncated".to_string());
+    match info.pixel_format {
+        jpeg_decoder::PixelFormat::CMYK32 => Ok(None),
+        jpeg_decoder::PixelFormat::L8 => {
+            let data = decoder.decode().map_err(|e| invalid(e.to_string()))?;
+            GrayImage::from_raw(width, height, data).map(|image| Some(DynamicImage::ImageLuma8(image))).ok_or_else(truncated)
+        }
+        jpeg_decoder::PixelFormat::RGB24 => {
+            let data = decoder.decode().map_err(|e| invalid(e.to_string()))?;
+            RgbImage::from_raw(width, height, data).map(|image| Some(DynamicImage::ImageRgb8(image))).ok_or_else(truncated)
+        }
+    }
+}
+
+/// Reads the component count and Adobe transform from the markers ahead of the scan.
+fn scan(bytes: &[u8]) -> Markers {
+    let mut markers = Markers { components: None, adobe_transform: None };
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return markers;
+    }
+    let mut position = 2;
+    while let (Some(0xFF), Some(&marker)) = (bytes.get(position), bytes.get(position + 1)) {
+        let Some(length) = bytes.get(position + 2..position + 4).map(|length| u16::from_be_bytes([length[0], length[1]]) as usize)
+        else {
+            break;
+        };
+        let segment = bytes.get(position + 4..position + 2 + length).unwrap_or_default();
+        match marker {
+            0xC0..=0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => markers.components = segment.get(5).copied(),
+            0xEE if segment.starts_with(b"Adobe") => markers.adobe_transform = segment.get(11).copied(),
+            0xDA => break,
+            _ => {}
+        }
+        position += 2 + length;
+    }
+    markers
+}
+
+/// Inserts an Adobe APP14 marker declaring no colour transform right after SOI.
+fn with_adobe_marker(bytes: &[u8]) -> Vec<u8> {
+    let mut marked = Vec::with_capacity(bytes.len() + 16);
+    marked.extend_from_slice(&bytes[..2]);
+    marked.extend_from_slice(&[0xFF, 0xEE, 0x00, 0x0E]);
+    marked.extend_from_slice(b"Adobe");
+    marked.extend_from_slice(&[0x00, 0x64, 0x00, 0x00, 0x00, 0x00, TRANSFORM_NONE]);
+    marked.extend_from_slice(&bytes[2..]);
+    marked
+}
+
+fn invalid(message: String) -> ImageDataErrors {
+    ImageDataErrors::UnableToDecodeImage(ImageError::Decoding(DecodingError::new(ImageFormatHint::Exact(ImageFormat::Jpeg), message)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An 8x8 baseline JPEG of four components, every pixel holding
+    /// `samples` as stored, with an Adobe marker giving `transform` if any.
+    /// Each component is one block with only a DC coefficient, quantised by
+    /// 1, so the decoder reproduces the samples exactly.
+    fn four_component_jpeg(samples: [u8; 4], transform: Option<u8>) -> Vec<u8> {
+        let segment = |marker: u8, body: &[u8]| [&[0xFF, marker][..], &(body.len() as u16 + 2).to_be_bytes(), body].concat();
+        let mut bytes = vec![0xFF, 0xD8];
+        if let Some(transform) = transform {
+            bytes.extend(segment(0xEE, &[&b"Adobe"[..], &[0x00, 0x64, 0x00, 0x00, 0x00, 0x00, transform]].concat()));
+        }
+        bytes.extend(segment(0xDB, &[&[0x00][..], &[1; 64]].concat()));
+        bytes.extend(segment(0xC0, &[8, 0, 8, 0, 8, 4, 1, 0x11, 0, 2, 0x11, 0, 3, 0x11, 0, 4, 0x11, 0]));
+        // DC categories 0 to 11 all take 4-bit codes, equal to the category.
+        let mut counts = [0u8; 16];
+        counts[3] = 12;
+        bytes.extend(segment(0xC4, &[&[0x00][..], &counts, &(0..12).collect::<Vec<u8>>()].concat()));
+        // The only AC symbol is the end of block, coded as a single 0 bit.
+        let mut counts = [0u8; 16];
+        counts[0] = 1;
+        bytes.extend(segment(0xC4, &[&[0x10][..], &counts, &[0x00]].concat()));
+        bytes.extend(segment(0xDA, &[4, 1, 0x00, 2, 0x00, 3, 0x00, 4, 0x00, 0, 63, 0]));
+
+        let mut bits: Vec<bool> = Vec::new();
+        let mut push = |value: u32, length: u32| bits.extend((0..length).rev().map(|bit| value >> bit & 1 == 1));
+        for sample in samples {
+            let dc = 8 * (i32::from(sample) - 128);
+            let category = 32 - dc.unsigned_abs().leading_zeros();
+            push(category, 4);
+            push(if dc < 0 { (dc + (1 << category) - 1) as u32 } else { dc as u32 }, category);
+            push(0, 1);
+        }
+        bits.resize(bits.len().div_ceil(8) * 8, true);
+        for byte in bits.chunks_exact(8).map(|bits| bits.iter().fold(0u8, |byte, &bit| byte << 1 | u8::from(bit))) {
+            bytes.push(byte);
+            if byte == 0xFF {
+                bytes.push(0x00);
+            }
+        }
+        bytes.extend([0xFF, 0xD9]);
+        bytes
+    }
+
+    fn first_pixel(bytes: &[u8]) -> [u8; 3] {
+        assert!(is_cmyk(bytes));
+        decode_cmyk(bytes).unwrap().to_rgb8().get_pixel(0, 0).0
+    }
+
+    fn assert_near(actual: [u8; 3], expected: [u8; 3]) {
+        assert!(actual.iter().zip(expected).all(|(a, b)| a.abs_diff(b) <= 2), "{:?} is not {:?}", actual, expected);
+    }
+
+    // Each file below holds the same ink, no cyan or magenta, full yellow
+    // and 40% black, which prints as a dark olive.
+    const OLIVE: [u8; 3] = [153, 153, 0];
+
+    #[test]
+    fn adobe_cmyk_is_stored_inverted() {
+        assert_near(first_pixel(&four_component_jpeg([255, 255, 0, 153], Some(TRANSFORM_NONE))), OLIVE);
+    }
+
+    #[test]
+    fn cmyk_without_adobe_marker_is_stored_straight() {
+        assert_near(first_pixel(&four_component_jpeg([0, 0, 255, 102], None)), OLIVE);
+    }
+
+    #[test]
+    fn ycck_holds_inverted_ink_as_ycc_and_inverted_black() {
+        // Inverted CMY of (255, 255, 0) as BT.601 YCbCr.
+        assert_near(first_pixel(&four_component_jpeg([227, 1, 149, 153], Some(2))), OLIVE);
+    }
+}
@@ -0,0 +1,48 @@
+use image::RgbaImage;
+
+use crate::crossfade::blend;
+use crate::hooks::Hooks;
+use crate::ImageDataErrors;
+
+/// Mirrors the left half of `image` onto its right half, with 2 `folds`, or
+/// its top-left quarter onto the other three, with 4. With `second` and a
+/// `weight`, the second input, mirrored the same way, is blended that far
+/// into every other section, in a checkerboard with 4 folds.
+pub(crate) fn mirror_images(image: &RgbaImage, second: Option<(&RgbaImage, f32)>, folds: u32, hooks: Hooks) -> Result<Vec<u8>, ImageDataErrors> {
+    let (width, height) = image.dimensions();
+    let source = |position: u32, size: u32| {
+        // The middle row or column of an odd size is its own reflection.
+        if position < size.div_ceil(2) {
+            (position, false)
+        } else {
+            (size - 1 - position, true)
+        }
+    };
+    let mirror = |image: &RgbaImage| {
+        RgbaImage::from_fn(width, height, |x, y| {
+            let (x, _) = source(x, width);
+            let y = if folds == 4 { source(y, height).0 } else { y };
+            *image.get_pixel(x, y)
+        })
+    };
+    let mirrored = mirror(image);
+    let Some((second, weight)) = second else {
+        hooks.step("combining", 1, 1)?;
+        return Ok(mirrored.into_raw());
+    };
+    let blended = blend(&mirrored, &mirror(second), weight);
+
+    let mut data = mirrored.into_raw();
+    for y in 0..height {
+        hooks.step("combining", y as usize, height as usize)?;
+        let lower = folds == 4 && source(y, height).1;
+        for x in 0..width {
+            if source(x, width).1 != lower {
+                let index = (y as usize * width as usize + x as usize) * 4;
+                data[index..index + 4].copy_from_slice(&blended[index..index + 4]);
+            }
+        }
+    }
+    hooks.step("combining", height as usize, height as usize)?;
+    Ok(data)
+}
@@ -0,0 +1,82 @@
+use image::{Rgba, RgbaImage};
+
+/// Interlaces images of the same size for printing behind a lenticular lens
+/// sheet of `lpi` lenses per inch at `dpi`. Each lens covers one strip of
+/// every image, in reverse order since the lens flips what is under it, so
+/// the first image shows when looking from the left.
+pub fn interlace(images: &[RgbaImage], lpi: f64, dpi: f64) -> RgbaImage {
+    let (width, height) = images[0].dimensions();
+    let pitch = dpi / lpi;
+    let count = images.len();
+    log::debug!("interlacing {} images at {:.3} pixels per lens", count, pitch);
+    RgbaImage::from_fn(width, height, |x, y| {
+        let within_lens = ((x as f64 + 0.5) / pitch).fract();
+        let strip = ((within_lens * count as f64) as usize).min(count - 1);
+        *images[count - 1 - strip].get_pixel(x, y)
+    })
+}
+
+/// A pitch test for finding the exact LPI of a lens sheet, printed at `dpi`:
+/// a labelled band of black lines for each of `lpis`, one line per lens.
+/// Through the lens, the band whose pitch matches turns evenly black or
+/// white as the print is tilted, while the others show moving stripes.
+pub fn calibration_pattern(lpis: &[f64], dpi: f64) -> RgbaImage {
+    let (width, band_height) = ((4.0 * dpi) as u32, (0.4 * dpi) as u32);
+    let (label_width, gap) = ((0.8 * dpi) as u32, (0.05 * dpi).max(1.0) as u32);
+    let mut pattern = RgbaImage::from_pixel(label_width + width, band_height * lpis.len() as u32, Rgba([255, 255, 255, 255]));
+    // Room for labels such as `100.0`, twenty font pixels wide.
+    let scale = ((label_width - 2 * gap) / 20).min(band_height / 10).max(1);
+    for (band, &lpi) in lpis.iter().enumerate() {
+        let top = band as u32 * band_height;
+        draw_text(&mut pattern, &format!("{:.1}", lpi), gap, top + (band_height - 5 * scale) / 2, scale);
+        let pitch = dpi / lpi;
+        let line = (pitch / 4.0).round().max(1.0);
+        for x in 0..width {
+            if (x as f64 % pitch) < line {
+                for y in top + gap..top + band_height - gap {
+                    pattern.put_pixel(label_width + x, y, Rgba([0, 0, 0, 255]));
+                }
+            }
+        }
+    }
+    pattern
+}
+
+/// Writes digits and points in a 3x5 pixel font, each pixel `scale` wide.
+fn draw_text(image: &mut RgbaImage, text: &str, left: u32, top: u32, scale: u32) {
+    const DIGITS: [[u8; 5]; 10] = [
+        [0b111, 0b101, 0b101, 0b101, 0b111],
+        [0b010, 0b110, 0b010, 0b010, 0b111],
+        [0b111, 0b001, 0b111, 0b100, 0b111],
+        [0b111, 0b001, 0b111, 0b001, 0b111],
+        [0b101, 0b101, 0b111, 0b001, 0b001],
+        [0b111, 0b100, 0b111, 0b001, 0b111],
+        [0b111, 0b100, 0b111, 0b101, 0b111],
+        [0b111, 0b001, 0b010, 0b010, 0b010],
+        [0b111, 0b101, 0b111, 0b101, 0b111],
+        [0b111, 0b101, 0b111, 0b001, 0b111],
+    ];
+    const POINT: [u8; 5] = [0, 0, 0, 0, 0b010];
+    for (index, character) in text.chars().enumerate() {
+        let glyph = match character.to_digit(10) {
+            Some(digit) => DIGITS[digit as usize],
+            None => POINT,
+        };
+        let glyph_left = left + index as u32 * 4 * scale;
+        for (row, bits) in glyph.iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+                for y in 0..scale {
+                    for x in 0..scale {
+                        let (x, y) = (glyph_left + column * scale + x, top + row as u32 * scale + y);
+                        if x < image.width() && y < image.height() {
+                            image.put_pixel(x, y, Rgba([0, 0, 0, 255]));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,1138 @@
+//! Combining two images into one, shared by the `combiner` command line tool
+//! and its bindings. Nothing here touches the filesystem: images come in as
+//! bytes, or by path through an [`ImageStore`] supplied by the caller. The
+//! exceptions are the `async` feature, whose helpers read and write with
+//! tokio, [`probe`], which reads image headers, the [`thumbnails`] cache,
+//! and the `ml` feature, whose runtime reads the matting model it is given.
+
+pub mod animate;
+pub mod annotate;
+pub mod astro;
+pub mod augment;
+pub mod bench;
+#[cfg(feature = "async")]
+mod async_io;
+pub mod camera_raw;
+mod canvas;
+pub mod caption;
+mod crossfade;
+mod depth;
+pub mod density;
+pub mod diff;
+pub mod displace;
+pub mod dzi;
+pub mod exif;
+pub mod faces;
+pub mod ffi;
+pub mod fit;
+pub mod flat_field;
+pub mod frame;
+pub mod geometry;
+pub mod icc;
+pub mod graph;
+pub mod gpu;
+mod kaleidoscope;
+pub mod lenticular;
+pub mod lut;
+pub mod mask;
+#[cfg(feature = "ml")]
+mod matting;
+#[cfg(feature = "ml")]
+mod onnx;
+pub mod mix;
+mod motion;
+pub mod noise;
+pub mod npy;
+pub mod options;
+mod pattern;
+pub mod photomosaic;
+pub mod pipeline;
+pub mod plugin;
+mod hooks;
+mod jpeg;
+pub mod pdf;
+pub mod pnm;
+pub mod preset;
+pub mod print;
+pub mod probe;
+pub mod qr;
+pub mod recipe;
+#[cfg(feature = "pyo3")]
+mod python;
+pub mod raw;
+pub mod seam;
+mod sky;
+pub mod simd;
+pub mod stamp;
+pub mod stego;
+pub mod svg;
+mod text;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod thumbnails;
+pub mod tone;
+pub mod transform;
+pub mod trim;
+pub mod warp;
+pub mod watermark;
+#[cfg(target_arch = "wasm32")]
+mod wasm;
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
+use image::{DynamicImage, ImageFormat, GenericImageView, ImageError};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType as PngFilter, PngEncoder};
+use diff::DiffReport;
+use options::{CombineOptions, Mode};
+use hooks::Hooks;
+
+pub use hooks::{CancelToken, ProgressCallback};
+
+#[cfg(feature = "async")]
+pub use async_io::{load_image_async, save_image_async};
+
+#[derive(Debug)]
+pub enum ImageDataErrors {
+    DifferentImageFormats,
+    BufferTooSmall,
+    UnableToReadImageFromPath(std::io::Error),
+    UnableToFormatImage(String),
+    UnableToDecodeImage(ImageError),
+    UnableToSaveImage(ImageError),
+    UnableToWriteReport(std::io::Error),
+    MissingArgument(&'static str),
+    InvalidArgument(String),
+    UnableToReadDirectory(std::io::Error),
+    UnableToCreateDirectory(std::io::Error),
+    BatchFailed { failed: usize, total: usize },
+    UnableToReadManifest(std::io::Error),
+    InvalidManifest(String),
+    UnableToReadState(std::io::Error),
+    UnableToWriteState(std::io::Error),
+    UnableToServe(String),
+    UnableToPreview(std::io::Error),
+    UnableToOpenWindow(String),
+    UnableToUseClipboard(String),
+    UnableToCapture(String),
+    UnableToRunScript(String),
+    UnableToUsePlugin(String),
+    UnableToRunModel(String),
+    UnableToFetch(String),
+    UnableToReadObject(String),
+    UnableToWriteObject(String),
+    UnableToReadArchive(String),
+    UnableToWriteArchive(String),
+    #[cfg(not(target_arch = "wasm32"))]
+    UnableToWatch(notify::Error),
+    WatcherStopped,
+    Cancelled,
+    RawSizeMismatch { expected: usize, actual: usize },
+    AlphaUnsupported(ImageFormat),
+    MessageTooLarge { capacity: usize, needed: usize },
+    MemoryLimitExceeded { needed: u64, limit: u64 },
+    NoHiddenMessage,
+}
+
+impl fmt::Display for ImageDataErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageDataErrors::DifferentImageFormats => write!(f, "the two images have different formats"),
+            ImageDataErrors::BufferTooSmall => write!(f, "the output buffer is too small"),
+            ImageDataErrors::UnableToReadImageFromPath(e) => write!(f, "unable to read image: {}", e),
+            ImageDataErrors::UnableToFormatImage(path) => write!(f, "unable to detect the format of {}", path),
+            ImageDataErrors::UnableToDecodeImage(e) => write!(f, "unable to decode image: {}", e),
+            ImageDataErrors::UnableToSaveImage(e) => write!(f, "unable to save image: {}", e),
+            ImageDataErrors::UnableToWriteReport(e) => write!(f, "unable to write report: {}", e),
+            ImageDataErrors::MissingArgument(name) => write!(f, "missing argument <{}>", name),
+            ImageDataErrors::InvalidArgument(message) => write!(f, "invalid argument: {}", message),
+            ImageDataErrors::UnableToReadDirectory(e) => write!(f, "unable to read directory: {}", e),
+            ImageDataErrors::UnableToCreateDirectory(e) => write!(f, "unable to create directory: {}", e),
+            ImageDataErrors::BatchFailed { failed, total } => write!(f, "{} of {} jobs failed", failed, total),
+            ImageDataErrors::UnableToReadManifest(e) => write!(f, "unable to read job manifest: {}", e),
+            ImageDataErrors::InvalidManifest(message) => write!(f, "invalid job manifest {}", message),
+            ImageDataErrors::UnableToReadState(e) => write!(f, "unable to read incremental state: {}", e),
+            ImageDataErrors::UnableToWriteState(e) => write!(f, "unable to write incremental state: {}", e),
+            ImageDataErrors::UnableToServe(message) => write!(f, "unable to start server: {}", message),
+            ImageDataErrors::UnableToPreview(e) => write!(f, "unable to preview in the terminal: {}", e),
+            ImageDataErrors::UnableToOpenWindow(message) => write!(f, "unable to open the window: {}", message),
+            ImageDataErrors::UnableToUseClipboard(message) => write!(f, "unable to use the clipboard: {}", message),
+            ImageDataErrors::UnableToCapture(message) => write!(f, "unable to capture the screen: {}", message),
+            ImageDataErrors::UnableToRunScript(message) => write!(f, "unable to run the script: {}", message),
+            ImageDataErrors::UnableToUsePlugin(message) => write!(f, "unable to use the plugin: {}", message),
+            ImageDataErrors::UnableToRunModel(message) => write!(f, "unable to run the model: {}", message),
+            ImageDataErrors::UnableToFetch(message) => write!(f, "unable to fetch image {}", message),
+            ImageDataErrors::UnableToReadObject(message) => write!(f, "unable to read object {}", message),
+            ImageDataErrors::UnableToWriteObject(message) => write!(f, "unable to write object {}", message),
+            ImageDataErrors::UnableToReadArchive(message) => write!(f, "unable to read archive {}", message),
+            ImageDataErrors::UnableToWriteArchive(message) => write!(f, "unable to write archive {}", message),
+            #[cfg(not(target_arch = "wasm32"))]
+            ImageDataErrors::UnableToWatch(e) => write!(f, "unable to watch directory: {}", e),
+            ImageDataErrors::WatcherStopped => write!(f, "the directory watcher stopped unexpectedly"),
+            ImageDataErrors::Cancelled => write!(f, "the operation was cancelled"),
+            ImageDataErrors::RawSizeMismatch { expected, actual } => {
+                write!(f, "the raw buffer holds {} bytes, but its layout needs {}", actual, expected)
+            }
+            ImageDataErrors::AlphaUnsupported(format) => write!(
+                f,
+                "the output is transparent but {:?} has no alpha channel; pick a background to flatten it onto",
+                format
+            ),
+            ImageDataErrors::MessageTooLarge { capacity, needed } => {
+                write!(f, "the message needs {} bytes, but the image can only hide {}", needed, capacity)
+            }
+            ImageDataErrors::MemoryLimitExceeded { needed, limit } => write!(
+                f,
+                "combining needs about {} MiB of memory, more than the {} MiB allowed",
+                needed.div_ceil(1 << 20),
+                limit / (1 << 20)
+            ),
+            ImageDataErrors::NoHiddenMessage => write!(f, "the image holds no hidden message"),
+        }
+    }
+}
+
+impl std::error::Error for ImageDataErrors {}
+
+/// A combined image: RGBA pixels and the output name it is meant for.
+#[derive(Clone)]
+pub struct FloatingImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+    pub name: String,
+    /// The resolution recorded in the encoded output, if any.
+    pub dpi: Option<f32>,
+}
+
+impl FloatingImage {
+    fn new(width: u32, height: u32, name: String) -> Self {
+        let buffer_capacity = height * width * 4;
+        let buffer = Vec::with_capacity(buffer_capacity.try_into().unwrap());
+        FloatingImage {
+            width,
+            height,
+            data: buffer,
+            name,
+            dpi: None,
+        }
+    }
+
+    fn set_data(&mut self, data: Vec<u8>) -> Result<(), ImageDataErrors> {
+        if data.len() > self.data.capacity() {
+            return Err(ImageDataErrors::BufferTooSmall)
+        }
+        self.data = data;
+        Ok(())
+    }
+
+    /// Whether any pixel is less than fully opaque.
+    pub fn has_transparency(&self) -> bool {
+        self.data.chunks_exact(4).any(|pixel| pixel[3] < 255)
+    }
+
+    /// Composites every pixel over an opaque `background` colour.
+    pub fn flatten(&mut self, background: [u8; 3]) {
+        for pixel in self.data.chunks_exact_mut(4) {
+            let alpha = pixel[3] as u32;
+            for (channel, background) in pixel[..3].iter_mut().zip(background) {
+                *channel = ((*channel as u32 * alpha + background as u32 * (255 - alpha) + 127) / 255) as u8;
+            }
+            pixel[3] = 255;
+        }
+    }
+
+    /// Makes the image tile seamlessly, a little smaller, see [`seam::merge_overlap`].
+    pub fn tileable(self) -> Result<Self, ImageDataErrors> {
+        let image = image::RgbaImage::from_raw(self.width, self.height, self.data).ok_or(ImageDataErrors::BufferTooSmall)?;
+        let tileable = seam::make_tileable(&image);
+        Ok(FloatingImage { width: tileable.width(), height: tileable.height(), data: tileable.into_raw(), ..self })
+    }
+
+    /// Draws a device mockup around the image, see [`frame::frame`].
+    pub fn framed(self, device: frame::Device) -> Result<Self, ImageDataErrors> {
+        let screen = image::RgbaImage::from_raw(self.width, self.height, self.data).ok_or(ImageDataErrors::BufferTooSmall)?;
+        let framed = frame::frame(&screen, device);
+        Ok(FloatingImage { width: framed.width(), height: framed.height(), data: framed.into_raw(), ..self })
+    }
+
+    /// Fits the image to a social network's size, see [`preset::SizePreset`],
+    /// cropping as `options` say.
+    pub fn fitted_to(self, preset: preset::SizePreset, options: &CombineOptions) -> Result<Self, ImageDataErrors> {
+        let image = image::RgbaImage::from_raw(self.width, self.height, self.data).ok_or(ImageDataErrors::BufferTooSmall)?;
+        let faces = crop_faces(&DynamicImage::ImageRgba8(image.clone()), options)?;
+        let fitted = preset::fit_to_preset(image, preset, options.smart_crop, &faces);
+        Ok(FloatingImage { width: fitted.width(), height: fitted.height(), data: fitted.into_raw(), ..self })
+    }
+
+    /// Adds the noise and grain `options` ask for, see [`noise::add_noise`]
+    /// and [`noise::add_grain`].
+    pub fn add_noise(&mut self, options: &CombineOptions) -> Result<(), ImageDataErrors> {
+        let mut image = image::RgbaImage::from_raw(self.width, self.height, std::mem::take(&mut self.data))
+            .ok_or(ImageDataErrors::BufferTooSmall)?;
+        if let Some(noise) = options.noise {
+            noise::add_noise(&mut image, noise, options.seed);
+        }
+        if let Some(grain) = options.grain {
+            // A different stream, so grain does not line up with the noise.
+            noise::add_grain(&mut image, grain, options.seed.wrapping_add(1));
+        }
+        self.data = image.into_raw();
+        Ok(())
+    }
+
+    /// Draws `data` as a QR code where `options` place it, see [`qr::draw_qr`].
+    pub fn draw_qr(&mut self, data: &str, options: &CombineOptions) -> Result<(), ImageDataErrors> {
+        let image = image::RgbaImage::from_raw(self.width, self.height, std::mem::take(&mut self.data))
+            .ok_or(ImageDataErrors::BufferTooSmall)?;
+        let image = DynamicImage::ImageRgba8(image);
+        let faces = if options.smart_position { faces::detect(options.face_model.as_deref(), &image) } else { Ok(Vec::new()) };
+        let mut image = image.into_rgba8();
+        let drawn = faces.and_then(|faces| {
+            let smart = options.smart_position.then_some(&faces[..]);
+            qr::draw_qr(&mut image, data, options.qr_position, smart, options.qr_size, options.qr_margin, options.qr_error_correction)
+        });
+        self.data = image.into_raw();
+        drawn
+    }
+
+    /// Prints the stamps `options` ask for from `exif`, the first input's
+    /// metadata, see [`stamp::draw`]. Stamps it has no data for are left out.
+    pub fn stamp(&mut self, exif: Option<&exif::Exif>, options: &CombineOptions) -> Result<(), ImageDataErrors> {
+        let exif = exif.copied().unwrap_or_default();
+        let lines: Vec<String> = options
+            .stamp
+            .iter()
+            .filter_map(|stamp| {
+                let line = stamp.text(&exif, &options.stamp_format);
+                if line.is_none() {
+                    log::warn!("the first input records no {}, so it is not stamped", stamp);
+                }
+                line
+            })
+            .collect();
+        if lines.is_empty() {
+            return Ok(());
+        }
+        let mut image = image::RgbaImage::from_raw(self.width, self.height, std::mem::take(&mut self.data))
+            .ok_or(ImageDataErrors::BufferTooSmall)?;
+        stamp::draw(&mut image, &lines, options.stamp_position);
+        self.data = image.into_raw();
+        Ok(())
+    }
+
+    /// Lays the image out on a printed page, see [`print::lay_out`], and
+    /// records the page's DPI.
+    pub fn printed(self, layout: print::PrintLayout, crop_marks: bool) -> Result<Self, ImageDataErrors> {
+        let image = image::RgbaImage::from_raw(self.width, self.height, self.data).ok_or(ImageDataErrors::BufferTooSmall)?;
+        let page = print::lay_out(image, layout, crop_marks);
+        Ok(FloatingImage { width: page.width(), height: page.height(), data: page.into_raw(), dpi: Some(layout.dpi), ..self })
+    }
+
+    /// Crops away the margins [`trim::trim_bounds`] finds.
+    pub fn trim(&mut self) {
+        let row = self.width as usize * 4;
+        let data = &self.data;
+        let pixel = |x: u32, y: u32| {
+            let at = y as usize * row + x as usize * 4;
+            [data[at], data[at + 1], data[at + 2], data[at + 3]]
+        };
+        let Some((x, y, width, height)) = trim::trim_bounds(self.width, self.height, pixel) else { return };
+        self.data = self
+            .data
+            .chunks_exact(row)
+            .skip(y as usize)
+            .take(height as usize)
+            .flat_map(|line| &line[x as usize * 4..(x + width) as usize * 4])
+            .copied()
+            .collect();
+        self.width = width;
+        self.height = height;
+    }
+}
+
+/// Whether `format` keeps an alpha channel when the output is named `name`,
+/// which picks the PNM subtype.
+pub fn keeps_alpha(format: ImageFormat, name: &str) -> bool {
+    match format {
+        ImageFormat::Jpeg => false,
+        ImageFormat::Pnm => pnm::PnmEncoding::for_path(name).kind == pnm::PnmKind::ArbitraryMap,
+        _ => true,
+    }
+}
+
+/// Brings both images to the same size and combines them into an output
+/// named `name`, along with the diff report when the mode is diff.
+pub fn combine_decoded(
+    image_1: DynamicImage,
+    image_2: DynamicImage,
+    options: &CombineOptions,
+    name: String,
+) -> Result<(FloatingImage, Option<DiffReport>), ImageDataErrors> {
+    combine_with_hooks(image_1, image_2, options, name, Hooks::NONE)
+}
+
+/// Like [`combine_decoded`], along with how much of each input went into
+/// the output in mixup and cutmix modes.
+pub fn combine_labelled(
+    image_1: DynamicImage,
+    image_2: DynamicImage,
+    options: &CombineOptions,
+    name: String,
+) -> Result<(FloatingImage, Option<DiffReport>, Option<mix::MixLabel>), ImageDataErrors> {
+    combine_stages(image_1, image_2, options, name, Hooks::NONE)
+}
+
+/// Like [`combine_decoded`], with each output pixel made by `pixel` from the
+/// pixels at the same column and row of both inputs, in place of the mode.
+/// The inputs are prepared and the output finished as for any mode.
+pub fn combine_pixels(
+    image_1: DynamicImage,
+    image_2: DynamicImage,
+    options: &CombineOptions,
+    name: String,
+    mut pixel: impl FnMut(u32, u32, [u8; 4], [u8; 4]) -> Result<[u8; 4], ImageDataErrors>,
+) -> Result<FloatingImage, ImageDataErrors> {
+    combine_custom(image_1, image_2, options, name, true, |image_1, image_2| {
+        let (width, height) = image_1.dimensions();
+        let mut data = Vec::with_capacity(width as usize * height as usize * 4);
+        for ((x, y, first), second) in image_1.enumerate_pixels().zip(image_2.pixels()) {
+            data.extend_from_slice(&pixel(x, y, first.0, second.0)?);
+        }
+        image::RgbaImage::from_raw(width, height, data).ok_or(ImageDataErrors::BufferTooSmall)
+    })
+}
+
+/// Like [`combine_decoded`], with the output made by `combine` from both
+/// inputs as RGBA, in place of the mode. With `same_size` the inputs are
+/// brought to one size first; otherwise each keeps its own. The inputs are
+/// prepared and the output finished as for any mode.
+pub fn combine_custom(
+    image_1: DynamicImage,
+    image_2: DynamicImage,
+    options: &CombineOptions,
+    name: String,
+    same_size: bool,
+    combine: impl FnOnce(image::RgbaImage, image::RgbaImage) -> Result<image::RgbaImage, ImageDataErrors>,
+) -> Result<FloatingImage, ImageDataErrors> {
+    if options.roi.is_some() {
+        return Err(ImageDataErrors::InvalidArgument("a custom combination cannot be limited to a region".to_string()));
+    }
+    let (image_1, image_2) = prepare_inputs(image_1, image_2, options, same_size, Hooks::NONE)?;
+    let mask = mask::prepare(options, &image_1, &image_2)?;
+    let mut output = timed("combining", || combine(image_1.to_rgba8(), image_2.to_rgba8()))?;
+    let (width, height) = output.dimensions();
+    if let Some((matte, base)) = mask {
+        mask::apply(&base, &matte, &mut output, (width, height))?;
+    }
+    finish_output(FloatingImage { width, height, data: output.into_raw(), name, dpi: options.dpi }, options)
+}
+
+fn combine_with_hooks(
+    image_1: DynamicImage,
+    image_2: DynamicImage,
+    options: &CombineOptions,
+    name: String,
+    hooks: Hooks,
+) -> Result<(FloatingImage, Option<DiffReport>), ImageDataErrors> {
+    combine_stages(image_1, image_2, options, name, hooks).map(|(output, report, _)| (output, report))
+}
+
+fn combine_stages(
+    image_1: DynamicImage,
+    image_2: DynamicImage,
+    options: &CombineOptions,
+    name: String,
+    hooks: Hooks,
+) -> Result<(FloatingImage, Option<DiffReport>, Option<mix::MixLabel>), ImageDataErrors> {
+    // Pipelines resize the second input when they use it.
+    let same_size = options.mode.resizes_inputs() && options.pipeline.is_none();
+    let (image_1, image_2) = prepare_inputs(image_1, image_2, options, same_size, hooks)?;
+    let mask = mask::prepare(options, &image_1, &image_2)?;
+    let mut combined = match options.roi {
+        Some(roi) => timed("combining the region", || combine_region(image_1, image_2, roi, options, &name, hooks))?,
+        None => timed("combining", || combine_prepared(image_1, image_2, options, &name, hooks))?,
+    };
+    if combined.data.len() != combined.width as usize * combined.height as usize * 4 {
+        return Err(ImageDataErrors::BufferTooSmall);
+    }
+    if let Some((matte, base)) = mask {
+        timed("masking", || mask::apply(&base, &matte, &mut combined.data, (combined.width, combined.height)))?;
+    }
+    // The combined pixels become the output as they are, with no copy on the way to the encoder.
+    let mut output = FloatingImage { width: combined.width, height: combined.height, data: combined.data, name, dpi: options.dpi };
+    // Diff and mix outputs are left whole so reports and boxes still line up.
+    if combined.report.is_none() && combined.label.is_none() {
+        output = finish_output(output, options)?;
+    }
+    Ok((output, combined.report, combined.label))
+}
+
+/// What a mode made of the two inputs, before finishing.
+struct ModeOutput {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+    report: Option<DiffReport>,
+    label: Option<mix::MixLabel>,
+}
+
+/// Combines inputs already brought through [`prepare_inputs`] the way
+/// `options.mode` says.
+fn combine_prepared(
+    image_1: DynamicImage,
+    image_2: DynamicImage,
+    options: &CombineOptions,
+    name: &str,
+    hooks: Hooks,
+) -> Result<ModeOutput, ImageDataErrors> {
+    if let Some(pipeline) = &options.pipeline {
+        let combine = |image, image_2, mode| combine_in_mode(image, image_2, mode, options, name, hooks);
+        let output = pipeline.run(image_1, image_2, options.gravity, options.mode, combine)?;
+        let (width, height) = output.dimensions();
+        return Ok(ModeOutput { width, height, data: output.into_rgba8().into_raw(), report: None, label: None });
+    }
+    let (width, height) = image_1.dimensions();
+    let whole = |data| ModeOutput { width, height, data, report: None, label: None };
+    match options.mode {
+        Mode::Alternate => Ok(whole(combine_images(image_1, image_2, (0, 0, width, height), hooks)?)),
+        Mode::Mixup | Mode::Cutmix => {
+            let (data, mixed) = mix::mix_images(&image_1.to_rgba8(), &image_2.to_rgba8(), options, name, hooks)?;
+            log::info!("{} with lambda {:.4}", options.mode.name(), mixed.lambda);
+            Ok(ModeOutput { label: Some(mixed), ..whole(data) })
+        }
+        Mode::Diff => {
+            let (data, report) = diff::diff_images(&image_1, &image_2, options.threshold, options.diff_style, hooks)?;
+            log::info!("{} changed regions, {:.2}% of pixels changed", report.regions.len(), report.change_percentage);
+            Ok(ModeOutput { report: Some(report), ..whole(data) })
+        }
+        Mode::Canvas => {
+            let mut positions = options.positions;
+            if options.smart_position {
+                let faces = faces::detect(options.face_model.as_deref(), &image_1)?;
+                let margin = width.min(height) / 40;
+                match faces::quietest_corner(&image_1.to_rgba8(), &faces, image_2.dimensions(), margin) {
+                    Some((x, y)) => positions = [(0, 0), (x as i32, y as i32)],
+                    None => log::warn!("the second input does not fit inside the first, so it stays where `--pos-2` puts it"),
+                }
+            }
+            let (width, height, data) = canvas::place_images(&image_1, &image_2, options.canvas, positions, hooks)?;
+            Ok(ModeOutput { width, height, ..whole(data) })
+        }
+        Mode::Warp => {
+            let quad = options.warp.as_ref().ok_or(ImageDataErrors::MissingArgument("--warp-2"))?;
+            let (width, height, data) = warp::warp_images(&image_1, &image_2, quad, hooks)?;
+            Ok(ModeOutput { width, height, ..whole(data) })
+        }
+        Mode::Tile => {
+            let (width, height) = options.canvas.unwrap_or((width, height));
+            let data = pattern::tile_pattern(&image_2.to_rgba8(), (width, height), options.tile_size, options.tile_offset, options.tile_mirror, hooks)?;
+            Ok(ModeOutput { width, height, ..whole(data) })
+        }
+        Mode::Mirror => {
+            let image_2 = options.mirror_blend.map(|weight| (image_2.to_rgba8(), weight));
+            let second = image_2.as_ref().map(|(image, weight)| (image, *weight));
+            Ok(whole(kaleidoscope::mirror_images(&image_1.to_rgba8(), second, options.mirror_folds, hooks)?))
+        }
+        Mode::SkyReplace => Ok(whole(sky::replace_sky(&image_1.to_rgba8(), &image_2.to_rgba8(), options.sky_feather, options.sky_harmonize, hooks)?)),
+        Mode::Motion => {
+            let (threshold, cleanup, background) = (options.threshold, options.motion_cleanup, options.motion_background);
+            Ok(whole(motion::extract_motion(&image_1.to_rgba8(), &image_2.to_rgba8(), threshold, cleanup, background, hooks)?))
+        }
+        Mode::SubtractBg => {
+            let (threshold, softness) = (options.threshold, options.subtract_softness);
+            Ok(whole(motion::subtract_background(&image_1.to_rgba8(), &image_2.to_rgba8(), threshold, softness, hooks)?))
+        }
+        Mode::Photomosaic => Err(ImageDataErrors::InvalidArgument(
+            "photomosaic mode builds from a directory of tiles, see `photomosaic`".to_string(),
+        )),
+        Mode::DepthBlend => Err(ImageDataErrors::InvalidArgument(
+            "depth-blend mode needs a depth map, see `depth_blend`".to_string(),
+        )),
+        Mode::AstroStack => Err(ImageDataErrors::InvalidArgument(
+            "astro-stack mode stacks a directory of frames, see `astro_stack`".to_string(),
+        )),
+        Mode::FlatField => Err(ImageDataErrors::InvalidArgument(
+            "flat-field mode writes 16-bit frames of its own, see `flat_field::correct`".to_string(),
+        )),
+    }
+}
+
+/// The images of the `outputs` of `graph`, each named by its path and
+/// finished as `options` say, like the output of any mode. Inputs are loaded
+/// with `load`, and `combine` steps use `options` besides their mode.
+pub fn evaluate_graph(
+    graph: &graph::Graph,
+    outputs: &[(String, graph::NodeId)],
+    options: &CombineOptions,
+    load: impl FnMut(&str) -> Result<DynamicImage, ImageDataErrors>,
+) -> Result<Vec<FloatingImage>, ImageDataErrors> {
+    let wanted: Vec<graph::NodeId> = outputs.iter().map(|(_, node)| *node).collect();
+    let combine = |image, image_2, mode| combine_in_mode(image, image_2, mode, options, "", Hooks::NONE);
+    let images = timed("evaluating", || graph.evaluate(&wanted, options.gravity, options.mode, load, combine))?;
+    outputs
+        .iter()
+        .zip(images)
+        .map(|((name, _), image)| {
+            let (width, height) = image.dimensions();
+            let output = FloatingImage { width, height, data: image.into_rgba8().into_raw(), name: name.clone(), dpi: options.dpi };
+            finish_output(output, options)
+        })
+        .collect()
+}
+
+/// Combines two images for `combine` steps of pipelines and recipes: brought
+/// to the same size when `mode` needs it, and otherwise as `options` say.
+fn combine_in_mode(
+    image_1: DynamicImage,
+    image_2: DynamicImage,
+    mode: Mode,
+    options: &CombineOptions,
+    name: &str,
+    hooks: Hooks,
+) -> Result<DynamicImage, ImageDataErrors> {
+    let options = CombineOptions { mode, pipeline: None, ..options.clone() };
+    let (image_1, image_2) = if mode.resizes_inputs() { standardise_size(image_1, image_2, &options, hooks)? } else { (image_1, image_2) };
+    let combined = combine_prepared(image_1, image_2, &options, name, hooks)?;
+    let pixels = image::RgbaImage::from_raw(combined.width, combined.height, combined.data).ok_or(ImageDataErrors::BufferTooSmall)?;
+    Ok(DynamicImage::ImageRgba8(pixels))
+}
+
+/// Combines only the `roi` of inputs already brought to the same size,
+/// copying the rest of the output from `image_1`, so small changes to large
+/// frames cost no more than the area they cover. Alternate mode keeps the
+/// pattern the whole frame would have; other modes combine the region as if
+/// it were the whole image, and diff reports give regions in the frame's
+/// coordinates.
+fn combine_region(
+    image_1: DynamicImage,
+    image_2: DynamicImage,
+    roi: geometry::CropRegion,
+    options: &CombineOptions,
+    name: &str,
+    hooks: Hooks,
+) -> Result<ModeOutput, ImageDataErrors> {
+    if options.pipeline.is_some() {
+        return Err(ImageDataErrors::InvalidArgument("a pipeline cannot be limited to a region".to_string()));
+    }
+    if !matches!(options.mode, Mode::Alternate | Mode::Diff | Mode::Mirror) {
+        return Err(ImageDataErrors::InvalidArgument(format!(
+            "{} mode cannot be limited to a region; use `--roi` with alternate, diff or mirror mode",
+            options.mode.name()
+        )));
+    }
+    let (width, height) = image_1.dimensions();
+    let region = roi.region((width, height)).ok_or_else(|| {
+        ImageDataErrors::InvalidArgument(format!("region `{}` lies outside the {}x{} output", roi, width, height))
+    })?;
+    log::debug!("combining {}x{} at {},{} of {}x{}", region.2, region.3, region.0, region.1, width, height);
+    let whole = |data| ModeOutput { width, height, data, report: None, label: None };
+    if options.mode == Mode::Alternate {
+        return Ok(whole(combine_images(image_1, image_2, region, hooks)?));
+    }
+
+    let (x, y, region_width, region_height) = region;
+    let crop = |image: &DynamicImage| image.crop_imm(x, y, region_width, region_height);
+    let ModeOutput { data, mut report, .. } = combine_prepared(crop(&image_1), crop(&image_2), options, name, hooks)?;
+    let pixels = image::RgbaImage::from_raw(region_width, region_height, data).ok_or(ImageDataErrors::BufferTooSmall)?;
+    let mut output = image_1.into_rgba8();
+    image::imageops::replace(&mut output, &pixels, x, y);
+    if let Some(report) = &mut report {
+        for changed in &mut report.regions {
+            changed.x += x;
+            changed.y += y;
+        }
+    }
+    Ok(ModeOutput { report, ..whole(output.into_raw()) })
+}
+
+/// Crossfades from `image_1` to `image_2` over `frames` frames, calling
+/// `frame` with each one's index, from 0, and the finished image, named
+/// `name`. The first frame is `image_1` and the last `image_2`, after both
+/// were prepared as for combining; frames are made one at a time, so long
+/// sequences need no more memory than short ones.
+pub fn crossfade_sequence(
+    image_1: DynamicImage,
+    image_2: DynamicImage,
+    options: &CombineOptions,
+    frames: usize,
+    name: &str,
+    mut frame: impl FnMut(usize, FloatingImage) -> Result<(), ImageDataErrors>,
+) -> Result<(), ImageDataErrors> {
+    if frames < 2 {
+        return Err(ImageDataErrors::InvalidArgument("a crossfade needs at least 2 frames".to_string()));
+    }
+    let (image_1, image_2) = prepare_inputs(image_1, image_2, options, true, Hooks::NONE)?;
+    let (image_1, image_2) = (image_1.to_rgba8(), image_2.to_rgba8());
+    for index in 0..frames {
+        let weight = index as f32 / (frames - 1) as f32;
+        let mut output = FloatingImage::new(image_1.width(), image_1.height(), name.to_string());
+        output.dpi = options.dpi;
+        output.set_data(timed("blending", || gpu::blend(&image_1, &image_2, weight, options.backend)))?;
+        frame(index, finish_output(output, options)?)?;
+    }
+    Ok(())
+}
+
+/// Makes an animated comparison of the two inputs, prepared as for
+/// combining, and encodes it in `format`: GIF, animated PNG or animated
+/// WebP. Every option that shapes the output applies to each frame.
+pub fn animate(
+    image_1: DynamicImage,
+    image_2: DynamicImage,
+    options: &CombineOptions,
+    animation: &animate::Animation,
+    format: ImageFormat,
+) -> Result<Vec<u8>, ImageDataErrors> {
+    if animation.frames < 2 {
+        return Err(ImageDataErrors::InvalidArgument("an animation needs at least 2 frames".to_string()));
+    }
+    let (image_1, image_2) = prepare_inputs(image_1, image_2, options, true, Hooks::NONE)?;
+    let (image_1, image_2) = (image_1.to_rgba8(), image_2.to_rgba8());
+    let positions = animation.positions();
+    let count = positions.len();
+    let frames = positions.into_iter().map(|position| {
+        let (width, height) = image_1.dimensions();
+        let data = match animation.style {
+            animate::AnimationStyle::Wipe => animate::wipe_frame(&image_1, &image_2, position).into_raw(),
+            animate::AnimationStyle::Crossfade => gpu::blend(&image_1, &image_2, position, options.backend),
+        };
+        let output = FloatingImage { width, height, data, name: String::new(), dpi: None };
+        let output = finish_output(output, options)?;
+        image::RgbaImage::from_raw(output.width, output.height, output.data).ok_or(ImageDataErrors::BufferTooSmall)
+    });
+    timed("encoding the animation", || animate::encode(frames, count, format, animation))
+}
+
+/// Rebuilds `image` from the tiles of `library`, as `options.mosaic_scale`
+/// and `options.mosaic_correction` say, then finishes it like any output.
+pub fn photomosaic(
+    image: DynamicImage,
+    library: &photomosaic::TileLibrary,
+    options: &CombineOptions,
+    name: String,
+) -> Result<FloatingImage, ImageDataErrors> {
+    photomosaic_with_hooks(image, library, options, name, Hooks::NONE)
+}
+
+fn photomosaic_with_hooks(
+    image: DynamicImage,
+    library: &photomosaic::TileLibrary,
+    options: &CombineOptions,
+    name: String,
+    hooks: Hooks,
+) -> Result<FloatingImage, ImageDataErrors> {
+    if library.is_empty() {
+        return Err(ImageDataErrors::InvalidArgument("the photomosaic library holds no images".to_string()));
+    }
+    let mosaic = timed("building the photomosaic", || {
+        photomosaic::build(&image.to_rgba8(), library, options.mosaic_scale.max(1), options.mosaic_correction, hooks)
+    })?;
+    let (width, height) = mosaic.dimensions();
+    let output = FloatingImage { width, height, data: mosaic.into_raw(), name, dpi: options.dpi };
+    finish_output(output, options)
+}
+
+/// Mixes `image_1` into `image_2` by `depth_map`, keeping `image_1` where
+/// the depth lies within `options.focus_near` and `options.focus_far` and
+/// giving way to `image_2`, blurred by `options.depth_blur`, beyond them.
+/// The inputs are prepared and the output finished as for any mode, and
+/// the depth map is stretched over the prepared inputs.
+pub fn depth_blend(
+    image_1: DynamicImage,
+    image_2: DynamicImage,
+    depth_map: &DynamicImage,
+    options: &CombineOptions,
+    name: String,
+) -> Result<FloatingImage, ImageDataErrors> {
+    depth_blend_with_hooks(image_1, image_2, depth_map, options, name, Hooks::NONE)
+}
+
+fn depth_blend_with_hooks(
+    image_1: DynamicImage,
+    image_2: DynamicImage,
+    depth_map: &DynamicImage,
+    options: &CombineOptions,
+    name: String,
+    hooks: Hooks,
+) -> Result<FloatingImage, ImageDataErrors> {
+    let (image_1, image_2) = prepare_inputs(image_1, image_2, options, true, hooks)?;
+    let (width, height) = image_1.dimensions();
+    let depth = image::imageops::resize(&depth_map.to_luma8(), width, height, image::imageops::FilterType::Triangle);
+    let soft = match options.depth_blur {
+        Some(sigma) => timed("blurring", || image::imageops::blur(&image_2.to_rgba8(), sigma)),
+        None => image_2.to_rgba8(),
+    };
+    let focus = (options.focus_near, options.focus_far);
+    let data = timed("blending by depth", || depth::blend(&image_1.to_rgba8(), &soft, &depth, focus, hooks))?;
+    let output = FloatingImage { width, height, data, name, dpi: options.dpi };
+    finish_output(output, options)
+}
+
+/// The frames of `stack` combined as its method says, then finished like
+/// any output.
+pub fn astro_stack(stack: astro::Stack, options: &CombineOptions, name: String) -> Result<FloatingImage, ImageDataErrors> {
+    astro_stack_with_hooks(stack, options, name, Hooks::NONE)
+}
+
+fn astro_stack_with_hooks(stack: astro::Stack, options: &CombineOptions, name: String, hooks: Hooks) -> Result<FloatingImage, ImageDataErrors> {
+    log::info!("stacking {} frames", stack.frames());
+    let stacked = timed("stacking", || stack.finish_with_hooks(hooks))?;
+    let (width, height) = stacked.dimensions();
+    let output = FloatingImage { width, height, data: stacked.into_raw(), name, dpi: options.dpi };
+    finish_output(output, options)
+}
+
+/// Brings `images` to the size of the smallest and interlaces them for a
+/// lens sheet of `lpi` lenses per inch, see [`lenticular::interlace`]. The
+/// output records `dpi`, the resolution it must be printed at.
+pub fn interlace_images(images: Vec<DynamicImage>, lpi: f64, dpi: f64, name: String) -> Result<FloatingImage, ImageDataErrors> {
+    if images.len() < 2 {
+        return Err(ImageDataErrors::InvalidArgument("interlacing needs at least 2 images".to_string()));
+    }
+    if dpi / lpi < images.len() as f64 {
+        log::warn!(
+            "{} images need at least {} pixels per lens, but {} DPI at {} LPI gives {:.2}; raise the DPI",
+            images.len(),
+            images.len(),
+            dpi,
+            lpi,
+            dpi / lpi
+        );
+    }
+    let size = images.iter().map(|image| image.dimensions()).reduce(get_smallest_dimensions).expect("at least 2 images");
+    let images: Vec<_> = timed("resizing", || {
+        images.into_iter().map(|image| fit::fit_to(image, size, fit::Fit::default(), false, &[], gpu::Backend::Cpu).to_rgba8()).collect()
+    });
+    let interlaced = timed("interlacing", || lenticular::interlace(&images, lpi, dpi));
+    let (width, height) = interlaced.dimensions();
+    Ok(FloatingImage { width, height, data: interlaced.into_raw(), name, dpi: Some(dpi as f32) })
+}
+
+/// Orients, crops, trims and reshapes both inputs as `options` say, then
+/// brings them to the same size when `same_size` is set.
+fn prepare_inputs(
+    image_1: DynamicImage,
+    image_2: DynamicImage,
+    options: &CombineOptions,
+    same_size: bool,
+    hooks: Hooks,
+) -> Result<(DynamicImage, DynamicImage), ImageDataErrors> {
+    let image_1 = transform::orient(image_1, options.rotations[0], options.flips[0]);
+    let image_2 = transform::orient(image_2, options.rotations[1], options.flips[1]);
+    let image_1 = tone::adjust(image_1, options.levels[0], options.curves[0].as_ref());
+    let image_2 = tone::adjust(image_2, options.levels[1], options.curves[1].as_ref());
+    let crop_input = |image, crop: Option<geometry::CropRegion>| match crop {
+        Some(crop) => crop.apply(image),
+        None => Ok(image),
+    };
+    let image_1 = crop_input(image_1, options.input_crops[0])?;
+    let image_2 = crop_input(image_2, options.input_crops[1])?;
+    let (image_1, image_2) = if options.trim {
+        timed("trimming", || (trim::trim_image(image_1), trim::trim_image(image_2)))
+    } else {
+        (image_1, image_2)
+    };
+    let (image_1, image_2) = if options.resize.is_some() || options.crop.is_some() {
+        let reshape = |image| geometry::reshape(image, options.resize.as_ref(), options.crop.as_ref(), options.gravity);
+        timed("reshaping", || Ok::<_, ImageDataErrors>((reshape(image_1)?, reshape(image_2)?)))?
+    } else {
+        (image_1, image_2)
+    };
+    if !same_size {
+        return Ok((image_1, image_2));
+    }
+    hooks.step("resizing", 0, 1)?;
+    let resized = timed("resizing", || standardise_size(image_1, image_2, options, hooks))?;
+    hooks.step("resizing", 1, 1)?;
+    Ok(resized)
+}
+
+/// Applies everything `options` do to a combined image: trimming, making
+/// it tileable, framing, fitting to a preset, noise, the QR code and the
+/// print layout, in order.
+fn finish_output(mut output: FloatingImage, options: &CombineOptions) -> Result<FloatingImage, ImageDataErrors> {
+    if options.trim {
+        output.trim();
+    }
+    if options.make_tileable {
+        output = timed("making it tileable", || output.tileable())?;
+    }
+    if let Some(device) = options.frame {
+        output = timed("framing", || output.framed(device))?;
+    }
+    if let Some(preset) = options.preset_size {
+        output = timed("fitting to preset", || output.fitted_to(preset, options))?;
+    }
+    if options.noise.is_some() || options.grain.is_some() {
+        timed("adding noise", || output.add_noise(options))?;
+    }
+    if let Some(data) = &options.qr {
+        timed("drawing the QR code", || output.draw_qr(data, options))?;
+    }
+    if let Some(layout) = options.print {
+        output = timed("laying out the page", || output.printed(layout, options.crop_marks))?;
+    }
+    Ok(output)
+}
+
+/// The pipeline for embedders: one set of options applied to any number of
+/// image pairs.
+#[derive(Clone)]
+pub struct Combiner {
+    options: CombineOptions,
+    progress: Option<ProgressCallback>,
+    cancel: Option<CancelToken>,
+}
+
+impl Combiner {
+    pub fn new(options: CombineOptions) -> Self {
+        Combiner { options, progress: None, cancel: None }
+    }
+
+    /// Calls `callback` with each stage and how far it has got while running,
+    /// so a GUI or server can show progress.
+    pub fn on_progress(mut self, callback: impl Fn(&str, f32) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// Lets `token` abort runs: once it is cancelled they stop with
+    /// [`ImageDataErrors::Cancelled`] at their next progress report, which
+    /// stages make up to once per percent of their rows.
+    pub fn with_cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    pub fn run(&self, image_1: DynamicImage, image_2: DynamicImage) -> Result<(FloatingImage, Option<DiffReport>), ImageDataErrors> {
+        combine_with_hooks(image_1, image_2, &self.options, String::new(), self.hooks())
+    }
+
+    /// [`photomosaic`] with this combiner's options and hooks.
+    pub fn photomosaic(&self, image: DynamicImage, library: &photomosaic::TileLibrary) -> Result<FloatingImage, ImageDataErrors> {
+        photomosaic_with_hooks(image, library, &self.options, String::new(), self.hooks())
+    }
+
+    /// [`depth_blend`] with this combiner's options and hooks.
+    pub fn depth_blend(&self, image_1: DynamicImage, image_2: DynamicImage, depth_map: &DynamicImage) -> Result<FloatingImage, ImageDataErrors> {
+        depth_blend_with_hooks(image_1, image_2, depth_map, &self.options, String::new(), self.hooks())
+    }
+
+    /// [`astro_stack`] with this combiner's options and hooks.
+    pub fn astro_stack(&self, stack: astro::Stack) -> Result<FloatingImage, ImageDataErrors> {
+        astro_stack_with_hooks(stack, &self.options, String::new(), self.hooks())
+    }
+
+    fn hooks(&self) -> Hooks<'_> {
+        Hooks::new(self.progress.as_ref(), self.cancel.as_ref())
+    }
+}
+
+impl fmt::Debug for Combiner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Combiner").field("options", &self.options).finish_non_exhaustive()
+    }
+}
+
+/// Runs one stage of the pipeline, logging how long it took.
+pub fn timed<T>(stage: &str, run: impl FnOnce() -> T) -> T {
+    // Also keeps `Instant`, which panics on wasm32, out of runs nobody logs.
+    if !log::log_enabled!(log::Level::Info) {
+        return run();
+    }
+    let start = Instant::now();
+    let result = run();
+    log::info!("{} took {:.2?}", stage, start.elapsed());
+    result
+}
+
+/// Where images named by path are read from and written to, so the same
+/// pipeline can run against the filesystem, object storage or the browser.
+pub trait ImageStore {
+    fn read(&self, path: &str) -> Result<Vec<u8>, ImageDataErrors>;
+    fn write(&self, path: &str, bytes: Vec<u8>) -> Result<(), ImageDataErrors>;
+}
+
+/// Reads and decodes the image at `path` through `store`.
+pub fn load_image(store: &impl ImageStore, path: &str) -> Result<(DynamicImage, ImageFormat), ImageDataErrors> {
+    decode_image_bytes(&store.read(path)?)
+}
+
+/// The encoded result of [`combine_bytes`].
+pub struct Combined {
+    pub bytes: Vec<u8>,
+    pub format: ImageFormat,
+    pub width: u32,
+    pub height: u32,
+    pub report: Option<DiffReport>,
+}
+
+/// Combines two encoded images into an encoded output, in `format` or else
+/// in the format of `image_1`, keeping the DPI of `image_1` unless the options set one
+/// and stamping it from the Exif metadata of `image_1` when they ask.
+pub fn combine_bytes(
+    image_1: &[u8],
+    image_2: &[u8],
+    options: &CombineOptions,
+    format: Option<ImageFormat>,
+) -> Result<Combined, ImageDataErrors> {
+    let dpi = density::read_dpi(image_1);
+    let metadata = exif::read(image_1);
+    let (image_1, format_1) = decode_image_bytes(image_1)?;
+    let (image_2, _) = decode_image_bytes(image_2)?;
+    let format = format.unwrap_or(format_1);
+
+    let (mut output, report) = combine_decoded(image_1, image_2, options, String::new())?;
+    output.dpi = output.dpi.or(dpi);
+    if !options.stamp.is_empty() {
+        output.stamp(metadata.as_ref(), options)?;
+    }
+    let (width, height) = (output.width, output.height);
+    Ok(Combined { bytes: encode_image_bytes(output, format)?, format, width, height, report })
+}
+
+/// Decodes an image held in memory, such as an upload, along with its detected format.
+pub fn decode_image_bytes(bytes: &[u8]) -> Result<(DynamicImage, ImageFormat), ImageDataErrors> {
+    let format = image::guess_format(bytes).map_err(ImageDataErrors::UnableToDecodeImage)?;
+    let image = match format {
+        ImageFormat::Pnm => pnm::decode(bytes)?,
+        ImageFormat::Jpeg if jpeg::is_cmyk(bytes) => jpeg::decode_cmyk(bytes)?,
+        _ => image::load_from_memory_with_format(bytes, format).map_err(ImageDataErrors::UnableToDecodeImage)?,
+    };
+    Ok((image, format))
+}
+
+/// Decodes an image held in memory like [`decode_image_bytes`], except that
+/// JPEGs are decoded at the smallest of their 1/8, 1/4, 1/2 and full scales
+/// that still covers `covering`, see [`scaled_jpeg_size`], for thumbnails
+/// and when memory is short, where the image is only going to be shrunk anyway.
+pub fn decode_image_bytes_covering(bytes: &[u8], covering: (u32, u32)) -> Result<(DynamicImage, ImageFormat), ImageDataErrors> {
+    if image::guess_format(bytes).ok() == Some(ImageFormat::Jpeg) {
+        if let Some(image) = jpeg::decode_covering(bytes, covering)? {
+            return Ok((image, ImageFormat::Jpeg));
+        }
+    }
+    decode_image_bytes(bytes)
+}
+
+/// The size a `size` JPEG decodes to with [`decode_image_bytes_covering`].
+pub fn scaled_jpeg_size(size: (u32, u32), covering: (u32, u32)) -> (u32, u32) {
+    jpeg::scaled_size(size, covering)
+}
+
+/// The quality JPEG outputs are written at.
+pub const JPEG_QUALITY: u8 = 75;
+
+/// Encodes a combined image in `format` without touching the filesystem.
+/// Transparent images are refused by formats without alpha rather than
+/// silently losing it; [`FloatingImage::flatten`] them first.
+pub fn encode_image_bytes(output: FloatingImage, format: ImageFormat) -> Result<Vec<u8>, ImageDataErrors> {
+    if !keeps_alpha(format, &output.name) && output.has_transparency() {
+        return Err(ImageDataErrors::AlphaUnsupported(format));
+    }
+    if format == ImageFormat::Pnm {
+        return Ok(pnm::PnmEncoding::for_path(&output.name).encode(&output));
+    }
+    let dpi = output.dpi;
+    let buffer = image::RgbaImage::from_raw(output.width, output.height, output.data).ok_or(ImageDataErrors::BufferTooSmall)?;
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    // Encoder settings are spelled out rather than left to the image crate's
+    // defaults, so the same pixels always encode to the same bytes.
+    let (width, height) = buffer.dimensions();
+    let written = match format {
+        ImageFormat::Png => PngEncoder::new_with_quality(&mut bytes, CompressionType::Fast, PngFilter::Sub)
+            .encode(&buffer, width, height, image::ColorType::Rgba8),
+        ImageFormat::Jpeg => JpegEncoder::new_with_quality(&mut bytes, JPEG_QUALITY)
+            .encode(&buffer, width, height, image::ColorType::Rgba8),
+        _ => DynamicImage::ImageRgba8(buffer).write_to(&mut bytes, image::ImageOutputFormat::from(format)),
+    };
+    written.map_err(ImageDataErrors::UnableToSaveImage)?;
+    Ok(match dpi {
+        Some(dpi) => density::set_dpi(bytes.into_inner(), format, dpi),
+        None => bytes.into_inner(),
+    })
+}
+
+pub fn get_smallest_dimensions(dim_1: (u32, u32) , dim_2: (u32, u32)) -> (u32, u32) {
+    let pix_1 = dim_1.0 * dim_1.1;
+    let pix_2 = dim_2.0 * dim_2.1;
+
+    if pix_1 < pix_2 { dim_1 } else { dim_2 }
+}
+
+fn standardise_size(
+    image_1: DynamicImage,
+    image_2: DynamicImage,
+    options: &CombineOptions,
+    hooks: Hooks,
+) -> Result<(DynamicImage, DynamicImage), ImageDataErrors> {
+    let ( width, height ) = get_smallest_dimensions(image_1.dimensions(), image_2.dimensions());
+    log::debug!("standardising both images to {}x{}", width, height);
+    let fit = |image: DynamicImage| -> Result<DynamicImage, ImageDataErrors> {
+        let faces = if options.fit == fit::Fit::Crop { crop_faces(&image, options)? } else { Vec::new() };
+        fit::fit_to_with_hooks(image, (width, height), options.fit, options.smart_crop, &faces, options.backend, hooks)
+    };
+
+    if image_1.dimensions() == image_2.dimensions() {
+        // Already the same size: nothing to resize, and nothing to copy.
+        Ok(( image_1, image_2 ))
+    } else if image_2.dimensions() == ( width, height ) {
+        Ok(( fit(image_1)?, image_2 ))
+    } else { Ok(( image_1, fit(image_2)? )) }
+}
+
+/// The faces smart crops of `image` keep, when `options` ask for them.
+fn crop_faces(image: &DynamicImage, options: &CombineOptions) -> Result<Vec<faces::Face>, ImageDataErrors> {
+    if !options.smart_crop {
+        return Ok(Vec::new());
+    }
+    faces::detect(options.face_model.as_deref(), image)
+}
+
+/// Alternates the pixels of two same-sized images within `region`,
+/// `(x, y, width, height)`, keeping the first image's pixels elsewhere. The
+/// output is written over the first image's pixels, so 8-bit RGBA inputs are
+/// combined without copying either of them.
+fn combine_images(
+    image_1: DynamicImage,
+    image_2: DynamicImage,
+    region: (u32, u32, u32, u32),
+    hooks: Hooks,
+) -> Result<Vec<u8>, ImageDataErrors> {
+    let width = image_1.width();
+    let vec_1 = image_1.into_rgba8().into_raw();
+    let vec_2 = match &image_2 {
+        DynamicImage::ImageRgba8(image) => std::borrow::Cow::Borrowed(image.as_raw()),
+        other => std::borrow::Cow::Owned(other.to_rgba8().into_raw()),
+    };
+
+    alternative_pixels(vec_1, &vec_2, width, region, hooks)
+}
+
+fn alternative_pixels(
+    mut vec_1: Vec<u8>,
+    vec_2: &[u8],
+    width: u32,
+    (x, y, region_width, region_height): (u32, u32, u32, u32),
+    hooks: Hooks,
+) -> Result<Vec<u8>, ImageDataErrors> {
+    if vec_1.len() != vec_2.len() {
+        return Err(ImageDataErrors::BufferTooSmall);
+    }
+    let pixels = region_width as usize * region_height as usize;
+    let mut done = 0;
+    for row in y as usize..(y + region_height) as usize {
+        let start = row * width as usize + x as usize;
+        for index in start..start + region_width as usize {
+            hooks.step("combining", done, pixels)?;
+            done += 1;
+            // Even pixels are the first image's already; odd ones come from the second.
+            if index % 2 == 1 {
+                vec_1[index * 4..index * 4 + 4].copy_from_slice(&vec_2[index * 4..index * 4 + 4]);
+            }
+        }
+    }
+    hooks.step("combining", pixels, pixels)?;
+    Ok(vec_1)
+}
@@ -0,0 +1,98 @@
+//! 3D colour lookup tables in the `.cube` format of Adobe and Resolve, the
+//! way colourists hand over a grade. Colours between the table's points are
+//! interpolated trilinearly; alpha is left alone.
+
+use image::DynamicImage;
+
+use crate::ImageDataErrors;
+
+#[derive(Debug, Clone)]
+pub struct Lut {
+    /// Points along each axis.
+    size: usize,
+    domain_min: [f32; 3],
+    domain_max: [f32; 3],
+    /// `size`³ output colours, red changing fastest and blue slowest.
+    table: Vec<[f32; 3]>,
+}
+
+impl Lut {
+    /// Reads a `.cube` file. 1D tables are refused.
+    pub fn parse(text: &str) -> Result<Self, ImageDataErrors> {
+        let invalid = |message: String| ImageDataErrors::InvalidArgument(format!("invalid LUT: {}", message));
+        let invalid_line = |line: usize, message: &str| invalid(format!("line {}: {}", line, message));
+        let mut size = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut table = Vec::new();
+        for (number, line) in text.lines().enumerate().map(|(index, line)| (index + 1, line.trim())) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap_or_default();
+            let triple = |words: std::str::SplitWhitespace| -> Result<[f32; 3], ImageDataErrors> {
+                let values = words.map(str::parse).collect::<Result<Vec<f32>, _>>().map_err(|_| invalid_line(number, "expected numbers"))?;
+                values.try_into().map_err(|_| invalid_line(number, "expected three numbers"))
+            };
+            match keyword {
+                "TITLE" => {}
+                "LUT_1D_SIZE" => return Err(invalid_line(number, "1D LUTs are not supported, only 3D ones")),
+                "LUT_3D_SIZE" => {
+                    let points = words.next().and_then(|points| points.parse().ok()).filter(|points| (2..=256).contains(points));
+                    size = Some(points.ok_or_else(|| invalid_line(number, "`LUT_3D_SIZE` must be between 2 and 256"))?);
+                }
+                "DOMAIN_MIN" => domain_min = triple(words)?,
+                "DOMAIN_MAX" => domain_max = triple(words)?,
+                _ if keyword.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                    log::debug!("ignoring the LUT keyword {}", keyword);
+                }
+                _ => table.push(triple(line.split_whitespace())?),
+            }
+        }
+        let size = size.ok_or_else(|| invalid("`LUT_3D_SIZE` is missing".to_string()))?;
+        if table.len() != size * size * size {
+            return Err(invalid(format!("{} colours for a size of {}, not {}", table.len(), size, size * size * size)));
+        }
+        if (0..3).any(|channel| domain_max[channel] <= domain_min[channel]) {
+            return Err(invalid("`DOMAIN_MAX` must be above `DOMAIN_MIN`".to_string()));
+        }
+        Ok(Lut { size, domain_min, domain_max, table })
+    }
+
+    /// Grades RGBA `pixels` in place.
+    pub fn apply(&self, pixels: &mut [u8]) {
+        // Where each 8-bit value of each channel falls between the table's points.
+        let last = (self.size - 1) as f32;
+        let positions: Vec<[(usize, f32); 256]> = (0..3)
+            .map(|channel| {
+                std::array::from_fn(|value| {
+                    let range = self.domain_max[channel] - self.domain_min[channel];
+                    let position = ((value as f32 / 255.0 - self.domain_min[channel]) / range).clamp(0.0, 1.0) * last;
+                    let below = (position.floor() as usize).min(self.size - 2);
+                    (below, position - below as f32)
+                })
+            })
+            .collect();
+        let at = |r: usize, g: usize, b: usize| self.table[r + self.size * (g + self.size * b)];
+        let lerp = |a: [f32; 3], b: [f32; 3], t: f32| std::array::from_fn::<f32, 3, _>(|i| a[i] + (b[i] - a[i]) * t);
+        for pixel in pixels.chunks_exact_mut(4) {
+            let (r, tr) = positions[0][usize::from(pixel[0])];
+            let (g, tg) = positions[1][usize::from(pixel[1])];
+            let (b, tb) = positions[2][usize::from(pixel[2])];
+            let near = lerp(lerp(at(r, g, b), at(r + 1, g, b), tr), lerp(at(r, g + 1, b), at(r + 1, g + 1, b), tr), tg);
+            let far = lerp(lerp(at(r, g, b + 1), at(r + 1, g, b + 1), tr), lerp(at(r, g + 1, b + 1), at(r + 1, g + 1, b + 1), tr), tg);
+            let colour = lerp(near, far, tb);
+            for (channel, value) in pixel.iter_mut().zip(colour) {
+                *channel = (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+            }
+        }
+    }
+
+    /// `image` graded, as 8-bit RGBA.
+    pub fn apply_image(&self, image: DynamicImage) -> DynamicImage {
+        let mut image = image.into_rgba8();
+        self.apply(&mut image);
+        DynamicImage::ImageRgba8(image)
+    }
+}
@@ -1,134 +1,1211 @@
-extern crate core;
-
+mod archive;
 mod args;
+mod batch;
+mod cache;
+mod capture;
+mod checksum;
+mod clipboard;
+mod daemon;
+#[cfg(feature = "grpc")]
+mod grpc;
+#[cfg(feature = "gui")]
+mod gui;
+mod incremental;
+mod info;
+mod manifest;
+mod memory;
+mod mosaic;
+mod plugins;
+mod preview;
+mod script;
+mod server;
+mod sidecar;
+mod storage;
+mod template;
+mod video;
+mod watch;
+mod webhook;
+
+
+use image::{io::Reader, DynamicImage, GenericImageView, ImageFormat};
+use combiner::{
+    combine_labelled, decode_image_bytes, encode_image_bytes, get_smallest_dimensions, keeps_alpha, load_image, timed,
+    FloatingImage, ImageDataErrors, ImageStore,
+};
+use args::{
+    AnnotateArgs, AugmentArgs, Args, CaptionArgs, BenchArgs, Cli, CombineOptions, Command, IdwmArgs, Inputs, LenticularArgs, Mode, MosaicArgs, RecipeArgs, ReplayArgs, Selection,
+    Sequence, StegoArgs,
+};
+use archive::ZipOutput;
+use batch::{Job, Outcome};
+use cache::DecodeCache;
+use combiner::animate::Animation;
+use combiner::diff::DiffReport;
+use combiner::{camera_raw, npy, pdf};
+use combiner::astro::Stack;
+use combiner::photomosaic::TileLibrary;
+use combiner::recipe::Recipe;
+use combiner::pnm::PnmEncoding;
+use combiner::svg;
+use incremental::IncrementalState;
+use plugins::Plugins;
+use script::Script;
+use sidecar::Sidecar;
+use storage::Storage;
+
+#[global_allocator]
+static ALLOCATOR: memory::Accounting = memory::Accounting;
+
+fn main() -> Result<(), ImageDataErrors> {
+    if std::env::var_os(clipboard::SERVE).is_some() {
+        return clipboard::serve();
+    }
+    let cli = Cli::new()?;
+
+    match cli.command {
+        Command::Combine(args) if args.dry_run => dry_run(&args),
+        Command::Combine(args) => {
+            let mut session = Session::new(&args)?;
+            match &args.inputs {
+                Inputs::Directory { input_dir, output_dir, image_2 } if args.watch => {
+                    watch::watch(input_dir, output_dir, &args.selection, image_2, args.notify_url.as_deref(), |job| {
+                        combine(job, &args, &mut session)
+                    })
+                }
+                Inputs::Single { .. } if args.preview.is_some() => preview(&batch::jobs(&args)?[0], &args, &mut session),
+                Inputs::Single { .. } => match (&args.sequence, &args.animate) {
+                    (Some(sequence), _) => crossfade(&batch::jobs(&args)?[0], sequence, &args, &mut session),
+                    (None, Some(animation)) => animate(&batch::jobs(&args)?[0], animation, &args, &mut session),
+                    (None, None) => combine(&batch::jobs(&args)?[0], &args, &mut session).map(|_| ()),
+                },
+                Inputs::Daemon { socket } => daemon::run(socket.as_deref(), &args.options, |job, options| {
+                    combine_with(job, options, &args, &mut session)
+                }),
+                Inputs::Directory { .. } | Inputs::Manifest { .. } => {
+                    let result = batch::run(&batch::jobs(&args)?, args.strict, args.notify_url.as_deref(), |job| {
+                        combine(job, &args, &mut session)
+                    });
+                    // Finish the archive even when jobs failed, so what succeeded can be read.
+                    let finished = session.zip_output.take().map_or(Ok(()), ZipOutput::finish);
+                    result.and(finished)
+                }
+            }
+        }
+        Command::Info(paths) => {
+            for path in paths {
+                info::print_info(&combiner::probe::probe(&path)?);
+            }
+            Ok(())
+        }
+        Command::Stego(args) => stego(&args),
+        Command::Idwm(args) => idwm(&args),
+        Command::Lenticular(args) => lenticular(&args),
+        Command::Augment(args) => augment(&args),
+        Command::Annotate(args) => annotate(&args),
+        Command::Caption(args) => caption(&args),
+        Command::MosaicAssemble(args) => mosaic_assemble(&args),
+        Command::Bench(args) => bench(&args),
+        Command::Serve(args) => server::serve(&args),
+        #[cfg(feature = "grpc")]
+        Command::Grpc(args) => grpc::serve(&args),
+        #[cfg(not(feature = "grpc"))]
+        Command::Grpc(args) => Err(ImageDataErrors::UnableToServe(format!(
+            "cannot serve gRPC on {}, this build lacks the `grpc` feature",
+            args.addr
+        ))),
+        Command::Recipe(args) => recipe(&args),
+        Command::Replay(args) => replay(&args),
+        #[cfg(feature = "gui")]
+        Command::Gui(args) => gui::run(&args),
+        #[cfg(not(feature = "gui"))]
+        Command::Gui(args) => Err(ImageDataErrors::UnableToOpenWindow(format!(
+            "cannot open a window to save {}, this build lacks the `gui` feature",
+            args.output
+        ))),
+    }
+}
+
+/// State shared by every job of one run.
+struct Session {
+    cache: DecodeCache,
+    incremental: Option<IncrementalState>,
+    zip_output: Option<ZipOutput>,
+    /// The photomosaic tiles of the library directory last used.
+    tile_library: Option<(String, TileLibrary)>,
+    script: Option<Script>,
+    plugins: Plugins,
+    /// The output names templates, scripts and `--name-by` have given so
+    /// far, and the inputs of each.
+    named_outputs: batch::Named,
+}
+
+impl Session {
+    fn new(args: &Args) -> Result<Self, ImageDataErrors> {
+        let session = Session {
+            cache: DecodeCache::new(args.cache_size),
+            incremental: args.incremental.as_deref().map(IncrementalState::load).transpose()?,
+            zip_output: match (&args.zip_output, &args.inputs) {
+                (Some(path), Inputs::Directory { output_dir, .. }) => Some(ZipOutput::create(path, output_dir)?),
+                // Manifest outputs are named relative to the manifest's directory.
+                (Some(path), Inputs::Manifest { path: manifest }) => {
+                    Some(ZipOutput::create(path, &storage::resolve(manifest, ""))?)
+                }
+                _ => None,
+            },
+            tile_library: None,
+            script: args.script.as_deref().map(Script::load).transpose()?.inspect(|script| {
+                if script.names_outputs() && args.name_template.is_some() {
+                    log::warn!("the script's output_name takes the place of --name-template");
+                }
+                if script.names_outputs() && args.name_by.is_some() {
+                    log::warn!("the script's output_name takes the place of --name-by");
+                }
+            }),
+            plugins: Plugins::load(args.plugins_dir.as_deref())?,
+            named_outputs: batch::Named::new(),
+        };
+        if let Some(name) = &args.plugin {
+            session.plugins.digest(name)?;
+            if session.script.as_ref().is_some_and(Script::combines_pixels) {
+                return Err(ImageDataErrors::InvalidArgument("`--plugin` and the script's pixel function both replace the mode".to_string()));
+            }
+        }
+        Ok(session)
+    }
+}
+
+/// Combines one job's images, returning where the result was written.
+fn combine(job: &Job, args: &Args, session: &mut Session) -> Result<Outcome, ImageDataErrors> {
+    let options = CombineOptions { mode: job.mode.unwrap_or(args.options.mode), ..args.options.clone() };
+    combine_with(job, options, args, session)
+}
+
+/// Combines one job's images as `options` say, rather than the command line.
+fn combine_with(job: &Job, options: CombineOptions, args: &Args, session: &mut Session) -> Result<Outcome, ImageDataErrors> {
+    let names_outputs = session.script.as_ref().is_some_and(Script::names_outputs);
+    let output_path = if args.name_template.is_some() || names_outputs {
+        // Names may use the output size, which the headers already tell.
+        let dimensions_of = |path: &str| {
+            if let Some(raw) = &args.raw {
+                Ok((raw.width, raw.height))
+            } else if let Some(image) = render_input(path, args) {
+                Ok(image?.dimensions())
+            } else if !storage::is_file(path) {
+                Reader::new(std::io::Cursor::new(Storage.read(path)?))
+                    .with_guessed_format()
+                    .map_err(ImageDataErrors::UnableToReadImageFromPath)?
+                    .into_dimensions()
+                    .map_err(ImageDataErrors::UnableToDecodeImage)
+            } else {
+                image::image_dimensions(path).map_err(ImageDataErrors::UnableToDecodeImage)
+            }
+        };
+        let dimensions = get_smallest_dimensions(dimensions_of(&job.image_1)?, dimensions_of(&job.image_2)?);
+        match &mut session.script {
+            Some(script) if names_outputs => {
+                batch::unique_path(&script.output_path(job, options.mode, dimensions)?, job, &mut session.named_outputs)
+            }
+            _ => batch::output_path(job, args.name_template.as_ref(), options.mode, dimensions, &mut session.named_outputs),
+        }
+    } else if args.name_by.is_some() {
+        dated_output_path(job, &mut session.named_outputs)
+    } else {
+        job.output.clone()
+    };
+
+    // Everything besides the inputs that shapes the output.
+    let mut settings = serde_json::to_string(&options).expect("options are always serialisable");
+    if !args.arguments.is_empty() {
+        // The flags a sidecar records, `--background`, `--raw` and the rest.
+        settings = format!("{} flags:{}", settings, serde_json::to_string(&args.arguments).expect("strings are always serialisable"));
+    }
+    if let Some(script) = &session.script {
+        settings = format!("{} script:{}", settings, script.digest());
+    }
+    if let Some(name) = &args.plugin {
+        settings = format!("{} plugin:{}:{}", settings, name, session.plugins.digest(name)?);
+    }
+    for digest in &args.luts.digests {
+        settings = format!("{} lut:{}", settings, digest);
+    }
+    if let Some((_, digest)) = &args.displace {
+        settings = format!("{} displace:{}", settings, digest);
+    }
+    if let Some(state) = &session.incremental {
+        if state.is_up_to_date(job, &output_path, &settings) {
+            log::info!("{} is up to date", output_path);
+            return Ok(Outcome::UpToDate(output_path));
+        }
+    }
+
+    if options.mode == Mode::Photomosaic {
+        let (image, format) = timed("decoding image_1", || session.cache.get_or_decode(&job.image_1, find_image_from_path))?;
+        let output = combiner::photomosaic(image, load_tile_library(&job.image_2, &options, session)?, &options, output_path)?;
+        let written = save_output(output, format, job, args, session)?;
+        finish_job(job, &options, &written, &settings, args, session)?;
+        return Ok(Outcome::Written(written));
+    }
+
+    if options.mode == Mode::AstroStack {
+        let (reference, format) = timed("decoding image_1", || session.cache.get_or_decode(&job.image_1, find_image_from_path))?;
+        let stack = stack_frames(&job.image_2, &job.image_1, &reference, &options)?;
+        let output = combiner::astro_stack(stack, &options, output_path)?;
+        let written = save_output(output, format, job, args, session)?;
+        finish_job(job, &options, &written, &settings, args, session)?;
+        return Ok(Outcome::Written(written));
+    }
+
+    if options.mode == Mode::FlatField {
+        let written = write_flat_field(job, &output_path, &options, args, session)?;
+        finish_job(job, &options, &written, &settings, args, session)?;
+        return Ok(Outcome::Written(written));
+    }
+
+    let (image_1, image_2, format) = decode_for_output(job, &output_path, &options, args, &mut session.cache)?;
+    if let (Mode::DepthBlend, Some(path)) = (options.mode, &options.depth_map) {
+        let (depth_map, _) = timed("decoding the depth map", || session.cache.get_or_decode(path, find_image_from_path))?;
+        let output = combiner::depth_blend(image_1, image_2, &depth_map, &options, output_path)?;
+        let written = save_output(output, format, job, args, session)?;
+        finish_job(job, &options, &written, &settings, args, session)?;
+        return Ok(Outcome::Written(written));
+    }
+    let (output, report, label) = match (&mut session.script, &args.plugin) {
+        (Some(script), _) if script.combines_pixels() => {
+            let output = combiner::combine_pixels(image_1, image_2, &options, output_path, |x, y, a, b| script.pixel(x, y, a, b))?;
+            (output, None, None)
+        }
+        (_, Some(name)) => (session.plugins.combine(name, image_1, image_2, &options, output_path)?, None, None),
+        _ => combine_labelled(image_1, image_2, &options, output_path)?,
+    };
+    if let (Some(report), Some(path)) = (&report, &args.diff_report) {
+        write_report(report, path)?;
+    }
 
-use std::{fs::File, io::BufReader};
-use std::fmt::Error;
-use image::{io::Reader, DynamicImage, ImageFormat, GenericImageView, imageops::Triangle, ImageError};
-use args::Args;
+    let name = output.name.clone();
+    let pyramid = args.output_dzi.as_ref().map(|dir| (dir, output.clone()));
+    let written = save_output(output, format, job, args, session)?;
+    if let Some((dir, output)) = pyramid {
+        timed("writing the pyramid", || write_dzi(output, format, dir, args, session))?;
+    }
+    if let Some(label) = label {
+        // Beside the output, like checksum sidecars, so batches keep one per pair.
+        let mut json = serde_json::to_value(label).expect("mix labels are always serialisable");
+        json["image_1"] = job.image_1.clone().into();
+        json["image_2"] = job.image_2.clone().into();
+        let json = serde_json::to_string_pretty(&json).expect("mix labels are always serialisable");
+        let sidecar = write_output(&mut session.zip_output, &format!("{}.json", name), json.into_bytes())?;
+        log::info!("wrote {}", sidecar);
+    }
+    finish_job(job, &options, &written, &settings, args, session)?;
+    log::info!("peak memory use so far: {}", memory::format_bytes(memory::peak()));
+    Ok(Outcome::Written(written))
+}
 
-#[derive(Debug)]
-enum ImageDataErrors {
-    DifferentImageFormats,
-    BufferTooSmall,
-    UnableToReadImageFromPath(std::io::Error),
-    UnableToFormatImage(String),
-    UnableToDecodeImage(ImageError),
-    UnableToSaveImage(ImageError)
+/// Records the output `written` from `job`: its `--record` sidecar, and its
+/// `settings` in the `--incremental` state.
+fn finish_job(job: &Job, options: &CombineOptions, written: &str, settings: &str, args: &Args, session: &mut Session) -> Result<(), ImageDataErrors> {
+    if args.record {
+        write_sidecar(job, options, written, args)?;
+    }
+    if let Some(state) = &mut session.incremental {
+        state.record(job, written, settings)?;
+    }
+    Ok(())
 }
 
-struct FloatingImage {
-    width: u32,
-    height: u32,
-    data: Vec<u8>,
-    name: String,
+/// Writes the first input of `job` corrected by the flat frame of its second
+/// and the dark frame of `options`, as a 16-bit PNG or TIFF named by
+/// `output_path`. Frames are decoded at their full depth, so none of the
+/// 8-bit finishing other outputs get applies.
+fn write_flat_field(job: &Job, output_path: &str, options: &CombineOptions, args: &Args, session: &mut Session) -> Result<String, ImageDataErrors> {
+    let (light, _) = timed("decoding image_1", || find_image_from_path(&job.image_1))?;
+    let (flat, _) = timed("decoding image_2", || find_image_from_path(&job.image_2))?;
+    let dark = options.dark_frame.as_deref().map(|path| timed("decoding the dark frame", || find_image_from_path(path))).transpose()?;
+    let corrected = timed("correcting", || combiner::flat_field::correct(&light, &flat, dark.as_ref().map(|(dark, _)| dark)))?;
+    let format = ImageFormat::from_path(output_path).map_err(|_| ImageDataErrors::UnableToFormatImage(output_path.to_string()))?;
+    let bytes = timed("encoding", || corrected.encode(format))?;
+    publish(bytes, output_path, args, session)
 }
 
-impl FloatingImage {
-    fn new(width: u32, height: u32, name: String) -> Self {
-        let buffer_capacity = height * width * 4;
-        let buffer = Vec::with_capacity(buffer_capacity.try_into().unwrap());
-        FloatingImage {
-            width,
-            height,
-            data: buffer,
-            name,
+/// The output path `--name-by exif-date` gives a job, after the earlier of
+/// its inputs' capture times, or its own when neither records one.
+fn dated_output_path(job: &Job, named: &mut batch::Named) -> String {
+    let taken = [&job.image_1, &job.image_2]
+        .into_iter()
+        .filter_map(|path| combiner::exif::read(&Storage.read(path).ok()?)?.taken)
+        .min();
+    match taken {
+        Some(taken) => batch::dated_path(job, taken, named),
+        None => {
+            log::warn!("neither {} nor {} records when it was taken, so its output keeps its name", job.image_1, job.image_2);
+            job.output.clone()
         }
     }
+}
+
+/// Runs `--dry-run`: lists what every job would write, at what size and for
+/// about how much memory, from the inputs' headers alone. Inputs that must be
+/// fetched or rendered first are not read, and their sizes are left unknown.
+fn dry_run(args: &Args) -> Result<(), ImageDataErrors> {
+    let jobs = batch::jobs(args)?;
+    let probe = |path: &str| {
+        let plain = storage::is_file(path) && !is_rendered(path, args);
+        plain.then(|| combiner::probe::probe(path).map(|info| (info.width, info.height))).transpose()
+    };
+    let mut rows = Vec::new();
+    let mut failed = 0;
+    let mut named_outputs = batch::Named::new();
+    for job in &jobs {
+        let options = CombineOptions { mode: job.mode.unwrap_or(args.options.mode), ..args.options.clone() };
+        let sizes = if options.mode == Mode::Photomosaic {
+            // The second input is the tile directory.
+            probe(&job.image_1).map(|size| size.map(|(width, height)| ((width, height), (width, height))))
+        } else {
+            probe(&job.image_1).and_then(|size_1| Ok(size_1.zip(probe(&job.image_2)?)))
+        };
+        let row = match sizes {
+            Ok(Some((size_1, size_2))) => {
+                let output = match options.mode {
+                    Mode::Photomosaic => (size_1.0 * options.mosaic_scale.max(1), size_1.1 * options.mosaic_scale.max(1)),
+                    _ => output_size(size_1, size_2, &options),
+                };
+                let path = match args.name_by {
+                    Some(_) => dated_output_path(job, &mut named_outputs),
+                    None => {
+                        let dimensions = get_smallest_dimensions(size_1, size_2);
+                        batch::output_path(job, args.name_template.as_ref(), options.mode, dimensions, &mut named_outputs)
+                    }
+                };
+                let needed = memory::format_bytes(memory::estimate([size_1, size_2], output));
+                (path, format!("{}x{} {}, about {}", output.0, output.1, options.mode.name(), needed))
+            }
+            Ok(None) => (job.output.clone(), format!("{}, size known once decoded", options.mode.name())),
+            Err(e) => {
+                failed += 1;
+                (job.output.clone(), format!("cannot be read: {}", e))
+            }
+        };
+        rows.push((job, row));
+    }
 
-    fn set_data(&mut self, data: Vec<u8>) -> Result<(), ImageDataErrors> {
-        if data.len() > self.data.capacity() {
-            return Err(ImageDataErrors::BufferTooSmall)
+    let width = jobs.iter().map(|job| job.image_1.len()).max().unwrap_or(0).max("INPUT".len());
+    let output_width = rows.iter().map(|(_, (output, _))| output.len()).max().unwrap_or(0).max("OUTPUT".len());
+    println!("{:<width$} {:<output_width$} DETAIL", "INPUT", "OUTPUT", width = width, output_width = output_width);
+    for (job, (output, detail)) in &rows {
+        println!("{:<width$} {:<output_width$} {}", job.image_1, output, detail, width = width, output_width = output_width);
+    }
+    println!("{} jobs, {} cannot be read; nothing was written", jobs.len(), failed);
+    if (failed > 0 && args.strict) || (failed == jobs.len() && !jobs.is_empty()) {
+        return Err(ImageDataErrors::BatchFailed { failed, total: jobs.len() });
+    }
+    Ok(())
+}
+
+/// Runs `--preview`, then writes the output with the options it ends with,
+/// unless it was left without writing.
+fn preview(job: &Job, args: &Args, session: &mut Session) -> Result<(), ImageDataErrors> {
+    let protocol = args.preview.unwrap_or(preview::Protocol::Blocks);
+    let (image_1, image_2, _) = decode_inputs(job, &job.output, args, &mut session.cache)?;
+    match preview::run(&image_1, &image_2, args.options.clone(), protocol)? {
+        Some(options) => combine_with(job, options, args, session).map(|_| ()),
+        None => {
+            log::info!("left the preview without writing {}", job.output);
+            Ok(())
+        }
+    }
+}
+
+/// Writes the `--sequence` crossfade from one input to the other as
+/// numbered frames.
+fn crossfade(job: &Job, sequence: &Sequence, args: &Args, session: &mut Session) -> Result<(), ImageDataErrors> {
+    let (image_1, image_2, format) = decode_inputs(job, &job.output, args, &mut session.cache)?;
+    combiner::crossfade_sequence(image_1, image_2, &args.options, sequence.frames, &job.output, |index, mut frame| {
+        frame.name = sequence.pattern.render(index + 1);
+        save_output(frame, format, job, args, session).map(|_| ())
+    })?;
+    log::info!("wrote {} frames", sequence.frames);
+    Ok(())
+}
+
+/// Writes the `--animate` comparison of the two inputs as a GIF, animated
+/// PNG or animated WebP, as the output's extension says.
+fn animate(job: &Job, animation: &Animation, args: &Args, session: &mut Session) -> Result<(), ImageDataErrors> {
+    let format = output_format(&job.output)?;
+    if !matches!(format, ImageFormat::Gif | ImageFormat::Png | ImageFormat::WebP) {
+        return Err(ImageDataErrors::InvalidArgument(format!(
+            "{:?} outputs cannot be animated; name the output .gif, .png or .webp",
+            format
+        )));
+    }
+    let (image_1, image_2, _) = decode_inputs(job, &job.output, args, &mut session.cache)?;
+    let bytes = combiner::animate(image_1, image_2, &args.options, animation, format)?;
+    publish(bytes, &job.output, args, session).map(|_| ())
+}
+
+/// The photomosaic tiles made from every image in `dir`, loaded once and
+/// kept for the rest of the run. Files that cannot be decoded are skipped.
+fn load_tile_library<'a>(dir: &str, options: &CombineOptions, session: &'a mut Session) -> Result<&'a TileLibrary, ImageDataErrors> {
+    let loaded = session.tile_library.as_ref().is_some_and(|(loaded, library)| {
+        loaded == dir && library.tile_size() == options.mosaic_tile
+    });
+    if !loaded {
+        let mut library = TileLibrary::new(options.mosaic_tile);
+        timed("loading tiles", || -> Result<(), ImageDataErrors> {
+            for file in batch::directory_jobs(dir, "", &Selection::default(), "")? {
+                match find_image_from_path(&file.image_1) {
+                    Ok((image, _)) => library.add(image),
+                    Err(e) => log::warn!("skipping {}: {}", file.image_1, e),
+                }
+            }
+            Ok(())
+        })?;
+        log::info!("loaded {} tiles from {}", library.len(), dir);
+        session.tile_library = Some((dir.to_string(), library));
+    }
+    Ok(&session.tile_library.as_ref().expect("the library was just loaded").1)
+}
+
+/// `reference` stacked with every frame in `dir` besides itself, decoded
+/// one at a time so only the stack stays in memory. Frames that cannot be
+/// decoded or aligned are skipped.
+fn stack_frames(dir: &str, reference_path: &str, reference: &DynamicImage, options: &CombineOptions) -> Result<Stack, ImageDataErrors> {
+    let mut stack = Stack::new(reference, options.stack_method);
+    timed("aligning frames", || -> Result<(), ImageDataErrors> {
+        for file in batch::directory_jobs(dir, "", &Selection::default(), "")? {
+            if std::path::Path::new(&file.image_1) == std::path::Path::new(reference_path) {
+                continue;
+            }
+            match find_image_from_path(&file.image_1) {
+                Ok((frame, _)) if stack.add(&frame) => {}
+                Ok(_) => log::warn!("skipping {}: too few of its stars match the reference", file.image_1),
+                Err(e) => log::warn!("skipping {}: {}", file.image_1, e),
+            }
         }
-        self.data = data;
         Ok(())
+    })?;
+    Ok(stack)
+}
+
+/// Decodes both inputs of `job`, which must share a format, along with that format.
+fn decode_inputs(
+    job: &Job,
+    output_path: &str,
+    args: &Args,
+    cache: &mut DecodeCache,
+) -> Result<(DynamicImage, DynamicImage, ImageFormat), ImageDataErrors> {
+    let decode = |path: &str| match render_input(path, args) {
+        Some(image) => Ok((image?, output_format(output_path)?)),
+        None => find_image_from_path(path),
+    };
+    if clipboard::is_clipboard(&job.image_1) {
+        // The clipboard holds pixels in no format, so it takes the other input's.
+        let (image_2, image_format_2) = timed("decoding image_2", || cache.get_or_decode(&job.image_2, decode))?;
+        let image_1 = timed("reading the clipboard", clipboard::read)?;
+        return Ok((grade(image_1, 0, args), grade(image_2, 1, args), image_format_2));
+    }
+    let (image_1, image_format_1) = timed("decoding image_1", || cache.get_or_decode(&job.image_1, decode))?;
+    let (image_2, image_format_2) = timed("decoding image_2", || cache.get_or_decode(&job.image_2, decode))?;
+
+    if image_format_1 != image_format_2 {
+        return Err(ImageDataErrors::DifferentImageFormats);
     }
+    Ok((grade(image_1, 0, args), grade(image_2, 1, args), image_format_1))
 }
 
-fn main() -> Result<(), ImageDataErrors> {
-    let args = Args::new();
-    let (image_1, image_format_1) = find_image_from_path(args.image_1)?;
-    let (image_2, image_format_2) = find_image_from_path(args.image_2)?;
+/// The input at `index` graded with its `--lut-1` or `--lut-2`, if it has
+/// one, and the second then displaced by `--displace`.
+fn grade(image: DynamicImage, index: usize, args: &Args) -> DynamicImage {
+    let image = match &args.luts.inputs[index] {
+        Some(lut) => timed("grading", || lut.apply_image(image)),
+        None => image,
+    };
+    match (&args.displace, index) {
+        (Some((displacement, _)), 1) => timed("displacing", || displacement.apply(&image)),
+        _ => image,
+    }
+}
+
+/// Decodes both inputs of `job` as [`decode_inputs`] does, except that JPEG
+/// inputs that only get shrunk to the output are decoded at the smallest
+/// scale that still covers it, unless `--full-decode` is set. When the inputs
+/// are much larger than the output, as for thumbnails, that gives an output of
+/// the same size and almost the same pixels many times faster and in a
+/// fraction of the memory. Under `--max-memory`, scaled decoding is also used
+/// when a full decode would not fit, and jobs that fit neither way fail with
+/// the memory they need.
+fn decode_for_output(
+    job: &Job,
+    output_path: &str,
+    options: &CombineOptions,
+    args: &Args,
+    cache: &mut DecodeCache,
+) -> Result<(DynamicImage, DynamicImage, ImageFormat), ImageDataErrors> {
+    let local_size = |path: &str| {
+        let plain = storage::is_file(path) && !is_rendered(path, args);
+        plain.then(|| image::image_dimensions(path).ok()).flatten()
+    };
+    // Only local files tell their size without being read; others are decoded
+    // whole, and only what combining them takes can be checked.
+    let (Some(size_1), Some(size_2)) = (local_size(&job.image_1), local_size(&job.image_2)) else {
+        let (image_1, image_2, format) = decode_inputs(job, output_path, args, cache)?;
+        if let Some(limit) = args.max_memory {
+            let sizes = [image_1.dimensions(), image_2.dimensions()];
+            let needed = memory::estimate(sizes, output_size(sizes[0], sizes[1], options)) - memory::estimate(sizes, (0, 0));
+            check_memory(needed, limit)?;
+        }
+        return Ok((image_1, image_2, format));
+    };
+    let output = output_size(size_1, size_2, options);
+    let jpeg = |path: &str| {
+        let format = Reader::open(path).and_then(Reader::with_guessed_format).ok().and_then(|reader| reader.format());
+        format == Some(ImageFormat::Jpeg)
+    };
+    let scaled = |path: &str, size| if scales_freely(options) && jpeg(path) { combiner::scaled_jpeg_size(size, output) } else { size };
+    let scaled_sizes = [scaled(&job.image_1, size_1), scaled(&job.image_2, size_2)];
+    let shrinks = scaled_sizes != [size_1, size_2];
+    let mut use_scaled = shrinks && !args.full_decode;
 
+    if let Some(limit) = args.max_memory {
+        let available = limit.saturating_sub(memory::in_use());
+        let mut needed = memory::estimate([size_1, size_2], output);
+        if args.cache_size > 0 {
+            // The cache keeps a copy of each input.
+            needed += memory::estimate([size_1, size_2], (0, 0));
+        }
+        if !use_scaled && needed > available && shrinks {
+            log::info!(
+                "decoding at {}x{} and {}x{} to combine within {} ({} needed at full size)",
+                scaled_sizes[0].0,
+                scaled_sizes[0].1,
+                scaled_sizes[1].0,
+                scaled_sizes[1].1,
+                memory::format_bytes(limit),
+                memory::format_bytes(needed),
+            );
+            use_scaled = true;
+        }
+        if use_scaled {
+            needed = memory::estimate(scaled_sizes, output);
+        }
+        check_memory(needed, limit)?;
+    }
+    if !use_scaled {
+        return decode_inputs(job, output_path, args, cache);
+    }
+
+    log::debug!(
+        "decoding at {}x{} and {}x{} for a {}x{} output",
+        scaled_sizes[0].0,
+        scaled_sizes[0].1,
+        scaled_sizes[1].0,
+        scaled_sizes[1].1,
+        output.0,
+        output.1
+    );
+    let decode = |path: &str| {
+        let bytes = std::fs::read(path).map_err(ImageDataErrors::UnableToReadImageFromPath)?;
+        combiner::decode_image_bytes_covering(&bytes, output)
+    };
+    // Kept out of the cache: a smaller copy would be wrong for other outputs.
+    let (image_1, image_format_1) = timed("decoding image_1", || decode(&job.image_1))?;
+    let (image_2, image_format_2) = timed("decoding image_2", || decode(&job.image_2))?;
     if image_format_1 != image_format_2 {
         return Err(ImageDataErrors::DifferentImageFormats);
     }
+    Ok((grade(image_1, 0, args), grade(image_2, 1, args), image_format_1))
+}
 
-    let (image_1, image_2) = standardise_size(image_1, image_2);
-    let mut output = FloatingImage::new(image_1.width(), image_1.height(), args.output);
+/// Fails with [`ImageDataErrors::MemoryLimitExceeded`] unless `needed` more
+/// bytes fit within `limit`.
+fn check_memory(needed: u64, limit: u64) -> Result<(), ImageDataErrors> {
+    let in_use = memory::in_use();
+    if needed > limit.saturating_sub(in_use) {
+        return Err(ImageDataErrors::MemoryLimitExceeded { needed: needed + in_use, limit });
+    }
+    Ok(())
+}
 
-    let combined_data = combine_images(image_1, image_2);
-    output.set_data(combined_data)?;
+/// The size of the output made from inputs of these sizes, before finishing.
+fn output_size(size_1: (u32, u32), size_2: (u32, u32), options: &CombineOptions) -> (u32, u32) {
+    match options.canvas {
+        Some(canvas) => canvas,
+        None if options.mode.resizes_inputs() => combiner::get_smallest_dimensions(size_1, size_2),
+        None => (size_1.0.max(size_2.0), size_1.1.max(size_2.1)),
+    }
+}
+
+/// Whether inputs can be decoded at a reduced scale without changing the
+/// output's size: they must only be shrunk to it, not rotated, cropped,
+/// trimmed or reshaped first.
+fn scales_freely(options: &CombineOptions) -> bool {
+    options.mode.resizes_inputs()
+        && options.canvas.is_none()
+        && options.rotations.iter().all(|&degrees| degrees == 0.0)
+        && options.input_crops.iter().all(Option::is_none)
+        && !options.trim
+        && options.resize.is_none()
+        && options.crop.is_none()
+}
+
+/// Encodes an output in `format` and writes it where its name says,
+/// returning where it went.
+fn save_output(
+    mut output: FloatingImage,
+    image_format_1: ImageFormat,
+    job: &Job,
+    args: &Args,
+    session: &mut Session,
+) -> Result<String, ImageDataErrors> {
+    if let Some(lut) = &args.luts.output {
+        timed("grading", || lut.apply(&mut output.data));
+    }
+    if !args.options.stamp.is_empty() {
+        let exif = Storage.read(&job.image_1).ok().and_then(|bytes| combiner::exif::read(&bytes));
+        timed("stamping", || output.stamp(exif.as_ref(), &args.options))?;
+    }
+    if args.to_clipboard {
+        clipboard::write(&output)?;
+        if clipboard::is_clipboard(&output.name) {
+            return Ok(output.name);
+        }
+    }
+    if !args.deterministic {
+        output.dpi = output.dpi.or_else(|| input_dpi(&job.image_1));
+    }
+    if let Some(profile) = &args.proof {
+        write_proof(&output, profile, session)?;
+    }
+    if let Some(profile) = &args.gamut_warn {
+        let mut image = image::RgbaImage::from_raw(output.width, output.height, std::mem::take(&mut output.data))
+            .ok_or(ImageDataErrors::BufferTooSmall)?;
+        let marked = timed("checking the gamut", || combiner::icc::mark_out_of_gamut(&mut image, profile, args.gamut_colour));
+        log::info!("{} of {} pixels are out of gamut", marked, image.width() * image.height());
+        output.data = image.into_raw();
+    }
+    let name = output.name.clone();
+    if npy::is_npy(&name) {
+        // Arrays are always RGB, so the same model input fits every output.
+        output.flatten(args.background.unwrap_or([255; 3]));
+        let bytes = timed("encoding", || npy::encode(&output, args.npy_dtype));
+        return publish(bytes, &name, args, session);
+    }
+    if let Some(background) = args.background {
+        if !keeps_alpha(image_format_1, &output.name) {
+            output.flatten(background);
+        }
+    }
+    if let Some(bytes) = timed("encoding", || session.plugins.encode(&output)) {
+        return publish(bytes?, &name, args, session);
+    }
+    // Transparent PNM outputs without alpha fall through to be refused.
+    let encode = |output: FloatingImage| match image_format_1 {
+        ImageFormat::Pnm if keeps_alpha(ImageFormat::Pnm, &output.name) || !output.has_transparency() => Ok(PnmEncoding { maxval: args.pnm_maxval, ..PnmEncoding::for_path(&output.name) }.encode(&output)),
+        format => encode_image_bytes(output, format),
+    };
+    let bytes = timed("encoding", || encode(output))?;
+    publish(bytes, &name, args, session)
+}
+
+/// Writes the Deep Zoom pyramid of `output` into `dir`, as `<stem>.dzi`
+/// beside a `<stem>_files` directory of tiles. Tiles are JPEG when the
+/// output is, and PNG otherwise.
+fn write_dzi(mut output: FloatingImage, format: ImageFormat, dir: &str, args: &Args, session: &mut Session) -> Result<(), ImageDataErrors> {
+    let (tile_format, extension) = match format {
+        ImageFormat::Jpeg => (ImageFormat::Jpeg, "jpg"),
+        _ => (ImageFormat::Png, "png"),
+    };
+    if !keeps_alpha(tile_format, "") {
+        output.flatten(args.background.unwrap_or([255; 3]));
+    }
+    let stem = std::path::Path::new(&output.name).file_stem().unwrap_or_default().to_string_lossy().into_owned();
+    let base = std::path::Path::new(dir).join(stem).to_string_lossy().into_owned();
+    let (width, height) = (output.width, output.height);
+    let pixels = image::RgbaImage::from_raw(width, height, output.data).ok_or(ImageDataErrors::BufferTooSmall)?;
+
+    let mut count = 0;
+    combiner::dzi::tiles(pixels, |level, column, row, tile| {
+        let name = format!("{}_files/{}/{}_{}.{}", base, level, column, row, extension);
+        let (width, height) = tile.dimensions();
+        let tile = FloatingImage { width, height, data: tile.into_raw(), name: name.clone(), dpi: None };
+        count += 1;
+        write_output(&mut session.zip_output, &name, encode_image_bytes(tile, tile_format)?).map(|_| ())
+    })?;
+    let descriptor = combiner::dzi::descriptor(width, height, extension);
+    let written = write_output(&mut session.zip_output, &format!("{}.dzi", base), descriptor.into_bytes())?;
+    log::info!("wrote {} with {} tiles", written, count);
+    Ok(())
+}
+
+/// Writes an encoded output to `name` along with the checksum `--checksum`
+/// asks for, returning where it went.
+fn publish(bytes: Vec<u8>, name: &str, args: &Args, session: &mut Session) -> Result<String, ImageDataErrors> {
+    // Hashed before writing, so the output is never read back.
+    let digest = args.checksum.map(|algorithm| (algorithm, algorithm.digest(&bytes)));
+    let written = timed("writing", || write_output(&mut session.zip_output, name, bytes))?;
+    log::info!("wrote {}", written);
+    if let Some((algorithm, digest)) = digest {
+        if args.checksum_sidecar {
+            let file_name = std::path::Path::new(name).file_name().unwrap_or_default().to_string_lossy();
+            let line = checksum::line(&digest, &file_name);
+            let sidecar = format!("{}.{}", name, algorithm.extension());
+            let sidecar = write_output(&mut session.zip_output, &sidecar, line.into_bytes())?;
+            log::info!("wrote {}", sidecar);
+        } else {
+            print!("{}", checksum::line(&digest, &written));
+        }
+    }
+    Ok(written)
+}
+
+/// Writes `output` beside its soft proof in `profile` to a PNG named after
+/// it, `photo_proof.png` for `photo.jpg`.
+fn write_proof(output: &FloatingImage, profile: &combiner::icc::Profile, session: &mut Session) -> Result<(), ImageDataErrors> {
+    let image = image::RgbaImage::from_raw(output.width, output.height, output.data.clone()).ok_or(ImageDataErrors::BufferTooSmall)?;
+    let sheet = timed("soft-proofing", || combiner::icc::proof_sheet(&image, profile));
+    let path = std::path::Path::new(&output.name);
+    let name = path.with_file_name(format!("{}_proof.png", path.file_stem().unwrap_or_default().to_string_lossy()));
+    let name = name.to_string_lossy().into_owned();
+    let (width, height) = sheet.dimensions();
+    let sheet = FloatingImage { width, height, data: sheet.into_raw(), name: name.clone(), dpi: output.dpi };
+    let written = write_output(&mut session.zip_output, &name, encode_image_bytes(sheet, ImageFormat::Png)?)?;
+    log::info!("wrote {}", written);
+    Ok(())
+}
 
-    if let Err(e) = image::save_buffer_with_format(output.name, &output.data, output.width, output.height, image::ColorType::Rgba8, image_format_1) {
-        Err(ImageDataErrors::UnableToSaveImage(e))
+/// Writes the `--record` sidecar of the output `written` from `job`.
+fn write_sidecar(job: &Job, options: &CombineOptions, written: &str, args: &Args) -> Result<(), ImageDataErrors> {
+    let sidecar = Sidecar::record(job, options, &args.arguments)?;
+    let json = serde_json::to_string_pretty(&sidecar).expect("sidecars are always serialisable");
+    let path = sidecar::path_for(written);
+    Storage.write(&path, json.into_bytes())?;
+    log::info!("wrote {}", path);
+    Ok(())
+}
+
+/// Runs the `replay` subcommand: makes the output a sidecar recorded again,
+/// from the same inputs, in the working directory it was made in.
+fn replay(args: &ReplayArgs) -> Result<(), ImageDataErrors> {
+    let json = Storage.read(&args.sidecar)?;
+    let recorded: Sidecar = serde_json::from_slice(&json)
+        .map_err(|e| ImageDataErrors::InvalidArgument(format!("{} is not a sidecar: {}", args.sidecar, e)))?;
+    let output = match &args.output {
+        Some(output) => output.as_str(),
+        None => sidecar::output_for(&args.sidecar)?,
+    };
+    // Made absolute before moving to the recorded directory, which the
+    // recorded paths are relative to.
+    let output = if storage::is_remote(output) {
+        output.to_string()
     } else {
-        Ok(())
+        std::path::absolute(output).map_err(ImageDataErrors::UnableToReadDirectory)?.to_string_lossy().into_owned()
+    };
+    if recorded.version != env!("CARGO_PKG_VERSION") {
+        log::warn!("{} was made by version {}, and is replayed by {}", output, recorded.version, env!("CARGO_PKG_VERSION"));
+    }
+    std::env::set_current_dir(&recorded.directory).map_err(ImageDataErrors::UnableToReadDirectory)?;
+    for (name, input) in [("image_1", &recorded.image_1), ("image_2", &recorded.image_2)] {
+        if input.is_unchanged()? {
+            continue;
+        }
+        if !args.allow_changed {
+            return Err(ImageDataErrors::InvalidArgument(format!(
+                "{} {} has changed since the output was made; pass --allow-changed to replay it anyway",
+                name, input.path
+            )));
+        }
+        log::warn!("{} {} has changed since the output was made", name, input.path);
+    }
+
+    let options = recorded.options.clone();
+    let combine_args = Args::replayed(recorded.arguments, options.clone(), recorded.image_1.path, recorded.image_2.path, output)?;
+    let mut session = Session::new(&combine_args)?;
+    combine_with(&batch::jobs(&combine_args)?[0], options, &combine_args, &mut session).map(|_| ())
+}
+
+/// Writes an output into the run's archive when there is one, or else to its
+/// file or object, returning where it went.
+fn write_output(zip_output: &mut Option<ZipOutput>, name: &str, bytes: Vec<u8>) -> Result<String, ImageDataErrors> {
+    match zip_output {
+        Some(zip) => zip.add(name, &bytes),
+        None => Storage.write(name, bytes).map(|_| name.to_string()),
+    }
+}
+
+/// Runs the `stego` subcommand.
+fn stego(args: &StegoArgs) -> Result<(), ImageDataErrors> {
+    match args {
+        StegoArgs::Embed { image, output, message } => {
+            let format = output_format(output)?;
+            if !combiner::stego::keeps_samples(format, output) {
+                return Err(ImageDataErrors::InvalidArgument(format!(
+                    "{:?} outputs would lose the hidden message; write a lossless format such as PNG",
+                    format
+                )));
+            }
+            let (decoded, _) = find_image_from_path(image)?;
+            let mut pixels = decoded.to_rgba8();
+            combiner::stego::embed(&mut pixels, message)?;
+            log::info!("hid {} bytes of a possible {}", message.len(), combiner::stego::capacity(&pixels));
+
+            save_pixels(pixels, image, output)
+        }
+        StegoArgs::Extract { image, output } => {
+            let (decoded, _) = find_image_from_path(image)?;
+            let message = combiner::stego::extract(&decoded.to_rgba8())?;
+            match output {
+                Some(path) => std::fs::write(path, message).map_err(ImageDataErrors::UnableToWriteReport),
+                None => {
+                    use std::io::Write;
+                    std::io::stdout().write_all(&message).map_err(ImageDataErrors::UnableToWriteReport)
+                }
+            }
+        }
     }
 }
 
-fn find_image_from_path(path: String) -> Result<(DynamicImage, ImageFormat), ImageDataErrors> {
-    match Reader::open(&path) {
+/// Runs the `idwm` subcommand.
+fn idwm(args: &IdwmArgs) -> Result<(), ImageDataErrors> {
+    match args {
+        IdwmArgs::Embed { image, output, key, strength } => {
+            let (decoded, _) = find_image_from_path(image)?;
+            let mut pixels = decoded.to_rgba8();
+            timed("watermarking", || combiner::watermark::embed(&mut pixels, key, *strength));
+            save_pixels(pixels, image, output)
+        }
+        IdwmArgs::Verify { image, key } => {
+            let (decoded, _) = find_image_from_path(image)?;
+            let detection = combiner::watermark::detect(&decoded.to_rgba8(), key);
+            let verdict = if detection.detected { "watermark detected" } else { "no watermark found" };
+            println!(
+                "{}: {} (z = {:.2}, chance of a false match {:.1e})",
+                image, verdict, detection.z_score, detection.false_match_probability
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Runs the `mosaic-assemble` subcommand.
+fn mosaic_assemble(args: &MosaicArgs) -> Result<(), ImageDataErrors> {
+    if !storage::is_file(&args.output) || output_format(&args.output)? != ImageFormat::Png {
+        return Err(ImageDataErrors::InvalidArgument(
+            "mosaics are streamed to a local PNG file; name the output .png".to_string(),
+        ));
+    }
+    let decode = |path: &str| find_image_from_path(path).map(|(tile, _)| tile);
+    let (width, height) = timed("assembling", || mosaic::assemble(&args.tile_dir, &args.pattern, args.overlap, &args.output, decode))?;
+    log::info!("wrote {} at {}x{}", args.output, width, height);
+    Ok(())
+}
+
+/// Runs the `annotate` subcommand.
+fn annotate(args: &AnnotateArgs) -> Result<(), ImageDataErrors> {
+    let read_text = |path: &str| {
+        String::from_utf8(Storage.read(path)?)
+            .map_err(|_| ImageDataErrors::InvalidArgument(format!("{} is not UTF-8 text", path)))
+    };
+    let (decoded, _) = find_image_from_path(&args.image)?;
+    let annotations = read_text(&args.annotations)?;
+    let annotations = if args.annotations.to_ascii_lowercase().ends_with(".json") {
+        let dataset: combiner::annotate::CocoDataset = serde_json::from_str(&annotations)
+            .map_err(|e| ImageDataErrors::InvalidArgument(format!("{} is not COCO annotations: {}", args.annotations, e)))?;
+        dataset.annotations_for(&args.image, args.image_id)?
+    } else {
+        let names = match &args.names {
+            Some(path) => read_text(path)?.lines().map(|name| name.trim().to_string()).collect(),
+            None => Vec::new(),
+        };
+        combiner::annotate::parse_yolo(&annotations, &names, decoded.dimensions())?
+    };
+    log::info!("drawing {} annotations", annotations.len());
+
+    let mut pixels = decoded.to_rgba8();
+    timed("annotating", || combiner::annotate::draw(&mut pixels, &annotations));
+    save_pixels(pixels, &args.image, &args.output)
+}
+
+/// Runs the `caption` subcommand.
+fn caption(args: &CaptionArgs) -> Result<(), ImageDataErrors> {
+    let (decoded, _) = find_image_from_path(&args.image)?;
+    let pixels = timed("captioning", || combiner::caption::draw(&decoded.to_rgba8(), &args.caption))?;
+    save_pixels(pixels, &args.image, &args.output)
+}
+
+/// Runs the `augment` subcommand: writes `variants` randomly transformed
+/// copies of every image under the input directory, mirroring its tree, and
+/// records what was done to each in a JSON manifest. Images that cannot be
+/// decoded are skipped with a warning rather than stopping the run.
+fn augment(args: &AugmentArgs) -> Result<(), ImageDataErrors> {
+    let selection = Selection { recursive: args.recursive, ..Selection::default() };
+    let sources = batch::directory_jobs(&args.input_dir, &args.output_dir, &selection, "")?;
+    if sources.is_empty() {
+        return Err(ImageDataErrors::InvalidArgument(format!("no images in {}", args.input_dir)));
+    }
+    let decode = |path: &str| {
+        find_image_from_path(path).map(|(image, _)| image).inspect_err(|e| log::warn!("skipping {}: {}", path, e)).ok()
+    };
+
+    let mut records = Vec::new();
+    for (index, source) in sources.iter().enumerate() {
+        let Some(image) = timed("decoding", || decode(&source.image_1)) else { continue };
+        let relative = std::path::Path::new(&source.image_1).strip_prefix(&args.input_dir).unwrap_or(source.image_1.as_ref());
+        let output = std::path::Path::new(&source.output);
+        let (stem, extension) = (output.file_stem().unwrap_or_default(), output.extension().unwrap_or_default());
+        for variant in 1..=args.variants {
+            // Seeded by the source's place in the tree, so moving the output directory changes nothing.
+            let name = format!("{}#{}", relative.to_string_lossy(), variant);
+            let plan = combiner::augment::plan(&args.augmentation, args.seed, &name, index, sources.len());
+            let partner = match plan.blend {
+                Some(blend) => match decode(&sources[blend.partner].image_1) {
+                    Some(partner) => Some(partner),
+                    None => continue,
+                },
+                None => None,
+            };
+            let pixels = timed("augmenting", || combiner::augment::apply(image.clone(), &plan, partner));
+
+            let path = output.with_file_name(format!("{}_{}.{}", stem.to_string_lossy(), variant, extension.to_string_lossy()));
+            let path = path.to_string_lossy().into_owned();
+            let (width, height) = pixels.dimensions();
+            let floating = FloatingImage { width, height, data: pixels.into_raw(), name: path.clone(), dpi: input_dpi(&source.image_1) };
+            Storage.write(&path, encode_image_bytes(floating, output_format(&path)?)?)?;
+
+            let mut record = serde_json::to_value(&plan).expect("augment plans are always serialisable");
+            if let Some(blend) = plan.blend {
+                record["blend"]["partner"] = sources[blend.partner].image_1.clone().into();
+            }
+            record["source"] = source.image_1.clone().into();
+            record["output"] = path.into();
+            record["variant"] = variant.into();
+            records.push(record);
+        }
+    }
+
+    log::info!("wrote {} variants of {} images", records.len(), sources.len());
+    let manifest = serde_json::json!({ "seed": args.seed, "variants": records });
+    let manifest = serde_json::to_string_pretty(&manifest).expect("augment manifests are always serialisable");
+    Storage.write(&args.manifest, manifest.into_bytes())
+}
+
+/// Runs the `recipe` subcommand: builds every output of the recipe, making
+/// the steps they share once.
+fn recipe(args: &RecipeArgs) -> Result<(), ImageDataErrors> {
+    let json = String::from_utf8(Storage.read(&args.path)?)
+        .map_err(|_| ImageDataErrors::InvalidArgument(format!("{} is not UTF-8 text", args.path)))?;
+    let recipe: Recipe = serde_json::from_str(&json)
+        .map_err(|e| ImageDataErrors::InvalidArgument(format!("{} is not a recipe: {}", args.path, e)))?;
+    let (graph, outputs) = recipe.build()?;
+    log::info!("{} outputs from {} operations", outputs.len(), graph.len());
+
+    let load = |path: &str| timed("decoding", || find_image_from_path(&storage::resolve(&args.path, path)).map(|(image, _)| image));
+    for mut output in combiner::evaluate_graph(&graph, &outputs, &args.options, load)? {
+        let path = storage::resolve(&args.path, &output.name);
+        let format = output_format(&path)?;
+        if let Some(background) = args.background.filter(|_| !keeps_alpha(format, &path)) {
+            output.flatten(background);
+        }
+        Storage.write(&path, timed("encoding", || encode_image_bytes(output, format))?)?;
+        log::info!("wrote {}", path);
+    }
+    Ok(())
+}
+
+/// Runs the `bench` subcommand, printing the throughput of every stage at
+/// every size in megapixels per second.
+fn bench(args: &BenchArgs) -> Result<(), ImageDataErrors> {
+    use combiner::bench::{self, StageTiming, STAGES};
+
+    println!(
+        "megapixels per second: {} mode, {:?} fit, {} backend, {:?} output, {} thread(s), {} run(s) each",
+        args.options.mode.name(),
+        args.options.fit,
+        args.options.backend.name(),
+        args.format,
+        args.threads,
+        args.runs
+    );
+    println!("{:<11} {:>9} {:>9} {:>9} {:>9}", "size", STAGES[0], STAGES[1], STAGES[2], "pipeline");
+    for &(width, height) in &args.sizes {
+        let (image_1, image_2) = timed("making the inputs", || bench::synthetic_pair(width, height, 0));
+        let run_thread = || {
+            let mut totals = [StageTiming::default(); 3];
+            for _ in 0..args.runs {
+                let timings = bench::run_stages(image_1.clone(), image_2.clone(), &args.options, args.format)?;
+                totals = [0, 1, 2].map(|stage| totals[stage] + timings[stage]);
+            }
+            Ok::<_, ImageDataErrors>(totals)
+        };
+        let threads = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..args.threads).map(|_| scope.spawn(run_thread)).collect();
+            workers.into_iter().map(|worker| worker.join().expect("benchmark threads do not panic")).collect::<Result<Vec<_>, _>>()
+        })?;
+        // The threads ran side by side, so their throughputs add up.
+        let throughput = |timing: fn(&[StageTiming; 3]) -> StageTiming| threads.iter().map(|totals| timing(totals).throughput()).sum::<f64>();
+        let pipeline = |totals: &[StageTiming; 3]| {
+            StageTiming { megapixels: totals[2].megapixels, seconds: totals.iter().map(|timing| timing.seconds).sum() }
+        };
+        println!(
+            "{:<11} {:>9.1} {:>9.1} {:>9.1} {:>9.1}",
+            format!("{}x{}", width, height),
+            throughput(|totals| totals[0]),
+            throughput(|totals| totals[1]),
+            throughput(|totals| totals[2]),
+            throughput(pipeline)
+        );
+    }
+    Ok(())
+}
+
+/// Runs the `lenticular` subcommand.
+fn lenticular(args: &LenticularArgs) -> Result<(), ImageDataErrors> {
+    let (output, bytes) = match args {
+        LenticularArgs::Interlace { images, output, lpi, dpi } => {
+            let decoded = images.iter().map(|image| find_image_from_path(image).map(|(decoded, _)| decoded)).collect::<Result<_, _>>()?;
+            let interlaced = combiner::interlace_images(decoded, *lpi, *dpi, output.clone())?;
+            log::info!("print {} at exactly {} DPI", output, dpi);
+            (output, encode_image_bytes(interlaced, output_format(output)?)?)
+        }
+        LenticularArgs::Calibrate { output, lpi, dpi, spread, step } => {
+            let bands = (2.0 * spread / step).round() as usize + 1;
+            let lpis: Vec<f64> = (0..bands).map(|band| lpi - spread + band as f64 * step).collect();
+            let pattern = combiner::lenticular::calibration_pattern(&lpis, *dpi);
+            let (width, height) = pattern.dimensions();
+            let floating = FloatingImage { width, height, data: pattern.into_raw(), name: output.clone(), dpi: Some(*dpi as f32) };
+            (output, encode_image_bytes(floating, output_format(output)?)?)
+        }
+    };
+    Storage.write(output, bytes)
+}
+
+/// Encodes pixels derived from the image at `source` into `output`, in the
+/// format its extension names and keeping the source's DPI.
+fn save_pixels(pixels: image::RgbaImage, source: &str, output: &str) -> Result<(), ImageDataErrors> {
+    let (width, height) = pixels.dimensions();
+    let floating = FloatingImage { width, height, data: pixels.into_raw(), name: output.to_string(), dpi: input_dpi(source) };
+    let bytes = encode_image_bytes(floating, output_format(output)?)?;
+    if storage::is_file(output) {
+        std::fs::write(output, bytes).map_err(|e| ImageDataErrors::UnableToSaveImage(image::ImageError::IoError(e)))
+    } else {
+        Storage.write(output, bytes)
+    }
+}
+
+fn find_image_from_path(path: &str) -> Result<(DynamicImage, ImageFormat), ImageDataErrors> {
+    if !storage::is_file(path) {
+        return load_image(&Storage, path);
+    }
+    match Reader::open(path) {
         Ok(image_reader) => {
             if let Some(image_format) = image_reader.format() {
+                // The library decodes these itself, covering what `image` gets wrong.
+                if matches!(image_format, ImageFormat::Pnm | ImageFormat::Jpeg) {
+                    let bytes = std::fs::read(path).map_err(ImageDataErrors::UnableToReadImageFromPath)?;
+                    return decode_image_bytes(&bytes);
+                }
                 match image_reader.decode() {
                     Ok(image) => Ok((image, image_format)),
                     Err(e) => Err(ImageDataErrors::UnableToDecodeImage(e))
                 }
             } else {
-                return Err(ImageDataErrors::UnableToFormatImage(path));
+                Err(ImageDataErrors::UnableToFormatImage(path.to_string()))
             }
         },
         Err(e) => Err(ImageDataErrors::UnableToReadImageFromPath(e))
     }
 }
 
-fn get_smallest_dimensions(dim_1: (u32, u32) , dim_2: (u32, u32)) -> (u32, u32) {
-    let pix_1 = dim_1.0 * dim_1.1;
-    let pix_2 = dim_2.0 * dim_2.1;
+/// The DPI recorded in a local input, which sits in its first few kilobytes.
+/// Remote inputs are not fetched a second time just for it.
+fn input_dpi(path: &str) -> Option<f32> {
+    use std::io::Read;
 
-    return if pix_1 < pix_2 { dim_1 } else { dim_2 }
+    if !storage::is_file(path) {
+        return None;
+    }
+    let mut head = Vec::new();
+    std::fs::File::open(path).ok()?.take(64 * 1024).read_to_end(&mut head).ok()?;
+    combiner::density::read_dpi(&head)
 }
 
-fn standardise_size(image_1: DynamicImage, image_2: DynamicImage) -> (DynamicImage, DynamicImage) {
-    let ( width, height ) = get_smallest_dimensions(image_1.dimensions(), image_2.dimensions());
-    println!("width = {} & height = {}", width, height);
-
-    if image_2.dimensions() == ( width, height ) {
-        ( image_1.resize_exact(width, height, Triangle), image_2 )
-    } else { ( image_1, image_2.resize_exact(width, height, Triangle) ) }
+/// Reads the inputs `image` cannot decode itself: raw buffers, SVG drawings,
+/// PDF pages and camera RAW files. Other paths give `None`.
+fn render_input(path: &str, args: &Args) -> Option<Result<DynamicImage, ImageDataErrors>> {
+    let rendered = if let Some(raw) = &args.raw {
+        Storage.read(path).and_then(|bytes| raw.decode(&bytes))
+    } else if svg::is_svg(path) {
+        Storage.read(path).and_then(|bytes| svg::rasterize(&bytes, &args.svg))
+    } else if let Some((document, page)) = pdf::split_page(path) {
+        Storage.read(document).and_then(|bytes| pdf::rasterize(bytes, page, args.pdf_dpi))
+    } else if camera_raw::is_camera_raw(path) {
+        Storage.read(path).and_then(|bytes| camera_raw::decode(&bytes))
+    } else if let Some((video, seconds)) = video::split_timestamp(path) {
+        video::extract_frame(video, seconds)
+    } else if let Some(source) = captured(path, args) {
+        capture::grab(source)
+    } else {
+        return None;
+    };
+    Some(rendered)
 }
 
-fn combine_images(image_1: DynamicImage, image_2: DynamicImage) -> Vec<u8> {
-    let vec_1 = image_1.to_rgba8().into_vec();
-    let vec_2 = image_2.to_rgba8().into_vec();
-
-    alternative_pixels(vec_1, vec_2)
+/// Whether [`render_input`] reads `path`, rather than `image`, or it is
+/// the clipboard, which [`decode_inputs`] reads.
+fn is_rendered(path: &str, args: &Args) -> bool {
+    args.raw.is_some()
+        || clipboard::is_clipboard(path)
+        || svg::is_svg(path)
+        || pdf::split_page(path).is_some()
+        || camera_raw::is_camera_raw(path)
+        || video::split_timestamp(path).is_some()
+        || captured(path, args).is_some()
 }
 
-fn alternative_pixels(vec_1: Vec<u8>, vec_2: Vec<u8>) -> Vec<u8> {
-    let mut combined_data = vec![0u8; vec_1.len()];
+/// The `--capture` source, when `path` is the input standing for it.
+fn captured<'a>(path: &str, args: &'a Args) -> Option<&'a capture::Source> {
+    args.capture.as_ref().filter(|source| source.to_string() == path)
+}
 
-    let mut i = 0;
-    while i < vec_1.len() {
-        if i % 8 == 0 {
-            combined_data.splice(i..=i + 3, set_rgba(&vec_1, i, i + 3));
-        } else { combined_data.splice(i..=i + 3, set_rgba(&vec_2, i, i + 3));
-        }
-        i += 4;
+/// Rendered inputs carry no raster format of their own, so the output's
+/// extension picks the one it is encoded in.
+fn output_format(output: &str) -> Result<ImageFormat, ImageDataErrors> {
+    if clipboard::is_clipboard(output) {
+        // Never encoded; lossless and with alpha, so nothing is lost on the way.
+        return Ok(ImageFormat::Png);
     }
-    combined_data
+    ImageFormat::from_path(output).map_err(|_| ImageDataErrors::UnableToFormatImage(output.to_string()))
 }
 
-fn set_rgba (vec: &Vec<u8>, start: usize, end: usize) -> Vec<u8> {
-    let mut rgba = Vec::new();
+fn write_report(report: &DiffReport, path: &str) -> Result<(), ImageDataErrors> {
+    let json = serde_json::to_string_pretty(report).expect("diff report is always serialisable");
+    std::fs::write(path, json).map_err(ImageDataErrors::UnableToWriteReport)
+}
 
-    for i in start..=end {
-        let val = match vec.get(i) {
-            Some(d) => *d,
-            None => panic!("Index out of bounds")
-        };
-        rgba.push(val);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_recorded_outputs() {
+        let dir = std::env::temp_dir().join(format!("combiner-replay-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        image::RgbaImage::from_fn(6, 4, |x, y| image::Rgba([x as u8 * 40, y as u8 * 60, 90, (x * y * 20) as u8])).save(file("a.png")).unwrap();
+        image::RgbaImage::from_fn(5, 5, |x, y| image::Rgba([200, x as u8 * 50, y as u8 * 50, 128])).save(file("b.png")).unwrap();
+
+        let arguments = ["--background", "#204060"].map(String::from).to_vec();
+        let mut args = Args::replayed(arguments, CombineOptions::default(), file("a.png"), file("b.png"), file("made.jpg")).unwrap();
+        args.record = true;
+        let mut session = Session::new(&args).unwrap();
+        combine_with(&batch::jobs(&args).unwrap()[0], args.options.clone(), &args, &mut session).unwrap();
+        let recorded: Sidecar = serde_json::from_slice(&std::fs::read(sidecar::path_for(&file("made.jpg"))).unwrap()).unwrap();
+        assert_eq!(recorded.arguments, ["--background", "#204060"]);
+
+        let replay_args = |output: &str| ReplayArgs { sidecar: sidecar::path_for(&file("made.jpg")), output: Some(file(output)), allow_changed: false };
+        replay(&replay_args("again.jpg")).unwrap();
+        assert_eq!(std::fs::read(file("made.jpg")).unwrap(), std::fs::read(file("again.jpg")).unwrap());
+
+        image::RgbaImage::new(5, 5).save(file("b.png")).unwrap();
+        assert!(matches!(replay(&replay_args("changed.jpg")), Err(ImageDataErrors::InvalidArgument(e)) if e.contains("has changed")));
+        std::fs::remove_dir_all(dir).unwrap();
     }
-    rgba
 }
@@ -0,0 +1,108 @@
+use combiner::ImageStore;
+use serde::Deserialize;
+
+use crate::args::Mode;
+use crate::batch::Job;
+use crate::storage::{self, Storage};
+use crate::ImageDataErrors;
+
+/// One row of a job manifest. `mode` falls back to the command line's `--mode`.
+#[derive(Debug, Deserialize)]
+struct Row {
+    image_1: String,
+    image_2: String,
+    #[serde(default)]
+    mode: Option<String>,
+    output: String,
+}
+
+/// Reads the jobs listed in a manifest: a JSON array of rows when the file
+/// ends in `.json`, otherwise CSV with an `image_1,image_2,mode,output`
+/// header. Relative paths are resolved against the manifest's directory, and
+/// the manifest and its paths may be `s3://` or `gs://` URIs.
+pub fn manifest_jobs(path: &str) -> Result<Vec<Job>, ImageDataErrors> {
+    let contents = if !storage::is_file(path) {
+        String::from_utf8_lossy(&Storage.read(path)?).into_owned()
+    } else {
+        std::fs::read_to_string(path).map_err(ImageDataErrors::UnableToReadManifest)?
+    };
+    let invalid = |e: &dyn std::fmt::Display| ImageDataErrors::InvalidManifest(format!("{}: {}", path, e));
+
+    let rows: Vec<Row> = if path.ends_with(".json") {
+        serde_json::from_str(&contents).map_err(|e| invalid(&e))?
+    } else {
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(contents.as_bytes())
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .map_err(|e| invalid(&e))?
+    };
+
+    let resolve = |file: &str| storage::resolve(path, file);
+    rows.into_iter()
+        .enumerate()
+        .map(|(i, row)| {
+            let mode = match row.mode.as_deref() {
+                None | Some("") => None,
+                Some(mode) => Some(Mode::parse(mode).map_err(|e| invalid(&format!("row {}: {}", i + 1, e)))?),
+            };
+            Ok(Job {
+                image_1: resolve(&row.image_1),
+                image_2: resolve(&row.image_2),
+                output: resolve(&row.output),
+                index: i + 1,
+                mode,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(name: &str, contents: &str) -> (std::path::PathBuf, String) {
+        let dir = std::env::temp_dir().join(format!("combiner-manifest-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        let path_str = path.to_string_lossy().into_owned();
+        (dir, path_str)
+    }
+
+    #[test]
+    fn resolves_paths_against_the_manifest() {
+        let (dir, path) = manifest(
+            "jobs.csv",
+            "image_1,image_2,mode,output\n\
+             a.png, sub/b.png, diff, out/1.png\n\
+             /abs/a.png,s3://bucket/b.png,,2.png\n",
+        );
+        let jobs = manifest_jobs(&path).unwrap();
+        let within = |file: &str| dir.join(file).to_string_lossy().into_owned();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!((jobs[0].image_1.as_str(), jobs[0].image_2.as_str()), (within("a.png").as_str(), within("sub/b.png").as_str()));
+        assert_eq!(jobs[0].output, within("out/1.png"));
+        assert_eq!(jobs[0].mode, Some(Mode::Diff));
+        assert_eq!((jobs[1].image_1.as_str(), jobs[1].image_2.as_str()), ("/abs/a.png", "s3://bucket/b.png"));
+        assert_eq!((jobs[1].index, jobs[1].mode), (2, None));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reads_json_and_reports_bad_rows() {
+        let (dir, path) = manifest("jobs.json", r#"[{"image_1": "a.png", "image_2": "b.png", "output": "c.png"}]"#);
+        let jobs = manifest_jobs(&path).unwrap();
+        assert_eq!(jobs[0].output, dir.join("c.png").to_string_lossy());
+        std::fs::write(&path, r#"[{"image_1": "a.png", "image_2": "b.png", "mode": "blend", "output": "c.png"}]"#).unwrap();
+        assert!(matches!(manifest_jobs(&path), Err(ImageDataErrors::InvalidManifest(e)) if e.contains("row 1")));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn resolves_remote_manifests_within_their_bucket() {
+        assert_eq!(storage::resolve("s3://bucket/runs/jobs.csv", "a.png"), "s3://bucket/runs/a.png");
+        assert_eq!(storage::resolve("gs://bucket/jobs.csv", "gs://other/b.png"), "gs://other/b.png");
+    }
+}
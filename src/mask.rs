@@ -0,0 +1,144 @@
+//! Masks: a soft matte made from one input's brightness, or from a matting
+//! model's idea of its foreground, that limits where the combination shows
+//! over the first input, such as only in its highlights. The rest of the
+//! output stays the first input.
+
+use std::fmt;
+
+use image::{DynamicImage, GenericImageView, GrayImage, Luma, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::options::CombineOptions;
+use crate::ImageDataErrors;
+
+/// Where a matte comes from, written as `luminosity:2` or `matte:1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum MaskSource {
+    /// The brightness of the input at this index, from 0.
+    Luminosity(usize),
+    /// The foreground of the input at this index, from 0, as the model at
+    /// [`CombineOptions::matte_model`] finds it.
+    Matte(usize),
+}
+
+impl MaskSource {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        match value.split_once(':') {
+            Some(("luminosity", "1")) => Ok(MaskSource::Luminosity(0)),
+            Some(("luminosity", "2")) => Ok(MaskSource::Luminosity(1)),
+            Some(("matte", "1")) => Ok(MaskSource::Matte(0)),
+            Some(("matte", "2")) => Ok(MaskSource::Matte(1)),
+            _ => Err(ImageDataErrors::InvalidArgument(format!(
+                "invalid mask `{}`, expected luminosity:1, luminosity:2, matte:1 or matte:2",
+                value
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for MaskSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaskSource::Luminosity(input) => write!(f, "luminosity:{}", input + 1),
+            MaskSource::Matte(input) => write!(f, "matte:{}", input + 1),
+        }
+    }
+}
+
+impl TryFrom<String> for MaskSource {
+    type Error = ImageDataErrors;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        MaskSource::parse(&value)
+    }
+}
+
+impl From<MaskSource> for String {
+    fn from(source: MaskSource) -> Self {
+        source.to_string()
+    }
+}
+
+/// Parses `--mask-range`: the brightness, from 0 to 1, where the matte
+/// starts to let the combination through and where it lets all of it through.
+pub fn parse_range(value: &str) -> Result<(f32, f32), ImageDataErrors> {
+    let invalid = || ImageDataErrors::InvalidArgument(format!("invalid mask range `{}`, expected e.g. 0.6,1.0", value));
+    let (start, full) = value.split_once(',').ok_or_else(invalid)?;
+    let level = |level: &str| level.trim().parse::<f32>().ok().filter(|level| (0.0..=1.0).contains(level)).ok_or_else(invalid);
+    let (start, full) = (level(start)?, level(full)?);
+    if start == full {
+        return Err(invalid());
+    }
+    Ok((start, full))
+}
+
+/// The Rec. 709 luma of each pixel of `image`, from its encoded values as
+/// editors' luminosity masks use.
+fn luminosity(image: &DynamicImage) -> GrayImage {
+    let image = image.to_rgba8();
+    GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        let [r, g, b, _] = image.get_pixel(x, y).0;
+        Luma([(0.2126 * f32::from(r) + 0.7152 * f32::from(g) + 0.0722 * f32::from(b)).round() as u8])
+    })
+}
+
+/// `values` mapped to a matte: none of the combination where they are at
+/// `range.0`, all of it at `range.1`, and a ramp between. A range that
+/// falls, as `0.4,0`, selects the shadows instead.
+fn ramp(mut values: GrayImage, (start, full): (f32, f32)) -> GrayImage {
+    let table: [u8; 256] = std::array::from_fn(|value| {
+        let weight = ((value as f32 / 255.0 - start) / (full - start)).clamp(0.0, 1.0);
+        (weight * 255.0).round() as u8
+    });
+    for value in values.pixels_mut() {
+        value.0[0] = table[usize::from(value.0[0])];
+    }
+    values
+}
+
+/// Lays `combined` over `base` through `matte`, in place.
+pub(crate) fn apply(base: &RgbaImage, matte: &GrayImage, combined: &mut [u8], size: (u32, u32)) -> Result<(), ImageDataErrors> {
+    if base.dimensions() != size || matte.dimensions() != size {
+        return Err(ImageDataErrors::InvalidArgument(format!(
+            "a mask needs the output and the inputs at one size, not {}x{} from {}x{}",
+            size.0,
+            size.1,
+            base.width(),
+            base.height()
+        )));
+    }
+    for ((pixel, base), weight) in combined.chunks_exact_mut(4).zip(base.pixels()).zip(matte.pixels()) {
+        let weight = u16::from(weight.0[0]);
+        for (channel, &under) in pixel.iter_mut().zip(&base.0) {
+            *channel = ((u16::from(*channel) * weight + u16::from(under) * (255 - weight) + 127) / 255) as u8;
+        }
+    }
+    Ok(())
+}
+
+/// The matte and the first input it lays the combination over, when
+/// `options` ask for a mask, from inputs already prepared.
+pub(crate) fn prepare(
+    options: &CombineOptions,
+    image_1: &DynamicImage,
+    image_2: &DynamicImage,
+) -> Result<Option<(GrayImage, RgbaImage)>, ImageDataErrors> {
+    let Some(source) = options.mask_from else {
+        return Ok(None);
+    };
+    let values = match source {
+        MaskSource::Luminosity(input) => luminosity([image_1, image_2][input]),
+        #[cfg(feature = "ml")]
+        MaskSource::Matte(input) => {
+            let model = options.matte_model.as_deref().ok_or(ImageDataErrors::MissingArgument("--matte-model"))?;
+            crate::matting::foreground_matte(model, [image_1, image_2][input])?
+        }
+        #[cfg(not(feature = "ml"))]
+        MaskSource::Matte(_) => {
+            return Err(ImageDataErrors::InvalidArgument("`matte:` masks need combiner built with the `ml` feature".to_string()))
+        }
+    };
+    log::debug!("masking by {}, {}x{}", source, image_1.width(), image_1.height());
+    Ok(Some((ramp(values, options.mask_range), image_1.to_rgba8())))
+}
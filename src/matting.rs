@@ -0,0 +1,39 @@
+//! Foreground mattes from an ONNX portrait matting model such as MODNet.
+
+use image::{imageops, DynamicImage, GenericImageView, GrayImage, Luma};
+
+use crate::onnx::Model;
+use crate::ImageDataErrors;
+
+/// The longest side models without a fixed input size are run at.
+const RUN_SIZE: u32 = 512;
+
+/// How much of each pixel of `image` is foreground, from the matting model
+/// at `model`. The model takes one RGB image scaled to -1..1, channels
+/// first, at its own size or one of [`RUN_SIZE`] pixels along the longest
+/// side, and gives a 0-to-1 matte as its first output.
+pub(crate) fn foreground_matte(model: &str, image: &DynamicImage) -> Result<GrayImage, ImageDataErrors> {
+    let model = Model::load(model)?;
+    let (width, height) = image.dimensions();
+    let input_shape = model.input_shape()?;
+    let size = match input_shape[..] {
+        [_, 3, run_height, run_width] if run_height > 0 && run_width > 0 => (run_width as u32, run_height as u32),
+        [_, 3, _, _] => {
+            // Free sizes are rounded to the multiple of 32 models downsample by.
+            let scale = RUN_SIZE as f32 / width.max(height) as f32;
+            let side = |side: u32| ((side as f32 * scale / 32.0).round() as u32).max(1) * 32;
+            (side(width), side(height))
+        }
+        _ => return Err(ImageDataErrors::UnableToRunModel(format!("expected a 1x3xHxW input, not {:?}", input_shape))),
+    };
+    let outputs = crate::timed("running the matting model", || model.run_image(image, size))?;
+    let matte = outputs.first().ok_or_else(|| ImageDataErrors::UnableToRunModel("the model has no outputs".to_string()))?;
+    let (matte_width, matte_height) = match matte.shape[..] {
+        [.., matte_height, matte_width] if matte.shape.iter().rev().skip(2).all(|&side| side == 1) => (matte_width as u32, matte_height as u32),
+        _ => return Err(ImageDataErrors::UnableToRunModel(format!("expected a single 1xHxW matte, not {:?}", matte.shape))),
+    };
+    let matte = GrayImage::from_fn(matte_width, matte_height, |x, y| {
+        Luma([(matte.data[(y * matte_width + x) as usize].clamp(0.0, 1.0) * 255.0).round() as u8])
+    });
+    Ok(imageops::resize(&matte, width, height, imageops::Triangle))
+}
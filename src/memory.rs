@@ -0,0 +1,134 @@
+//! Memory accounting for `--max-memory`. Every allocation goes through
+//! [`Accounting`], which keeps count of the bytes in use and the peak. The
+//! limit itself is enforced before the work that needs the memory: jobs
+//! check their [`estimate`]d needs against what is left, and fail with an
+//! error or decode smaller rather than start. The allocator never refuses,
+//! since a refused allocation aborts the whole process, long-running
+//! `--daemon` and `serve` ones included.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::ImageDataErrors;
+
+static IN_USE: AtomicU64 = AtomicU64::new(0);
+static PEAK: AtomicU64 = AtomicU64::new(0);
+
+/// The system allocator, counting what it hands out.
+pub struct Accounting;
+
+unsafe impl GlobalAlloc for Accounting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let pointer = unsafe { System.alloc(layout) };
+        if !pointer.is_null() {
+            reserve(layout.size() as u64);
+        }
+        pointer
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let pointer = unsafe { System.alloc_zeroed(layout) };
+        if !pointer.is_null() {
+            reserve(layout.size() as u64);
+        }
+        pointer
+    }
+
+    unsafe fn dealloc(&self, pointer: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(pointer, layout) };
+        release(layout.size() as u64);
+    }
+
+    unsafe fn realloc(&self, pointer: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let resized = unsafe { System.realloc(pointer, layout, new_size) };
+        if !resized.is_null() {
+            let (old_size, new_size) = (layout.size() as u64, new_size as u64);
+            if new_size > old_size {
+                reserve(new_size - old_size);
+            } else {
+                release(old_size - new_size);
+            }
+        }
+        resized
+    }
+}
+
+fn reserve(size: u64) {
+    let in_use = IN_USE.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK.fetch_max(in_use, Ordering::Relaxed);
+}
+
+fn release(size: u64) {
+    IN_USE.fetch_sub(size, Ordering::Relaxed);
+}
+
+pub fn in_use() -> u64 {
+    IN_USE.load(Ordering::Relaxed)
+}
+
+pub fn peak() -> u64 {
+    PEAK.load(Ordering::Relaxed)
+}
+
+/// Parses a size in bytes such as `2G`, `512M` or `1.5GiB`. Suffixes are
+/// binary, so `1K` is 1024 bytes, and may end in `B` or `iB`.
+pub fn parse_bytes(value: &str) -> Result<u64, ImageDataErrors> {
+    let invalid = || ImageDataErrors::InvalidArgument(format!("`{}` is not a size such as 512M or 2G", value));
+    let upper = value.trim().to_ascii_uppercase();
+    let number = upper.trim_end_matches("IB").trim_end_matches('B');
+    let (number, shift) = match number.chars().last() {
+        Some('K') => (&number[..number.len() - 1], 10),
+        Some('M') => (&number[..number.len() - 1], 20),
+        Some('G') => (&number[..number.len() - 1], 30),
+        Some('T') => (&number[..number.len() - 1], 40),
+        _ => (number, 0),
+    };
+    let number: f64 = number.trim().parse().map_err(|_| invalid())?;
+    if !(number.is_finite() && number > 0.0) {
+        return Err(invalid());
+    }
+    Ok((number * (1u64 << shift) as f64) as u64)
+}
+
+/// `bytes` in the largest binary unit that keeps it above 1, such as `1.5 GiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let exponent = ((63 - bytes.max(1).leading_zeros()) / 10).min(units.len() as u32 - 1);
+    if exponent == 0 {
+        return format!("{} B", bytes);
+    }
+    format!("{:.1} {}", bytes as f64 / (1u64 << (10 * exponent)) as f64, units[exponent as usize])
+}
+
+/// Roughly the most memory combining inputs of these sizes into an `output`
+/// takes: both inputs decoded as RGBA, plus about four output-sized buffers
+/// for the resized input, the copies modes work on, the output and its
+/// encoding.
+pub fn estimate(inputs: [(u32, u32); 2], output: (u32, u32)) -> u64 {
+    let pixels = |(width, height): (u32, u32)| width as u64 * height as u64;
+    4 * (pixels(inputs[0]) + pixels(inputs[1])) + 16 * pixels(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_formats_sizes() {
+        assert_eq!(parse_bytes("512").unwrap(), 512);
+        assert_eq!(parse_bytes("2G").unwrap(), 2 << 30);
+        assert_eq!(parse_bytes("1.5kib").unwrap(), 1536);
+        assert!(parse_bytes("-1M").is_err());
+        assert!(parse_bytes("lots").is_err());
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(3 << 29), "1.5 GiB");
+    }
+
+    #[test]
+    fn counts_without_refusing() {
+        let before = peak();
+        let buffer = vec![0u8; 64 << 20];
+        assert!(peak() >= before.max(64 << 20));
+        drop(buffer);
+    }
+}
@@ -0,0 +1,75 @@
+use image::RgbaImage;
+use serde::Serialize;
+
+use crate::gpu;
+use crate::hooks::Hooks;
+use crate::noise::Random;
+use crate::options::{CombineOptions, Mode};
+use crate::ImageDataErrors;
+
+/// How much of each input a Mixup or CutMix output holds, so the labels of
+/// the two inputs can be mixed in the same proportion when training.
+#[derive(Debug, Clone, Serialize)]
+pub struct MixLabel {
+    pub mode: Mode,
+    /// The share of the first input, from 0 to 1; the second has the rest.
+    pub lambda: f64,
+    /// The region of the second input pasted over the first in cutmix mode.
+    #[serde(rename = "box", skip_serializing_if = "Option::is_none")]
+    pub cut_box: Option<CutBox>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct CutBox {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Mixes two images of the same size as `options.mode`, Mixup or CutMix,
+/// returning the mixed pixels and how much of each went in. Without an
+/// `options.lambda` one is drawn uniformly, the Beta(1, 1) both papers
+/// default to. Draws depend on `options.seed` and the output's `name`, so
+/// every output of a batch gets its own mix and a rerun gets the same ones.
+pub(crate) fn mix_images(
+    image_1: &RgbaImage,
+    image_2: &RgbaImage,
+    options: &CombineOptions,
+    name: &str,
+    hooks: Hooks,
+) -> Result<(Vec<u8>, MixLabel), ImageDataErrors> {
+    let mode = options.mode;
+    let mut random = Random::for_name(options.seed, name);
+    let lambda = options.lambda.unwrap_or_else(|| random.next_f64());
+    if mode == Mode::Mixup {
+        hooks.check()?;
+        let data = gpu::blend(image_1, image_2, (1.0 - lambda) as f32, options.backend);
+        hooks.step("combining", 1, 1)?;
+        return Ok((data, MixLabel { mode, lambda, cut_box: None }));
+    }
+
+    // A box covering `1 - lambda` of the image, centred anywhere and then
+    // clipped to the image, so the true share is worked out from what is left.
+    let (width, height) = image_1.dimensions();
+    let side = (1.0 - lambda).sqrt();
+    let (centre_x, centre_y) = (random.next_f64() * width as f64, random.next_f64() * height as f64);
+    let span = |centre: f64, length: u32| {
+        let half = side * length as f64 / 2.0;
+        let start = (centre - half).round().clamp(0.0, length as f64) as u32;
+        let end = (centre + half).round().clamp(0.0, length as f64) as u32;
+        (start, end - start)
+    };
+    let ((x, box_width), (y, box_height)) = (span(centre_x, width), span(centre_y, height));
+    let mut mixed = image_1.clone();
+    for box_y in y..y + box_height {
+        hooks.step("combining", (box_y - y) as usize, box_height as usize)?;
+        for box_x in x..x + box_width {
+            mixed.put_pixel(box_x, box_y, *image_2.get_pixel(box_x, box_y));
+        }
+    }
+    let lambda = 1.0 - (box_width as f64 * box_height as f64) / (width as f64 * height as f64);
+    let cut_box = CutBox { x, y, width: box_width, height: box_height };
+    hooks.step("combining", 1, 1)?;
+    Ok((mixed.into_raw(), MixLabel { mode, lambda, cut_box: Some(cut_box) }))
+}
@@ -0,0 +1,119 @@
+//! Stitching a directory of tiles named by their place in a grid into one
+//! image, written a row of tiles at a time so mosaics far larger than
+//! memory can be assembled. Tiles that overlap their neighbours are joined
+//! along the seams where they differ least.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+use image::error::{EncodingError, ImageFormatHint};
+use image::{imageops, DynamicImage, GenericImageView, ImageError, ImageFormat, RgbaImage};
+
+use crate::template::TilePattern;
+use combiner::seam;
+use crate::ImageDataErrors;
+
+/// Stitches the tiles in `tile_dir` whose names match `pattern` into the PNG
+/// `output`, returning its size. Each column is as wide as its widest tile
+/// and each row as tall as its tallest; smaller tiles sit at the top-left of
+/// their cell and missing ones leave it transparent. Grid positions need
+/// not start at 0. Neighbouring cells share `overlap` pixels, merged along
+/// the seam of least difference, see [`seam::merge_overlap`].
+pub fn assemble(
+    tile_dir: &str,
+    pattern: &TilePattern,
+    overlap: u32,
+    output: &str,
+    decode: impl Fn(&str) -> Result<DynamicImage, ImageDataErrors>,
+) -> Result<(u32, u32), ImageDataErrors> {
+    let mut tiles = BTreeMap::new();
+    for entry in std::fs::read_dir(tile_dir).map_err(ImageDataErrors::UnableToReadDirectory)? {
+        let path = entry.map_err(ImageDataErrors::UnableToReadDirectory)?.path();
+        if let Some(position) = path.file_name().and_then(|name| pattern.matches(&name.to_string_lossy())) {
+            tiles.insert(position, path.to_string_lossy().into_owned());
+        }
+    }
+    if tiles.is_empty() {
+        return Err(ImageDataErrors::InvalidArgument(format!("no tiles in {} match the pattern", tile_dir)));
+    }
+    let (first_column, first_row) = tiles.keys().fold((u32::MAX, u32::MAX), |(x, y), &(column, row)| (x.min(column), y.min(row)));
+    let (last_column, last_row) = tiles.keys().fold((0, 0), |(x, y), &(column, row)| (x.max(column), y.max(row)));
+
+    // Only the headers are read up front; each tile is decoded once, when its row is written.
+    let mut widths = vec![0u32; (last_column - first_column + 1) as usize];
+    let mut heights = vec![0u32; (last_row - first_row + 1) as usize];
+    for (&(column, row), path) in &tiles {
+        let (width, height) = image::image_dimensions(path).map_err(ImageDataErrors::UnableToDecodeImage)?;
+        let (column, row) = ((column - first_column) as usize, (row - first_row) as usize);
+        widths[column] = widths[column].max(width);
+        heights[row] = heights[row].max(height);
+    }
+    let missing = widths.len() * heights.len() - tiles.len();
+    if missing > 0 {
+        log::warn!("{} cells of the {}x{} grid have no tile and are left transparent", missing, widths.len(), heights.len());
+    }
+    if widths.iter().chain(&heights).any(|&size| size < 2 * overlap) {
+        return Err(ImageDataErrors::InvalidArgument("`--overlap` must be at most half of the smallest column or row".to_string()));
+    }
+    let too_large = || ImageDataErrors::InvalidArgument("the mosaic is larger than a PNG can hold".to_string());
+    let total = |sizes: &[u32]| {
+        let shared = overlap * (sizes.len() as u32 - 1);
+        sizes.iter().try_fold(0u32, |sum, &size| sum.checked_add(size)).map(|sum| sum - shared).filter(|&sum| sum <= i32::MAX as u32)
+    };
+    let (width, height) = (total(&widths).ok_or_else(too_large)?, total(&heights).ok_or_else(too_large)?);
+    log::info!("assembling {} tiles into {}x{}", tiles.len(), width, height);
+
+    let failed = |e: png::EncodingError| {
+        ImageDataErrors::UnableToSaveImage(ImageError::Encoding(EncodingError::new(ImageFormatHint::Exact(ImageFormat::Png), e)))
+    };
+    if let Some(parent) = Path::new(output).parent() {
+        std::fs::create_dir_all(parent).map_err(ImageDataErrors::UnableToCreateDirectory)?;
+    }
+    let file = std::fs::File::create(output).map_err(|e| ImageDataErrors::UnableToSaveImage(ImageError::IoError(e)))?;
+    let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    // The settings every other PNG output is written with.
+    encoder.set_compression(png::Compression::Fast);
+    encoder.set_filter(png::FilterType::Sub);
+    let mut stream = encoder.write_header().map_err(failed)?.into_stream_writer().map_err(failed)?;
+
+    let write = |stream: &mut png::StreamWriter<_>, strip: &RgbaImage| {
+        stream.write_all(strip.as_raw()).map_err(|e| ImageDataErrors::UnableToSaveImage(ImageError::IoError(e)))
+    };
+    // The bottom lines of the last row, held back to merge with the top of the next.
+    let mut held: Option<RgbaImage> = None;
+    for (row, &row_height) in heights.iter().enumerate() {
+        let mut strip = RgbaImage::new(width, row_height);
+        let mut left = 0;
+        for (column, &column_width) in widths.iter().enumerate() {
+            let position = (first_column + column as u32, first_row + row as u32);
+            if let Some(path) = tiles.get(&position) {
+                let mut cell = RgbaImage::new(column_width, row_height);
+                imageops::replace(&mut cell, &decode(path)?.to_rgba8(), 0, 0);
+                if column > 0 && overlap > 0 {
+                    let shared = strip.view(left, 0, overlap, row_height).to_image();
+                    let merged = seam::merge_overlap(&shared, &cell.view(0, 0, overlap, row_height).to_image(), true);
+                    imageops::replace(&mut cell, &merged, 0, 0);
+                }
+                imageops::replace(&mut strip, &cell, left, 0);
+            }
+            left += column_width - overlap;
+        }
+        let mut top = 0;
+        if let Some(above) = held.take() {
+            let merged = seam::merge_overlap(&above, &strip.view(0, 0, width, overlap).to_image(), false);
+            write(&mut stream, &merged)?;
+            top = overlap;
+        }
+        let bottom = if row + 1 < heights.len() { row_height - overlap } else { row_height };
+        write(&mut stream, &strip.view(0, top, width, bottom - top).to_image())?;
+        if bottom < row_height {
+            held = Some(strip.view(0, bottom, width, row_height - bottom).to_image());
+        }
+        log::debug!("wrote row {} of {}", row + 1, heights.len());
+    }
+    stream.finish().map_err(failed)?;
+    Ok((width, height))
+}
@@ -0,0 +1,99 @@
+use image::RgbaImage;
+
+use crate::hooks::Hooks;
+use crate::simd::{self, Kernel};
+use crate::ImageDataErrors;
+
+/// Keeps what moved between two frames of the same size: the pixels of
+/// `frame_2` whose largest channel difference from `frame_1` exceeds
+/// `threshold`, with everything else cleared to `background`, or to
+/// transparent without one. The changed pixels are opened, then closed, by a
+/// square `cleanup` pixels from its centre to its edge, which drops specks of
+/// noise and fills pinholes in what moved; 0 keeps them as they are.
+pub(crate) fn extract_motion(
+    frame_1: &RgbaImage,
+    frame_2: &RgbaImage,
+    threshold: u8,
+    cleanup: u32,
+    background: Option<[u8; 3]>,
+    hooks: Hooks,
+) -> Result<Vec<u8>, ImageDataErrors> {
+    let (width, height) = frame_1.dimensions();
+    let magnitudes = simd::difference_magnitudes(frame_1.as_raw(), frame_2.as_raw(), Kernel::detect());
+    let mut moved: Vec<bool> = magnitudes.iter().map(|&magnitude| magnitude > threshold).collect();
+    if cleanup > 0 {
+        let (width, height, reach) = (width as usize, height as usize, cleanup as usize);
+        // Opening is an erosion then a dilation; closing the reverse.
+        for grow in [false, true, true, false] {
+            hooks.check()?;
+            moved = morph(&moved, width, height, reach, grow);
+        }
+    }
+    let kept = moved.iter().filter(|&&moved| moved).count();
+    log::info!("{:.2}% of pixels moved", kept as f64 * 100.0 / moved.len().max(1) as f64);
+
+    let cleared = background.map_or([0; 4], |[r, g, b]| [r, g, b, 255]);
+    let mut data = frame_2.as_raw().clone();
+    let row = width as usize;
+    for (y, (pixels, moved)) in data.chunks_exact_mut(row * 4).zip(moved.chunks_exact(row)).enumerate() {
+        hooks.step("combining", y, height as usize)?;
+        for (pixel, moved) in pixels.chunks_exact_mut(4).zip(moved) {
+            if !moved {
+                pixel.copy_from_slice(&cleared);
+            }
+        }
+    }
+    hooks.step("combining", height as usize, height as usize)?;
+    Ok(data)
+}
+
+/// Cuts out what stands in front of a clean background `plate`: `image`
+/// with alpha from how far each pixel's largest channel difference from the
+/// plate exceeds `threshold`, ramping from transparent to as opaque as it was
+/// across the next `softness` levels, so edges and shadows fade rather than
+/// step.
+pub(crate) fn subtract_background(image: &RgbaImage, plate: &RgbaImage, threshold: u8, softness: u8, hooks: Hooks) -> Result<Vec<u8>, ImageDataErrors> {
+    let magnitudes = simd::difference_magnitudes(image.as_raw(), plate.as_raw(), Kernel::detect());
+    let softness = u32::from(softness.max(1));
+    let mut data = image.as_raw().clone();
+    let (row, height) = (image.width() as usize, image.height() as usize);
+    let mut kept = 0;
+    for (y, (pixels, magnitudes)) in data.chunks_exact_mut(row * 4).zip(magnitudes.chunks_exact(row)).enumerate() {
+        hooks.step("combining", y, height)?;
+        for (pixel, &magnitude) in pixels.chunks_exact_mut(4).zip(magnitudes) {
+            let coverage = u32::from(magnitude.saturating_sub(threshold)).min(softness);
+            pixel[3] = (u32::from(pixel[3]) * coverage / softness) as u8;
+            kept += usize::from(pixel[3] > 0);
+        }
+    }
+    hooks.step("combining", height, height)?;
+    log::info!("kept {:.2}% of pixels in front of the plate", kept as f64 * 100.0 / magnitudes.len().max(1) as f64);
+    Ok(data)
+}
+
+/// `mask` dilated, when `grow`, or eroded by a square reaching `reach` pixels
+/// either side, as a pass along the rows then one down the columns. Pixels
+/// past the edges count as unset for dilation and set for erosion, so
+/// erosion does not eat in from the border.
+fn morph(mask: &[bool], width: usize, height: usize, reach: usize, grow: bool) -> Vec<bool> {
+    let pass = |mask: &[bool], along: usize, across: usize, at: &dyn Fn(usize, usize) -> usize| {
+        let mut out = vec![false; mask.len()];
+        for line in 0..across {
+            // How many set pixels the window around each position holds.
+            let mut set = (0..reach.min(along)).filter(|&i| mask[at(line, i)]).count();
+            for i in 0..along {
+                if i + reach < along && mask[at(line, i + reach)] {
+                    set += 1;
+                }
+                if i > reach && mask[at(line, i - reach - 1)] {
+                    set -= 1;
+                }
+                let window = (i + reach).min(along - 1) + 1 - i.saturating_sub(reach);
+                out[at(line, i)] = if grow { set > 0 } else { set == window };
+            }
+        }
+        out
+    };
+    let rows = pass(mask, width, height, &|y, x| y * width + x);
+    pass(&rows, height, width, &|x, y| y * width + x)
+}
@@ -0,0 +1,208 @@
+use std::fmt;
+
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+
+use crate::ImageDataErrors;
+
+/// The distribution random colour noise is drawn from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoiseKind {
+    Gaussian,
+    Uniform,
+}
+
+/// Random noise added to every colour sample, written as `gaussian:8`. For
+/// Gaussian noise the amount is the standard deviation in levels out of 255;
+/// for uniform noise it is the largest change either way.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Noise {
+    pub kind: NoiseKind,
+    pub amount: f32,
+}
+
+impl Noise {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        let invalid = || ImageDataErrors::InvalidArgument(format!("invalid noise `{}`, expected e.g. gaussian:8", value));
+        let (kind, amount) = value.split_once(':').ok_or_else(invalid)?;
+        let kind = match kind.to_ascii_lowercase().as_str() {
+            "gaussian" => NoiseKind::Gaussian,
+            "uniform" => NoiseKind::Uniform,
+            _ => return Err(invalid()),
+        };
+        let amount: f32 = amount.parse().map_err(|_| invalid())?;
+        if !(0.0..=255.0).contains(&amount) {
+            return Err(invalid());
+        }
+        Ok(Noise { kind, amount })
+    }
+}
+
+impl fmt::Display for Noise {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            NoiseKind::Gaussian => "gaussian",
+            NoiseKind::Uniform => "uniform",
+        };
+        write!(f, "{}:{}", kind, self.amount)
+    }
+}
+
+impl TryFrom<String> for Noise {
+    type Error = ImageDataErrors;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Noise::parse(&value)
+    }
+}
+
+impl From<Noise> for String {
+    fn from(noise: Noise) -> Self {
+        noise.to_string()
+    }
+}
+
+/// The look of photographic grain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrainKind {
+    /// Clumped, colourless grain that is strongest in the midtones, as in
+    /// film stock.
+    Film,
+}
+
+/// Photographic grain, written as `film:0.3` with an intensity from 0 to 1.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Grain {
+    pub kind: GrainKind,
+    pub intensity: f32,
+}
+
+impl Grain {
+    /// The standard deviation of the grain at full intensity, in levels.
+    const FULL_STRENGTH: f32 = 40.0;
+
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        let invalid = || ImageDataErrors::InvalidArgument(format!("invalid grain `{}`, expected e.g. film:0.3", value));
+        let (kind, intensity) = value.split_once(':').ok_or_else(invalid)?;
+        let kind = match kind.to_ascii_lowercase().as_str() {
+            "film" => GrainKind::Film,
+            _ => return Err(invalid()),
+        };
+        let intensity: f32 = intensity.parse().map_err(|_| invalid())?;
+        if !(0.0..=1.0).contains(&intensity) {
+            return Err(invalid());
+        }
+        Ok(Grain { kind, intensity })
+    }
+}
+
+impl fmt::Display for Grain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            GrainKind::Film => write!(f, "film:{}", self.intensity),
+        }
+    }
+}
+
+impl TryFrom<String> for Grain {
+    type Error = ImageDataErrors;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Grain::parse(&value)
+    }
+}
+
+impl From<Grain> for String {
+    fn from(grain: Grain) -> Self {
+        grain.to_string()
+    }
+}
+
+/// Adds `noise` to each colour sample independently. The same `seed` always
+/// gives the same noise, so reruns reproduce an output exactly.
+pub fn add_noise(image: &mut RgbaImage, noise: Noise, seed: u64) {
+    let mut random = Random::new(seed);
+    for pixel in image.pixels_mut() {
+        for channel in &mut pixel.0[..3] {
+            let delta = match noise.kind {
+                NoiseKind::Gaussian => random.next_gaussian() * noise.amount as f64,
+                NoiseKind::Uniform => (random.next_f64() * 2.0 - 1.0) * noise.amount as f64,
+            };
+            *channel = (*channel as f64 + delta).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+/// Adds `grain` to the brightness of the image. Neighbouring grains are
+/// blurred together so they clump like silver halide, and the grain fades
+/// towards black and white, where film shows it least.
+pub fn add_grain(image: &mut RgbaImage, grain: Grain, seed: u64) {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let mut random = Random::new(seed);
+    let field: Vec<f64> = (0..width * height).map(|_| random.next_gaussian()).collect();
+    // A [1 2 1] kernel each way. Its weights' root sum of squares is 6, so
+    // dividing by that keeps the clumped grain at unit standard deviation.
+    const WEIGHTS: [f64; 3] = [1.0, 2.0, 1.0];
+    let strength = (grain.intensity * Grain::FULL_STRENGTH) as f64;
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let (x, y) = (x as usize, y as usize);
+        let mut clumped = 0.0;
+        for (dy, weight_y) in WEIGHTS.iter().enumerate() {
+            for (dx, weight_x) in WEIGHTS.iter().enumerate() {
+                let sample_x = (x + dx).saturating_sub(1).min(width - 1);
+                let sample_y = (y + dy).saturating_sub(1).min(height - 1);
+                clumped += weight_x * weight_y * field[sample_y * width + sample_x];
+            }
+        }
+        clumped /= 6.0;
+
+        let [red, green, blue, _] = pixel.0;
+        let luma = (0.299 * red as f64 + 0.587 * green as f64 + 0.114 * blue as f64) / 255.0;
+        let midtones = 0.25 + 3.0 * luma * (1.0 - luma);
+        let delta = clumped * strength * midtones;
+        for channel in &mut pixel.0[..3] {
+            *channel = (*channel as f64 + delta).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+/// A small seeded generator, xorshift64* behind a SplitMix64 seed, so no
+/// dependency is needed for reproducible noise.
+pub(crate) struct Random(u64);
+
+impl Random {
+    pub(crate) fn new(seed: u64) -> Self {
+        let mut mixed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        mixed = (mixed ^ (mixed >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        mixed = (mixed ^ (mixed >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        Random((mixed ^ (mixed >> 31)).max(1))
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A generator for the output `name`, so every output drawn from one
+    /// seed gets its own numbers, the same on every run.
+    pub(crate) fn for_name(seed: u64, name: &str) -> Self {
+        // FNV-1a, which unlike std's hasher stays the same across Rust versions.
+        let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3));
+        Random::new(seed ^ hash)
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Standard normal, by the Box-Muller transform.
+    fn next_gaussian(&mut self) -> f64 {
+        let radius = (-2.0 * (1.0 - self.next_f64()).ln()).sqrt();
+        radius * (std::f64::consts::TAU * self.next_f64()).cos()
+    }
+}
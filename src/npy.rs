@@ -0,0 +1,64 @@
+//! NumPy `.npy` arrays, so training pipelines can load outputs with
+//! `numpy.load` instead of decoding an image.
+
+use crate::{FloatingImage, ImageDataErrors};
+
+/// The element type of a written array.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NpyDtype {
+    /// Samples as they are, from 0 to 255.
+    #[default]
+    Uint8,
+    /// Samples divided by 255, from 0 to 1.
+    Float32,
+}
+
+impl NpyDtype {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        match value {
+            "uint8" | "u8" => Ok(NpyDtype::Uint8),
+            "float32" | "f32" => Ok(NpyDtype::Float32),
+            _ => Err(ImageDataErrors::InvalidArgument(format!("unknown dtype `{}`, expected uint8 or float32", value))),
+        }
+    }
+
+    /// The dtype as the header spells it, little-endian where it matters.
+    fn descr(self) -> &'static str {
+        match self {
+            NpyDtype::Uint8 => "|u1",
+            NpyDtype::Float32 => "<f4",
+        }
+    }
+}
+
+/// Whether `path` names a `.npy` file.
+pub fn is_npy(path: &str) -> bool {
+    std::path::Path::new(path).extension().is_some_and(|extension| extension.eq_ignore_ascii_case("npy"))
+}
+
+/// Encodes `image` as a `(height, width, 3)` RGB array in row-major order,
+/// dropping alpha, so flatten transparent images first.
+pub fn encode(image: &FloatingImage, dtype: NpyDtype) -> Vec<u8> {
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': ({}, {}, 3), }}",
+        dtype.descr(),
+        image.height,
+        image.width
+    );
+    // Magic, version and length take 10 bytes, and the data must start on a
+    // multiple of 64 after a newline-terminated, space-padded header.
+    let padding = 63 - (10 + header.len()) % 64;
+    header.extend(std::iter::repeat_n(' ', padding));
+    header.push('\n');
+
+    let samples = image.data.chunks_exact(4).flat_map(|pixel| pixel[..3].iter().copied());
+    let mut bytes = Vec::with_capacity(10 + header.len() + image.data.len() / 4 * 3 * 4);
+    bytes.extend_from_slice(b"\x93NUMPY\x01\x00");
+    bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(header.as_bytes());
+    match dtype {
+        NpyDtype::Uint8 => bytes.extend(samples),
+        NpyDtype::Float32 => bytes.extend(samples.flat_map(|sample| (sample as f32 / 255.0).to_le_bytes())),
+    }
+    bytes
+}
@@ -0,0 +1,443 @@
+//! ONNX models run by the ONNX Runtime shared library: the one
+//! `ORT_DYLIB_PATH` names, or `onnxruntime` from the library path, loaded
+//! the first time a model is needed. Its C API is bound through [`OrtApi`],
+//! laid out as `onnxruntime_c_api.h` declares the table for API version 11;
+//! later versions only append to it, so a newer runtime's table starts with
+//! the same entries.
+
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::ptr;
+use std::sync::OnceLock;
+
+use image::{imageops, DynamicImage};
+use libloading::Library;
+
+use crate::ImageDataErrors;
+
+/// The oldest `OrtApi` with every entry used here, that of ONNX Runtime 1.11.
+const ORT_API_VERSION: u32 = 11;
+
+const ORT_LOGGING_LEVEL_ERROR: i32 = 3;
+const ORT_ARENA_ALLOCATOR: i32 = 1;
+const ORT_MEM_TYPE_DEFAULT: i32 = 0;
+const ONNX_TENSOR_ELEMENT_DATA_TYPE_FLOAT: i32 = 1;
+
+type Status = *mut c_void;
+type Out = *mut *mut c_void;
+/// An entry nothing here calls, kept for its place in the table.
+type Unused = *const c_void;
+type Release = unsafe extern "system" fn(object: *mut c_void);
+type GetName = unsafe extern "system" fn(session: *mut c_void, index: usize, allocator: *mut c_void, out: *mut *mut c_char) -> Status;
+type Run = unsafe extern "system" fn(
+    session: *mut c_void,
+    run_options: *const c_void,
+    input_names: *const *const c_char,
+    inputs: *const *mut c_void,
+    input_count: usize,
+    output_names: *const *const c_char,
+    output_count: usize,
+    outputs: Out,
+) -> Status;
+
+#[repr(C)]
+struct OrtApiBase {
+    get_api: unsafe extern "system" fn(version: u32) -> *const OrtApi,
+    get_version_string: unsafe extern "system" fn() -> *const c_char,
+}
+
+/// The start of the runtime's `OrtApi` table, entry for entry, up to
+/// `ReleaseSessionOptions`.
+#[repr(C)]
+#[allow(dead_code)]
+struct OrtApi {
+    create_status: Unused,
+    get_error_code: Unused,
+    get_error_message: unsafe extern "system" fn(status: Status) -> *const c_char,
+    create_env: unsafe extern "system" fn(level: i32, id: *const c_char, out: Out) -> Status,
+    create_env_with_custom_logger: Unused,
+    enable_telemetry_events: Unused,
+    disable_telemetry_events: Unused,
+    create_session: unsafe extern "system" fn(env: *mut c_void, path: *const c_void, options: *mut c_void, out: Out) -> Status,
+    create_session_from_array: Unused,
+    run: Run,
+    create_session_options: unsafe extern "system" fn(out: Out) -> Status,
+    set_optimized_model_file_path: Unused,
+    clone_session_options: Unused,
+    set_session_execution_mode: Unused,
+    enable_profiling: Unused,
+    disable_profiling: Unused,
+    enable_mem_pattern: Unused,
+    disable_mem_pattern: Unused,
+    enable_cpu_mem_arena: Unused,
+    disable_cpu_mem_arena: Unused,
+    set_session_log_id: Unused,
+    set_session_log_verbosity_level: Unused,
+    set_session_log_severity_level: Unused,
+    set_session_graph_optimization_level: Unused,
+    set_intra_op_num_threads: Unused,
+    set_inter_op_num_threads: Unused,
+    create_custom_op_domain: Unused,
+    custom_op_domain_add: Unused,
+    add_custom_op_domain: Unused,
+    register_custom_ops_library: Unused,
+    session_get_input_count: Unused,
+    session_get_output_count: unsafe extern "system" fn(session: *mut c_void, out: *mut usize) -> Status,
+    session_get_overridable_initializer_count: Unused,
+    session_get_input_type_info: unsafe extern "system" fn(session: *mut c_void, index: usize, out: Out) -> Status,
+    session_get_output_type_info: Unused,
+    session_get_overridable_initializer_type_info: Unused,
+    session_get_input_name: GetName,
+    session_get_output_name: GetName,
+    session_get_overridable_initializer_name: Unused,
+    create_run_options: Unused,
+    run_options_set_run_log_verbosity_level: Unused,
+    run_options_set_run_log_severity_level: Unused,
+    run_options_set_run_tag: Unused,
+    run_options_get_run_log_verbosity_level: Unused,
+    run_options_get_run_log_severity_level: Unused,
+    run_options_get_run_tag: Unused,
+    run_options_set_terminate: Unused,
+    run_options_unset_terminate: Unused,
+    create_tensor_as_ort_value: Unused,
+    create_tensor_with_data_as_ort_value: unsafe extern "system" fn(info: *mut c_void, data: *mut c_void, bytes: usize, shape: *const i64, dimensions: usize, kind: i32, out: Out) -> Status,
+    is_tensor: Unused,
+    get_tensor_mutable_data: unsafe extern "system" fn(value: *mut c_void, out: Out) -> Status,
+    fill_string_tensor: Unused,
+    get_string_tensor_data_length: Unused,
+    get_string_tensor_content: Unused,
+    cast_type_info_to_tensor_info: unsafe extern "system" fn(type_info: *mut c_void, out: Out) -> Status,
+    get_onnx_type_from_type_info: Unused,
+    create_tensor_type_and_shape_info: Unused,
+    set_tensor_element_type: Unused,
+    set_dimensions: Unused,
+    get_tensor_element_type: Unused,
+    get_dimensions_count: unsafe extern "system" fn(info: *mut c_void, out: *mut usize) -> Status,
+    get_dimensions: unsafe extern "system" fn(info: *mut c_void, values: *mut i64, count: usize) -> Status,
+    get_symbolic_dimensions: Unused,
+    get_tensor_shape_element_count: Unused,
+    get_tensor_type_and_shape: unsafe extern "system" fn(value: *mut c_void, out: Out) -> Status,
+    get_type_info: Unused,
+    get_value_type: Unused,
+    create_memory_info: Unused,
+    create_cpu_memory_info: unsafe extern "system" fn(allocator: i32, memory: i32, out: Out) -> Status,
+    compare_memory_info: Unused,
+    memory_info_get_name: Unused,
+    memory_info_get_id: Unused,
+    memory_info_get_mem_type: Unused,
+    memory_info_get_type: Unused,
+    allocator_alloc: Unused,
+    allocator_free: unsafe extern "system" fn(allocator: *mut c_void, pointer: *mut c_void) -> Status,
+    allocator_get_info: Unused,
+    get_allocator_with_default_options: unsafe extern "system" fn(out: Out) -> Status,
+    add_free_dimension_override: Unused,
+    get_value: Unused,
+    get_value_count: Unused,
+    create_value: Unused,
+    create_opaque_value: Unused,
+    get_opaque_value: Unused,
+    kernel_info_get_attribute_float: Unused,
+    kernel_info_get_attribute_int64: Unused,
+    kernel_info_get_attribute_string: Unused,
+    kernel_context_get_input_count: Unused,
+    kernel_context_get_output_count: Unused,
+    kernel_context_get_input: Unused,
+    kernel_context_get_output: Unused,
+    release_env: Release,
+    release_status: Release,
+    release_memory_info: Release,
+    release_session: Release,
+    release_value: Release,
+    release_run_options: Release,
+    release_type_info: Release,
+    release_tensor_type_and_shape_info: Release,
+    release_session_options: Release,
+}
+
+// Pins the entries the header numbers, counting from 0.
+const _: () = {
+    let entry = std::mem::size_of::<Unused>();
+    assert!(std::mem::offset_of!(OrtApi, run) == 9 * entry);
+    assert!(std::mem::offset_of!(OrtApi, create_tensor_with_data_as_ort_value) == 49 * entry);
+    assert!(std::mem::offset_of!(OrtApi, get_allocator_with_default_options) == 78 * entry);
+    assert!(std::mem::offset_of!(OrtApi, release_env) == 92 * entry);
+    assert!(std::mem::size_of::<OrtApi>() == 101 * entry);
+};
+
+struct Runtime {
+    api: &'static OrtApi,
+    _library: Library,
+}
+
+// The table is immutable and the runtime is thread-safe.
+unsafe impl Send for Runtime {}
+unsafe impl Sync for Runtime {}
+
+/// The minor version of an ONNX Runtime version string like `1.16.3`.
+fn minor_version(version: &str) -> Option<u32> {
+    let mut parts = version.split('.');
+    match (parts.next(), parts.next()) {
+        (Some("1"), Some(minor)) => minor.parse().ok(),
+        _ => None,
+    }
+}
+
+impl Runtime {
+    fn load() -> Result<Self, String> {
+        let path = std::env::var_os("ORT_DYLIB_PATH").unwrap_or_else(|| libloading::library_filename("onnxruntime"));
+        unsafe {
+            let library = Library::new(&path).map_err(|e| format!("cannot load ONNX Runtime from {}: {}", path.to_string_lossy(), e))?;
+            let get_api_base = library
+                .get::<unsafe extern "system" fn() -> *const OrtApiBase>(b"OrtGetApiBase\0")
+                .map_err(|e| format!("{} is not ONNX Runtime: {}", path.to_string_lossy(), e))?;
+            let base = get_api_base().as_ref().ok_or_else(|| format!("{} gave no API base", path.to_string_lossy()))?;
+            let version = CStr::from_ptr((base.get_version_string)()).to_string_lossy().into_owned();
+            // Runtimes older than the table asked for may not refuse it.
+            if minor_version(&version).is_none_or(|minor| minor < ORT_API_VERSION) {
+                return Err(format!("ONNX Runtime {} is not supported; 1.{} or later is needed", version, ORT_API_VERSION));
+            }
+            let api = (base.get_api)(ORT_API_VERSION)
+                .as_ref()
+                .ok_or_else(|| format!("ONNX Runtime {} does not offer API version {}", version, ORT_API_VERSION))?;
+            log::debug!("loaded ONNX Runtime {}", version);
+            Ok(Runtime { api, _library: library })
+        }
+    }
+
+    fn get() -> Result<&'static Runtime, ImageDataErrors> {
+        static RUNTIME: OnceLock<Result<Runtime, String>> = OnceLock::new();
+        RUNTIME.get_or_init(Runtime::load).as_ref().map_err(|message| ImageDataErrors::UnableToRunModel(message.clone()))
+    }
+
+    /// An error for a non-null `status`, which is released.
+    fn check(&self, status: Status) -> Result<(), ImageDataErrors> {
+        if status.is_null() {
+            return Ok(());
+        }
+        unsafe {
+            let message = CStr::from_ptr((self.api.get_error_message)(status)).to_string_lossy().into_owned();
+            (self.api.release_status)(status);
+            Err(ImageDataErrors::UnableToRunModel(message))
+        }
+    }
+
+    /// The object `call` writes out, to be freed with `release`.
+    fn make(&'static self, release: Release, call: impl FnOnce(Out) -> Status) -> Result<Owned, ImageDataErrors> {
+        let mut object = ptr::null_mut();
+        self.check(call(&mut object))?;
+        Ok(Owned { runtime: self, release, object })
+    }
+
+    /// The dimensions of a tensor's shape, -1 where they are free.
+    unsafe fn dimensions(&self, info: *mut c_void) -> Result<Vec<i64>, ImageDataErrors> {
+        let mut count = 0;
+        self.check((self.api.get_dimensions_count)(info, &mut count))?;
+        let mut dimensions = vec![0i64; count];
+        self.check((self.api.get_dimensions)(info, dimensions.as_mut_ptr(), count))?;
+        Ok(dimensions)
+    }
+}
+
+/// A runtime object, released when dropped.
+struct Owned {
+    runtime: &'static Runtime,
+    release: Release,
+    object: *mut c_void,
+}
+
+impl Drop for Owned {
+    fn drop(&mut self) {
+        if !self.object.is_null() {
+            unsafe { (self.release)(self.object) }
+        }
+    }
+}
+
+/// An output of a model.
+pub(crate) struct Tensor {
+    pub shape: Vec<i64>,
+    pub data: Vec<f32>,
+}
+
+/// A model loaded from an `.onnx` file, taking one float tensor.
+pub(crate) struct Model {
+    session: Owned,
+    _env: Owned,
+}
+
+impl Model {
+    pub(crate) fn load(path: &str) -> Result<Self, ImageDataErrors> {
+        let runtime = Runtime::get()?;
+        let api = runtime.api;
+        unsafe {
+            let env = runtime.make(api.release_env, |out| (api.create_env)(ORT_LOGGING_LEVEL_ERROR, c"combiner".as_ptr(), out))?;
+            let session_options = runtime.make(api.release_session_options, |out| (api.create_session_options)(out))?;
+            // Paths are wide on Windows and bytes elsewhere.
+            #[cfg(windows)]
+            let model: Vec<u16> = std::os::windows::ffi::OsStrExt::encode_wide(std::ffi::OsStr::new(path)).chain([0]).collect();
+            #[cfg(not(windows))]
+            let model = CString::new(path).map_err(|_| ImageDataErrors::InvalidArgument(format!("invalid model path {}", path)))?;
+            let session = runtime.make(api.release_session, |out| (api.create_session)(env.object, model.as_ptr().cast(), session_options.object, out))?;
+            log::debug!("loaded the model {}", path);
+            Ok(Model { session, _env: env })
+        }
+    }
+
+    fn runtime(&self) -> &'static Runtime {
+        self.session.runtime
+    }
+
+    /// The shape of the input, -1 where it is free.
+    pub(crate) fn input_shape(&self) -> Result<Vec<i64>, ImageDataErrors> {
+        let runtime = self.runtime();
+        let api = runtime.api;
+        unsafe {
+            let type_info = runtime.make(api.release_type_info, |out| (api.session_get_input_type_info)(self.session.object, 0, out))?;
+            // The tensor info belongs to the type info.
+            let mut tensor_info = ptr::null_mut();
+            runtime.check((api.cast_type_info_to_tensor_info)(type_info.object, &mut tensor_info))?;
+            runtime.dimensions(tensor_info)
+        }
+    }
+
+    /// The name `get_name` gives the input or output at `index`.
+    unsafe fn name(&self, get_name: GetName, index: usize) -> Result<CString, ImageDataErrors> {
+        let runtime = self.runtime();
+        let mut allocator = ptr::null_mut();
+        runtime.check((runtime.api.get_allocator_with_default_options)(&mut allocator))?;
+        let mut name = ptr::null_mut();
+        runtime.check(get_name(self.session.object, index, allocator, &mut name))?;
+        let owned = CStr::from_ptr(name).to_owned();
+        runtime.check((runtime.api.allocator_free)(allocator, name.cast()))?;
+        Ok(owned)
+    }
+
+    /// Runs the model on `input`, of `shape`, giving every output.
+    pub(crate) fn run(&self, input: &mut [f32], shape: &[i64]) -> Result<Vec<Tensor>, ImageDataErrors> {
+        let runtime = self.runtime();
+        let api = runtime.api;
+        unsafe {
+            let input_name = self.name(api.session_get_input_name, 0)?;
+            let mut count = 0;
+            runtime.check((api.session_get_output_count)(self.session.object, &mut count))?;
+            let output_names = (0..count).map(|index| self.name(api.session_get_output_name, index)).collect::<Result<Vec<_>, _>>()?;
+            let output_pointers: Vec<*const c_char> = output_names.iter().map(|name| name.as_ptr()).collect();
+
+            let memory_info = runtime.make(api.release_memory_info, |out| (api.create_cpu_memory_info)(ORT_ARENA_ALLOCATOR, ORT_MEM_TYPE_DEFAULT, out))?;
+            let bytes = std::mem::size_of_val(input);
+            let input = runtime.make(api.release_value, |out| {
+                (api.create_tensor_with_data_as_ort_value)(
+                    memory_info.object,
+                    input.as_mut_ptr().cast(),
+                    bytes,
+                    shape.as_ptr(),
+                    shape.len(),
+                    ONNX_TENSOR_ELEMENT_DATA_TYPE_FLOAT,
+                    out,
+                )
+            })?;
+
+            let mut outputs = vec![ptr::null_mut(); count];
+            let status = (api.run)(self.session.object, ptr::null(), &input_name.as_ptr(), &input.object, 1, output_pointers.as_ptr(), count, outputs.as_mut_ptr());
+            let outputs: Vec<Owned> = outputs.into_iter().map(|object| Owned { runtime, release: api.release_value, object }).collect();
+            runtime.check(status)?;
+
+            outputs
+                .iter()
+                .map(|output| {
+                    let info = runtime.make(api.release_tensor_type_and_shape_info, |out| (api.get_tensor_type_and_shape)(output.object, out))?;
+                    let shape = runtime.dimensions(info.object)?;
+                    let mut values = ptr::null_mut();
+                    runtime.check((api.get_tensor_mutable_data)(output.object, &mut values))?;
+                    let length = shape.iter().product::<i64>().max(0) as usize;
+                    let data = if length == 0 { Vec::new() } else { std::slice::from_raw_parts(values.cast::<f32>(), length).to_vec() };
+                    Ok(Tensor { shape, data })
+                })
+                .collect()
+        }
+    }
+
+    /// Runs the model on `image` resized to `width`x`height`, as RGB scaled
+    /// to -1..1, channels first.
+    pub(crate) fn run_image(&self, image: &DynamicImage, (width, height): (u32, u32)) -> Result<Vec<Tensor>, ImageDataErrors> {
+        let resized = image.resize_exact(width, height, imageops::Triangle).to_rgb8();
+        let plane = (width * height) as usize;
+        let mut data = vec![0.0f32; plane * 3];
+        for (index, pixel) in resized.pixels().enumerate() {
+            for (channel, &value) in pixel.0.iter().enumerate() {
+                data[channel * plane + index] = f32::from(value) / 127.5 - 1.0;
+            }
+        }
+        self.run(&mut data, &[1, 3, i64::from(height), i64::from(width)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint(mut value: u64, out: &mut Vec<u8>) {
+        while value >= 0x80 {
+            out.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    fn number(field: u64, value: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        varint(field << 3, &mut out);
+        varint(value, &mut out);
+        out
+    }
+
+    fn bytes(field: u64, value: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        varint(field << 3 | 2, &mut out);
+        varint(value.len() as u64, &mut out);
+        out.extend_from_slice(value);
+        out
+    }
+
+    /// A float tensor `name` of `shape`, as a `ValueInfoProto`.
+    fn value_info(name: &str, shape: &[u64]) -> Vec<u8> {
+        let dimensions: Vec<u8> = shape.iter().flat_map(|&size| bytes(1, &number(1, size))).collect();
+        let tensor = [number(1, ONNX_TENSOR_ELEMENT_DATA_TYPE_FLOAT as u64), bytes(2, &dimensions)].concat();
+        [bytes(1, name.as_bytes()), bytes(2, &bytes(1, &tensor))].concat()
+    }
+
+    /// A `ModelProto` negating a 1x3x2x2 tensor, at IR version 7 and opset 13.
+    fn negate_model() -> Vec<u8> {
+        let shape = [1, 3, 2, 2];
+        let node = [bytes(1, b"x"), bytes(2, b"y"), bytes(4, b"Neg")].concat();
+        let graph = [bytes(1, &node), bytes(2, b"negate"), bytes(11, &value_info("x", &shape)), bytes(12, &value_info("y", &shape))].concat();
+        [number(1, 7), bytes(7, &graph), bytes(8, &number(2, 13))].concat()
+    }
+
+    #[test]
+    fn minor_versions() {
+        assert_eq!(minor_version("1.16.3"), Some(16));
+        assert_eq!(minor_version("1.11.0"), Some(11));
+        assert_eq!(minor_version("2.0.0"), None);
+        assert_eq!(minor_version("unknown"), None);
+    }
+
+    #[test]
+    fn runs_a_model() {
+        if let Err(error) = Runtime::get() {
+            eprintln!("skipped: {:?}", error);
+            return;
+        }
+        let path = std::env::temp_dir().join(format!("combiner-negate-{}.onnx", std::process::id()));
+        std::fs::write(&path, negate_model()).unwrap();
+        let model = Model::load(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        let model = model.unwrap();
+        assert_eq!(model.input_shape().unwrap(), [1, 3, 2, 2]);
+
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(2, 2, |x, y| image::Rgb([255, 0, if x == y { 255 } else { 0 }])));
+        let outputs = model.run_image(&image, (2, 2)).unwrap();
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].shape, [1, 3, 2, 2]);
+        assert_eq!(outputs[0].data, [-1.0, -1.0, -1.0, -1.0, 1.0, 1.0, 1.0, 1.0, -1.0, 1.0, 1.0, -1.0]);
+    }
+}
@@ -0,0 +1,605 @@
+use serde::{Deserialize, Serialize};
+
+use crate::astro::StackMethod;
+use crate::fit::Fit;
+use crate::frame::Device;
+use crate::mask::MaskSource;
+use crate::noise::{Grain, Noise};
+use crate::pipeline::Pipeline;
+use crate::preset::SizePreset;
+use crate::print::PrintLayout;
+use crate::qr::QrErrorCorrection;
+use crate::stamp::Stamp;
+use crate::tone::{Curve, Levels};
+use crate::geometry::{CropRegion, Geometry, Gravity};
+use crate::gpu::Backend;
+use crate::transform::Flip;
+use crate::warp::Quad;
+use crate::ImageDataErrors;
+
+/// The schema version written with serialised [`CombineOptions`]. Older
+/// versions load as they are; newer ones load without the fields this
+/// version does not know yet.
+pub const OPTIONS_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    Alternate,
+    Diff,
+    Canvas,
+    Warp,
+    Mixup,
+    Cutmix,
+    Photomosaic,
+    Tile,
+    Mirror,
+    #[serde(rename = "sky-replace")]
+    SkyReplace,
+    Motion,
+    #[serde(rename = "subtract-bg")]
+    SubtractBg,
+    #[serde(rename = "flat-field")]
+    FlatField,
+    #[serde(rename = "astro-stack")]
+    AstroStack,
+    #[serde(rename = "depth-blend")]
+    DepthBlend,
+}
+
+impl Mode {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        match value {
+            "alternate" => Ok(Mode::Alternate),
+            "diff" => Ok(Mode::Diff),
+            "canvas" => Ok(Mode::Canvas),
+            "warp" => Ok(Mode::Warp),
+            "mixup" => Ok(Mode::Mixup),
+            "cutmix" => Ok(Mode::Cutmix),
+            "photomosaic" => Ok(Mode::Photomosaic),
+            "tile" => Ok(Mode::Tile),
+            "mirror" => Ok(Mode::Mirror),
+            "sky-replace" => Ok(Mode::SkyReplace),
+            "motion" => Ok(Mode::Motion),
+            "subtract-bg" => Ok(Mode::SubtractBg),
+            "flat-field" => Ok(Mode::FlatField),
+            "astro-stack" => Ok(Mode::AstroStack),
+            "depth-blend" => Ok(Mode::DepthBlend),
+            _ => Err(ImageDataErrors::InvalidArgument(format!("unknown mode `{}`", value))),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mode::Alternate => "alternate",
+            Mode::Diff => "diff",
+            Mode::Canvas => "canvas",
+            Mode::Warp => "warp",
+            Mode::Mixup => "mixup",
+            Mode::Cutmix => "cutmix",
+            Mode::Photomosaic => "photomosaic",
+            Mode::Tile => "tile",
+            Mode::Mirror => "mirror",
+            Mode::SkyReplace => "sky-replace",
+            Mode::Motion => "motion",
+            Mode::SubtractBg => "subtract-bg",
+            Mode::FlatField => "flat-field",
+            Mode::AstroStack => "astro-stack",
+            Mode::DepthBlend => "depth-blend",
+        }
+    }
+
+    /// Whether the inputs are brought to the same size before combining.
+    /// Canvas, warp and tile layers keep their own sizes.
+    pub fn resizes_inputs(&self) -> bool {
+        !matches!(self, Mode::Canvas | Mode::Warp | Mode::Tile)
+    }
+
+    /// Whether the mode mixes the inputs for data augmentation, with a
+    /// [`MixLabel`](crate::mix::MixLabel) saying how.
+    pub fn mixes(&self) -> bool {
+        matches!(self, Mode::Mixup | Mode::Cutmix)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffStyle {
+    Highlight,
+    Heatmap,
+}
+
+impl DiffStyle {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        match value {
+            "highlight" => Ok(DiffStyle::Highlight),
+            "heatmap" => Ok(DiffStyle::Heatmap),
+            _ => Err(ImageDataErrors::InvalidArgument(format!("unknown diff style `{}`", value))),
+        }
+    }
+}
+
+/// How two images are combined, independent of where they come from. The
+/// serialised form is shared by option files, the HTTP API and embedders.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "OptionsSchema", into = "OptionsSchema")]
+pub struct CombineOptions {
+    pub mode: Mode,
+    pub threshold: u8,
+    pub diff_style: DiffStyle,
+    /// Whether transparent or solid-colour margins are cropped from both
+    /// inputs and from the output.
+    pub trim: bool,
+    /// Whether the output is quilted with itself so that it tiles seamlessly.
+    pub make_tileable: bool,
+    /// The size of the canvas in canvas and tile modes, or `None` to fit
+    /// both inputs, or take the first input's size when tiling.
+    pub canvas: Option<(u32, u32)>,
+    /// Where the top-left corners of the two inputs go in canvas mode.
+    pub positions: [(i32, i32); 2],
+    /// How each input is resized before combining, as ImageMagick's `-resize`.
+    pub resize: Option<Geometry>,
+    /// The region of each input kept after resizing, as ImageMagick's `-crop`.
+    pub crop: Option<Geometry>,
+    /// Which edge `crop` offsets are measured from.
+    pub gravity: Gravity,
+    /// The region taken out of each input before anything else is done to it.
+    pub input_crops: [Option<CropRegion>; 2],
+    /// The only region of the output that is combined, once both inputs
+    /// are the same size; the rest is copied from the first input.
+    pub roi: Option<CropRegion>,
+    /// What the matte comes from that lays the combination over the first
+    /// input, once both inputs are the same size.
+    pub mask_from: Option<MaskSource>,
+    /// The brightness where the matte starts to let the combination
+    /// through and where it lets all of it through.
+    pub mask_range: (f32, f32),
+    /// The ONNX matting model `matte:` masks are made with.
+    pub matte_model: Option<String>,
+    /// How the larger input is brought to the size of the smaller one.
+    pub fit: Fit,
+    /// Whether crops made by [`Fit::Crop`] keep the most detailed window
+    /// rather than the centre.
+    pub smart_crop: bool,
+    /// Whether the QR code and the second input in canvas mode go in the
+    /// corner of the image covering the fewest faces, then the least detail.
+    pub smart_position: bool,
+    /// The ONNX face detector smart crops and placement avoid cutting or
+    /// covering faces with.
+    pub face_model: Option<String>,
+    /// How far each input is rotated clockwise, in degrees, right after decoding.
+    pub rotations: [f64; 2],
+    /// How each input is flipped after rotating.
+    pub flips: [Option<Flip>; 2],
+    /// The levels each input is adjusted with after flipping.
+    pub levels: [Option<Levels>; 2],
+    /// The tone curve each input is adjusted with after its levels.
+    pub curves: [Option<Curve>; 2],
+    /// Where the corners of the second input go on the first in warp mode.
+    pub warp: Option<Quad>,
+    /// The device mockup the output is framed in.
+    pub frame: Option<Device>,
+    /// The social network size the output is finally fitted to.
+    pub preset_size: Option<SizePreset>,
+    /// The page the output is laid out on for printing.
+    pub print: Option<PrintLayout>,
+    /// Whether printed pages get crop marks around the image.
+    pub crop_marks: bool,
+    /// The DPI recorded in the output, unless a print layout sets its own.
+    /// Without either, the first input's DPI is kept when it has one.
+    pub dpi: Option<f32>,
+    /// Text encoded as a QR code drawn onto the output.
+    pub qr: Option<String>,
+    /// The corner or edge the QR code is drawn at.
+    pub qr_position: Gravity,
+    /// The largest side of the QR code in pixels, or `None` for a fifth of
+    /// the output's shorter side.
+    pub qr_size: Option<u32>,
+    /// The gap between the QR code and the output's edges, in pixels.
+    pub qr_margin: u32,
+    pub qr_error_correction: QrErrorCorrection,
+    /// What is printed in a corner of the output from the first input's
+    /// Exif metadata, a line each.
+    pub stamp: Vec<Stamp>,
+    /// How the capture time is written, see [`crate::exif::DateTime::format`].
+    pub stamp_format: String,
+    /// The corner or edge the stamp is printed at.
+    pub stamp_position: Gravity,
+    /// Random noise added to the output, for example to hide banding.
+    pub noise: Option<Noise>,
+    /// Film grain added to the output.
+    pub grain: Option<Grain>,
+    /// The share of the first input in mixup and cutmix modes, or `None`
+    /// to draw one for each output.
+    pub lambda: Option<f64>,
+    /// The side of each tile of a photomosaic, in output pixels.
+    pub mosaic_tile: u32,
+    /// How many times larger than the first input a photomosaic is.
+    pub mosaic_scale: u32,
+    /// How far, from 0 to 1, each photomosaic tile's colours are shifted
+    /// toward the part of the image it stands for.
+    pub mosaic_correction: f64,
+    /// How wide each repeat of the second input is in tile mode, keeping its
+    /// aspect ratio, or `None` for its own size.
+    pub tile_size: Option<u32>,
+    /// How far the tile pattern is shifted right and down.
+    pub tile_offset: (i32, i32),
+    /// Whether every other repeat of the tile pattern is mirrored, so
+    /// neighbouring tiles meet without seams.
+    pub tile_mirror: bool,
+    /// Whether mirror mode reflects the first input's left half, with 2,
+    /// or its top-left quarter, with 4.
+    pub mirror_folds: u32,
+    /// How far the second input is blended into every other mirrored
+    /// section, or `None` to mirror the first input alone.
+    pub mirror_blend: Option<f32>,
+    /// How many pixels the matte ramps across at the horizon in
+    /// sky-replace mode, or `None` for 2% of the height.
+    pub sky_feather: Option<u32>,
+    /// How far the rest of the first input is tinted toward the new sky's
+    /// colour in sky-replace mode, from 0 to 1.
+    pub sky_harmonize: f32,
+    /// How far motion mode's opening and closing reach, in pixels, to drop
+    /// specks and fill pinholes in what moved, or 0 for neither.
+    pub motion_cleanup: u32,
+    /// The colour left where nothing moved in motion mode, or `None` for
+    /// transparent.
+    pub motion_background: Option<[u8; 3]>,
+    /// How many levels of difference past the threshold subtract-bg mode's
+    /// alpha ramps across, from transparent to opaque.
+    pub subtract_softness: u8,
+    /// The dark frame flat-field mode subtracts from the first input before
+    /// dividing by the flat, or `None` to subtract nothing.
+    pub dark_frame: Option<String>,
+    /// How astro-stack mode stacks the aligned frames.
+    pub stack_method: StackMethod,
+    /// The grayscale depth map depth-blend mode mixes the inputs by, black
+    /// nearest and white farthest.
+    pub depth_map: Option<String>,
+    /// The nearest and farthest depths, from 0 to 1, that depth-blend mode
+    /// keeps the first input at.
+    pub focus_near: f32,
+    pub focus_far: f32,
+    /// How much the second input is blurred in depth-blend mode, as a
+    /// Gaussian's standard deviation in pixels, or `None` to use it as it is.
+    pub depth_blur: Option<f32>,
+    /// Where resizing and blending run.
+    pub backend: Backend,
+    /// The steps run in place of the mode, starting from the first input.
+    pub pipeline: Option<Pipeline>,
+    /// Seeds `noise`, `grain` and the draws of mixup and cutmix, so the same
+    /// seed reproduces an output.
+    pub seed: u64,
+}
+
+impl Default for CombineOptions {
+    fn default() -> Self {
+        CombineOptions {
+            mode: Mode::Alternate,
+            threshold: 0,
+            diff_style: DiffStyle::Highlight,
+            trim: false,
+            make_tileable: false,
+            canvas: None,
+            positions: [(0, 0); 2],
+            resize: None,
+            crop: None,
+            gravity: Gravity::default(),
+            input_crops: [None; 2],
+            roi: None,
+            mask_from: None,
+            mask_range: (0.0, 1.0),
+            matte_model: None,
+            fit: Fit::default(),
+            smart_crop: false,
+            smart_position: false,
+            face_model: None,
+            rotations: [0.0; 2],
+            flips: [None; 2],
+            levels: [None; 2],
+            curves: [None, None],
+            warp: None,
+            frame: None,
+            preset_size: None,
+            print: None,
+            crop_marks: false,
+            dpi: None,
+            qr: None,
+            qr_position: Gravity::SouthEast,
+            qr_size: None,
+            qr_margin: 16,
+            qr_error_correction: QrErrorCorrection::default(),
+            stamp: Vec::new(),
+            stamp_format: "'%y %m %d".to_string(),
+            stamp_position: Gravity::SouthEast,
+            noise: None,
+            grain: None,
+            lambda: None,
+            mosaic_tile: 32,
+            mosaic_scale: 1,
+            mosaic_correction: 0.0,
+            tile_size: None,
+            tile_offset: (0, 0),
+            tile_mirror: false,
+            mirror_folds: 4,
+            mirror_blend: None,
+            sky_feather: None,
+            sky_harmonize: 0.3,
+            motion_cleanup: 2,
+            motion_background: None,
+            subtract_softness: 32,
+            dark_frame: None,
+            stack_method: StackMethod::default(),
+            depth_map: None,
+            focus_near: 0.0,
+            focus_far: 0.3,
+            depth_blur: None,
+            backend: Backend::Cpu,
+            pipeline: None,
+            seed: 0,
+        }
+    }
+}
+
+/// The serialised form of [`CombineOptions`], in which every field may be left out.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct OptionsSchema {
+    version: u32,
+    mode: Mode,
+    threshold: u8,
+    diff_style: DiffStyle,
+    trim: bool,
+    make_tileable: bool,
+    canvas: Option<(u32, u32)>,
+    positions: [(i32, i32); 2],
+    resize: Option<Geometry>,
+    crop: Option<Geometry>,
+    gravity: Gravity,
+    input_crops: [Option<CropRegion>; 2],
+    roi: Option<CropRegion>,
+    mask_from: Option<MaskSource>,
+    mask_range: (f32, f32),
+    matte_model: Option<String>,
+    fit: Fit,
+    smart_crop: bool,
+    smart_position: bool,
+    face_model: Option<String>,
+    rotations: [f64; 2],
+    flips: [Option<Flip>; 2],
+    levels: [Option<Levels>; 2],
+    curves: [Option<Curve>; 2],
+    warp: Option<Quad>,
+    frame: Option<Device>,
+    preset_size: Option<SizePreset>,
+    print: Option<PrintLayout>,
+    crop_marks: bool,
+    dpi: Option<f32>,
+    qr: Option<String>,
+    qr_position: Gravity,
+    qr_size: Option<u32>,
+    qr_margin: u32,
+    qr_error_correction: QrErrorCorrection,
+    stamp: Vec<Stamp>,
+    stamp_format: String,
+    stamp_position: Gravity,
+    noise: Option<Noise>,
+    grain: Option<Grain>,
+    lambda: Option<f64>,
+    mosaic_tile: u32,
+    mosaic_scale: u32,
+    mosaic_correction: f64,
+    tile_size: Option<u32>,
+    tile_offset: (i32, i32),
+    tile_mirror: bool,
+    mirror_folds: u32,
+    mirror_blend: Option<f32>,
+    sky_feather: Option<u32>,
+    sky_harmonize: f32,
+    motion_cleanup: u32,
+    motion_background: Option<[u8; 3]>,
+    subtract_softness: u8,
+    dark_frame: Option<String>,
+    stack_method: StackMethod,
+    depth_map: Option<String>,
+    focus_near: f32,
+    focus_far: f32,
+    depth_blur: Option<f32>,
+    backend: Backend,
+    pipeline: Option<Pipeline>,
+    seed: u64,
+}
+
+impl Default for OptionsSchema {
+    fn default() -> Self {
+        // Options written before versioning was introduced are version 1.
+        OptionsSchema { version: 1, ..CombineOptions::default().into() }
+    }
+}
+
+impl From<OptionsSchema> for CombineOptions {
+    fn from(schema: OptionsSchema) -> Self {
+        if schema.version > OPTIONS_VERSION {
+            log::warn!(
+                "options are version {}, newer than {}; settings this version does not know are ignored",
+                schema.version,
+                OPTIONS_VERSION
+            );
+        }
+        CombineOptions {
+            mode: schema.mode,
+            threshold: schema.threshold,
+            diff_style: schema.diff_style,
+            trim: schema.trim,
+            make_tileable: schema.make_tileable,
+            canvas: schema.canvas,
+            positions: schema.positions,
+            resize: schema.resize,
+            crop: schema.crop,
+            gravity: schema.gravity,
+            input_crops: schema.input_crops,
+            roi: schema.roi,
+            mask_from: schema.mask_from,
+            mask_range: schema.mask_range,
+            matte_model: schema.matte_model,
+            fit: schema.fit,
+            smart_crop: schema.smart_crop,
+            smart_position: schema.smart_position,
+            face_model: schema.face_model,
+            rotations: schema.rotations,
+            flips: schema.flips,
+            levels: schema.levels,
+            curves: schema.curves,
+            warp: schema.warp,
+            frame: schema.frame,
+            preset_size: schema.preset_size,
+            print: schema.print,
+            crop_marks: schema.crop_marks,
+            dpi: schema.dpi,
+            qr: schema.qr,
+            qr_position: schema.qr_position,
+            qr_size: schema.qr_size,
+            qr_margin: schema.qr_margin,
+            qr_error_correction: schema.qr_error_correction,
+            stamp: schema.stamp,
+            stamp_format: schema.stamp_format,
+            stamp_position: schema.stamp_position,
+            noise: schema.noise,
+            grain: schema.grain,
+            lambda: schema.lambda,
+            mosaic_tile: schema.mosaic_tile,
+            mosaic_scale: schema.mosaic_scale,
+            mosaic_correction: schema.mosaic_correction,
+            tile_size: schema.tile_size,
+            tile_offset: schema.tile_offset,
+            tile_mirror: schema.tile_mirror,
+            mirror_folds: schema.mirror_folds,
+            mirror_blend: schema.mirror_blend,
+            sky_feather: schema.sky_feather,
+            sky_harmonize: schema.sky_harmonize,
+            motion_cleanup: schema.motion_cleanup,
+            motion_background: schema.motion_background,
+            subtract_softness: schema.subtract_softness,
+            dark_frame: schema.dark_frame,
+            stack_method: schema.stack_method,
+            depth_map: schema.depth_map,
+            focus_near: schema.focus_near,
+            focus_far: schema.focus_far,
+            depth_blur: schema.depth_blur,
+            backend: schema.backend,
+            pipeline: schema.pipeline,
+            seed: schema.seed,
+        }
+    }
+}
+
+impl From<CombineOptions> for OptionsSchema {
+    fn from(options: CombineOptions) -> Self {
+        OptionsSchema {
+            version: OPTIONS_VERSION,
+            mode: options.mode,
+            threshold: options.threshold,
+            diff_style: options.diff_style,
+            trim: options.trim,
+            make_tileable: options.make_tileable,
+            canvas: options.canvas,
+            positions: options.positions,
+            resize: options.resize,
+            crop: options.crop,
+            gravity: options.gravity,
+            input_crops: options.input_crops,
+            roi: options.roi,
+            mask_from: options.mask_from,
+            mask_range: options.mask_range,
+            matte_model: options.matte_model,
+            fit: options.fit,
+            smart_crop: options.smart_crop,
+            smart_position: options.smart_position,
+            face_model: options.face_model,
+            rotations: options.rotations,
+            flips: options.flips,
+            levels: options.levels,
+            curves: options.curves,
+            warp: options.warp,
+            frame: options.frame,
+            preset_size: options.preset_size,
+            print: options.print,
+            crop_marks: options.crop_marks,
+            dpi: options.dpi,
+            qr: options.qr,
+            qr_position: options.qr_position,
+            qr_size: options.qr_size,
+            qr_margin: options.qr_margin,
+            qr_error_correction: options.qr_error_correction,
+            stamp: options.stamp,
+            stamp_format: options.stamp_format,
+            stamp_position: options.stamp_position,
+            noise: options.noise,
+            grain: options.grain,
+            lambda: options.lambda,
+            mosaic_tile: options.mosaic_tile,
+            mosaic_scale: options.mosaic_scale,
+            mosaic_correction: options.mosaic_correction,
+            tile_size: options.tile_size,
+            tile_offset: options.tile_offset,
+            tile_mirror: options.tile_mirror,
+            mirror_folds: options.mirror_folds,
+            mirror_blend: options.mirror_blend,
+            sky_feather: options.sky_feather,
+            sky_harmonize: options.sky_harmonize,
+            motion_cleanup: options.motion_cleanup,
+            motion_background: options.motion_background,
+            subtract_softness: options.subtract_softness,
+            dark_frame: options.dark_frame,
+            stack_method: options.stack_method,
+            depth_map: options.depth_map,
+            focus_near: options.focus_near,
+            focus_far: options.focus_far,
+            depth_blur: options.depth_blur,
+            backend: options.backend,
+            pipeline: options.pipeline,
+            seed: options.seed,
+        }
+    }
+}
+
+/// [`CombineOptions`] as a client of the `serve` subcommand may send them:
+/// the JSON of an option file, without the paths of files on the server,
+/// `matte_model`, `face_model`, `dark_frame` and `depth_map`, through which
+/// any client could have the server read any file it can.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "OptionsSchema")]
+pub struct RemoteOptions(pub CombineOptions);
+
+impl TryFrom<OptionsSchema> for RemoteOptions {
+    type Error = String;
+
+    fn try_from(schema: OptionsSchema) -> Result<Self, Self::Error> {
+        let paths = [
+            ("matte_model", &schema.matte_model),
+            ("face_model", &schema.face_model),
+            ("dark_frame", &schema.dark_frame),
+            ("depth_map", &schema.depth_map),
+        ];
+        if let Some((field, _)) = paths.iter().find(|(_, path)| path.is_some()) {
+            return Err(format!("`{}` names a file on the server and cannot be sent", field));
+        }
+        Ok(RemoteOptions(schema.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remote_options_leave_out_server_paths() {
+        let RemoteOptions(options) = serde_json::from_str(r#"{"mode": "diff", "canvas": [640, 480]}"#).unwrap();
+        assert_eq!((options.mode, options.canvas), (Mode::Diff, Some((640, 480))));
+        for field in ["matte_model", "face_model", "dark_frame", "depth_map"] {
+            let json = format!(r#"{{"mode": "diff", "{}": "/etc/passwd"}}"#, field);
+            let error = serde_json::from_str::<RemoteOptions>(&json).unwrap_err().to_string();
+            assert!(error.contains(field), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+
+use crate::hooks::Hooks;
+use crate::ImageDataErrors;
+
+/// Repeats `tile` across a `width` by `height` canvas, first scaled to
+/// `tile_width` wide if given. The pattern is shifted by `offset`, and with
+/// `mirror` every other column of tiles is flipped horizontally and every
+/// other row vertically, so edges meet their own reflection.
+pub(crate) fn tile_pattern(
+    tile: &RgbaImage,
+    (width, height): (u32, u32),
+    tile_width: Option<u32>,
+    offset: (i32, i32),
+    mirror: bool,
+    hooks: Hooks,
+) -> Result<Vec<u8>, ImageDataErrors> {
+    let scaled;
+    let tile = match tile_width.filter(|&tile_width| tile_width != tile.width()) {
+        Some(tile_width) => {
+            let tile_height = (tile.height() as u64 * tile_width as u64 / tile.width() as u64).max(1) as u32;
+            scaled = imageops::resize(tile, tile_width, tile_height, FilterType::Triangle);
+            &scaled
+        }
+        None => tile,
+    };
+    let (tile_width, tile_height) = (tile.width() as i64, tile.height() as i64);
+    // Where each output column and row falls in the tile, worked out once.
+    let source = |position: u32, offset: i32, size: i64| {
+        let shifted = position as i64 - offset as i64;
+        let within = shifted.rem_euclid(size);
+        if mirror && shifted.div_euclid(size) % 2 != 0 {
+            (size - 1 - within) as u32
+        } else {
+            within as u32
+        }
+    };
+    let columns: Vec<u32> = (0..width).map(|x| source(x, offset.0, tile_width)).collect();
+    let rows: Vec<u32> = (0..height).map(|y| source(y, offset.1, tile_height)).collect();
+
+    let mut data = Vec::with_capacity(width as usize * height as usize * 4);
+    for (y, &row) in rows.iter().enumerate() {
+        hooks.step("combining", y, rows.len())?;
+        for &column in &columns {
+            data.extend_from_slice(&tile.get_pixel(column, row).0);
+        }
+    }
+    hooks.step("combining", rows.len(), rows.len())?;
+    Ok(data)
+}
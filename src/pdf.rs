@@ -0,0 +1,63 @@
+//! Rasterising PDF pages with hayro, behind the `pdf` feature, so report
+//! pages can be diffed or composited like any other image.
+
+use image::DynamicImage;
+
+use crate::ImageDataErrors;
+
+/// The DPI pages are rendered at by default: one pixel per PDF point.
+pub const DEFAULT_DPI: f32 = 72.0;
+
+/// Splits `document.pdf#page=3` into the document and its 1-based page,
+/// the first page when no fragment is given. Paths of other files give `None`.
+pub fn split_page(path: &str) -> Option<(&str, usize)> {
+    let (document, page) = match path.rsplit_once("#page=") {
+        Some((document, page)) => (document, page.parse().ok()?),
+        None => (path, 1),
+    };
+    let extension = std::path::Path::new(document).extension().and_then(|extension| extension.to_str());
+    extension.is_some_and(|extension| extension.eq_ignore_ascii_case("pdf")).then_some((document, page))
+}
+
+/// Renders the 1-based `page` of a PDF document onto white at `dpi`.
+#[cfg(all(feature = "pdf", not(target_arch = "wasm32")))]
+pub fn rasterize(bytes: Vec<u8>, page: usize, dpi: f32) -> Result<DynamicImage, ImageDataErrors> {
+    use hayro::hayro_interpret::InterpreterSettings;
+    use hayro::hayro_syntax::Pdf;
+    use hayro::vello_cpu::color::palette::css::WHITE;
+    use hayro::vello_cpu::peniko::ImageAlphaType;
+    use hayro::{PixmapSettings, RenderCache, RenderSettings};
+    use image::error::{DecodingError, ImageFormatHint};
+    use image::{ImageError, RgbaImage};
+
+    let invalid = |message: String| {
+        ImageDataErrors::UnableToDecodeImage(ImageError::Decoding(DecodingError::new(
+            ImageFormatHint::Name("PDF".to_string()),
+            message,
+        )))
+    };
+
+    let pdf = Pdf::new(bytes).map_err(|e| invalid(format!("{:?}", e)))?;
+    let pages = pdf.pages();
+    let Some(document_page) = page.checked_sub(1).and_then(|index| pages.get(index)) else {
+        return Err(invalid(format!("page {} of a {} page document", page, pages.len())));
+    };
+
+    let scale = dpi / 72.0;
+    let pixmap = hayro::render(
+        document_page,
+        &RenderCache::new(),
+        &InterpreterSettings::default(),
+        &RenderSettings::default(),
+        &PixmapSettings { x_scale: scale, y_scale: scale, bg_color: WHITE },
+    );
+    let (width, height) = (pixmap.width() as u32, pixmap.height() as u32);
+    // The white background leaves every pixel opaque, so premultiplication changes nothing.
+    let data = pixmap.take_rgba8(ImageAlphaType::AlphaPremultiplied);
+    Ok(DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, data).expect("the pixmap matches its size")))
+}
+
+#[cfg(not(all(feature = "pdf", not(target_arch = "wasm32"))))]
+pub fn rasterize(_bytes: Vec<u8>, _page: usize, _dpi: f32) -> Result<DynamicImage, ImageDataErrors> {
+    Err(ImageDataErrors::InvalidArgument("PDF inputs need a build with the `pdf` feature".to_string()))
+}
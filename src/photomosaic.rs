@@ -0,0 +1,106 @@
+use image::{DynamicImage, RgbaImage};
+
+use crate::fit::{fit_to, Fit};
+use crate::gpu::Backend;
+use crate::hooks::Hooks;
+use crate::ImageDataErrors;
+
+/// The small images a photomosaic is built from, each cropped square and
+/// shrunk to the tile size as it is added, so large libraries fit in memory.
+pub struct TileLibrary {
+    size: u32,
+    tiles: Vec<(RgbaImage, [f64; 3])>,
+}
+
+impl TileLibrary {
+    pub fn new(size: u32) -> Self {
+        TileLibrary { size: size.max(1), tiles: Vec::new() }
+    }
+
+    pub fn add(&mut self, image: DynamicImage) {
+        let tile = fit_to(image, (self.size, self.size), Fit::Crop, false, &[], Backend::Cpu).to_rgba8();
+        let average = average(&tile, 0, 0, self.size, self.size);
+        self.tiles.push((tile, average));
+    }
+
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+
+    pub fn tile_size(&self) -> u32 {
+        self.size
+    }
+}
+
+/// Rebuilds `image` from the library's tiles, `scale` times larger: every
+/// cell of the image is replaced by the tile closest to its average colour,
+/// never the same tile as the cell to its left or above when the library
+/// has others. `correction`, from 0 to 1, shifts each tile's colours that
+/// far toward its cell's average.
+pub(crate) fn build(image: &RgbaImage, library: &TileLibrary, scale: u32, correction: f64, hooks: Hooks) -> Result<RgbaImage, ImageDataErrors> {
+    let tile = library.size;
+    let cell = (tile / scale).max(1);
+    let (columns, rows) = (image.width().div_ceil(cell), image.height().div_ceil(cell));
+    let mut mosaic = RgbaImage::new(image.width() * tile / cell, image.height() * tile / cell);
+    let mut chosen = vec![usize::MAX; (columns * rows) as usize];
+    for row in 0..rows {
+        hooks.step("combining", row as usize, rows as usize)?;
+        for column in 0..columns {
+            let target = average(image, column * cell, row * cell, cell, cell);
+            let neighbours = [
+                column.checked_sub(1).map(|left| chosen[(row * columns + left) as usize]),
+                row.checked_sub(1).map(|above| chosen[(above * columns + column) as usize]),
+            ];
+            let candidates = library.tiles.iter().enumerate().filter(|(index, _)| {
+                library.tiles.len() <= 2 || !neighbours.contains(&Some(*index))
+            });
+            let (index, (pixels, colour)) = candidates
+                .min_by(|(_, (_, a)), (_, (_, b))| distance(target, *a).total_cmp(&distance(target, *b)))
+                .expect("the library is not empty");
+            chosen[(row * columns + column) as usize] = index;
+
+            let shift = [0, 1, 2].map(|channel| (target[channel] - colour[channel]) * correction);
+            let (left, top) = (column * tile, row * tile);
+            for (x, y, pixel) in pixels.enumerate_pixels() {
+                if left + x >= mosaic.width() || top + y >= mosaic.height() {
+                    continue;
+                }
+                let mut pixel = *pixel;
+                for (channel, shift) in pixel.0[..3].iter_mut().zip(shift) {
+                    *channel = (*channel as f64 + shift).round().clamp(0.0, 255.0) as u8;
+                }
+                mosaic.put_pixel(left + x, top + y, pixel);
+            }
+        }
+    }
+    hooks.step("combining", rows as usize, rows as usize)?;
+    Ok(mosaic)
+}
+
+/// The average colour of a region, which may run past the image's edges.
+fn average(image: &RgbaImage, left: u32, top: u32, width: u32, height: u32) -> [f64; 3] {
+    let (right, bottom) = ((left + width).min(image.width()), (top + height).min(image.height()));
+    let mut sum = [0.0; 3];
+    for y in top..bottom {
+        for x in left..right {
+            let pixel = image.get_pixel(x, y).0;
+            for channel in 0..3 {
+                sum[channel] += pixel[channel] as f64;
+            }
+        }
+    }
+    let count = ((right - left) * (bottom - top)).max(1) as f64;
+    sum.map(|total| total / count)
+}
+
+/// The "redmean" colour distance, a cheap approximation of how different
+/// two colours look, squared.
+fn distance(a: [f64; 3], b: [f64; 3]) -> f64 {
+    let mean_red = (a[0] + b[0]) / 2.0;
+    let [red, green, blue] = [a[0] - b[0], a[1] - b[1], a[2] - b[2]];
+    (2.0 + mean_red / 256.0) * red * red + 4.0 * green * green + (2.0 + (255.0 - mean_red) / 256.0) * blue * blue
+}
@@ -0,0 +1,316 @@
+//! Several edits in one run, written as steps separated by `|`, such as
+//! `resize:800x600 | blur:2 | blend:multiply | border:4:#fff`. The steps
+//! start from the first input and each works on the result of the one
+//! before; `blend` and `combine` bring in the second input. A pipeline takes
+//! the place of the mode, and is kept in its written form in option files.
+//! Each step becomes a node of a [`Graph`], as the branches of recipes do.
+
+use std::fmt;
+
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::{self, Geometry, Gravity};
+use crate::graph::Graph;
+use crate::options::Mode;
+use crate::simd::{self, Kernel};
+use crate::transform::{self, Flip};
+use crate::trim;
+use crate::ImageDataErrors;
+
+/// How `blend` mixes the colours of the second input into the image so far,
+/// as the blend modes of image editors do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendMode {
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    Difference,
+    Add,
+}
+
+impl BlendMode {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        match value {
+            "normal" => Ok(BlendMode::Normal),
+            "multiply" => Ok(BlendMode::Multiply),
+            "screen" => Ok(BlendMode::Screen),
+            "overlay" => Ok(BlendMode::Overlay),
+            "darken" => Ok(BlendMode::Darken),
+            "lighten" => Ok(BlendMode::Lighten),
+            "difference" => Ok(BlendMode::Difference),
+            "add" => Ok(BlendMode::Add),
+            _ => Err(ImageDataErrors::InvalidArgument(format!("unknown blend mode `{}`", value))),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BlendMode::Normal => "normal",
+            BlendMode::Multiply => "multiply",
+            BlendMode::Screen => "screen",
+            BlendMode::Overlay => "overlay",
+            BlendMode::Darken => "darken",
+            BlendMode::Lighten => "lighten",
+            BlendMode::Difference => "difference",
+            BlendMode::Add => "add",
+        }
+    }
+
+    /// The blended colour of one channel, from 0 to 1, of the image so far
+    /// (`backdrop`) and the second input (`source`).
+    fn apply(&self, backdrop: f32, source: f32) -> f32 {
+        match self {
+            BlendMode::Normal => source,
+            BlendMode::Multiply => backdrop * source,
+            BlendMode::Screen => backdrop + source - backdrop * source,
+            BlendMode::Overlay if backdrop <= 0.5 => 2.0 * backdrop * source,
+            BlendMode::Overlay => 1.0 - 2.0 * (1.0 - backdrop) * (1.0 - source),
+            BlendMode::Darken => backdrop.min(source),
+            BlendMode::Lighten => backdrop.max(source),
+            BlendMode::Difference => (backdrop - source).abs(),
+            BlendMode::Add => (backdrop + source).min(1.0),
+        }
+    }
+}
+
+/// One step of a [`Pipeline`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// `resize:<geometry>`, as `--resize` takes it.
+    Resize(Geometry),
+    /// `crop:<geometry>`, as `--crop` takes it, measured from `--gravity`.
+    Crop(Geometry),
+    /// `rotate:<degrees>`, clockwise.
+    Rotate(f64),
+    /// `flip:horizontal`, `flip:vertical` or `flip:both`.
+    Flip(Flip),
+    /// `blur:<sigma>`, a Gaussian blur.
+    Blur(f32),
+    /// `grayscale`.
+    Grayscale,
+    /// `trim`, as `--trim` crops each input.
+    Trim,
+    /// `blend:<mode>[:<opacity>]`: the second input, brought to the size of
+    /// the image so far, blended onto it.
+    Blend { mode: BlendMode, opacity: f32 },
+    /// `combine[:<mode>]`: the image so far and the second input combined
+    /// like two inputs are, in the mode given or else in `--mode`.
+    Combine(Option<Mode>),
+    /// `border:<width>[:<colour>]`: a margin around the image, black unless
+    /// a colour is given.
+    Border { width: u32, colour: [u8; 4] },
+}
+
+/// The steps of `--pipeline`, in order.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Pipeline {
+    pub steps: Vec<Step>,
+}
+
+impl Pipeline {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        let steps = value.split('|').map(|step| Step::parse(step.trim())).collect::<Result<Vec<_>, _>>()?;
+        Ok(Pipeline { steps })
+    }
+
+    /// Runs the steps on `image_1`, with `combine` combining two images in a
+    /// mode for `combine` steps, `default_mode` unless they name one.
+    pub(crate) fn run(
+        &self,
+        image_1: DynamicImage,
+        image_2: DynamicImage,
+        gravity: Gravity,
+        default_mode: Mode,
+        combine: impl FnMut(DynamicImage, DynamicImage, Mode) -> Result<DynamicImage, ImageDataErrors>,
+    ) -> Result<DynamicImage, ImageDataErrors> {
+        let mut graph = Graph::new();
+        let (first, second) = (graph.input("image_1"), graph.input("image_2"));
+        let output = graph.pipeline(self, first, Some(second))?;
+        let mut inputs = [Some(image_1), Some(image_2)];
+        let load = |name: &str| Ok(inputs[usize::from(name == "image_2")].take().expect("each input is loaded once"));
+        let mut outputs = graph.evaluate(&[output], gravity, default_mode, load, combine)?;
+        Ok(outputs.remove(0))
+    }
+}
+
+impl Step {
+    /// Whether the step blends or combines a second image into the first.
+    pub fn uses_second(&self) -> bool {
+        matches!(self, Step::Blend { .. } | Step::Combine(_))
+    }
+
+    /// Runs the step on `image`, and on `second` for steps that use it.
+    pub(crate) fn apply(
+        &self,
+        image: DynamicImage,
+        second: Option<DynamicImage>,
+        gravity: Gravity,
+        default_mode: Mode,
+        combine: &mut impl FnMut(DynamicImage, DynamicImage, Mode) -> Result<DynamicImage, ImageDataErrors>,
+    ) -> Result<DynamicImage, ImageDataErrors> {
+        log::debug!("running {}", self);
+        let second = || second.ok_or_else(|| ImageDataErrors::InvalidArgument(format!("`{}` needs a second image", self)));
+        Ok(match self {
+            Step::Resize(resize) => geometry::reshape(image, Some(resize), None, gravity)?,
+            Step::Crop(crop) => geometry::reshape(image, None, Some(crop), gravity)?,
+            Step::Rotate(degrees) => transform::orient(image, *degrees, None),
+            Step::Flip(flip) => transform::orient(image, 0.0, Some(*flip)),
+            Step::Blur(sigma) => image.blur(*sigma),
+            Step::Grayscale => DynamicImage::ImageRgba8(image.grayscale().to_rgba8()),
+            Step::Trim => trim::trim_image(image),
+            Step::Blend { mode, opacity } => DynamicImage::ImageRgba8(blend(&image.to_rgba8(), &second()?, *mode, *opacity)),
+            Step::Combine(mode) => combine(image, second()?, mode.unwrap_or(default_mode))?,
+            Step::Border { width, colour } => DynamicImage::ImageRgba8(border(&image.to_rgba8(), *width, *colour)),
+        })
+    }
+
+    fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        let invalid = |message: &str| ImageDataErrors::InvalidArgument(format!("pipeline step `{}` {}", value, message));
+        let number = |text: &str| text.parse::<f64>().ok().filter(|number| number.is_finite());
+        let mut parts = value.split(':');
+        let name = parts.next().unwrap_or_default();
+        let arguments: Vec<&str> = parts.collect();
+        match (name, arguments.as_slice()) {
+            ("resize", [geometry]) => Ok(Step::Resize(Geometry::parse(geometry)?)),
+            ("crop", [geometry]) => Ok(Step::Crop(Geometry::parse(geometry)?)),
+            ("rotate", [degrees]) => number(degrees).map(Step::Rotate).ok_or_else(|| invalid("takes a number of degrees")),
+            ("flip", [flip]) => Ok(Step::Flip(Flip::parse(flip)?)),
+            ("blur", [sigma]) => match number(sigma) {
+                Some(sigma) if sigma > 0.0 => Ok(Step::Blur(sigma as f32)),
+                _ => Err(invalid("takes a radius above 0")),
+            },
+            ("grayscale", []) => Ok(Step::Grayscale),
+            ("trim", []) => Ok(Step::Trim),
+            ("blend", [mode]) => Ok(Step::Blend { mode: BlendMode::parse(mode)?, opacity: 1.0 }),
+            ("blend", [mode, opacity]) => match number(opacity) {
+                Some(opacity) if (0.0..=1.0).contains(&opacity) => {
+                    Ok(Step::Blend { mode: BlendMode::parse(mode)?, opacity: opacity as f32 })
+                }
+                _ => Err(invalid("takes an opacity between 0 and 1")),
+            },
+            ("combine", []) => Ok(Step::Combine(None)),
+            ("combine", [mode]) => Ok(Step::Combine(Some(Mode::parse(mode)?))),
+            ("border", [width, rest @ ..]) if rest.len() <= 1 => {
+                let width = width.parse().map_err(|_| invalid("takes a width in pixels"))?;
+                let colour = match rest {
+                    [colour] => parse_colour(colour).ok_or_else(|| invalid("takes a colour such as #fff or #ff000080"))?,
+                    _ => [0, 0, 0, 255],
+                };
+                Ok(Step::Border { width, colour })
+            }
+            ("resize" | "crop" | "rotate" | "flip" | "blur" | "grayscale" | "trim" | "blend" | "combine" | "border", _) => {
+                Err(invalid("has the wrong number of arguments"))
+            }
+            _ => Err(invalid(
+                "is unknown; steps are resize, crop, rotate, flip, blur, grayscale, trim, blend, combine and border",
+            )),
+        }
+    }
+}
+
+/// `#rgb`, `#rrggbb` or `#rrggbbaa`, or `white`, `black` or `transparent`.
+fn parse_colour(value: &str) -> Option<[u8; 4]> {
+    match value {
+        "white" => return Some([255, 255, 255, 255]),
+        "black" => return Some([0, 0, 0, 255]),
+        "transparent" => return Some([0, 0, 0, 0]),
+        _ => {}
+    }
+    let hex = value.strip_prefix('#')?;
+    let digits: Vec<u8> = hex.chars().map(|digit| digit.to_digit(16).map(|digit| digit as u8)).collect::<Option<_>>()?;
+    match *digits.as_slice() {
+        [red, green, blue] => Some([red * 17, green * 17, blue * 17, 255]),
+        [r1, r2, g1, g2, b1, b2] => Some([r1 * 16 + r2, g1 * 16 + g2, b1 * 16 + b2, 255]),
+        [r1, r2, g1, g2, b1, b2, a1, a2] => Some([r1 * 16 + r2, g1 * 16 + g2, b1 * 16 + b2, a1 * 16 + a2]),
+        _ => None,
+    }
+}
+
+/// `source` stretched over `backdrop` and blended onto it, composited as
+/// the W3C's compositing specification describes, so transparent parts of
+/// either image keep the other's colours.
+fn blend(backdrop: &RgbaImage, source: &DynamicImage, mode: BlendMode, opacity: f32) -> RgbaImage {
+    let (width, height) = backdrop.dimensions();
+    let source = if source.dimensions() == (width, height) {
+        source.to_rgba8()
+    } else {
+        source.resize_exact(width, height, image::imageops::Triangle).to_rgba8()
+    };
+    if mode == BlendMode::Multiply {
+        let data = simd::multiply(backdrop.as_raw(), source.as_raw(), opacity, Kernel::detect());
+        return RgbaImage::from_raw(width, height, data).expect("multiplying keeps the size");
+    }
+    let mut output = backdrop.clone();
+    for (out, from) in output.pixels_mut().zip(source.pixels()) {
+        let backdrop_alpha = out[3] as f32 / 255.0;
+        let source_alpha = from[3] as f32 / 255.0 * opacity;
+        let alpha = source_alpha + backdrop_alpha * (1.0 - source_alpha);
+        if alpha == 0.0 {
+            continue;
+        }
+        for channel in 0..3 {
+            let (b, s) = (out[channel] as f32 / 255.0, from[channel] as f32 / 255.0);
+            let mixed = (1.0 - source_alpha) * backdrop_alpha * b
+                + (1.0 - backdrop_alpha) * source_alpha * s
+                + source_alpha * backdrop_alpha * mode.apply(b, s);
+            out[channel] = (mixed / alpha * 255.0).round().clamp(0.0, 255.0) as u8;
+        }
+        out[3] = (alpha * 255.0).round() as u8;
+    }
+    output
+}
+
+fn border(image: &RgbaImage, width: u32, colour: [u8; 4]) -> RgbaImage {
+    let mut output = RgbaImage::from_pixel(image.width() + 2 * width, image.height() + 2 * width, Rgba(colour));
+    image::imageops::replace(&mut output, image, width, width);
+    output
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::Resize(geometry) => write!(f, "resize:{}", geometry),
+            Step::Crop(geometry) => write!(f, "crop:{}", geometry),
+            Step::Rotate(degrees) => write!(f, "rotate:{}", degrees),
+            Step::Flip(Flip::Horizontal) => write!(f, "flip:horizontal"),
+            Step::Flip(Flip::Vertical) => write!(f, "flip:vertical"),
+            Step::Flip(Flip::Both) => write!(f, "flip:both"),
+            Step::Blur(sigma) => write!(f, "blur:{}", sigma),
+            Step::Grayscale => write!(f, "grayscale"),
+            Step::Trim => write!(f, "trim"),
+            Step::Blend { mode, opacity } if *opacity == 1.0 => write!(f, "blend:{}", mode.name()),
+            Step::Blend { mode, opacity } => write!(f, "blend:{}:{}", mode.name(), opacity),
+            Step::Combine(None) => write!(f, "combine"),
+            Step::Combine(Some(mode)) => write!(f, "combine:{}", mode.name()),
+            Step::Border { width, colour: [red, green, blue, alpha] } => {
+                write!(f, "border:{}:#{:02x}{:02x}{:02x}{:02x}", width, red, green, blue, alpha)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let steps: Vec<String> = self.steps.iter().map(Step::to_string).collect();
+        write!(f, "{}", steps.join(" | "))
+    }
+}
+
+impl TryFrom<String> for Pipeline {
+    type Error = ImageDataErrors;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Pipeline::parse(&value)
+    }
+}
+
+impl From<Pipeline> for String {
+    fn from(pipeline: Pipeline) -> Self {
+        pipeline.to_string()
+    }
+}
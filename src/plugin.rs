@@ -0,0 +1,94 @@
+//! The plugin ABI, declared with the C API in `include/imgcombine.h`. A
+//! plugin is a dynamic library that exports [`ENTRY_POINT`] as an
+//! [`ImgcombinePluginEntry`], returning a description of the blend modes,
+//! layouts and encoders it adds:
+//!
+//! ```text
+//! static const ImgcombineBlendMode BLEND_MODES[] = { { "hard-mix", hard_mix } };
+//! static const ImgcombinePlugin PLUGIN = {
+//!     IMGCOMBINE_PLUGIN_ABI_VERSION, "example", BLEND_MODES, 1, NULL, 0, NULL, 0,
+//! };
+//! const ImgcombinePlugin *imgcombine_plugin(void) { return &PLUGIN; }
+//! ```
+//!
+//! Pixels are always 8-bit RGBA, row after row with no padding. Everything a
+//! description points to must stay valid for as long as the library is loaded.
+
+use std::ffi::{c_char, c_int, c_void};
+
+/// The ABI version this build loads; plugins built for another are skipped.
+pub const IMGCOMBINE_PLUGIN_ABI_VERSION: u32 = 1;
+
+/// The symbol every plugin exports.
+pub const ENTRY_POINT: &str = "imgcombine_plugin";
+
+/// The type of `imgcombine_plugin`, the function every plugin exports.
+pub type ImgcombinePluginEntry = unsafe extern "C" fn() -> *const ImgcombinePlugin;
+
+/// Receives the encoded bytes an encoder writes, in order, passing back the
+/// `context` the encoder was given.
+pub type ImgcombineWrite = unsafe extern "C" fn(context: *mut c_void, data: *const u8, len: usize);
+
+/// An image of `width * height` RGBA pixels. Inputs are read-only.
+#[repr(C)]
+pub struct ImgcombinePixels {
+    pub data: *mut u8,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImgcombineSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Blends `pixels` pixels of `first` and `second`, both of one size, into `out`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ImgcombineBlendMode {
+    pub name: *const c_char,
+    pub blend: unsafe extern "C" fn(first: *const u8, second: *const u8, out: *mut u8, pixels: usize),
+}
+
+/// Arranges both inputs, each at its own size, into an output whose size
+/// `size` gives. `layout` returns 0 once `out` is filled, and anything else
+/// when it cannot be.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ImgcombineLayout {
+    pub name: *const c_char,
+    pub size: unsafe extern "C" fn(first: ImgcombineSize, second: ImgcombineSize) -> ImgcombineSize,
+    pub layout: unsafe extern "C" fn(first: *const ImgcombinePixels, second: *const ImgcombinePixels, out: *mut ImgcombinePixels) -> c_int,
+}
+
+/// Encodes outputs whose file extension is `extension`, without the dot and
+/// in any case, passing the bytes to `write`. `encode` returns 0 on success.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ImgcombineEncoder {
+    pub extension: *const c_char,
+    pub encode: unsafe extern "C" fn(image: *const ImgcombinePixels, write: ImgcombineWrite, context: *mut c_void) -> c_int,
+}
+
+/// What a plugin adds. Each list may be NULL when its count is 0.
+#[repr(C)]
+pub struct ImgcombinePlugin {
+    /// [`IMGCOMBINE_PLUGIN_ABI_VERSION`] when the plugin was built.
+    pub abi_version: u32,
+    pub name: *const c_char,
+    pub blend_modes: *const ImgcombineBlendMode,
+    pub blend_mode_count: usize,
+    pub layouts: *const ImgcombineLayout,
+    pub layout_count: usize,
+    pub encoders: *const ImgcombineEncoder,
+    pub encoder_count: usize,
+}
+
+// Descriptions are only read, and point to data that is never freed, so
+// plugins written in Rust can keep theirs in a static.
+unsafe impl Sync for ImgcombineBlendMode {}
+unsafe impl Sync for ImgcombineLayout {}
+unsafe impl Sync for ImgcombineEncoder {}
+unsafe impl Sync for ImgcombinePlugin {}
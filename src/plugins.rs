@@ -0,0 +1,273 @@
+//! `--plugin name`: blend modes and layouts from dynamic libraries behind the
+//! `plugins` feature, in place of the mode. Plugins may also add encoders,
+//! used for outputs with the extensions they name. Every library in
+//! `--plugins-dir`, or else the user's plugin directory, is loaded; see
+//! [`combiner::plugin`] for what a plugin exports.
+
+use combiner::options::CombineOptions;
+use combiner::{FloatingImage, ImageDataErrors};
+use image::DynamicImage;
+
+#[cfg(feature = "plugins")]
+pub use loader::Plugins;
+
+#[cfg(feature = "plugins")]
+mod loader {
+    use std::collections::BTreeMap;
+    use std::ffi::{c_char, c_void, CStr};
+    use std::path::{Path, PathBuf};
+
+    use combiner::plugin::{
+        ImgcombineBlendMode, ImgcombineEncoder, ImgcombineLayout, ImgcombinePixels, ImgcombinePlugin, ImgcombinePluginEntry,
+        ImgcombineSize, ENTRY_POINT, IMGCOMBINE_PLUGIN_ABI_VERSION,
+    };
+    use image::RgbaImage;
+    use libloading::Library;
+    use sha2::{Digest, Sha256};
+
+    use super::{CombineOptions, DynamicImage, FloatingImage, ImageDataErrors};
+
+    fn failed(message: impl std::fmt::Display) -> ImageDataErrors {
+        ImageDataErrors::UnableToUsePlugin(message.to_string())
+    }
+
+    /// The user's plugin directory: `$XDG_CONFIG_HOME/combiner/plugins`, or
+    /// under `~/.config`, or `%APPDATA%` on Windows.
+    fn user_dir() -> Option<PathBuf> {
+        let base = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))?;
+        Some(base.join("combiner").join("plugins"))
+    }
+
+    /// A plugin's list of `count` items.
+    ///
+    /// # Safety
+    ///
+    /// `items` must point to `count` items, or `count` must be 0.
+    unsafe fn list<'a, T>(items: *const T, count: usize) -> &'a [T] {
+        if count == 0 {
+            &[]
+        } else {
+            std::slice::from_raw_parts(items, count)
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `text` must be NULL or NUL-terminated.
+    unsafe fn text(text: *const c_char) -> Result<String, ImageDataErrors> {
+        if text.is_null() {
+            return Err(failed("a name is missing"));
+        }
+        CStr::from_ptr(text).to_str().map(str::to_owned).map_err(|_| failed("a name is not UTF-8"))
+    }
+
+    fn pixels(image: &mut RgbaImage) -> ImgcombinePixels {
+        ImgcombinePixels { width: image.width(), height: image.height(), data: image.as_mut_ptr() }
+    }
+
+    /// Collects what an encoder writes into the `Vec<u8>` behind `context`.
+    unsafe extern "C" fn append(context: *mut c_void, data: *const u8, len: usize) {
+        if len > 0 {
+            (*context.cast::<Vec<u8>>()).extend_from_slice(std::slice::from_raw_parts(data, len));
+        }
+    }
+
+    enum Combination {
+        Blend(ImgcombineBlendMode),
+        Layout(ImgcombineLayout),
+    }
+
+    /// Something a plugin adds, with the plugin it came from.
+    struct Provided<T> {
+        plugin: String,
+        /// The hash of the plugin's library, so `--incremental` rebuilds when it changes.
+        digest: String,
+        item: T,
+    }
+
+    /// The loaded plugins, by what they add.
+    pub struct Plugins {
+        combinations: BTreeMap<String, Provided<Combination>>,
+        /// By lowercase extension.
+        encoders: BTreeMap<String, Provided<ImgcombineEncoder>>,
+        /// Declared last, so the libraries are unloaded after everything
+        /// pointing into them is dropped.
+        libraries: Vec<Library>,
+    }
+
+    impl Plugins {
+        /// Loads every plugin in `dir`, or in the user's plugin directory if
+        /// there is one. Libraries that are not plugins for this build are
+        /// skipped with a warning.
+        pub fn load(dir: Option<&str>) -> Result<Self, ImageDataErrors> {
+            let mut plugins = Plugins { combinations: BTreeMap::new(), encoders: BTreeMap::new(), libraries: Vec::new() };
+            let dir = match (dir, user_dir()) {
+                (Some(dir), _) => PathBuf::from(dir),
+                (None, Some(dir)) if dir.is_dir() => dir,
+                (None, _) => return Ok(plugins),
+            };
+            let mut paths = std::fs::read_dir(&dir)
+                .map_err(ImageDataErrors::UnableToReadDirectory)?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|extension| extension == std::env::consts::DLL_EXTENSION))
+                .collect::<Vec<_>>();
+            paths.sort();
+            for path in paths {
+                if let Err(e) = plugins.add(&path) {
+                    log::warn!("skipping {}: {}", path.display(), e);
+                }
+            }
+            Ok(plugins)
+        }
+
+        fn add(&mut self, path: &Path) -> Result<(), ImageDataErrors> {
+            let bytes = std::fs::read(path).map_err(ImageDataErrors::UnableToReadImageFromPath)?;
+            let digest: String = Sha256::digest(&bytes).iter().map(|byte| format!("{:02x}", byte)).collect();
+            // SAFETY: loading runs the library's initialisers, so plugins are
+            // trusted like any program the user installs.
+            let library = unsafe { Library::new(path) }.map_err(failed)?;
+            let entry = *unsafe { library.get::<ImgcombinePluginEntry>(ENTRY_POINT.as_bytes()) }.map_err(failed)?;
+            // SAFETY: the entry point returns NULL or a description that lives as long as the library.
+            let plugin: &ImgcombinePlugin = unsafe { entry().as_ref() }.ok_or_else(|| failed("the plugin described nothing"))?;
+            if plugin.abi_version != IMGCOMBINE_PLUGIN_ABI_VERSION {
+                return Err(failed(format!("built for plugin ABI {}, not {}", plugin.abi_version, IMGCOMBINE_PLUGIN_ABI_VERSION)));
+            }
+            // SAFETY: the description's names are NUL-terminated and its lists as long as their counts.
+            let name = unsafe { text(plugin.name) }?;
+            let provided = |item| Provided { plugin: name.clone(), digest: digest.clone(), item };
+            let encoded = |item| Provided { plugin: name.clone(), digest: digest.clone(), item };
+            let mut combinations = Vec::new();
+            for mode in unsafe { list(plugin.blend_modes, plugin.blend_mode_count) } {
+                combinations.push((unsafe { text(mode.name) }?, provided(Combination::Blend(*mode))));
+            }
+            for layout in unsafe { list(plugin.layouts, plugin.layout_count) } {
+                combinations.push((unsafe { text(layout.name) }?, provided(Combination::Layout(*layout))));
+            }
+            let mut encoders = Vec::new();
+            for encoder in unsafe { list(plugin.encoders, plugin.encoder_count) } {
+                let extension = unsafe { text(encoder.extension) }?.to_lowercase();
+                encoders.push((extension, encoded(*encoder)));
+            }
+
+            for (key, item) in combinations {
+                match self.combinations.get(&key) {
+                    Some(first) => log::warn!("`{}` from {} is already added by {}, which is kept", key, name, first.plugin),
+                    None => drop(self.combinations.insert(key, item)),
+                }
+            }
+            for (extension, item) in encoders {
+                match self.encoders.get(&extension) {
+                    Some(first) => log::warn!(".{} outputs from {} are already encoded by {}, which is kept", extension, name, first.plugin),
+                    None => drop(self.encoders.insert(extension, item)),
+                }
+            }
+            log::info!("loaded plugin {} from {}", name, path.display());
+            self.libraries.push(library);
+            Ok(())
+        }
+
+        fn combination(&self, name: &str) -> Result<&Provided<Combination>, ImageDataErrors> {
+            self.combinations.get(name).ok_or_else(|| {
+                let known = self.combinations.keys().map(String::as_str).collect::<Vec<_>>();
+                if known.is_empty() {
+                    failed(format!("no plugin adds `{}`, and none are loaded", name))
+                } else {
+                    failed(format!("no plugin adds `{}`, only {}", name, known.join(", ")))
+                }
+            })
+        }
+
+        /// The hash of the library adding the blend mode or layout `name`.
+        pub fn digest(&self, name: &str) -> Result<&str, ImageDataErrors> {
+            self.combination(name).map(|provided| provided.digest.as_str())
+        }
+
+        /// Combines the inputs with the blend mode or layout `name`, which
+        /// blends inputs brought to one size, or lays them out at their own.
+        pub fn combine(
+            &self,
+            name: &str,
+            image_1: DynamicImage,
+            image_2: DynamicImage,
+            options: &CombineOptions,
+            output_path: String,
+        ) -> Result<FloatingImage, ImageDataErrors> {
+            let provided = self.combination(name)?;
+            match &provided.item {
+                Combination::Blend(mode) => combiner::combine_custom(image_1, image_2, options, output_path, true, |first, second| {
+                    let mut out = RgbaImage::new(first.width(), first.height());
+                    let count = first.width() as usize * first.height() as usize;
+                    // SAFETY: all three buffers hold `count` pixels.
+                    unsafe { (mode.blend)(first.as_ptr(), second.as_ptr(), out.as_mut_ptr(), count) };
+                    Ok(out)
+                }),
+                Combination::Layout(layout) => combiner::combine_custom(image_1, image_2, options, output_path, false, |mut first, mut second| {
+                    let size_of = |image: &RgbaImage| ImgcombineSize { width: image.width(), height: image.height() };
+                    // SAFETY: the plugin only reads the sizes, and writes `out` within the size it gave.
+                    let size = unsafe { (layout.size)(size_of(&first), size_of(&second)) };
+                    if size.width == 0 || size.height == 0 {
+                        return Err(failed(format!("`{}` of {} laid out an empty image", name, provided.plugin)));
+                    }
+                    let mut out = RgbaImage::new(size.width, size.height);
+                    let status = unsafe { (layout.layout)(&pixels(&mut first), &pixels(&mut second), &mut pixels(&mut out)) };
+                    match status {
+                        0 => Ok(out),
+                        status => Err(failed(format!("`{}` of {} failed with status {}", name, provided.plugin, status))),
+                    }
+                }),
+            }
+        }
+
+        /// The output encoded by the plugin that encodes its extension, or
+        /// `None` when none does.
+        pub fn encode(&self, output: &FloatingImage) -> Option<Result<Vec<u8>, ImageDataErrors>> {
+            let extension = Path::new(&output.name).extension()?.to_string_lossy().to_lowercase();
+            let provided = self.encoders.get(&extension)?;
+            let image = ImgcombinePixels { width: output.width, height: output.height, data: output.data.as_ptr().cast_mut() };
+            let mut bytes = Vec::<u8>::new();
+            // SAFETY: the plugin only reads the image, and `append` is only given `bytes` back.
+            let status = unsafe { (provided.item.encode)(&image, append, (&mut bytes as *mut Vec<u8>).cast()) };
+            Some(match status {
+                0 => Ok(bytes),
+                status => Err(failed(format!(".{} encoder of {} failed with status {}", extension, provided.plugin, status))),
+            })
+        }
+    }
+}
+
+/// Stands for the plugins in builds without the `plugins` feature, where
+/// none can be loaded.
+#[cfg(not(feature = "plugins"))]
+pub struct Plugins;
+
+#[cfg(not(feature = "plugins"))]
+impl Plugins {
+    pub fn load(dir: Option<&str>) -> Result<Self, ImageDataErrors> {
+        match dir {
+            Some(dir) => Err(ImageDataErrors::UnableToUsePlugin(format!("cannot load {}, this build lacks the `plugins` feature", dir))),
+            None => Ok(Plugins),
+        }
+    }
+
+    pub fn digest(&self, name: &str) -> Result<&str, ImageDataErrors> {
+        Err(ImageDataErrors::UnableToUsePlugin(format!("cannot use `{}`, this build lacks the `plugins` feature", name)))
+    }
+
+    pub fn combine(
+        &self,
+        name: &str,
+        _image_1: DynamicImage,
+        _image_2: DynamicImage,
+        _options: &CombineOptions,
+        _output_path: String,
+    ) -> Result<FloatingImage, ImageDataErrors> {
+        self.digest(name).map(|_| unreachable!("no plugin is ever loaded"))
+    }
+
+    pub fn encode(&self, _output: &FloatingImage) -> Option<Result<Vec<u8>, ImageDataErrors>> {
+        None
+    }
+}
@@ -0,0 +1,333 @@
+//! The PNM family (PBM, PGM, PPM and PAM), as emitted by many scientific
+//! tools. Both directions go through here rather than `image`, which
+//! rejects PAM files with an alpha channel, returns samples unscaled when
+//! the maxval is not 255 or 65535, and only encodes the colour types each
+//! subtype natively holds. [`decode`] rescales samples to the full 8- or
+//! 16-bit range whatever the maxval.
+
+use image::error::{DecodingError, ImageFormatHint};
+use image::{ColorType, DynamicImage, GrayAlphaImage, GrayImage, ImageBuffer, ImageError, ImageFormat, Luma, LumaA, Rgb, RgbImage, Rgba, RgbaImage};
+
+use crate::{FloatingImage, ImageDataErrors};
+
+/// Which member of the family an image is written as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PnmKind {
+    /// PBM: black and white, thresholded at half brightness.
+    Bitmap,
+    /// PGM: grayscale.
+    Graymap,
+    /// PPM: RGB, dropping alpha.
+    Pixmap,
+    /// PAM: RGBA.
+    ArbitraryMap,
+}
+
+/// How a combined image is encoded as PNM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PnmEncoding {
+    pub kind: PnmKind,
+    /// The largest sample value written; samples are scaled from 0..=255 to
+    /// 0..=maxval, taking two bytes each above 255. Bitmaps ignore it.
+    pub maxval: u16,
+}
+
+impl PnmEncoding {
+    /// The kind a path's extension names, PAM for `.pnm` or anything else
+    /// since it keeps every channel, at a maxval of 255.
+    pub fn for_path(path: &str) -> Self {
+        let extension = std::path::Path::new(path).extension().and_then(|extension| extension.to_str());
+        let kind = match extension.map(str::to_ascii_lowercase).as_deref() {
+            Some("pbm") => PnmKind::Bitmap,
+            Some("pgm") => PnmKind::Graymap,
+            Some("ppm") => PnmKind::Pixmap,
+            _ => PnmKind::ArbitraryMap,
+        };
+        PnmEncoding { kind, maxval: 255 }
+    }
+
+    pub fn encode(&self, image: &FloatingImage) -> Vec<u8> {
+        let (width, height) = (image.width, image.height);
+        let pixels = || image.data.chunks_exact(4);
+        let maxval = self.maxval.max(1);
+        let (header, samples): (String, Vec<u8>) = match self.kind {
+            PnmKind::Bitmap => return encode_bitmap(image),
+            PnmKind::Graymap => (format!("P5\n{} {}\n{}\n", width, height, maxval), pixels().map(luma).collect()),
+            PnmKind::Pixmap => (
+                format!("P6\n{} {}\n{}\n", width, height, maxval),
+                pixels().flat_map(|pixel| pixel[..3].to_vec()).collect(),
+            ),
+            PnmKind::ArbitraryMap => (
+                format!("P7\nWIDTH {}\nHEIGHT {}\nDEPTH 4\nMAXVAL {}\nTUPLTYPE RGB_ALPHA\nENDHDR\n", width, height, maxval),
+                image.data.clone(),
+            ),
+        };
+
+        let mut bytes = header.into_bytes();
+        if maxval == 255 {
+            bytes.extend(samples);
+        } else {
+            for sample in samples {
+                let scaled = ((sample as u32 * maxval as u32 + 127) / 255) as u16;
+                if maxval > 255 {
+                    bytes.extend(scaled.to_be_bytes());
+                } else {
+                    bytes.push(scaled as u8);
+                }
+            }
+        }
+        bytes
+    }
+}
+
+/// Decodes any PNM image, scaling its samples to the full range of 8 or 16
+/// bits depending on its maxval.
+pub fn decode(bytes: &[u8]) -> Result<DynamicImage, ImageDataErrors> {
+    let header = Header::parse(bytes)?;
+    let raster = &bytes[header.len..];
+    let samples = match header.magic {
+        // Bitmaps have no maxval to scale by, which `image` handles as is.
+        b'1' | b'4' => {
+            return image::load_from_memory_with_format(bytes, ImageFormat::Pnm).map_err(ImageDataErrors::UnableToDecodeImage)
+        }
+        b'2' | b'3' => raster
+            .split(|byte| byte.is_ascii_whitespace())
+            .filter(|token| !token.is_empty())
+            .map(|token| std::str::from_utf8(token).ok().and_then(|token| token.parse().ok()).ok_or_else(|| invalid("invalid sample")))
+            .collect::<Result<Vec<u32>, _>>()?,
+        _ if header.maxval > 255 => {
+            raster.chunks_exact(2).map(|sample| u16::from_be_bytes([sample[0], sample[1]]) as u32).collect()
+        }
+        _ => raster.iter().map(|&sample| sample as u32).collect(),
+    };
+
+    let (width, height, depth) = (header.width, header.height, header.depth);
+    let needed = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|pixels| pixels.checked_mul(depth as usize))
+        .ok_or_else(|| invalid("image is too large"))?;
+    if samples.len() < needed {
+        return Err(invalid("image data is truncated"));
+    }
+    if samples[..needed].iter().any(|&sample| sample > header.maxval) {
+        return Err(invalid("a sample exceeds the maxval"));
+    }
+
+    let maxval = header.maxval;
+    let image = if maxval <= 255 {
+        let data = samples[..needed].iter().map(|&sample| ((sample * 255 + maxval / 2) / maxval) as u8).collect();
+        match depth {
+            1 => GrayImage::from_raw(width, height, data).map(DynamicImage::ImageLuma8),
+            2 => GrayAlphaImage::from_raw(width, height, data).map(DynamicImage::ImageLumaA8),
+            3 => RgbImage::from_raw(width, height, data).map(DynamicImage::ImageRgb8),
+            _ => RgbaImage::from_raw(width, height, data).map(DynamicImage::ImageRgba8),
+        }
+    } else {
+        let data = samples[..needed].iter().map(|&sample| ((sample * 65535 + maxval / 2) / maxval) as u16).collect();
+        match depth {
+            1 => ImageBuffer::<Luma<u16>, _>::from_raw(width, height, data).map(DynamicImage::ImageLuma16),
+            2 => ImageBuffer::<LumaA<u16>, _>::from_raw(width, height, data).map(DynamicImage::ImageLumaA16),
+            3 => ImageBuffer::<Rgb<u16>, _>::from_raw(width, height, data).map(DynamicImage::ImageRgb16),
+            _ => ImageBuffer::<Rgba<u16>, _>::from_raw(width, height, data).map(DynamicImage::ImageRgba16),
+        }
+    };
+    Ok(image.expect("the sample count was checked against the header"))
+}
+
+/// The dimensions and decoded colour type of a PNM image, read from its header alone.
+pub fn read_header(bytes: &[u8]) -> Result<((u32, u32), ColorType), ImageDataErrors> {
+    let header = Header::parse(bytes)?;
+    let color_type = match (header.depth, header.maxval > 255) {
+        (1, false) => ColorType::L8,
+        (2, false) => ColorType::La8,
+        (3, false) => ColorType::Rgb8,
+        (_, false) => ColorType::Rgba8,
+        (1, true) => ColorType::L16,
+        (2, true) => ColorType::La16,
+        (3, true) => ColorType::Rgb16,
+        (_, true) => ColorType::Rgba16,
+    };
+    Ok(((header.width, header.height), color_type))
+}
+
+struct Header {
+    magic: u8,
+    width: u32,
+    height: u32,
+    depth: u32,
+    maxval: u32,
+    /// Where the raster starts.
+    len: usize,
+}
+
+impl Header {
+    fn parse(bytes: &[u8]) -> Result<Self, ImageDataErrors> {
+        let magic = match bytes {
+            [b'P', magic @ b'1'..=b'7', ..] => *magic,
+            _ => return Err(invalid("not a PNM image")),
+        };
+        let header = if magic == b'7' { Header::parse_pam(bytes)? } else { Header::parse_classic(bytes, magic)? };
+        if header.width == 0 || header.height == 0 {
+            return Err(invalid("image has no pixels"));
+        }
+        if !(1..=4).contains(&header.depth) || !(1..=65535).contains(&header.maxval) {
+            return Err(invalid("unsupported depth or maxval"));
+        }
+        Ok(header)
+    }
+
+    /// `P1` to `P6`: whitespace separated numbers after the magic, with `#`
+    /// comments, ended by a single whitespace byte.
+    fn parse_classic(bytes: &[u8], magic: u8) -> Result<Self, ImageDataErrors> {
+        let fields = if matches!(magic, b'1' | b'4') { 2 } else { 3 };
+        let mut values = Vec::new();
+        let mut position = 2;
+        while values.len() < fields {
+            match bytes.get(position) {
+                Some(b'#') => {
+                    while bytes.get(position).is_some_and(|&byte| byte != b'\n') {
+                        position += 1;
+                    }
+                }
+                Some(byte) if byte.is_ascii_whitespace() => position += 1,
+                Some(byte) if byte.is_ascii_digit() => {
+                    let start = position;
+                    while bytes.get(position).is_some_and(u8::is_ascii_digit) {
+                        position += 1;
+                    }
+                    let value = std::str::from_utf8(&bytes[start..position]).expect("digits are ASCII");
+                    values.push(value.parse().map_err(|_| invalid("header value is too large"))?);
+                }
+                _ => return Err(invalid("malformed header")),
+            }
+        }
+        if !bytes.get(position).is_some_and(u8::is_ascii_whitespace) {
+            return Err(invalid("malformed header"));
+        }
+        Ok(Header {
+            magic,
+            width: values[0],
+            height: values[1],
+            depth: if matches!(magic, b'3' | b'6') { 3 } else { 1 },
+            maxval: values.get(2).copied().unwrap_or(1),
+            len: position + 1,
+        })
+    }
+
+    /// `P7`: `KEY value` lines up to `ENDHDR`.
+    fn parse_pam(bytes: &[u8]) -> Result<Self, ImageDataErrors> {
+        let (mut width, mut height, mut depth, mut maxval) = (None, None, None, None);
+        let mut position = 0;
+        for line in bytes.split(|&byte| byte == b'\n') {
+            position += line.len() + 1;
+            let line = std::str::from_utf8(line).map_err(|_| invalid("malformed header"))?.trim();
+            let (key, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let number = || value.trim().parse::<u32>().map_err(|_| invalid("malformed header"));
+            match key {
+                "ENDHDR" => {
+                    let (Some(width), Some(height), Some(depth), Some(maxval)) = (width, height, depth, maxval) else {
+                        return Err(invalid("header lacks WIDTH, HEIGHT, DEPTH or MAXVAL"));
+                    };
+                    return Ok(Header { magic: b'7', width, height, depth, maxval, len: position.min(bytes.len()) });
+                }
+                "WIDTH" => width = Some(number()?),
+                "HEIGHT" => height = Some(number()?),
+                "DEPTH" => depth = Some(number()?),
+                "MAXVAL" => maxval = Some(number()?),
+                // The depth alone decides the channels, so the tuple type is informational.
+                _ => {}
+            }
+        }
+        Err(invalid("header lacks ENDHDR"))
+    }
+}
+
+fn encode_bitmap(image: &FloatingImage) -> Vec<u8> {
+    let mut bytes = format!("P4\n{} {}\n", image.width, image.height).into_bytes();
+    for row in image.data.chunks_exact(image.width as usize * 4) {
+        for pixels in row.chunks(8 * 4) {
+            let bits = pixels
+                .chunks_exact(4)
+                .enumerate()
+                .fold(0u8, |bits, (i, pixel)| if luma(pixel) < 128 { bits | 0x80 >> i } else { bits });
+            bytes.push(bits);
+        }
+    }
+    bytes
+}
+
+/// Rec. 709 luma, as `image` converts to grayscale.
+fn luma(pixel: &[u8]) -> u8 {
+    ((2126 * pixel[0] as u32 + 7152 * pixel[1] as u32 + 722 * pixel[2] as u32) / 10000) as u8
+}
+
+fn invalid(message: &str) -> ImageDataErrors {
+    ImageDataErrors::UnableToDecodeImage(ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Exact(ImageFormat::Pnm),
+        message.to_string(),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32, height: u32, data: Vec<u8>) -> FloatingImage {
+        FloatingImage { width, height, data, name: "test.pam".to_string(), dpi: None }
+    }
+
+    fn error(bytes: &[u8]) -> String {
+        decode(bytes).expect_err("the image should not decode").to_string()
+    }
+
+    #[test]
+    fn sixteen_bit_graymap() {
+        let gray = image(2, 1, vec![200, 200, 200, 255, 0, 0, 0, 255]);
+        let bytes = PnmEncoding { kind: PnmKind::Graymap, maxval: 65535 }.encode(&gray);
+        assert!(bytes.starts_with(b"P5\n2 1\n65535\n"));
+        let DynamicImage::ImageLuma16(decoded) = decode(&bytes).unwrap() else { panic!("not 16-bit gray") };
+        assert_eq!(decoded.into_raw(), [200 * 257, 0]);
+    }
+
+    #[test]
+    fn arbitrary_map_keeps_alpha() {
+        let data = vec![255, 0, 0, 255, 0, 128, 255, 64, 10, 20, 30, 0];
+        let bytes = PnmEncoding::for_path("out.pam").encode(&image(3, 1, data.clone()));
+        let DynamicImage::ImageRgba8(decoded) = decode(&bytes).unwrap() else { panic!("not RGBA") };
+        assert_eq!(decoded.into_raw(), data);
+    }
+
+    #[test]
+    fn ascii_samples() {
+        let DynamicImage::ImageLuma8(gray) = decode(b"P2\n# a comment\n2 2\n255\n0 64\n128 255\n").unwrap() else { panic!("not gray") };
+        assert_eq!(gray.into_raw(), [0, 64, 128, 255]);
+        let DynamicImage::ImageRgb8(rgb) = decode(b"P3 1 2 255\n255 0 0\n0 0 255\n").unwrap() else { panic!("not RGB") };
+        assert_eq!(rgb.into_raw(), [255, 0, 0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn other_maxvals_are_scaled() {
+        let DynamicImage::ImageLuma8(gray) = decode(b"P5\n3 1\n15\n\x00\x07\x0f").unwrap() else { panic!("not 8-bit gray") };
+        assert_eq!(gray.into_raw(), [0, 119, 255]);
+        let DynamicImage::ImageLuma16(gray) = decode(b"P2\n3 1\n1023\n0 512 1023\n").unwrap() else { panic!("not 16-bit gray") };
+        assert_eq!(gray.into_raw(), [0, 32800, 65535]);
+    }
+
+    #[test]
+    fn encodes_other_maxvals() {
+        let pixel = image(1, 1, vec![255, 128, 0, 255]);
+        let bytes = PnmEncoding { kind: PnmKind::Pixmap, maxval: 1023 }.encode(&pixel);
+        assert_eq!(bytes, b"P6\n1 1\n1023\n\x03\xff\x02\x02\x00\x00");
+        assert_eq!(decode(&bytes).unwrap().to_rgb8().into_raw(), [255, 128, 0]);
+        let bytes = PnmEncoding { kind: PnmKind::Pixmap, maxval: 15 }.encode(&pixel);
+        assert_eq!(bytes, b"P6\n1 1\n15\n\x0f\x08\x00");
+    }
+
+    #[test]
+    fn rejects_bad_samples() {
+        assert!(error(b"P5\n2 1\n3\n\x01\x04").contains("a sample exceeds the maxval"));
+        assert!(error(b"P2\n2 1\n1023\n0 1024\n").contains("a sample exceeds the maxval"));
+        assert!(error(b"P5\n2 2\n255\n\x01\x02\x03").contains("image data is truncated"));
+        assert!(error(b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 4\nMAXVAL 65535\nENDHDR\n\x00\x01\x02").contains("image data is truncated"));
+    }
+}
@@ -0,0 +1,74 @@
+use image::{imageops, DynamicImage, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::faces::Face;
+use crate::fit::{fit_to, Fit};
+use crate::gpu::Backend;
+use crate::ImageDataErrors;
+
+/// Output sizes wanted by social networks, so nobody has to remember them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SizePreset {
+    /// Open Graph link previews, 1200x630.
+    Og,
+    /// Twitter's large summary card, 1200x628.
+    TwitterCard,
+    /// Instagram's square post, 1080x1080.
+    InstagramSquare,
+    /// YouTube video thumbnails, 1280x720.
+    YoutubeThumb,
+}
+
+impl SizePreset {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        match value {
+            "og" => Ok(SizePreset::Og),
+            "twitter-card" => Ok(SizePreset::TwitterCard),
+            "instagram-square" => Ok(SizePreset::InstagramSquare),
+            "youtube-thumb" => Ok(SizePreset::YoutubeThumb),
+            _ => Err(ImageDataErrors::InvalidArgument(format!("unknown size preset `{}`", value))),
+        }
+    }
+
+    pub fn size(self) -> (u32, u32) {
+        match self {
+            SizePreset::Og => (1200, 630),
+            SizePreset::TwitterCard => (1200, 628),
+            SizePreset::InstagramSquare => (1080, 1080),
+            SizePreset::YoutubeThumb => (1280, 720),
+        }
+    }
+
+    /// How the image is brought into the safe area.
+    pub fn fit(self) -> Fit {
+        match self {
+            SizePreset::Og | SizePreset::InstagramSquare => Fit::Contain,
+            SizePreset::TwitterCard | SizePreset::YoutubeThumb => Fit::Crop,
+        }
+    }
+
+    /// The fraction of each side kept clear, where the network may crop the
+    /// preview or draw over it. Full-bleed presets have none.
+    pub fn safe_margin(self) -> f64 {
+        match self {
+            SizePreset::Og => 0.05,
+            SizePreset::InstagramSquare => 0.04,
+            SizePreset::TwitterCard | SizePreset::YoutubeThumb => 0.0,
+        }
+    }
+}
+
+/// Fits `image` into the preset's safe area, centred on a transparent canvas
+/// of the preset's size.
+pub(crate) fn fit_to_preset(image: RgbaImage, preset: SizePreset, smart_crop: bool, faces: &[Face]) -> RgbaImage {
+    let (width, height) = preset.size();
+    let margin_x = (width as f64 * preset.safe_margin()).round() as u32;
+    let margin_y = (height as f64 * preset.safe_margin()).round() as u32;
+    log::debug!("fitting {}x{} into {:?} with {}x{} margins", image.width(), image.height(), preset, margin_x, margin_y);
+
+    let fitted = fit_to(DynamicImage::ImageRgba8(image), (width - 2 * margin_x, height - 2 * margin_y), preset.fit(), smart_crop, faces, Backend::Cpu);
+    let mut canvas = RgbaImage::new(width, height);
+    imageops::replace(&mut canvas, &fitted.to_rgba8(), margin_x, margin_y);
+    canvas
+}
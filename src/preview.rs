@@ -0,0 +1,378 @@
+//! `--preview`: shows the result in the terminal and lets the mode, the
+//! second input's opacity and a before/after split be tweaked with keys
+//! before anything is written. Images are drawn with the kitty, iTerm2 or
+//! sixel graphics protocols where the terminal speaks one, and with coloured
+//! half blocks everywhere else.
+
+use std::io::{IsTerminal, Write};
+
+use base64::Engine;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::{cursor, terminal, ExecutableCommand, QueueableCommand};
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, RgbaImage};
+
+use combiner::options::{CombineOptions, Mode};
+
+use crate::ImageDataErrors;
+
+/// The modes the preview cycles through: those that combine any two inputs
+/// without further arguments.
+pub const MODES: [Mode; 6] = [Mode::Alternate, Mode::Mixup, Mode::Cutmix, Mode::Diff, Mode::Mirror, Mode::Tile];
+
+/// How far one key press moves the opacity or the split.
+const STEP: f32 = 0.05;
+
+/// The largest preview drawn with a graphics protocol, in pixels, so every
+/// key press redraws at once.
+const MAX_PIXELS: (u32, u32) = (1280, 800);
+
+/// How the preview is drawn in the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Kitty,
+    Iterm,
+    Sixel,
+    /// Two pixels per character cell, as the foreground and background
+    /// colours of `▀`. Works in any terminal with true colour.
+    Blocks,
+}
+
+impl Protocol {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        match value.to_ascii_lowercase().as_str() {
+            "kitty" => Ok(Protocol::Kitty),
+            "iterm" => Ok(Protocol::Iterm),
+            "sixel" => Ok(Protocol::Sixel),
+            "blocks" => Ok(Protocol::Blocks),
+            _ => Err(ImageDataErrors::InvalidArgument(format!(
+                "unknown preview protocol `{}`, expected kitty, iterm, sixel or blocks",
+                value
+            ))),
+        }
+    }
+
+    /// The protocol the terminal announces through its environment, or
+    /// half blocks when it announces none.
+    pub fn detect() -> Self {
+        let var = |name: &str| std::env::var(name).unwrap_or_default();
+        let (term, program) = (var("TERM"), var("TERM_PROGRAM"));
+        if std::env::var_os("KITTY_WINDOW_ID").is_some() || term.contains("kitty") || program == "ghostty" {
+            Protocol::Kitty
+        } else if program == "iTerm.app" || program == "WezTerm" {
+            Protocol::Iterm
+        } else if term.contains("sixel") || term.starts_with("foot") || term.starts_with("mlterm") {
+            Protocol::Sixel
+        } else {
+            Protocol::Blocks
+        }
+    }
+}
+
+/// Puts the terminal back as it was however the preview ends.
+struct Screen;
+
+impl Screen {
+    fn enter() -> std::io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut stdout = std::io::stdout();
+        stdout.execute(terminal::EnterAlternateScreen)?.execute(cursor::Hide)?;
+        Ok(Screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let mut stdout = std::io::stdout();
+        let _ = stdout.execute(cursor::Show).and_then(|stdout| stdout.execute(terminal::LeaveAlternateScreen));
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// What the keys have changed so far.
+pub struct Tweaks {
+    pub options: CombineOptions,
+    /// The share of the width, from the left, that shows the first input as
+    /// it was, for comparing before and after. Only the preview has it.
+    pub split: f32,
+}
+
+impl Tweaks {
+    /// How opaque the second input is, where the mode blends it in.
+    pub fn opacity(&self) -> Option<f32> {
+        match self.options.mode {
+            Mode::Mixup | Mode::Cutmix => Some(1.0 - self.options.lambda.unwrap_or(0.5) as f32),
+            Mode::Mirror => Some(self.options.mirror_blend.unwrap_or(0.0)),
+            _ => None,
+        }
+    }
+
+    pub fn set_opacity(&mut self, opacity: f32) {
+        let opacity = opacity.clamp(0.0, 1.0);
+        match self.options.mode {
+            Mode::Mixup | Mode::Cutmix => self.options.lambda = Some(((1.0 - opacity as f64) * 100.0).round() / 100.0),
+            Mode::Mirror => self.options.mirror_blend = (opacity > 0.0).then_some((opacity * 100.0).round() / 100.0),
+            _ => {}
+        }
+    }
+
+    fn cycle_mode(&mut self, forward: bool) {
+        let current = MODES.iter().position(|&mode| mode == self.options.mode);
+        let next = match (current, forward) {
+            (Some(index), true) => (index + 1) % MODES.len(),
+            (Some(index), false) => (index + MODES.len() - 1) % MODES.len(),
+            (None, _) => 0,
+        };
+        self.options.mode = MODES[next];
+    }
+
+    fn status(&self, error: Option<&str>) -> String {
+        let opacity = self.opacity().map_or("-".to_string(), |opacity| format!("{:.2}", opacity));
+        let state = format!("mode {}  opacity {}  split {:.0}%", self.options.mode.name(), opacity, self.split * 100.0);
+        match error {
+            Some(error) => format!("{}  ({})", state, error),
+            None => format!("{}  [m]ode [+/-] opacity [</>] split [enter] write [q]uit", state),
+        }
+    }
+}
+
+/// Shows `image_1` and `image_2` combined as `options` say until the output
+/// is written, with Enter, or the preview is left, with q or Escape. Returns
+/// the options as tweaked, or `None` when nothing should be written.
+pub fn run(
+    image_1: &DynamicImage,
+    image_2: &DynamicImage,
+    options: CombineOptions,
+    protocol: Protocol,
+) -> Result<Option<CombineOptions>, ImageDataErrors> {
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        return Err(ImageDataErrors::InvalidArgument("`--preview` needs an interactive terminal".to_string()));
+    }
+    let _screen = Screen::enter().map_err(ImageDataErrors::UnableToPreview)?;
+    let mut tweaks = Tweaks { options, split: 0.0 };
+    let mut inputs = None;
+    loop {
+        let (columns, rows) = terminal::size().map_err(ImageDataErrors::UnableToPreview)?;
+        let area = preview_area(protocol, columns, rows.saturating_sub(1));
+        // The inputs are shrunk once per terminal size, so each key combines little.
+        if inputs.as_ref().is_none_or(|(shrunk_to, _, _)| *shrunk_to != area) {
+            let shrink = |image: &DynamicImage| image.resize(area.0.max(1), area.1.max(1), FilterType::Triangle);
+            inputs = Some((area, shrink(image_1), shrink(image_2)));
+        }
+        let (_, small_1, small_2) = inputs.as_ref().expect("the inputs were just shrunk");
+        let frame = compose(small_1, small_2, &tweaks, area);
+        let error = frame.as_ref().err().map(ToString::to_string);
+        draw(frame.ok().as_ref(), protocol, rows, &tweaks.status(error.as_deref())).map_err(ImageDataErrors::UnableToPreview)?;
+
+        let key = loop {
+            match event::read().map_err(ImageDataErrors::UnableToPreview)? {
+                Event::Key(key @ KeyEvent { kind: KeyEventKind::Press, .. }) => break Some(key),
+                Event::Resize(..) => break None,
+                _ => {}
+            }
+        };
+        let Some(key) = key else { continue };
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(None),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(None),
+            KeyCode::Enter | KeyCode::Char('w') => return Ok(Some(tweaks.options)),
+            KeyCode::Char('m') | KeyCode::Tab => tweaks.cycle_mode(true),
+            KeyCode::Char('M') | KeyCode::BackTab => tweaks.cycle_mode(false),
+            KeyCode::Char('+') | KeyCode::Char('=') | KeyCode::Up => {
+                let opacity = tweaks.opacity().unwrap_or(0.0);
+                tweaks.set_opacity(opacity + STEP);
+            }
+            KeyCode::Char('-') | KeyCode::Down => {
+                let opacity = tweaks.opacity().unwrap_or(0.0);
+                tweaks.set_opacity(opacity - STEP);
+            }
+            KeyCode::Char('<') | KeyCode::Char(',') | KeyCode::Left => tweaks.split = (tweaks.split - STEP).max(0.0),
+            KeyCode::Char('>') | KeyCode::Char('.') | KeyCode::Right => tweaks.split = (tweaks.split + STEP).min(1.0),
+            _ => {}
+        }
+    }
+}
+
+/// The pixels the preview may take up in `columns` by `rows` cells.
+fn preview_area(protocol: Protocol, columns: u16, rows: u16) -> (u32, u32) {
+    let (columns, rows) = (columns as u32, rows as u32);
+    if protocol == Protocol::Blocks {
+        return (columns, rows * 2);
+    }
+    // Terminals that do not report their size in pixels get typical cells.
+    let (cell_width, cell_height) = match terminal::window_size() {
+        Ok(size) if size.width > 0 && size.height > 0 && size.columns > 0 && size.rows > 0 => {
+            (size.width as u32 / size.columns as u32, size.height as u32 / size.rows as u32)
+        }
+        _ => (10, 20),
+    };
+    ((columns * cell_width).min(MAX_PIXELS.0), (rows * cell_height).min(MAX_PIXELS.1))
+}
+
+/// Combines the shrunk inputs as `tweaks` say, fitted to `area`, with the
+/// first input shown as it was left of the split.
+pub fn compose(small_1: &DynamicImage, small_2: &DynamicImage, tweaks: &Tweaks, area: (u32, u32)) -> Result<RgbaImage, ImageDataErrors> {
+    let (output, _, _) = combiner::combine_labelled(small_1.clone(), small_2.clone(), &tweaks.options, "preview.png".to_string())?;
+    let pixels = RgbaImage::from_raw(output.width, output.height, output.data).ok_or(ImageDataErrors::BufferTooSmall)?;
+    let mut pixels = DynamicImage::ImageRgba8(pixels);
+    // Finishing options such as frames and print layouts can grow the output.
+    if pixels.width() > area.0 || pixels.height() > area.1 {
+        pixels = pixels.resize(area.0, area.1, FilterType::Triangle);
+    }
+    let mut pixels = pixels.to_rgba8();
+    let (width, height) = pixels.dimensions();
+    let divider = (tweaks.split * width as f32).round() as u32;
+    if divider > 0 {
+        let before = small_1.resize_exact(width, height, FilterType::Triangle).to_rgba8();
+        for (x, y, pixel) in pixels.enumerate_pixels_mut() {
+            if x < divider {
+                *pixel = *before.get_pixel(x, y);
+            } else if x == divider {
+                *pixel = image::Rgba([255, 255, 255, 255]);
+            }
+        }
+    }
+    // Transparency is shown over a dark grey, as terminals cannot show it.
+    for pixel in pixels.pixels_mut() {
+        let alpha = pixel[3] as u32;
+        for channel in 0..3 {
+            pixel[channel] = ((pixel[channel] as u32 * alpha + 32 * (255 - alpha) + 127) / 255) as u8;
+        }
+        pixel[3] = 255;
+    }
+    Ok(pixels)
+}
+
+/// Clears the screen, draws `frame` at the top left and `status` on the last of `rows`.
+fn draw(frame: Option<&RgbaImage>, protocol: Protocol, rows: u16, status: &str) -> std::io::Result<()> {
+    let mut out = Vec::new();
+    if protocol == Protocol::Kitty {
+        // Deletes the images drawn before.
+        out.extend_from_slice(b"\x1b_Ga=d,d=A,q=2\x1b\\");
+    }
+    out.queue(terminal::Clear(terminal::ClearType::All))?.queue(cursor::MoveTo(0, 0))?;
+    // A terminal too small to hold a pixel only gets the status line.
+    if let Some(frame) = frame.filter(|frame| frame.width() > 0 && frame.height() > 0) {
+        match protocol {
+            Protocol::Kitty => kitty(frame, &mut out)?,
+            Protocol::Iterm => iterm(frame, &mut out)?,
+            Protocol::Sixel => sixel(frame, &mut out),
+            Protocol::Blocks => blocks(frame, &mut out)?,
+        }
+    }
+    out.queue(cursor::MoveTo(0, rows.saturating_sub(1)))?;
+    out.extend_from_slice(status.as_bytes());
+    let mut stdout = std::io::stdout();
+    stdout.write_all(&out)?;
+    stdout.flush()
+}
+
+fn png_bytes(frame: &RgbaImage) -> std::io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    image::codecs::png::PngEncoder::new(&mut bytes)
+        .encode(frame, frame.width(), frame.height(), image::ColorType::Rgba8)
+        .map_err(std::io::Error::other)?;
+    Ok(bytes)
+}
+
+/// The kitty graphics protocol: a PNG sent in base64 chunks of at most 4096 bytes.
+fn kitty(frame: &RgbaImage, out: &mut Vec<u8>) -> std::io::Result<()> {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(png_bytes(frame)?);
+    let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(4096).collect();
+    for (index, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(index + 1 < chunks.len());
+        // Responses are suppressed, or they would arrive as key presses.
+        let control = if index == 0 { format!("a=T,f=100,q=2,m={}", more) } else { format!("m={}", more) };
+        write!(out, "\x1b_G{};", control)?;
+        out.extend_from_slice(chunk);
+        out.extend_from_slice(b"\x1b\\");
+    }
+    Ok(())
+}
+
+/// iTerm2's inline images: a whole PNG in one escape sequence.
+fn iterm(frame: &RgbaImage, out: &mut Vec<u8>) -> std::io::Result<()> {
+    let png = png_bytes(frame)?;
+    write!(
+        out,
+        "\x1b]1337;File=inline=1;size={};width={}px;height={}px;preserveAspectRatio=1:{}\x07",
+        png.len(),
+        frame.width(),
+        frame.height(),
+        base64::engine::general_purpose::STANDARD.encode(png)
+    )
+}
+
+/// Sixel graphics, in the 216 colours of a 6x6x6 cube. Each band of six
+/// rows is drawn once per colour it uses, with runs of the same sixel
+/// compressed.
+fn sixel(frame: &RgbaImage, out: &mut Vec<u8>) {
+    let (width, height) = frame.dimensions();
+    let level = |value: u8| (value as u32 * 5 + 127) / 255;
+    let index = |pixel: &image::Rgba<u8>| (level(pixel[0]) * 36 + level(pixel[1]) * 6 + level(pixel[2])) as usize;
+    let indices: Vec<usize> = frame.pixels().map(index).collect();
+
+    out.extend_from_slice(format!("\x1bPq\"1;1;{};{}", width, height).as_bytes());
+    let mut used = [false; 216];
+    indices.iter().for_each(|&colour| used[colour] = true);
+    for colour in (0..216).filter(|&colour| used[colour]) {
+        let percent = |level: usize| level * 100 / 5;
+        out.extend_from_slice(format!("#{};2;{};{};{}", colour, percent(colour / 36), percent(colour / 6 % 6), percent(colour % 6)).as_bytes());
+    }
+    for band in (0..height).step_by(6) {
+        let rows = band..(band + 6).min(height);
+        let mut in_band = [false; 216];
+        for y in rows.clone() {
+            let line = &indices[(y * width) as usize..((y + 1) * width) as usize];
+            line.iter().for_each(|&colour| in_band[colour] = true);
+        }
+        for colour in (0..216).filter(|&colour| in_band[colour]) {
+            out.extend_from_slice(format!("#{}", colour).as_bytes());
+            let sixel_at = |x: u32| {
+                let bits = rows.clone().enumerate().filter(|&(_, y)| indices[(y * width + x) as usize] == colour);
+                63 + bits.fold(0u8, |bits, (bit, _)| bits | (1 << bit))
+            };
+            let mut x = 0;
+            while x < width {
+                let sixel = sixel_at(x);
+                let mut run = 1;
+                while x + run < width && sixel_at(x + run) == sixel {
+                    run += 1;
+                }
+                if run > 3 {
+                    out.extend_from_slice(format!("!{}", run).as_bytes());
+                    out.push(sixel);
+                } else {
+                    out.extend(std::iter::repeat_n(sixel, run as usize));
+                }
+                x += run;
+            }
+            // Back to the start of the band for the next colour.
+            out.push(b'$');
+        }
+        out.push(b'-');
+    }
+    out.extend_from_slice(b"\x1b\\");
+}
+
+/// Half blocks in true colour: the upper pixel of each cell as the
+/// foreground of `▀`, the lower as its background.
+fn blocks(frame: &RgbaImage, out: &mut Vec<u8>) -> std::io::Result<()> {
+    let (width, height) = frame.dimensions();
+    for (row, y) in (0..height).step_by(2).enumerate() {
+        out.queue(cursor::MoveTo(0, row as u16))?;
+        let mut last = None;
+        for x in 0..width {
+            let top = frame.get_pixel(x, y);
+            let bottom = if y + 1 < height { *frame.get_pixel(x, y + 1) } else { image::Rgba([0, 0, 0, 255]) };
+            let colours = (top.0, bottom.0);
+            if last != Some(colours) {
+                write!(out, "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m", top[0], top[1], top[2], bottom[0], bottom[1], bottom[2])?;
+                last = Some(colours);
+            }
+            out.extend_from_slice("▀".as_bytes());
+        }
+        out.extend_from_slice(b"\x1b[0m");
+    }
+    Ok(())
+}
@@ -0,0 +1,149 @@
+use std::fmt;
+
+use image::{imageops, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::ImageDataErrors;
+
+const MM_PER_INCH: f64 = 25.4;
+/// The white border around the image, wide enough for crop marks.
+const MARGIN_MM: f64 = 10.0;
+const MARK_GAP_MM: f64 = 3.0;
+const MARK_LENGTH_MM: f64 = 5.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageSize {
+    A3,
+    A4,
+    A5,
+    Letter,
+    Legal,
+}
+
+impl PageSize {
+    /// The portrait width and height in millimetres.
+    fn millimetres(self) -> (f64, f64) {
+        match self {
+            PageSize::A3 => (297.0, 420.0),
+            PageSize::A4 => (210.0, 297.0),
+            PageSize::A5 => (148.0, 210.0),
+            PageSize::Letter => (215.9, 279.4),
+            PageSize::Legal => (215.9, 355.6),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            PageSize::A3 => "A3",
+            PageSize::A4 => "A4",
+            PageSize::A5 => "A5",
+            PageSize::Letter => "letter",
+            PageSize::Legal => "legal",
+        }
+    }
+}
+
+/// A page to print on and the resolution to print at, written as
+/// `A4@300dpi`. The DPI defaults to 300 when left out.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PrintLayout {
+    pub page: PageSize,
+    pub dpi: f32,
+}
+
+impl PrintLayout {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        let invalid = || ImageDataErrors::InvalidArgument(format!("invalid print layout `{}`, expected e.g. A4@300dpi", value));
+        let (page, dpi) = value.split_once('@').unwrap_or((value, "300dpi"));
+        let page = match page.to_ascii_lowercase().as_str() {
+            "a3" => PageSize::A3,
+            "a4" => PageSize::A4,
+            "a5" => PageSize::A5,
+            "letter" => PageSize::Letter,
+            "legal" => PageSize::Legal,
+            _ => return Err(invalid()),
+        };
+        let dpi: f32 = dpi.strip_suffix("dpi").unwrap_or(dpi).parse().map_err(|_| invalid())?;
+        if !(dpi > 0.0 && dpi <= 4800.0) {
+            return Err(invalid());
+        }
+        Ok(PrintLayout { page, dpi })
+    }
+
+    /// The portrait width and height of the page in pixels.
+    pub fn page_size(&self) -> (u32, u32) {
+        let (width, height) = self.page.millimetres();
+        (self.pixels(width), self.pixels(height))
+    }
+
+    fn pixels(&self, millimetres: f64) -> u32 {
+        (millimetres / MM_PER_INCH * self.dpi as f64).round() as u32
+    }
+}
+
+impl fmt::Display for PrintLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}dpi", self.page.name(), self.dpi)
+    }
+}
+
+impl TryFrom<String> for PrintLayout {
+    type Error = ImageDataErrors;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        PrintLayout::parse(&value)
+    }
+}
+
+impl From<PrintLayout> for String {
+    fn from(layout: PrintLayout) -> Self {
+        layout.to_string()
+    }
+}
+
+/// Scales `image` to fit inside the page's margins and centres it on a white
+/// page, turned to landscape when the image is. With `crop_marks`, short
+/// lines in the margin mark where to cut along the image's edges.
+pub(crate) fn lay_out(image: RgbaImage, layout: PrintLayout, crop_marks: bool) -> RgbaImage {
+    let (short, long) = layout.page.millimetres();
+    let (page_width, page_height) = if image.width() > image.height() { (long, short) } else { (short, long) };
+    let (width, height) = (layout.pixels(page_width), layout.pixels(page_height));
+    let margin = layout.pixels(MARGIN_MM);
+    log::debug!("laying {}x{} out on a {}x{} page", image.width(), image.height(), width, height);
+
+    let (box_width, box_height) = ((width - 2 * margin) as f64, (height - 2 * margin) as f64);
+    let scale = (box_width / image.width() as f64).min(box_height / image.height() as f64);
+    let scaled_width = ((image.width() as f64 * scale).round() as u32).clamp(1, box_width as u32);
+    let scaled_height = ((image.height() as f64 * scale).round() as u32).clamp(1, box_height as u32);
+    let scaled = imageops::resize(&image, scaled_width, scaled_height, imageops::FilterType::Triangle);
+
+    let mut page = RgbaImage::from_pixel(width, height, Rgba([255, 255, 255, 255]));
+    let (left, top) = ((width - scaled_width) / 2, (height - scaled_height) / 2);
+    imageops::overlay(&mut page, &scaled, left, top);
+    if crop_marks {
+        draw_crop_marks(&mut page, (left, top, left + scaled_width, top + scaled_height), &layout);
+    }
+    page
+}
+
+fn draw_crop_marks(page: &mut RgbaImage, (left, top, right, bottom): (u32, u32, u32, u32), layout: &PrintLayout) {
+    let gap = layout.pixels(MARK_GAP_MM);
+    let length = layout.pixels(MARK_LENGTH_MM);
+    let thickness = ((layout.dpi / 300.0).round() as u32).max(1);
+    let mut line = |x: u32, y: u32, width: u32, height: u32| {
+        for y in y..(y + height).min(page.height()) {
+            for x in x..(x + width).min(page.width()) {
+                page.put_pixel(x, y, Rgba([0, 0, 0, 255]));
+            }
+        }
+    };
+    for x in [left, right.saturating_sub(thickness)] {
+        line(x, top.saturating_sub(gap + length), thickness, length);
+        line(x, bottom + gap, thickness, length);
+    }
+    for y in [top, bottom.saturating_sub(thickness)] {
+        line(left.saturating_sub(gap + length), y, length, thickness);
+        line(right + gap, y, length, thickness);
+    }
+}
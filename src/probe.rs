@@ -0,0 +1,205 @@
+//! Reads what an image file is, its size, colour type, frames and metadata,
+//! from its headers alone, without decoding any pixels, so thousands of
+//! files can be probed in the time it takes to decode a few.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+
+use image::codecs::{bmp, dds, farbfeld, gif, hdr, ico, jpeg, png, tga, tiff, webp};
+use image::{ColorType, ImageDecoder, ImageError, ImageFormat};
+
+use crate::ImageDataErrors;
+
+/// How much of the start of a file is read. Every header, and the metadata
+/// of PNG and JPEG files, sits within it.
+const HEAD: u64 = 256 * 1024;
+
+/// Properties of an image file, gathered without decoding its pixel data.
+#[derive(Debug, Clone)]
+pub struct ImageInfo {
+    pub path: String,
+    pub format: ImageFormat,
+    pub width: u32,
+    pub height: u32,
+    pub color_type: ColorType,
+    pub frames: u32,
+    pub has_icc_profile: bool,
+    pub has_exif: bool,
+    pub dpi: Option<f32>,
+}
+
+impl ImageInfo {
+    pub fn bit_depth(&self) -> u16 {
+        self.color_type.bits_per_pixel() / self.color_type.channel_count() as u16
+    }
+
+    /// Bytes needed to hold every frame once decoded in its native colour type.
+    pub fn decoded_bytes(&self) -> u64 {
+        self.width as u64 * self.height as u64 * self.color_type.bytes_per_pixel() as u64 * self.frames as u64
+    }
+}
+
+/// Probes the image file at `path`. Only the first few hundred kilobytes are
+/// read, except that WebP files are walked chunk by chunk and GIFs, whose
+/// frames can only be counted by passing over them, are read through.
+pub fn probe(path: &str) -> Result<ImageInfo, ImageDataErrors> {
+    let mut file = BufReader::new(File::open(path).map_err(ImageDataErrors::UnableToReadImageFromPath)?);
+    let mut head = Vec::new();
+    file.by_ref().take(HEAD).read_to_end(&mut head).map_err(ImageDataErrors::UnableToReadImageFromPath)?;
+    let format = image::guess_format(&head).map_err(|_| ImageDataErrors::UnableToFormatImage(path.to_string()))?;
+
+    let ((width, height), color_type) = match format {
+        // The PNM header is read by the same code that decodes it.
+        ImageFormat::Pnm => crate::pnm::read_header(&head)?,
+        _ => {
+            file.seek(SeekFrom::Start(0)).map_err(ImageDataErrors::UnableToReadImageFromPath)?;
+            read_header(&mut file, format).map_err(ImageDataErrors::UnableToDecodeImage)?
+        }
+    };
+
+    let metadata = match format {
+        ImageFormat::Png => scan_png(&head),
+        ImageFormat::Jpeg => scan_jpeg(&head),
+        ImageFormat::Gif => {
+            let mut bytes = head.clone();
+            file.seek(SeekFrom::Start(head.len() as u64)).map_err(ImageDataErrors::UnableToReadImageFromPath)?;
+            file.read_to_end(&mut bytes).map_err(ImageDataErrors::UnableToReadImageFromPath)?;
+            scan_gif(&bytes)
+        }
+        ImageFormat::WebP => scan_webp(&mut file).map_err(ImageDataErrors::UnableToReadImageFromPath)?,
+        _ => Metadata::default(),
+    };
+
+    Ok(ImageInfo {
+        path: path.to_string(),
+        format,
+        width,
+        height,
+        color_type,
+        frames: metadata.frames.max(1),
+        has_icc_profile: metadata.has_icc_profile,
+        has_exif: metadata.has_exif,
+        dpi: crate::density::read_dpi(&head),
+    })
+}
+
+/// Builds the format's decoder, which only parses the header until pixels are requested.
+fn read_header<R: BufRead + Seek>(file: R, format: ImageFormat) -> Result<((u32, u32), ColorType), ImageError> {
+    fn header<'a>(decoder: impl ImageDecoder<'a>) -> ((u32, u32), ColorType) {
+        (decoder.dimensions(), decoder.color_type())
+    }
+
+    Ok(match format {
+        ImageFormat::Png => header(png::PngDecoder::new(file)?),
+        ImageFormat::Jpeg => header(jpeg::JpegDecoder::new(file)?),
+        ImageFormat::Gif => header(gif::GifDecoder::new(file)?),
+        ImageFormat::WebP => header(webp::WebPDecoder::new(file)?),
+        ImageFormat::Tiff => header(tiff::TiffDecoder::new(file)?),
+        ImageFormat::Tga => header(tga::TgaDecoder::new(file)?),
+        ImageFormat::Dds => header(dds::DdsDecoder::new(file)?),
+        ImageFormat::Bmp => header(bmp::BmpDecoder::new(file)?),
+        ImageFormat::Ico => header(ico::IcoDecoder::new(file)?),
+        ImageFormat::Hdr => header(hdr::HdrAdapter::new(file)?),
+        ImageFormat::Farbfeld => header(farbfeld::FarbfeldDecoder::new(file)?),
+        format => return Err(ImageError::Unsupported(image::error::ImageFormatHint::Exact(format).into())),
+    })
+}
+
+#[derive(Default)]
+struct Metadata {
+    frames: u32,
+    has_icc_profile: bool,
+    has_exif: bool,
+}
+
+fn read_u32_be(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Walks the chunk list up to the image data: `iCCP` and `eXIf` carry
+/// metadata, `acTL` holds the APNG frame count, and all must come first.
+fn scan_png(bytes: &[u8]) -> Metadata {
+    let mut metadata = Metadata::default();
+    let mut at = 8;
+    while let (Some(length), Some(kind)) = (read_u32_be(bytes, at), bytes.get(at + 4..at + 8)) {
+        match kind {
+            b"iCCP" => metadata.has_icc_profile = true,
+            b"eXIf" => metadata.has_exif = true,
+            b"acTL" => metadata.frames = read_u32_be(bytes, at + 8).unwrap_or(1),
+            b"IDAT" | b"IEND" => break,
+            _ => {}
+        }
+        at += 12 + length as usize;
+    }
+    metadata
+}
+
+/// Walks the marker segments up to the first scan, looking for the Exif
+/// (APP1) and ICC_PROFILE (APP2) application segments.
+fn scan_jpeg(bytes: &[u8]) -> Metadata {
+    let mut metadata = Metadata { frames: 1, ..Metadata::default() };
+    let mut at = 2;
+    while at + 4 <= bytes.len() && bytes[at] == 0xFF {
+        let marker = bytes[at + 1];
+        if marker == 0xDA || marker == 0xD9 {
+            break;
+        }
+        let length = u16::from_be_bytes([bytes[at + 2], bytes[at + 3]]) as usize;
+        let payload = bytes.get(at + 4..at + 2 + length).unwrap_or(&[]);
+        match marker {
+            0xE1 if payload.starts_with(b"Exif\0\0") => metadata.has_exif = true,
+            0xE2 if payload.starts_with(b"ICC_PROFILE\0") => metadata.has_icc_profile = true,
+            _ => {}
+        }
+        at += 2 + length;
+    }
+    metadata
+}
+
+/// Counts image descriptors, skipping colour tables and data sub-blocks.
+fn scan_gif(bytes: &[u8]) -> Metadata {
+    let mut metadata = Metadata::default();
+    let skip_sub_blocks = |mut at: usize| {
+        while let Some(&size) = bytes.get(at) {
+            at += 1 + size as usize;
+            if size == 0 {
+                break;
+            }
+        }
+        at
+    };
+    let colour_table_size = |flags: u8| if flags & 0x80 != 0 { 3 << ((flags & 0x07) + 1) } else { 0 };
+
+    let mut at = 13 + bytes.get(10).map_or(0, |flags| colour_table_size(*flags));
+    while let Some(&block) = bytes.get(at) {
+        match block {
+            0x2C => {
+                metadata.frames += 1;
+                let flags = bytes.get(at + 9).copied().unwrap_or(0);
+                at = skip_sub_blocks(at + 10 + colour_table_size(flags) + 1);
+            }
+            0x21 => at = skip_sub_blocks(at + 2),
+            _ => break,
+        }
+    }
+    metadata
+}
+
+/// Walks the RIFF chunks of an extended WebP file, reading each chunk's
+/// header and seeking over its payload, since `EXIF` comes after the frames.
+fn scan_webp<R: Read + Seek>(file: &mut R) -> std::io::Result<Metadata> {
+    let mut metadata = Metadata::default();
+    file.seek(SeekFrom::Start(12))?;
+    let mut header = [0u8; 8];
+    while file.read_exact(&mut header).is_ok() {
+        let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as i64;
+        match &header[..4] {
+            b"ICCP" => metadata.has_icc_profile = true,
+            b"EXIF" => metadata.has_exif = true,
+            b"ANMF" => metadata.frames += 1,
+            _ => {}
+        }
+        file.seek(SeekFrom::Current(length + (length & 1)))?;
+    }
+    Ok(metadata)
+}
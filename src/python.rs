@@ -0,0 +1,105 @@
+use image::{DynamicImage, ImageError, ImageFormat, RgbImage, RgbaImage};
+use numpy::{PyArray1, PyArray3, PyArrayMethods, PyReadonlyArray3};
+use pyo3::exceptions::{PyOSError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::options::{CombineOptions, DiffStyle, Mode};
+use crate::{combine_bytes, combine_decoded, ImageDataErrors, ImageStore};
+
+/// The `imagescombiner` Python module.
+#[pymodule]
+fn imagescombiner(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(combine, module)?)?;
+    module.add_function(wrap_pyfunction!(combine_arrays, module)?)?;
+    Ok(())
+}
+
+/// Combines the images at `path_a` and `path_b`. The result is written to
+/// `output` in the format its extension names, or returned as encoded bytes
+/// in the format of the first image when `output` is `None`.
+#[pyfunction]
+#[pyo3(signature = (path_a, path_b, mode = "alternate", output = None, threshold = 0, diff_style = "highlight"))]
+fn combine(
+    py: Python<'_>,
+    path_a: &str,
+    path_b: &str,
+    mode: &str,
+    output: Option<&str>,
+    threshold: u8,
+    diff_style: &str,
+) -> PyResult<Option<Py<PyBytes>>> {
+    let options = options(mode, threshold, diff_style)?;
+    let bytes = py.detach(|| -> Result<Vec<u8>, ImageDataErrors> {
+        let format = output
+            .map(|output| ImageFormat::from_path(output).map_err(|_| ImageDataErrors::UnableToFormatImage(output.to_string())))
+            .transpose()?;
+        Ok(combine_bytes(&Files.read(path_a)?, &Files.read(path_b)?, &options, format)?.bytes)
+    })?;
+
+    match output {
+        Some(output) => {
+            Files.write(output, bytes)?;
+            Ok(None)
+        }
+        None => Ok(Some(PyBytes::new(py, &bytes).unbind())),
+    }
+}
+
+/// Combines two `uint8` arrays of shape `(height, width, 3)` or
+/// `(height, width, 4)`, returning an RGBA array of shape `(height, width, 4)`.
+#[pyfunction]
+#[pyo3(signature = (image_a, image_b, mode = "alternate", threshold = 0, diff_style = "highlight"))]
+fn combine_arrays<'py>(
+    py: Python<'py>,
+    image_a: PyReadonlyArray3<'py, u8>,
+    image_b: PyReadonlyArray3<'py, u8>,
+    mode: &str,
+    threshold: u8,
+    diff_style: &str,
+) -> PyResult<Bound<'py, PyArray3<u8>>> {
+    let options = options(mode, threshold, diff_style)?;
+    let (image_1, image_2) = (to_image(&image_a)?, to_image(&image_b)?);
+    let (output, _) = py.detach(|| combine_decoded(image_1, image_2, &options, String::new()))?;
+    PyArray1::from_vec(py, output.data).reshape([output.height as usize, output.width as usize, 4])
+}
+
+fn options(mode: &str, threshold: u8, diff_style: &str) -> Result<CombineOptions, ImageDataErrors> {
+    Ok(CombineOptions { mode: Mode::parse(mode)?, threshold, diff_style: DiffStyle::parse(diff_style)?, ..CombineOptions::default() })
+}
+
+fn to_image(array: &PyReadonlyArray3<'_, u8>) -> PyResult<DynamicImage> {
+    let array = array.as_array();
+    let (height, width, channels) = array.dim();
+    let data: Vec<u8> = array.iter().copied().collect();
+    let image = match channels {
+        3 => RgbImage::from_raw(width as u32, height as u32, data).map(DynamicImage::ImageRgb8),
+        4 => RgbaImage::from_raw(width as u32, height as u32, data).map(DynamicImage::ImageRgba8),
+        _ => None,
+    };
+    image.ok_or_else(|| PyValueError::new_err("expected an array of shape (height, width, 3) or (height, width, 4)"))
+}
+
+/// Python callers name plain files.
+struct Files;
+
+impl ImageStore for Files {
+    fn read(&self, path: &str) -> Result<Vec<u8>, ImageDataErrors> {
+        std::fs::read(path).map_err(ImageDataErrors::UnableToReadImageFromPath)
+    }
+
+    fn write(&self, path: &str, bytes: Vec<u8>) -> Result<(), ImageDataErrors> {
+        std::fs::write(path, bytes).map_err(|e| ImageDataErrors::UnableToSaveImage(ImageError::IoError(e)))
+    }
+}
+
+impl From<ImageDataErrors> for PyErr {
+    fn from(e: ImageDataErrors) -> Self {
+        match e {
+            ImageDataErrors::UnableToReadImageFromPath(_) | ImageDataErrors::UnableToSaveImage(ImageError::IoError(_)) => {
+                PyOSError::new_err(e.to_string())
+            }
+            _ => PyValueError::new_err(e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use image::{Rgba, RgbaImage};
+use qrcode::{Color, EcLevel, QrCode};
+use serde::{Deserialize, Serialize};
+
+use crate::faces::{quietest_corner, Face};
+use crate::geometry::Gravity;
+use crate::ImageDataErrors;
+
+/// The light border scanners need around the code, in modules.
+const QUIET_ZONE: u32 = 4;
+
+/// How much of a QR code may be damaged or covered and still scan. Higher
+/// levels make denser codes for the same data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QrErrorCorrection {
+    /// About 7% can be recovered.
+    Low,
+    /// About 15% can be recovered.
+    #[default]
+    Medium,
+    /// About 25% can be recovered.
+    Quartile,
+    /// About 30% can be recovered.
+    High,
+}
+
+impl QrErrorCorrection {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        match value.to_ascii_lowercase().as_str() {
+            "l" | "low" => Ok(QrErrorCorrection::Low),
+            "m" | "medium" => Ok(QrErrorCorrection::Medium),
+            "q" | "quartile" => Ok(QrErrorCorrection::Quartile),
+            "h" | "high" => Ok(QrErrorCorrection::High),
+            _ => Err(ImageDataErrors::InvalidArgument(format!("unknown QR error correction `{}`", value))),
+        }
+    }
+
+    fn level(self) -> EcLevel {
+        match self {
+            QrErrorCorrection::Low => EcLevel::L,
+            QrErrorCorrection::Medium => EcLevel::M,
+            QrErrorCorrection::Quartile => EcLevel::Q,
+            QrErrorCorrection::High => EcLevel::H,
+        }
+    }
+}
+
+/// Encodes `data` as a QR code and draws it onto `image`, black on a white
+/// quiet zone, at the `position` corner or edge and `margin` pixels in from
+/// it, or with `smart` at the corner covering the least of those faces and
+/// then the least detail. The code is at most `size` pixels square, or a fifth of the shorter
+/// side when `None`, rounded down to whole pixels per module so it stays sharp.
+pub(crate) fn draw_qr(
+    image: &mut RgbaImage,
+    data: &str,
+    position: Gravity,
+    smart: Option<&[Face]>,
+    size: Option<u32>,
+    margin: u32,
+    error_correction: QrErrorCorrection,
+) -> Result<(), ImageDataErrors> {
+    let code = QrCode::with_error_correction_level(data, error_correction.level())
+        .map_err(|error| ImageDataErrors::InvalidArgument(format!("cannot encode `{}` as a QR code: {}", data, error)))?;
+    let modules = code.width() as u32 + 2 * QUIET_ZONE;
+    let room = image.width().min(image.height()).saturating_sub(2 * margin);
+    let wanted = size.unwrap_or(image.width().min(image.height()) / 5).min(room);
+    let scale = wanted / modules;
+    if scale == 0 {
+        return Err(ImageDataErrors::InvalidArgument(format!(
+            "the QR code needs at least {} pixels a side, but only {} are available",
+            modules, wanted
+        )));
+    }
+    let side = modules * scale;
+    let (anchor_x, anchor_y) = position.anchor();
+    let place = |extent: u32, anchor: i8| match anchor {
+        -1 => margin,
+        0 => (extent - side) / 2,
+        _ => extent - side - margin,
+    };
+    let anchored = (place(image.width(), anchor_x), place(image.height(), anchor_y));
+    let (left, top) = smart.and_then(|faces| quietest_corner(image, faces, (side, side), margin)).unwrap_or(anchored);
+    log::debug!("drawing a {} module QR code at {}x{} pixels each, at {},{}", modules, scale, scale, left, top);
+
+    let colors = code.to_colors();
+    for y in 0..side {
+        for x in 0..side {
+            let (column, row) = (x / scale, y / scale);
+            let dark = (QUIET_ZONE..modules - QUIET_ZONE).contains(&column)
+                && (QUIET_ZONE..modules - QUIET_ZONE).contains(&row)
+                && colors[((row - QUIET_ZONE) * code.width() as u32 + column - QUIET_ZONE) as usize] == Color::Dark;
+            let value = if dark { 0 } else { 255 };
+            image.put_pixel(left + x, top + y, Rgba([value, value, value, 255]));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,78 @@
+//! Headerless pixel buffers, such as camera frames or framebuffer dumps,
+//! whose layout is given by the caller instead of sniffed from the bytes.
+
+use image::{DynamicImage, GrayImage, RgbImage, RgbaImage};
+
+use crate::ImageDataErrors;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawPixel {
+    Rgba8,
+    Rgb8,
+    Gray8,
+}
+
+impl RawPixel {
+    fn channels(&self) -> usize {
+        match self {
+            RawPixel::Rgba8 => 4,
+            RawPixel::Rgb8 => 3,
+            RawPixel::Gray8 => 1,
+        }
+    }
+}
+
+/// The layout of a raw buffer, written `WIDTHxHEIGHTxPIXEL` as in
+/// `640x480xrgba8`. Rows are tightly packed, top to bottom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawLayout {
+    pub width: u32,
+    pub height: u32,
+    pub pixel: RawPixel,
+}
+
+impl RawLayout {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        let invalid = || {
+            ImageDataErrors::InvalidArgument(format!(
+                "invalid raw layout `{}`, expected WIDTHxHEIGHTxPIXEL with a pixel of rgba8, rgb8 or gray8",
+                value
+            ))
+        };
+        let mut parts = value.splitn(3, 'x');
+        let (Some(width), Some(height), Some(pixel)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(invalid());
+        };
+        let pixel = match pixel {
+            "rgba8" => RawPixel::Rgba8,
+            "rgb8" => RawPixel::Rgb8,
+            "gray8" => RawPixel::Gray8,
+            _ => return Err(invalid()),
+        };
+        match (width.parse(), height.parse()) {
+            (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok(RawLayout { width, height, pixel }),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// How many bytes a buffer of this layout holds.
+    pub fn byte_len(&self) -> Option<usize> {
+        (self.width as usize)
+            .checked_mul(self.height as usize)?
+            .checked_mul(self.pixel.channels())
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> Result<DynamicImage, ImageDataErrors> {
+        let expected = self.byte_len().unwrap_or(usize::MAX);
+        if bytes.len() != expected {
+            return Err(ImageDataErrors::RawSizeMismatch { expected, actual: bytes.len() });
+        }
+        let (width, height, data) = (self.width, self.height, bytes.to_vec());
+        let image = match self.pixel {
+            RawPixel::Rgba8 => RgbaImage::from_raw(width, height, data).map(DynamicImage::ImageRgba8),
+            RawPixel::Rgb8 => RgbImage::from_raw(width, height, data).map(DynamicImage::ImageRgb8),
+            RawPixel::Gray8 => GrayImage::from_raw(width, height, data).map(DynamicImage::ImageLuma8),
+        };
+        Ok(image.expect("the buffer length was checked against the layout"))
+    }
+}
@@ -0,0 +1,99 @@
+//! Recipes: several outputs made from named images in one run, written as
+//! JSON. Each node continues another node, or an image, with a pipeline, and
+//! `with` names the image its `blend` and `combine` steps bring in:
+//!
+//! ```text
+//! {
+//!   "images": { "photo": "photo.jpg", "logo": "logo.png" },
+//!   "nodes": {
+//!     "base": { "from": "photo", "pipeline": "resize:1600x | trim" },
+//!     "card": { "from": "base", "with": "logo", "pipeline": "blend:multiply | border:4:#fff" },
+//!     "thumb": { "from": "base", "pipeline": "resize:200x" }
+//!   },
+//!   "outputs": { "card.png": "card", "thumb.jpg": "thumb" }
+//! }
+//! ```
+//!
+//! The recipe becomes one [`Graph`], so `base` is made once for both outputs.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Deserialize;
+
+use crate::graph::{Graph, NodeId};
+use crate::pipeline::Pipeline;
+use crate::ImageDataErrors;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Recipe {
+    /// Image paths by name. The caller decides what a path is relative to.
+    #[serde(default)]
+    pub images: BTreeMap<String, String>,
+    #[serde(default)]
+    pub nodes: BTreeMap<String, RecipeNode>,
+    /// The node or image written to each output path.
+    pub outputs: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecipeNode {
+    /// The node or image the pipeline starts from.
+    pub from: String,
+    /// The node or image that `blend` and `combine` steps bring in.
+    #[serde(default)]
+    pub with: Option<String>,
+    pub pipeline: Pipeline,
+}
+
+impl Recipe {
+    /// The recipe's graph, whose inputs are named by the images' paths, and
+    /// the node of each output path.
+    pub fn build(&self) -> Result<(Graph, Vec<(String, NodeId)>), ImageDataErrors> {
+        let mut builder = Builder { recipe: self, graph: Graph::new(), built: HashMap::new(), building: Vec::new() };
+        let outputs = self
+            .outputs
+            .iter()
+            .map(|(path, name)| Ok((path.clone(), builder.node(name)?)))
+            .collect::<Result<Vec<_>, ImageDataErrors>>()?;
+        Ok((builder.graph, outputs))
+    }
+}
+
+struct Builder<'a> {
+    recipe: &'a Recipe,
+    graph: Graph,
+    built: HashMap<&'a str, NodeId>,
+    /// The nodes being built, to catch nodes that depend on themselves.
+    building: Vec<&'a str>,
+}
+
+impl<'a> Builder<'a> {
+    fn node(&mut self, name: &'a str) -> Result<NodeId, ImageDataErrors> {
+        let invalid = |message: String| ImageDataErrors::InvalidArgument(format!("invalid recipe: {}", message));
+        if let Some(&node) = self.built.get(name) {
+            return Ok(node);
+        }
+        if self.building.contains(&name) {
+            return Err(invalid(format!("`{}` depends on itself through {}", name, self.building.join(" -> "))));
+        }
+        let node = match (self.recipe.images.get(name), self.recipe.nodes.get(name)) {
+            (Some(_), Some(_)) => return Err(invalid(format!("`{}` names both an image and a node", name))),
+            (Some(path), None) => self.graph.input(path),
+            (None, Some(node)) => {
+                self.building.push(name);
+                let from = self.node(&node.from)?;
+                let with = node.with.as_deref().map(|with| self.node(with)).transpose()?;
+                self.building.pop();
+                self.graph.pipeline(&node.pipeline, from, with).map_err(|e| match e {
+                    ImageDataErrors::InvalidArgument(message) => invalid(format!("`{}`: {}", name, message)),
+                    e => e,
+                })?
+            }
+            (None, None) => return Err(invalid(format!("`{}` is neither an image nor a node", name))),
+        };
+        self.built.insert(name, node);
+        Ok(node)
+    }
+}
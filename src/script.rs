@@ -0,0 +1,150 @@
+//! `--script custom.rhai`: logic written in [Rhai](https://rhai.rs) behind
+//! the `script` feature, so one-off combinations and naming schemes need no
+//! rebuild. A script defines either function, or both:
+//!
+//! ```text
+//! // Each output pixel from the inputs' pixels at column x, row y, as
+//! // [r, g, b, a] arrays of 0-255; alpha may be left out of the result.
+//! fn pixel(a, b, x, y) { [(a[0] + b[0]) / 2, a[1], b[2], 255] }
+//!
+//! // The output file name of a job, from the values `--name-template` has.
+//! fn output_name(job) { `${job.stem1}-${job.index}.${job.ext}` }
+//! ```
+
+use crate::batch::Job;
+use crate::ImageDataErrors;
+
+#[cfg(feature = "script")]
+pub use engine::Script;
+
+#[cfg(feature = "script")]
+mod engine {
+    use std::path::Path;
+
+    use rhai::{Array, Dynamic, Engine, Map, Scope, AST};
+    use sha2::{Digest, Sha256};
+
+    use super::{ImageDataErrors, Job};
+    use crate::args::Mode;
+
+    fn failed(message: impl std::fmt::Display) -> ImageDataErrors {
+        ImageDataErrors::UnableToRunScript(message.to_string())
+    }
+
+    /// A compiled script, run on the command line's thread.
+    pub struct Script {
+        engine: Engine,
+        ast: AST,
+        scope: Scope<'static>,
+        /// The hash of the source, so `--incremental` rebuilds when it changes.
+        digest: String,
+        pixel: bool,
+        output_name: bool,
+    }
+
+    impl Script {
+        pub fn load(path: &str) -> Result<Self, ImageDataErrors> {
+            let source = std::fs::read_to_string(path).map_err(|e| failed(format!("{}: {}", path, e)))?;
+            let engine = Engine::new();
+            let ast = engine.compile(&source).map_err(|e| failed(format!("{}: {}", path, e)))?;
+            let defines = |name: &str, arity: usize| ast.iter_functions().any(|f| f.name == name && f.params.len() == arity);
+            let (pixel, output_name) = (defines("pixel", 4), defines("output_name", 1));
+            if !pixel && !output_name {
+                return Err(failed(format!("{} defines neither `pixel(a, b, x, y)` nor `output_name(job)`", path)));
+            }
+            let digest = Sha256::digest(source.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect();
+            Ok(Script { engine, ast, scope: Scope::new(), digest, pixel, output_name })
+        }
+
+        pub fn digest(&self) -> &str {
+            &self.digest
+        }
+
+        /// Whether the script combines pixels in place of the mode.
+        pub fn combines_pixels(&self) -> bool {
+            self.pixel
+        }
+
+        /// Whether the script names outputs in place of `--name-template`.
+        pub fn names_outputs(&self) -> bool {
+            self.output_name
+        }
+
+        pub fn pixel(&mut self, x: u32, y: u32, a: [u8; 4], b: [u8; 4]) -> Result<[u8; 4], ImageDataErrors> {
+            let channels = |pixel: [u8; 4]| -> Array { pixel.iter().map(|&channel| Dynamic::from_int(channel.into())).collect() };
+            let result: Array = self
+                .engine
+                .call_fn(&mut self.scope, &self.ast, "pixel", (channels(a), channels(b), i64::from(x), i64::from(y)))
+                .map_err(|e| failed(format!("pixel({}, {}): {}", x, y, e)))?;
+            if !matches!(result.len(), 3 | 4) {
+                return Err(failed(format!("pixel({}, {}) returned {} channels, not 3 or 4", x, y, result.len())));
+            }
+            let mut pixel = [255; 4];
+            for (channel, value) in pixel.iter_mut().zip(result) {
+                let value = match value.as_int() {
+                    Ok(value) => value,
+                    Err(_) => value.as_float().map_err(|kind| failed(format!("pixel({}, {}) returned a {}", x, y, kind)))?.round() as i64,
+                };
+                *channel = value.clamp(0, 255) as u8;
+            }
+            Ok(pixel)
+        }
+
+        /// The file name the script gives the output of `job`, placed in the
+        /// directory of the job's own output.
+        pub fn output_path(&mut self, job: &Job, mode: Mode, (width, height): (u32, u32)) -> Result<String, ImageDataErrors> {
+            let stem = |path: &str| Path::new(path).file_stem().unwrap_or_default().to_string_lossy().into_owned();
+            let ext = Path::new(&job.image_1).extension().unwrap_or_default().to_string_lossy().into_owned();
+            let mut values = Map::new();
+            values.insert("image_1".into(), job.image_1.clone().into());
+            values.insert("image_2".into(), job.image_2.clone().into());
+            values.insert("stem1".into(), stem(&job.image_1).into());
+            values.insert("stem2".into(), stem(&job.image_2).into());
+            values.insert("ext".into(), ext.into());
+            values.insert("mode".into(), mode.name().into());
+            values.insert("width".into(), i64::from(width).into());
+            values.insert("height".into(), i64::from(height).into());
+            values.insert("index".into(), (job.index as i64).into());
+            let name: String = self
+                .engine
+                .call_fn(&mut self.scope, &self.ast, "output_name", (values,))
+                .map_err(|e| failed(format!("output_name for {}: {}", job.image_1, e)))?;
+            if name.is_empty() || name.contains(['/', '\\']) {
+                return Err(failed(format!("output_name for {} returned `{}`, which is not a file name", job.image_1, name)));
+            }
+            Ok(Path::new(&job.output).with_file_name(name).to_string_lossy().into_owned())
+        }
+    }
+}
+
+/// Stands for a script in builds without the `script` feature, where none
+/// can be loaded.
+#[cfg(not(feature = "script"))]
+pub enum Script {}
+
+#[cfg(not(feature = "script"))]
+impl Script {
+    pub fn load(path: &str) -> Result<Self, ImageDataErrors> {
+        Err(ImageDataErrors::UnableToRunScript(format!("cannot run {}, this build lacks the `script` feature", path)))
+    }
+
+    pub fn digest(&self) -> &str {
+        match *self {}
+    }
+
+    pub fn combines_pixels(&self) -> bool {
+        match *self {}
+    }
+
+    pub fn names_outputs(&self) -> bool {
+        match *self {}
+    }
+
+    pub fn pixel(&mut self, _x: u32, _y: u32, _a: [u8; 4], _b: [u8; 4]) -> Result<[u8; 4], ImageDataErrors> {
+        match *self {}
+    }
+
+    pub fn output_path(&mut self, _job: &Job, _mode: crate::args::Mode, _size: (u32, u32)) -> Result<String, ImageDataErrors> {
+        match *self {}
+    }
+}
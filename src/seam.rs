@@ -0,0 +1,166 @@
+//! Seams: the cheapest connected paths of pixels through an image, which
+//! seam carving removes and stitching joins overlapping tiles along.
+
+use image::{imageops, DynamicImage, GrayImage, Rgba, RgbaImage};
+
+use crate::fit::edge_energy;
+
+/// How many pixels tiles fade into each other across a stitching seam.
+const FEATHER: u32 = 8;
+
+/// Narrows `image` to `width` columns by repeatedly removing the vertical
+/// seam, one pixel per row, with the least edge energy.
+fn carve_width(image: &RgbaImage, width: u32) -> RgbaImage {
+    let height = image.height();
+    let mut current = image.width();
+    let mut pixels: Vec<[u8; 4]> = image.pixels().map(|pixel| pixel.0).collect();
+    let mut luma: Vec<u8> = pixels.iter().map(|pixel| brightness(*pixel)).collect();
+
+    while current > width {
+        let gray = GrayImage::from_raw(current, height, luma).expect("luma matches the pixel count");
+        let seam = cheapest_seam(&edge_energy(&gray), current as usize, height as usize);
+        luma = gray.into_raw();
+        remove_seam(&mut pixels, &seam, current as usize);
+        remove_seam(&mut luma, &seam, current as usize);
+        current -= 1;
+    }
+    RgbaImage::from_raw(width, height, pixels.concat()).expect("carving keeps the buffer whole")
+}
+
+/// Brings `image` to exactly `width`x`height` by carving seams from the
+/// dimension that overflows once it is scaled to cover that size.
+pub(crate) fn carve(scaled: DynamicImage, width: u32, height: u32) -> DynamicImage {
+    let mut image = scaled.to_rgba8();
+    if image.width() > width {
+        image = carve_width(&image, width);
+    }
+    if image.height() > height {
+        // Horizontal seams are vertical ones of the transposed image.
+        let transposed = image::imageops::rotate90(&image);
+        image = image::imageops::rotate270(&carve_width(&transposed, height));
+    }
+    DynamicImage::ImageRgba8(image)
+}
+
+/// Merges two copies of the band where neighbouring tiles overlap: `first`
+/// from the tile on the left, or above unless `across`, and `second` from
+/// the other. They meet along the seam where they differ least, found by
+/// dynamic programming, rather than a straight line, and fade into each
+/// other over a few pixels either side of it. Where either is transparent
+/// the other shows.
+pub fn merge_overlap(first: &RgbaImage, second: &RgbaImage, across: bool) -> RgbaImage {
+    merge(first, second, across, false)
+}
+
+/// [`merge_overlap`], with the seam starting and ending at the middle of
+/// the band when `pinned`, so that bands which tile themselves still do.
+fn merge(first: &RgbaImage, second: &RgbaImage, across: bool, pinned: bool) -> RgbaImage {
+    if !across {
+        // Seams between rows are those between columns of the bands turned on their side.
+        return imageops::rotate90(&merge(&imageops::rotate270(first), &imageops::rotate270(second), true, pinned));
+    }
+    let (width, height) = first.dimensions();
+    let opaque = |a: &Rgba<u8>, b: &Rgba<u8>| a[3] != 0 && b[3] != 0;
+    let mut difference: Vec<u32> = first
+        .pixels()
+        .zip(second.pixels())
+        .map(|(a, b)| if opaque(a, b) { (0..3).map(|channel| (a[channel] as i32 - b[channel] as i32).pow(2) as u32).sum() } else { 0 })
+        .collect();
+    // Keep the seam far enough from the band's edges for the fade to fit.
+    if width > FEATHER {
+        for (i, difference) in difference.iter_mut().enumerate() {
+            let x = i as u32 % width;
+            if x < FEATHER / 2 || x >= width - FEATHER / 2 {
+                *difference += 1 << 20;
+            }
+        }
+    }
+    if pinned {
+        let last = (height as usize - 1) * width as usize;
+        for x in (0..width as usize).filter(|&x| x != width as usize / 2) {
+            difference[x] += 1 << 28;
+            difference[last + x] += 1 << 28;
+        }
+    }
+    let seam = cheapest_seam(&difference, width as usize, height as usize);
+    RgbaImage::from_fn(width, height, |x, y| {
+        let (a, b) = (first.get_pixel(x, y), second.get_pixel(x, y));
+        if !opaque(a, b) {
+            return if a[3] == 0 { *b } else { *a };
+        }
+        let weight = ((x as f32 - seam[y as usize] as f32) / FEATHER as f32 + 0.5).clamp(0.0, 1.0);
+        Rgba(std::array::from_fn(|channel| (a[channel] as f32 * (1.0 - weight) + b[channel] as f32 * weight).round() as u8))
+    })
+}
+
+/// `image` made to tile seamlessly, by quilting it with itself: its right
+/// edge is merged with its left, and its bottom with its top, along the
+/// seams where they differ least. Each side loses an eighth of its length.
+pub(crate) fn make_tileable(image: &RgbaImage) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let (overlap_x, overlap_y) = (width / 8, height / 8);
+    let mut image = image.clone();
+    if overlap_x > 0 {
+        let merged = merge_overlap(
+            &imageops::crop_imm(&image, width - overlap_x, 0, overlap_x, height).to_image(),
+            &imageops::crop_imm(&image, 0, 0, overlap_x, height).to_image(),
+            true,
+        );
+        imageops::replace(&mut image, &merged, 0, 0);
+        image = imageops::crop_imm(&image, 0, 0, width - overlap_x, height).to_image();
+    }
+    let width = image.width();
+    if overlap_y > 0 {
+        // The rows already tile across, so their seam must meet itself at the sides.
+        let merged = merge(
+            &imageops::crop_imm(&image, 0, height - overlap_y, width, overlap_y).to_image(),
+            &imageops::crop_imm(&image, 0, 0, width, overlap_y).to_image(),
+            false,
+            true,
+        );
+        imageops::replace(&mut image, &merged, 0, 0);
+        image = imageops::crop_imm(&image, 0, 0, width, height - overlap_y).to_image();
+    }
+    image
+}
+
+/// The column of the cheapest connected top-to-bottom path in every row.
+fn cheapest_seam(energy: &[u32], width: usize, height: usize) -> Vec<usize> {
+    let mut cost: Vec<u64> = energy[..width].iter().map(|energy| *energy as u64).collect();
+    cost.reserve(width * (height - 1));
+    for y in 1..height {
+        for x in 0..width {
+            let above = &cost[(y - 1) * width + x.saturating_sub(1)..(y - 1) * width + (x + 2).min(width)];
+            let cheapest = *above.iter().min().expect("a neighbour is always above");
+            cost.push(cheapest + energy[y * width + x] as u64);
+        }
+    }
+
+    let last_row = &cost[(height - 1) * width..];
+    let mut x = (0..width).min_by_key(|x| last_row[*x]).expect("images are at least one pixel wide");
+    let mut seam = vec![x; height];
+    for y in (0..height - 1).rev() {
+        let row = &cost[y * width..(y + 1) * width];
+        x = (x.saturating_sub(1)..(x + 2).min(width)).min_by_key(|x| row[*x]).expect("a neighbour is always above");
+        seam[y] = x;
+    }
+    seam
+}
+
+/// Removes the pixel at `seam[y]` from every row `y` of a `width` wide buffer.
+fn remove_seam<T: Copy>(buffer: &mut Vec<T>, seam: &[usize], width: usize) {
+    let mut row = 0;
+    let mut column = 0;
+    buffer.retain(|_| {
+        let keep = column != seam[row];
+        column += 1;
+        if column == width {
+            (row, column) = (row + 1, 0);
+        }
+        keep
+    });
+}
+
+fn brightness([red, green, blue, _]: [u8; 4]) -> u8 {
+    ((red as u32 * 299 + green as u32 * 587 + blue as u32 * 114) / 1000) as u8
+}
@@ -0,0 +1,441 @@
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::sync::Arc;
+
+use combiner::options::RemoteOptions;
+use combiner::pipeline::Step;
+use combiner::{combine_bytes, pnm};
+use image::{io::Reader, ImageFormat};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::args::{parse_number, CombineOptions, DiffStyle, Mode, ServeArgs};
+use crate::ImageDataErrors;
+
+/// Largest request body accepted, so a single upload cannot exhaust memory.
+const MAX_BODY_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Largest input decoded and output made, in pixels, since a small upload
+/// can hold a huge image and options can make the output larger still.
+const MAX_PIXELS: u64 = 64 * 1024 * 1024;
+
+/// Serves `POST /combine` until the process is stopped. The two images are
+/// sent as `multipart/form-data` file fields `image_1` and `image_2`, or as
+/// `image_1_url`/`image_2_url` fields to fetch them from when their host is
+/// one of `args.fetch_hosts`; `mode`,
+/// `threshold`, `diff_style` and `format` may be given as fields or in the
+/// query string. The response body is the combined image.
+pub fn serve(args: &ServeArgs) -> Result<(), ImageDataErrors> {
+    let server = Arc::new(Server::http(&args.addr).map_err(|e| ImageDataErrors::UnableToServe(e.to_string()))?);
+    log::warn!("listening on http://{}", args.addr);
+
+    let fetch_hosts = Arc::new(args.fetch_hosts.clone());
+    let workers: Vec<_> = (0..args.threads.max(1))
+        .map(|_| {
+            let (server, fetch_hosts) = (Arc::clone(&server), Arc::clone(&fetch_hosts));
+            std::thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle(request, &fetch_hosts);
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        let _ = worker.join();
+    }
+    Ok(())
+}
+
+fn handle(mut request: Request, fetch_hosts: &[String]) {
+    let started = std::time::Instant::now();
+    let (path, query) = match request.url().split_once('?') {
+        Some((path, query)) => (path.to_string(), query.to_string()),
+        None => (request.url().to_string(), String::new()),
+    };
+
+    let response = match (request.method(), path.as_str()) {
+        (Method::Get, "/health") => Response::from_string("ok").boxed(),
+        (Method::Post, "/combine") => match combine_request(&mut request, &query, fetch_hosts) {
+            Ok((bytes, format)) => Response::from_data(bytes)
+                .with_header(header("Content-Type", content_type(format)))
+                .boxed(),
+            Err(e) => {
+                let status = match e {
+                    ImageDataErrors::UnableToSaveImage(_) => 500,
+                    ImageDataErrors::UnableToFetch(_) => 502,
+                    _ => 400,
+                };
+                Response::from_string(e.to_string()).with_status_code(status).boxed()
+            }
+        },
+        _ => Response::from_string("not found").with_status_code(404).boxed(),
+    };
+
+    log::info!("{} {} -> {} in {:.2?}", request.method(), path, response.status_code().0, started.elapsed());
+    if let Err(e) = request.respond(response) {
+        log::warn!("unable to send response: {}", e);
+    }
+}
+
+fn combine_request(request: &mut Request, query: &str, fetch_hosts: &[String]) -> Result<(Vec<u8>, ImageFormat), ImageDataErrors> {
+    let mut fields = parse_query(query);
+    let mut files = HashMap::new();
+
+    let content_type = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Content-Type"))
+        .map(|header| header.value.as_str().to_string())
+        .unwrap_or_default();
+    let boundary = content_type
+        .split(';')
+        .find_map(|param| param.trim().strip_prefix("boundary="))
+        .map(|boundary| boundary.trim_matches('"').to_string())
+        .ok_or_else(|| invalid("expected a multipart/form-data body"))?;
+
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_BODY_BYTES + 1)
+        .read_to_end(&mut body)
+        .map_err(ImageDataErrors::UnableToReadImageFromPath)?;
+    if body.len() as u64 > MAX_BODY_BYTES {
+        return Err(invalid("request body is too large"));
+    }
+
+    for part in parse_multipart(&body, &boundary) {
+        if part.is_file {
+            files.insert(part.name, part.data);
+        } else {
+            fields.insert(part.name, String::from_utf8_lossy(&part.data).into_owned());
+        }
+    }
+
+    let image_1 = load_input("image_1", &mut files, &fields, fetch_hosts)?;
+    let image_2 = load_input("image_2", &mut files, &fields, fetch_hosts)?;
+    let options = options_from(&fields)?;
+    check_pixels(&image_1, &image_2, &options)?;
+    let format = fields
+        .get("format")
+        .map(|format| ImageFormat::from_extension(format).ok_or_else(|| invalid(&format!("unknown format `{}`", format))))
+        .transpose()?;
+
+    let combined = combine_bytes(&image_1, &image_2, &options, format)?;
+    Ok((combined.bytes, combined.format))
+}
+
+/// An uploaded file field, or a fetched `<name>_url` field.
+fn load_input(
+    name: &'static str,
+    files: &mut HashMap<String, Vec<u8>>,
+    fields: &HashMap<String, String>,
+    fetch_hosts: &[String],
+) -> Result<Vec<u8>, ImageDataErrors> {
+    match (files.remove(name), fields.get(&format!("{}_url", name))) {
+        (Some(bytes), _) => Ok(bytes),
+        (None, Some(url)) => fetch(url, fetch_hosts),
+        (None, None) => Err(ImageDataErrors::MissingArgument(name)),
+    }
+}
+
+/// Fetches `url`, which must be on one of `fetch_hosts`. Redirects are not
+/// followed, as they could lead anywhere.
+fn fetch(url: &str, fetch_hosts: &[String]) -> Result<Vec<u8>, ImageDataErrors> {
+    let uri: ureq::http::Uri = url.parse().map_err(|_| invalid(&format!("invalid URL `{}`", url)))?;
+    let allowed = matches!(uri.scheme_str(), Some("http" | "https"))
+        && uri.host().is_some_and(|host| fetch_hosts.iter().any(|allowed| host.eq_ignore_ascii_case(allowed)));
+    if !allowed {
+        return Err(invalid(&format!("`{}` is not on a host the server may fetch from, see `--allow-fetch`", url)));
+    }
+    let fetch_error = |e: ureq::Error| ImageDataErrors::UnableToFetch(format!("{}: {}", url, e));
+    ureq::get(url)
+        .config()
+        .max_redirects(0)
+        .build()
+        .call()
+        .map_err(fetch_error)?
+        .body_mut()
+        .with_config()
+        .limit(MAX_BODY_BYTES)
+        .read_to_vec()
+        .map_err(fetch_error)
+}
+
+/// An `options` field holding the JSON form of [`RemoteOptions`], overridden
+/// by any individual `mode`, `threshold` or `diff_style` field.
+fn options_from(fields: &HashMap<String, String>) -> Result<CombineOptions, ImageDataErrors> {
+    let mut options = match fields.get("options") {
+        Some(json) => {
+            let RemoteOptions(options) = serde_json::from_str(json).map_err(|e| invalid(&format!("invalid options: {}", e)))?;
+            options
+        }
+        None => CombineOptions::default(),
+    };
+    if let Some(mode) = fields.get("mode") {
+        options.mode = Mode::parse(mode)?;
+    }
+    if let Some(threshold) = fields.get("threshold") {
+        options.threshold = parse_number("threshold", threshold)?;
+    }
+    if let Some(style) = fields.get("diff_style") {
+        options.diff_style = DiffStyle::parse(style)?;
+    }
+    Ok(options)
+}
+
+/// Refuses uploads that decode, or combine with `options`, to more than
+/// [`MAX_PIXELS`], before anything is decoded.
+pub fn check_pixels(image_1: &[u8], image_2: &[u8], options: &CombineOptions) -> Result<(), ImageDataErrors> {
+    let sizes = [image_size(image_1)?, image_size(image_2)?];
+    if sizes.iter().any(|&size| pixels(size) > MAX_PIXELS) || largest_output(sizes, options) > MAX_PIXELS {
+        return Err(invalid(&format!("images are limited to {} pixels", MAX_PIXELS)));
+    }
+    Ok(())
+}
+
+/// The size an encoded image decodes to, read from its header alone.
+fn image_size(bytes: &[u8]) -> Result<(u32, u32), ImageDataErrors> {
+    let reader = Reader::new(Cursor::new(bytes)).with_guessed_format().map_err(ImageDataErrors::UnableToReadImageFromPath)?;
+    if reader.format() == Some(ImageFormat::Pnm) {
+        return Ok(pnm::read_header(bytes)?.0);
+    }
+    reader.into_dimensions().map_err(ImageDataErrors::UnableToDecodeImage)
+}
+
+fn pixels((width, height): (u32, u32)) -> u64 {
+    width as u64 * height as u64
+}
+
+/// At least as many pixels as the output of inputs of `sizes` has, and any
+/// image made on the way: the canvas or the inputs as resized and rotated,
+/// grown by the pipeline's steps, a photomosaic's scale and a print page.
+fn largest_output(sizes: [(u32, u32); 2], options: &CombineOptions) -> u64 {
+    // A turned image fits in a square as wide as both its sides.
+    let turned = |(width, height): (u32, u32)| {
+        let side = width.saturating_add(height);
+        (side, side)
+    };
+    let inputs = [0, 1].map(|input| {
+        let size = options.resize.map_or(sizes[input], |geometry| geometry.resized_size(sizes[input]));
+        if options.rotations[input] == 0.0 { size } else { turned(size) }
+    });
+    let mut size = options.canvas.unwrap_or((inputs[0].0.max(inputs[1].0), inputs[0].1.max(inputs[1].1)));
+    let mut largest = inputs.into_iter().chain([size]).map(pixels).max().unwrap_or(0);
+    for step in options.pipeline.iter().flat_map(|pipeline| &pipeline.steps) {
+        size = match *step {
+            Step::Resize(geometry) => geometry.resized_size(size),
+            Step::Rotate(degrees) if degrees != 0.0 => turned(size),
+            Step::Border { width, .. } => {
+                let border = width.saturating_mul(2);
+                (size.0.saturating_add(border), size.1.saturating_add(border))
+            }
+            _ => size,
+        };
+        largest = largest.max(pixels(size));
+    }
+    if options.mode == Mode::Photomosaic {
+        largest = largest.saturating_mul((options.mosaic_scale as u64).pow(2));
+    }
+    if let Some(print) = options.print {
+        largest = largest.max(pixels(print.page_size()));
+    }
+    largest
+}
+
+struct Part {
+    name: String,
+    is_file: bool,
+    data: Vec<u8>,
+}
+
+/// Splits a `multipart/form-data` body into its named parts.
+fn parse_multipart(body: &[u8], boundary: &str) -> Vec<Part> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut parts = Vec::new();
+
+    let mut at = match find(body, &delimiter, 0) {
+        Some(start) => start + delimiter.len(),
+        None => return parts,
+    };
+    while let Some(end) = find(body, &delimiter, at) {
+        let section = &body[at..end];
+        at = end + delimiter.len();
+
+        let section = section.strip_prefix(b"\r\n").unwrap_or(section);
+        let section = section.strip_suffix(b"\r\n").unwrap_or(section);
+        let Some(split) = find(section, b"\r\n\r\n", 0) else { continue };
+        let headers = String::from_utf8_lossy(&section[..split]);
+        let disposition = headers
+            .lines()
+            .find(|line| line.to_ascii_lowercase().starts_with("content-disposition"))
+            .unwrap_or_default();
+
+        if let Some(name) = disposition_param(disposition, "name") {
+            parts.push(Part {
+                name,
+                is_file: disposition_param(disposition, "filename").is_some(),
+                data: section[split + 4..].to_vec(),
+            });
+        }
+    }
+    parts
+}
+
+fn disposition_param(disposition: &str, key: &str) -> Option<String> {
+    disposition.split(';').find_map(|param| {
+        let (name, value) = param.trim().split_once('=')?;
+        (name == key).then(|| value.trim_matches('"').to_string())
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack.get(from..)?.windows(needle.len()).position(|window| window == needle).map(|position| position + from)
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter_map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (!key.is_empty()).then(|| (percent_decode(key), percent_decode(value)))
+        })
+        .collect()
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => match value.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                Some(byte) => {
+                    decoded.push(byte);
+                    i += 2;
+                }
+                None => decoded.push(b'%'),
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn content_type(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Png => "image/png",
+        ImageFormat::Jpeg => "image/jpeg",
+        ImageFormat::Gif => "image/gif",
+        ImageFormat::WebP => "image/webp",
+        ImageFormat::Bmp => "image/bmp",
+        ImageFormat::Tiff => "image/tiff",
+        ImageFormat::Ico => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+}
+
+fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes()).expect("static header is valid")
+}
+
+fn invalid(message: &str) -> ImageDataErrors {
+    ImageDataErrors::InvalidArgument(message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDARY: &str = "combiner-test";
+
+    /// Posts `parts`, as `(name, is_file, data)`, to a server that handles
+    /// this one request, returning the status and body of its response.
+    fn post(parts: &[(&str, bool, &[u8])], fetch_hosts: &[&str]) -> (u16, Vec<u8>) {
+        let mut body = Vec::new();
+        for (name, is_file, data) in parts {
+            let filename = if *is_file { format!("; filename=\"{}\"", name) } else { String::new() };
+            body.extend(format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"{}\r\n\r\n", BOUNDARY, name, filename).bytes());
+            body.extend_from_slice(data);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend(format!("--{}--\r\n", BOUNDARY).bytes());
+
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let addr = server.server_addr().to_ip().unwrap();
+        let fetch_hosts: Vec<String> = fetch_hosts.iter().map(|host| host.to_string()).collect();
+        let worker = std::thread::spawn(move || handle(server.recv().unwrap(), &fetch_hosts));
+        let mut response = ureq::post(format!("http://{}/combine", addr))
+            .config()
+            .http_status_as_error(false)
+            .build()
+            .header("Content-Type", format!("multipart/form-data; boundary={}", BOUNDARY))
+            .send(&body[..])
+            .unwrap();
+        worker.join().unwrap();
+        let status = response.status().as_u16();
+        (status, response.body_mut().with_config().limit(MAX_BODY_BYTES).read_to_vec().unwrap())
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(width, height, image::Rgba([10, 20, 30, 255])));
+        let mut bytes = Vec::new();
+        image.write_to(&mut bytes, ImageFormat::Png).unwrap();
+        bytes
+    }
+
+    fn error(parts: &[(&str, bool, &[u8])], fetch_hosts: &[&str]) -> String {
+        let (status, body) = post(parts, fetch_hosts);
+        assert_eq!(status, 400);
+        String::from_utf8(body).unwrap()
+    }
+
+    #[test]
+    fn combines_uploads() {
+        let image = png(4, 3);
+        let (status, body) = post(&[("image_1", true, &image), ("image_2", true, &image), ("mode", false, b"diff")], &[]);
+        assert_eq!(status, 200);
+        assert_eq!(image::load_from_memory(&body).unwrap().to_rgba8().dimensions(), (4, 3));
+    }
+
+    #[test]
+    fn rejects_oversized_bodies() {
+        let huge = vec![0; MAX_BODY_BYTES as usize];
+        assert!(error(&[("image_1", true, &huge)], &[]).contains("too large"));
+    }
+
+    #[test]
+    fn rejects_server_paths() {
+        let image = png(4, 3);
+        let options = br#"{"mode": "depth-blend", "depth_map": "/etc/passwd"}"#;
+        let message = error(&[("image_1", true, &image), ("image_2", true, &image), ("options", false, options)], &[]);
+        assert!(message.contains("depth_map"), "{}", message);
+    }
+
+    #[test]
+    fn limits_pixels() {
+        let image = png(4, 3);
+        // A header is all it takes to claim a huge image.
+        let huge = b"P5 20000 20000 255\n";
+        let message = error(&[("image_1", true, huge), ("image_2", true, &image)], &[]);
+        assert!(message.contains("pixels"), "{}", message);
+
+        for options in [&br#"{"mode": "canvas", "canvas": [100000, 100000]}"#[..], br#"{"pipeline": "resize:100000x100000!"}"#, br#"{"print": "A3@4800dpi"}"#] {
+            let message = error(&[("image_1", true, &image), ("image_2", true, &image), ("options", false, options)], &[]);
+            assert!(message.contains("pixels"), "{}", message);
+        }
+    }
+
+    #[test]
+    fn fetches_only_from_allowed_hosts() {
+        let image = png(4, 3);
+        let url = b"http://127.0.0.1:1/image.png";
+        let message = error(&[("image_1_url", false, url), ("image_2", true, &image)], &[]);
+        assert!(message.contains("--allow-fetch"), "{}", message);
+        let message = error(&[("image_1_url", false, url), ("image_2", true, &image)], &["example.com"]);
+        assert!(message.contains("--allow-fetch"), "{}", message);
+        assert!(matches!(fetch("file:///etc/passwd", &["example.com".to_string()]), Err(ImageDataErrors::InvalidArgument(_))));
+        // Allowed hosts are fetched; nothing listens on this one.
+        assert!(matches!(fetch("http://127.0.0.1:1/image.png", &["127.0.0.1".to_string()]), Err(ImageDataErrors::UnableToFetch(_))));
+    }
+}
@@ -0,0 +1,88 @@
+//! `--record`: a `<output>.imgcombine.json` sidecar beside each output,
+//! recording the inputs it was made from, with their hashes, the options and
+//! flags that shaped it and the version that made it, so that `replay` can
+//! make it again.
+
+use serde::{Deserialize, Serialize};
+
+use crate::batch::Job;
+use crate::checksum::ChecksumAlgorithm;
+use crate::storage::Storage;
+use combiner::options::CombineOptions;
+use combiner::{pdf, ImageDataErrors, ImageStore};
+
+/// Appended to an output's path to name its sidecar.
+pub const SUFFIX: &str = ".imgcombine.json";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Sidecar {
+    /// The version of combiner that made the output.
+    pub version: String,
+    /// The working directory the paths are relative to.
+    pub directory: String,
+    pub image_1: Input,
+    pub image_2: Input,
+    pub options: CombineOptions,
+    /// The flags besides the options that shaped the output, as given.
+    #[serde(default)]
+    pub arguments: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Input {
+    pub path: String,
+    /// The SHA-256 of the file read, or `None` for directories such as
+    /// photomosaic tile libraries.
+    pub sha256: Option<String>,
+}
+
+impl Input {
+    fn new(path: &str) -> Result<Self, ImageDataErrors> {
+        Ok(Input { path: path.to_string(), sha256: digest(path)? })
+    }
+
+    /// Whether the input still has the hash it had when recorded.
+    pub fn is_unchanged(&self) -> Result<bool, ImageDataErrors> {
+        Ok(self.sha256.is_none() || digest(&self.path)? == self.sha256)
+    }
+}
+
+impl Sidecar {
+    pub fn record(job: &Job, options: &CombineOptions, arguments: &[String]) -> Result<Self, ImageDataErrors> {
+        let directory = std::env::current_dir().map_err(ImageDataErrors::UnableToReadDirectory)?;
+        Ok(Sidecar {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            directory: directory.to_string_lossy().into_owned(),
+            image_1: Input::new(&job.image_1)?,
+            image_2: Input::new(&job.image_2)?,
+            options: options.clone(),
+            arguments: arguments.to_vec(),
+        })
+    }
+}
+
+/// The sidecar of the output at `output`.
+pub fn path_for(output: &str) -> String {
+    format!("{}{}", output, SUFFIX)
+}
+
+/// The output the sidecar at `path` belongs to.
+pub fn output_for(path: &str) -> Result<&str, ImageDataErrors> {
+    path.strip_suffix(SUFFIX)
+        .filter(|output| !output.is_empty())
+        .ok_or_else(|| ImageDataErrors::InvalidArgument(format!("{} is not named `<output>{}`", path, SUFFIX)))
+}
+
+/// The SHA-256 of the file an input is read from: the document of a PDF
+/// page and the video of a frame.
+fn digest(path: &str) -> Result<Option<String>, ImageDataErrors> {
+    let file = match (pdf::split_page(path), crate::video::split_timestamp(path)) {
+        (Some((document, _)), _) => document,
+        (None, Some((video, _))) => video,
+        (None, None) => path,
+    };
+    if std::path::Path::new(file).is_dir() {
+        return Ok(None);
+    }
+    Ok(Some(ChecksumAlgorithm::Sha256.digest(&Storage.read(file)?)))
+}
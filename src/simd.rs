@@ -0,0 +1,332 @@
+//! The hottest per-pixel loops, blending, multiplying and differencing, with
+//! explicit SIMD versions picked at runtime for CPUs that support them. Every
+//! version gives byte-identical results to the scalar one, so outputs do not
+//! depend on the machine that made them. `cargo bench` compares the two.
+
+/// Which instructions a kernel runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kernel {
+    Scalar,
+    /// x86-64 AVX2, processing 2 pixels a step when blending or multiplying
+    /// and 8 when differencing.
+    Avx2,
+}
+
+impl Kernel {
+    /// The fastest kernel this CPU supports.
+    pub fn detect() -> Self {
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx2") {
+            return Kernel::Avx2;
+        }
+        Kernel::Scalar
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kernel::Scalar => "scalar",
+            Kernel::Avx2 => "avx2",
+        }
+    }
+
+    /// Whether this CPU can run the kernel; those it cannot run fall back to
+    /// scalar.
+    fn supported(&self) -> bool {
+        match self {
+            Kernel::Scalar => true,
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2 => is_x86_feature_detected!("avx2"),
+            #[cfg(not(target_arch = "x86_64"))]
+            Kernel::Avx2 => false,
+        }
+    }
+}
+
+/// Mixes two RGBA buffers of the same length, `weight` of the way from
+/// `from` to `to`, premultiplying colours by alpha so the colour hidden in
+/// transparent pixels never bleeds into the mix.
+pub fn blend(from: &[u8], to: &[u8], weight: f32, kernel: Kernel) -> Vec<u8> {
+    let mut output = vec![0u8; from.len().min(to.len()) / 4 * 4];
+    let done = match kernel {
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Avx2 if kernel.supported() => {
+            // SAFETY: the CPU supports AVX2, checked just above.
+            unsafe { x86::blend(from, to, weight, &mut output) }
+        }
+        _ => 0,
+    };
+    blend_scalar(&from[done..], &to[done..], weight, &mut output[done..]);
+    output
+}
+
+fn blend_scalar(from: &[u8], to: &[u8], weight: f32, output: &mut [u8]) {
+    for ((from, to), pixel) in from.chunks_exact(4).zip(to.chunks_exact(4)).zip(output.chunks_exact_mut(4)) {
+        let alpha = |pixel: &[u8]| pixel[3] as f32 / 255.0;
+        let (from_alpha, to_alpha) = (alpha(from), alpha(to));
+        let mixed_alpha = from_alpha + (to_alpha - from_alpha) * weight;
+        for channel in 0..3 {
+            pixel[channel] = if mixed_alpha > 0.0 {
+                let (from, to) = (from[channel] as f32 * from_alpha, to[channel] as f32 * to_alpha);
+                ((from + (to - from) * weight) / mixed_alpha).round().clamp(0.0, 255.0) as u8
+            } else {
+                0
+            };
+        }
+        pixel[3] = (mixed_alpha * 255.0).round() as u8;
+    }
+}
+
+/// Multiplies `source` onto `backdrop`, RGBA buffers of the same length,
+/// at `opacity`, composited as the `multiply` blend mode of pipelines is:
+/// where either is transparent the other's colour shows, and where both are
+/// fully transparent `backdrop` is kept as it is.
+pub fn multiply(backdrop: &[u8], source: &[u8], opacity: f32, kernel: Kernel) -> Vec<u8> {
+    let length = backdrop.len().min(source.len()) / 4 * 4;
+    let mut output = backdrop[..length].to_vec();
+    let done = match kernel {
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Avx2 if kernel.supported() => {
+            // SAFETY: the CPU supports AVX2, checked just above.
+            unsafe { x86::multiply(source, opacity, &mut output) }
+        }
+        _ => 0,
+    };
+    multiply_scalar(&source[done..], opacity, &mut output[done..]);
+    output
+}
+
+fn multiply_scalar(source: &[u8], opacity: f32, output: &mut [u8]) {
+    for (out, from) in output.chunks_exact_mut(4).zip(source.chunks_exact(4)) {
+        let backdrop_alpha = out[3] as f32 / 255.0;
+        let source_alpha = from[3] as f32 / 255.0 * opacity;
+        let alpha = source_alpha + backdrop_alpha * (1.0 - source_alpha);
+        if alpha == 0.0 {
+            continue;
+        }
+        for channel in 0..3 {
+            let (b, s) = (out[channel] as f32 / 255.0, from[channel] as f32 / 255.0);
+            let mixed = (1.0 - source_alpha) * backdrop_alpha * b + (1.0 - backdrop_alpha) * source_alpha * s + source_alpha * backdrop_alpha * (b * s);
+            out[channel] = (mixed / alpha * 255.0).round().clamp(0.0, 255.0) as u8;
+        }
+        out[3] = (alpha * 255.0).round() as u8;
+    }
+}
+
+/// The largest per-channel difference of every pair of RGBA pixels.
+pub fn difference_magnitudes(vec_1: &[u8], vec_2: &[u8], kernel: Kernel) -> Vec<u8> {
+    let mut output = vec![0u8; vec_1.len().min(vec_2.len()) / 4];
+    let done = match kernel {
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Avx2 if kernel.supported() => {
+            // SAFETY: the CPU supports AVX2, checked just above.
+            unsafe { x86::difference_magnitudes(vec_1, vec_2, &mut output) }
+        }
+        _ => 0,
+    };
+    difference_scalar(&vec_1[done * 4..], &vec_2[done * 4..], &mut output[done..]);
+    output
+}
+
+fn difference_scalar(vec_1: &[u8], vec_2: &[u8], output: &mut [u8]) {
+    for ((a, b), magnitude) in vec_1.chunks_exact(4).zip(vec_2.chunks_exact(4)).zip(output) {
+        *magnitude = a.iter().zip(b).map(|(x, y)| x.abs_diff(*y)).max().unwrap_or(0);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    /// Gathers the low byte of each 32-bit lane into the low 8 bytes.
+    #[target_feature(enable = "avx2")]
+    fn low_bytes(lanes: __m256i) -> __m128i {
+        let gather = _mm256_setr_epi8(
+            0, 4, 8, 12, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, //
+            0, 4, 8, 12, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1,
+        );
+        let packed = _mm256_permutevar8x32_epi32(_mm256_shuffle_epi8(lanes, gather), _mm256_setr_epi32(0, 4, 1, 1, 1, 1, 1, 1));
+        _mm256_castsi256_si128(packed)
+    }
+
+    /// Blends whole pairs of pixels, as `blend_scalar` does, and returns how
+    /// many bytes it wrote. The arithmetic is done in the same order, without
+    /// fused multiply-adds, so every byte matches.
+    ///
+    /// # Safety
+    ///
+    /// The CPU must support AVX2.
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn blend(from: &[u8], to: &[u8], weight: f32, output: &mut [u8]) -> usize {
+        let (weight, max, zero) = (_mm256_set1_ps(weight), _mm256_set1_ps(255.0), _mm256_setzero_ps());
+        let alphas = _mm256_setr_epi32(3, 3, 3, 3, 7, 7, 7, 7);
+        let alpha_lanes = _mm256_castsi256_ps(_mm256_setr_epi32(0, 0, 0, -1, 0, 0, 0, -1));
+        let pairs = from.chunks_exact(8).zip(to.chunks_exact(8)).zip(output.chunks_exact_mut(8));
+        let mut written = 0;
+        for ((from, to), pixels) in pairs {
+            // SAFETY: each chunk is 8 bytes long, as much as these read and write.
+            let (from, to) = unsafe {
+                (_mm_loadl_epi64(from.as_ptr().cast()), _mm_loadl_epi64(to.as_ptr().cast()))
+            };
+            let (from, to) = (_mm256_cvtepi32_ps(_mm256_cvtepu8_epi32(from)), _mm256_cvtepi32_ps(_mm256_cvtepu8_epi32(to)));
+            let from_alpha = _mm256_div_ps(_mm256_permutevar8x32_ps(from, alphas), max);
+            let to_alpha = _mm256_div_ps(_mm256_permutevar8x32_ps(to, alphas), max);
+            let mixed_alpha = _mm256_add_ps(from_alpha, _mm256_mul_ps(_mm256_sub_ps(to_alpha, from_alpha), weight));
+            let (from, to) = (_mm256_mul_ps(from, from_alpha), _mm256_mul_ps(to, to_alpha));
+            let colour = _mm256_div_ps(_mm256_add_ps(from, _mm256_mul_ps(_mm256_sub_ps(to, from), weight)), mixed_alpha);
+            // Fully transparent mixes divide by 0; those become 0.
+            let colour = _mm256_and_ps(colour, _mm256_cmp_ps::<_CMP_GT_OQ>(mixed_alpha, zero));
+            let rounded = round_to_byte(_mm256_blendv_ps(colour, _mm256_mul_ps(mixed_alpha, max), alpha_lanes));
+            // SAFETY: as for the loads.
+            unsafe { _mm_storel_epi64(pixels.as_mut_ptr().cast(), low_bytes(_mm256_cvttps_epi32(rounded))) };
+            written += 8;
+        }
+        written
+    }
+
+    /// Rounds half away from zero, as `f32::round` does, then clamps to a byte.
+    #[target_feature(enable = "avx2")]
+    fn round_to_byte(values: __m256) -> __m256 {
+        let (zero, one, half, max) = (_mm256_setzero_ps(), _mm256_set1_ps(1.0), _mm256_set1_ps(0.5), _mm256_set1_ps(255.0));
+        let whole = _mm256_round_ps::<{ _MM_FROUND_TO_ZERO | _MM_FROUND_NO_EXC }>(values);
+        let up = _mm256_and_ps(_mm256_cmp_ps::<_CMP_GE_OQ>(_mm256_sub_ps(values, whole), half), one);
+        _mm256_min_ps(_mm256_max_ps(_mm256_add_ps(whole, up), zero), max)
+    }
+
+    /// Multiplies whole pairs of pixels of `source` onto `output`, as
+    /// `multiply_scalar` does, and returns how many bytes it wrote. As in
+    /// `blend`, the arithmetic is done in the same order so every byte matches.
+    ///
+    /// # Safety
+    ///
+    /// The CPU must support AVX2.
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn multiply(source: &[u8], opacity: f32, output: &mut [u8]) -> usize {
+        let (opacity, max, zero, one) = (_mm256_set1_ps(opacity), _mm256_set1_ps(255.0), _mm256_setzero_ps(), _mm256_set1_ps(1.0));
+        let alphas = _mm256_setr_epi32(3, 3, 3, 3, 7, 7, 7, 7);
+        let alpha_lanes = _mm256_castsi256_ps(_mm256_setr_epi32(0, 0, 0, -1, 0, 0, 0, -1));
+        let mut written = 0;
+        for (from, pixels) in source.chunks_exact(8).zip(output.chunks_exact_mut(8)) {
+            // SAFETY: each chunk is 8 bytes long, as much as these read and write.
+            let (backdrop, from) = unsafe { (_mm_loadl_epi64(pixels.as_ptr().cast()), _mm_loadl_epi64(from.as_ptr().cast())) };
+            let (backdrop, from) = (_mm256_cvtepi32_ps(_mm256_cvtepu8_epi32(backdrop)), _mm256_cvtepi32_ps(_mm256_cvtepu8_epi32(from)));
+            let backdrop_alpha = _mm256_div_ps(_mm256_permutevar8x32_ps(backdrop, alphas), max);
+            let source_alpha = _mm256_mul_ps(_mm256_div_ps(_mm256_permutevar8x32_ps(from, alphas), max), opacity);
+            let alpha = _mm256_add_ps(source_alpha, _mm256_mul_ps(backdrop_alpha, _mm256_sub_ps(one, source_alpha)));
+            let (b, s) = (_mm256_div_ps(backdrop, max), _mm256_div_ps(from, max));
+            let only_backdrop = _mm256_mul_ps(_mm256_mul_ps(_mm256_sub_ps(one, source_alpha), backdrop_alpha), b);
+            let only_source = _mm256_mul_ps(_mm256_mul_ps(_mm256_sub_ps(one, backdrop_alpha), source_alpha), s);
+            let both = _mm256_mul_ps(_mm256_mul_ps(source_alpha, backdrop_alpha), _mm256_mul_ps(b, s));
+            let mixed = _mm256_add_ps(_mm256_add_ps(only_backdrop, only_source), both);
+            let colour = _mm256_mul_ps(_mm256_div_ps(mixed, alpha), max);
+            let values = round_to_byte(_mm256_blendv_ps(colour, _mm256_mul_ps(alpha, max), alpha_lanes));
+            // Pixels where both are fully transparent keep the backdrop.
+            let values = _mm256_blendv_ps(values, backdrop, _mm256_cmp_ps::<_CMP_EQ_OQ>(alpha, zero));
+            // SAFETY: as for the loads.
+            unsafe { _mm_storel_epi64(pixels.as_mut_ptr().cast(), low_bytes(_mm256_cvttps_epi32(values))) };
+            written += 8;
+        }
+        written
+    }
+
+    /// Finds the magnitudes of 8 pixels at a time and returns how many
+    /// pixels it did.
+    ///
+    /// # Safety
+    ///
+    /// The CPU must support AVX2.
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn difference_magnitudes(vec_1: &[u8], vec_2: &[u8], output: &mut [u8]) -> usize {
+        let low_byte = _mm256_set1_epi32(0xff);
+        let blocks = vec_1.chunks_exact(32).zip(vec_2.chunks_exact(32)).zip(output.chunks_exact_mut(8));
+        let mut done = 0;
+        for ((a, b), magnitudes) in blocks {
+            // SAFETY: the chunks are 32 and 8 bytes long, as much as these read and write.
+            let (a, b) = unsafe { (_mm256_loadu_si256(a.as_ptr().cast()), _mm256_loadu_si256(b.as_ptr().cast())) };
+            let difference = _mm256_or_si256(_mm256_subs_epu8(a, b), _mm256_subs_epu8(b, a));
+            // Folds each pixel's 4 channels into its lowest byte.
+            let folded = _mm256_max_epu8(difference, _mm256_srli_epi32::<8>(difference));
+            let folded = _mm256_max_epu8(folded, _mm256_srli_epi32::<16>(folded));
+            let lanes = _mm256_and_si256(folded, low_byte);
+            // SAFETY: as for the loads.
+            unsafe { _mm_storel_epi64(magnitudes.as_mut_ptr().cast(), low_bytes(lanes)) };
+            done += 8;
+        }
+        done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `pixels` pixels of noise, with every fifth fully transparent and every
+    /// seventh fully opaque, so the blends' edge cases all come up.
+    fn noise(pixels: usize, mut state: u64) -> Vec<u8> {
+        let mut data: Vec<u8> = (0..pixels * 4)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 24) as u8
+            })
+            .collect();
+        for (index, pixel) in data.chunks_exact_mut(4).enumerate() {
+            match index % 35 {
+                0 | 5 | 10 | 15 | 20 | 25 | 30 => pixel[3] = 0,
+                7 | 14 | 21 | 28 => pixel[3] = 255,
+                _ => {}
+            }
+        }
+        data
+    }
+
+    /// Lengths in pixels around the 2 and 8 pixel steps of the AVX2 kernels,
+    /// most of them leaving a tail for the scalar code.
+    const LENGTHS: [usize; 9] = [0, 1, 2, 3, 7, 8, 9, 33, 1001];
+
+    fn assert_kernels_agree(run: impl Fn(&[u8], &[u8], Kernel) -> Vec<u8>) {
+        if !Kernel::Avx2.supported() {
+            eprintln!("skipped: this CPU has no AVX2");
+            return;
+        }
+        for (index, pixels) in LENGTHS.into_iter().enumerate() {
+            let (a, b) = (noise(pixels, 0x9e37_79b9 + index as u64), noise(pixels, 0x7f4a_7c15 + index as u64));
+            // A stray byte past the last whole pixel is ignored by both.
+            let (a, b) = ([&a[..], &[7]].concat(), b);
+            assert_eq!(run(&a, &b, Kernel::Avx2), run(&a, &b, Kernel::Scalar), "{} pixels", pixels);
+        }
+        for alpha in [0, 255] {
+            let (mut a, mut b) = (noise(33, 1), noise(33, 2));
+            a.chunks_exact_mut(4).chain(b.chunks_exact_mut(4)).for_each(|pixel| pixel[3] = alpha);
+            assert_eq!(run(&a, &b, Kernel::Avx2), run(&a, &b, Kernel::Scalar), "alpha {}", alpha);
+        }
+    }
+
+    #[test]
+    fn blend_kernels_agree() {
+        for weight in [0.0, 0.25, 0.5, 0.9, 1.0] {
+            assert_kernels_agree(|a, b, kernel| blend(a, b, weight, kernel));
+        }
+    }
+
+    #[test]
+    fn multiply_kernels_agree() {
+        for opacity in [0.0, 0.4, 1.0] {
+            assert_kernels_agree(|a, b, kernel| multiply(a, b, opacity, kernel));
+        }
+    }
+
+    #[test]
+    fn difference_kernels_agree() {
+        assert_kernels_agree(difference_magnitudes);
+    }
+
+    #[test]
+    fn scalar_kernels_at_known_pixels() {
+        let (red, clear) = ([255, 0, 0, 255], [0, 0, 255, 0]);
+        assert_eq!(blend(&red, &clear, 0.5, Kernel::Scalar), [255, 0, 0, 128]);
+        assert_eq!(multiply(&[200, 100, 50, 255], &[128, 255, 0, 255], 1.0, Kernel::Scalar), [100, 100, 0, 255]);
+        assert_eq!(multiply(&clear, &clear, 1.0, Kernel::Scalar), clear);
+        assert_eq!(difference_magnitudes(&red, &[250, 20, 3, 255], Kernel::Scalar), [20]);
+    }
+}
@@ -0,0 +1,102 @@
+use image::{imageops, GrayImage, Luma, RgbaImage};
+
+use crate::hooks::Hooks;
+use crate::mask;
+use crate::ImageDataErrors;
+
+/// Replaces the sky of `image` with `sky`, the same size: the sky is found
+/// column by column as the bright, smooth run of colour hanging from the top
+/// edge, and `sky` is laid over it through a matte ramping across `feather`
+/// pixels at the horizon. The rest of `image` is tinted `harmonize` of the
+/// way toward the new sky's colour, from 0 to 1, so both halves share a light.
+pub(crate) fn replace_sky(image: &RgbaImage, sky: &RgbaImage, feather: Option<u32>, harmonize: f32, hooks: Hooks) -> Result<Vec<u8>, ImageDataErrors> {
+    let (width, height) = image.dimensions();
+    let horizon = horizon(image, hooks)?;
+    let feather = feather.unwrap_or(height / 50).max(1) as f32;
+    let matte = GrayImage::from_fn(width, height, |x, y| {
+        // 1 above the horizon, 0 below, with a smoothstep across the feather.
+        let t = ((horizon[x as usize] as f32 + feather / 2.0 - y as f32 - 0.5) / feather).clamp(0.0, 1.0);
+        Luma([(t * t * (3.0 - 2.0 * t) * 255.0).round() as u8])
+    });
+    let found = horizon.iter().filter(|&&row| row > 0).count();
+    log::info!("found sky in {} of {} columns", found, width);
+    if found == 0 {
+        log::warn!("no sky found in the first input, so it is left as it is");
+    }
+
+    let base = harmonized(image, sky, &matte, harmonize);
+    let mut data = sky.clone().into_raw();
+    mask::apply(&base, &matte, &mut data, (width, height)).expect("the inputs and matte share a size");
+    hooks.step("combining", 1, 1)?;
+    Ok(data)
+}
+
+/// How many rows of each column of `image`, from the top, are sky. The
+/// search reports to `hooks` a column at a time.
+fn horizon(image: &RgbaImage, hooks: Hooks) -> Result<Vec<u32>, ImageDataErrors> {
+    let (width, height) = image.dimensions();
+    let luma = imageops::blur(&imageops::grayscale(image), 1.0);
+    let at = |x: u32, y: u32| f32::from(luma.get_pixel(x.min(width - 1), y.min(height - 1)).0[0]);
+    let rows: Vec<u32> = (0..width)
+        .map(|x| {
+            hooks.step("combining", x as usize, width as usize)?;
+            let mut mean = [0.0f32; 3];
+            for y in 0..height {
+                let [r, g, b, _] = image.get_pixel(x, y).0.map(f32::from);
+                let brightness = at(x, y);
+                let gradient = (at(x + 1, y) - at(x.saturating_sub(1), y)).abs() + (at(x, y + 1) - at(x, y.saturating_sub(1))).abs();
+                // Skies are blue or bright overcast, smooth, and drift in
+                // colour slowly down to the horizon.
+                let sky_like = brightness > 64.0 && (b >= r || brightness > 160.0) && gradient < 12.0;
+                let near = y == 0 || [r, g, b].iter().zip(mean).map(|(value, mean)| (value - mean).powi(2)).sum::<f32>().sqrt() < 40.0;
+                if !(sky_like && near) {
+                    return Ok(y);
+                }
+                mean = if y == 0 { [r, g, b] } else { std::array::from_fn(|i| mean[i] * 0.9 + [r, g, b][i] * 0.1) };
+            }
+            Ok(height)
+        })
+        .collect::<Result<_, ImageDataErrors>>()?;
+    // A running median evens out columns cut short by noise or poking up
+    // through a thin branch or wire.
+    let reach = (width / 200).max(2) as usize;
+    Ok((0..rows.len())
+        .map(|x| {
+            let mut window = rows[x.saturating_sub(reach)..(x + reach + 1).min(rows.len())].to_vec();
+            window.sort_unstable();
+            window[window.len() / 2]
+        })
+        .collect())
+}
+
+/// `image` with each channel scaled `strength` of the way toward the ratio
+/// of the new sky's mean to the old sky's, where `matte` marks sky.
+fn harmonized(image: &RgbaImage, sky: &RgbaImage, matte: &GrayImage, strength: f32) -> RgbaImage {
+    let mut sums = [[0.0f64; 3]; 2];
+    let mut count = 0u64;
+    for ((old, new), weight) in image.pixels().zip(sky.pixels()).zip(matte.pixels()) {
+        if weight.0[0] >= 128 {
+            count += 1;
+            for (sums, pixel) in sums.iter_mut().zip([old, new]) {
+                for (sum, &value) in sums.iter_mut().zip(&pixel.0) {
+                    *sum += f64::from(value);
+                }
+            }
+        }
+    }
+    if count == 0 || strength == 0.0 {
+        return image.clone();
+    }
+    let gains: [f32; 3] = std::array::from_fn(|channel| {
+        let ratio = ((sums[1][channel] + 1.0) / (sums[0][channel] + 1.0)) as f32;
+        (1.0 + strength * (ratio - 1.0)).clamp(0.5, 2.0)
+    });
+    log::debug!("harmonizing with gains {:.3?}", gains);
+    let mut image = image.clone();
+    for pixel in image.pixels_mut() {
+        for (channel, gain) in pixel.0.iter_mut().zip(gains) {
+            *channel = (f32::from(*channel) * gain).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    image
+}
@@ -0,0 +1,119 @@
+//! Date stamps: the capture time, and where the picture was taken, printed
+//! in a corner in the orange of a film camera's date back.
+
+use std::fmt;
+
+use image::{imageops, GrayImage, Luma, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::canvas::blend_over;
+use crate::exif::Exif;
+use crate::geometry::Gravity;
+use crate::text::{draw_text, text_width};
+use crate::ImageDataErrors;
+
+/// The orange of the LEDs film cameras expose the date with.
+const COLOUR: [u8; 3] = [255, 138, 36];
+
+/// What a line of the stamp shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Stamp {
+    /// The capture time from Exif, written by [`CombineOptions::stamp_format`](crate::options::CombineOptions::stamp_format).
+    ExifDatetime,
+    /// The latitude and longitude from Exif, in degrees.
+    ExifGps,
+}
+
+impl Stamp {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        match value {
+            "exif-datetime" => Ok(Stamp::ExifDatetime),
+            "exif-gps" => Ok(Stamp::ExifGps),
+            _ => Err(ImageDataErrors::InvalidArgument(format!(
+                "invalid stamp `{}`, expected exif-datetime or exif-gps",
+                value
+            ))),
+        }
+    }
+
+    /// The line this stamp shows for `exif`, or `None` when it lacks the data.
+    pub fn text(self, exif: &Exif, format: &str) -> Option<String> {
+        match self {
+            Stamp::ExifDatetime => exif.taken.map(|taken| taken.format(format)),
+            Stamp::ExifGps => exif.gps.map(|(latitude, longitude)| {
+                let hemisphere = |value: f64, positive: char, negative: char| if value < 0.0 { negative } else { positive };
+                format!(
+                    "{:.4}{} {:.4}{}",
+                    latitude.abs(),
+                    hemisphere(latitude, 'N', 'S'),
+                    longitude.abs(),
+                    hemisphere(longitude, 'E', 'W')
+                )
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Stamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Stamp::ExifDatetime => "exif-datetime",
+            Stamp::ExifGps => "exif-gps",
+        })
+    }
+}
+
+impl TryFrom<String> for Stamp {
+    type Error = ImageDataErrors;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Stamp::parse(&value)
+    }
+}
+
+impl From<Stamp> for String {
+    fn from(stamp: Stamp) -> Self {
+        stamp.to_string()
+    }
+}
+
+/// Prints `lines` onto `image` at the `position` corner or edge, with each
+/// letter a thirtieth of the shorter side tall, in orange with a faint glow
+/// as the LEDs of a date back leave on film.
+pub(crate) fn draw(image: &mut RgbaImage, lines: &[String], position: Gravity) {
+    let (width, height) = image.dimensions();
+    let scale = (width.min(height) / 30 / 8).max(1);
+    let margin = width.min(height) / 20;
+    let block_width = lines.iter().map(|line| text_width(line, scale)).max().unwrap_or(0);
+    let block_height = (lines.len() as u32 * 10 - 2) * scale;
+    let (anchor_x, anchor_y) = position.anchor();
+    let place = |extent: u32, size: u32, anchor: i8| -> i64 {
+        match anchor {
+            -1 => i64::from(margin),
+            0 => (i64::from(extent) - i64::from(size)) / 2,
+            _ => i64::from(extent) - i64::from(size) - i64::from(margin),
+        }
+    };
+    let (left, top) = (place(width, block_width, anchor_x), place(height, block_height, anchor_y));
+    log::debug!("stamping {} lines at {},{}", lines.len(), left, top);
+
+    let mut letters = RgbaImage::new(width, height);
+    for (index, line) in lines.iter().enumerate() {
+        // Right-aligned at the right, centred in the middle, as date backs are.
+        let shift = match anchor_x {
+            -1 => 0,
+            0 => i64::from(block_width - text_width(line, scale)) / 2,
+            _ => i64::from(block_width - text_width(line, scale)),
+        };
+        draw_text(&mut letters, line, left + shift, top + i64::from(index as u32 * 10 * scale), scale, [255, 255, 255, 255]);
+    }
+    let mask = GrayImage::from_fn(width, height, |x, y| Luma([letters.get_pixel(x, y).0[3]]));
+    let glow = imageops::blur(&mask, scale as f32);
+    for ((pixel, coverage), glow) in image.pixels_mut().zip(mask.pixels()).zip(glow.pixels()) {
+        let alpha = coverage.0[0].max(glow.0[0] / 2);
+        if alpha > 0 {
+            blend_over(&mut pixel.0, [COLOUR[0], COLOUR[1], COLOUR[2], alpha]);
+        }
+    }
+}
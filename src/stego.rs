@@ -0,0 +1,108 @@
+use image::{ImageFormat, RgbaImage};
+
+use crate::pnm::{PnmEncoding, PnmKind};
+use crate::ImageDataErrors;
+
+/// Marks images carrying a message, so extracting from any other image fails
+/// cleanly instead of returning noise.
+const MAGIC: &[u8; 4] = b"CMBs";
+/// The magic and the payload's length as a big-endian `u32`.
+const HEADER_LEN: usize = MAGIC.len() + 4;
+
+/// How many payload bytes `image` can hide: one bit in the lowest bit of each
+/// red, green and blue sample, less the header.
+pub fn capacity(image: &RgbaImage) -> usize {
+    (image.width() as usize * image.height() as usize * 3 / 8).saturating_sub(HEADER_LEN)
+}
+
+/// Hides `payload` in the least significant bits of the colour samples of
+/// `image`. Alpha is left alone, as encoders may discard the colour of
+/// fully transparent pixels either way.
+pub fn embed(image: &mut RgbaImage, payload: &[u8]) -> Result<(), ImageDataErrors> {
+    let capacity = capacity(image);
+    if payload.len() > capacity || u32::try_from(payload.len()).is_err() {
+        return Err(ImageDataErrors::MessageTooLarge { capacity, needed: payload.len() });
+    }
+    let mut message = Vec::with_capacity(HEADER_LEN + payload.len());
+    message.extend_from_slice(MAGIC);
+    message.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    message.extend_from_slice(payload);
+
+    let bits = message.iter().flat_map(|byte| (0..8).rev().map(move |bit| (byte >> bit) & 1));
+    for (sample, bit) in colour_samples(image).zip(bits) {
+        *sample = (*sample & !1) | bit;
+    }
+    Ok(())
+}
+
+/// Recovers a payload hidden by [`embed`].
+pub fn extract(image: &RgbaImage) -> Result<Vec<u8>, ImageDataErrors> {
+    let mut samples = image.pixels().flat_map(|pixel| pixel.0[..3].to_vec());
+    let mut read = |len: usize| -> Option<Vec<u8>> {
+        (0..len).map(|_| (0..8).try_fold(0u8, |byte, _| Some(byte << 1 | (samples.next()? & 1)))).collect()
+    };
+    let header = read(HEADER_LEN).ok_or(ImageDataErrors::NoHiddenMessage)?;
+    if &header[..MAGIC.len()] != MAGIC {
+        return Err(ImageDataErrors::NoHiddenMessage);
+    }
+    let len = u32::from_be_bytes(header[MAGIC.len()..].try_into().expect("four length bytes")) as usize;
+    if len > capacity(image) {
+        return Err(ImageDataErrors::NoHiddenMessage);
+    }
+    read(len).ok_or(ImageDataErrors::NoHiddenMessage)
+}
+
+/// Whether an output in `format` named `name` keeps every bit of the colour
+/// samples, which a hidden message needs to survive.
+pub fn keeps_samples(format: ImageFormat, name: &str) -> bool {
+    match format {
+        ImageFormat::Png | ImageFormat::Bmp | ImageFormat::Tiff | ImageFormat::Tga | ImageFormat::Farbfeld => true,
+        ImageFormat::Pnm => matches!(PnmEncoding::for_path(name).kind, PnmKind::Pixmap | PnmKind::ArbitraryMap),
+        _ => false,
+    }
+}
+
+fn colour_samples(image: &mut RgbaImage) -> impl Iterator<Item = &mut u8> {
+    image.pixels_mut().flat_map(|pixel| pixel.0[..3].iter_mut())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| image::Rgba([(x * 31) as u8, (y * 17) as u8, (x ^ y) as u8, 200]))
+    }
+
+    #[test]
+    fn extracts_what_was_embedded() {
+        let mut carrier = image(32, 32);
+        let original = carrier.clone();
+        embed(&mut carrier, "hidden in plain sight".as_bytes()).unwrap();
+        assert_eq!(extract(&carrier).unwrap(), b"hidden in plain sight");
+        for (marked, plain) in carrier.pixels().zip(original.pixels()) {
+            assert!(marked.0.iter().zip(plain.0).all(|(a, b)| a.abs_diff(b) <= 1));
+            assert_eq!(marked.0[3], plain.0[3]);
+        }
+    }
+
+    #[test]
+    fn fills_to_capacity_and_no_further() {
+        let mut carrier = image(16, 16);
+        let capacity = capacity(&carrier);
+        assert_eq!(capacity, 16 * 16 * 3 / 8 - HEADER_LEN);
+        let payload: Vec<u8> = (0..capacity as u8).collect();
+        embed(&mut carrier, &payload).unwrap();
+        assert_eq!(extract(&carrier).unwrap(), payload);
+        assert!(matches!(
+            embed(&mut carrier, &[0; 89]),
+            Err(ImageDataErrors::MessageTooLarge { capacity: 88, needed: 89 })
+        ));
+    }
+
+    #[test]
+    fn finds_nothing_in_plain_images() {
+        assert!(matches!(extract(&image(32, 32)), Err(ImageDataErrors::NoHiddenMessage)));
+        assert!(matches!(extract(&image(2, 2)), Err(ImageDataErrors::NoHiddenMessage)));
+    }
+}
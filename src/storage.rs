@@ -0,0 +1,118 @@
+use std::path::Path;
+
+use combiner::ImageStore;
+
+use crate::{archive, ImageDataErrors};
+
+/// URI schemes read from and written to object storage instead of the filesystem.
+const REMOTE_SCHEMES: &[&str] = &["s3://", "gs://"];
+
+/// Whether `path` is an object storage URI such as `s3://bucket/key`.
+pub fn is_remote(path: &str) -> bool {
+    REMOTE_SCHEMES.iter().any(|scheme| path.starts_with(scheme))
+}
+
+/// Whether `path` names a plain file, rather than an object storage URI or
+/// an `archive.zip!entry` path that has to be read through [`Storage`].
+pub fn is_file(path: &str) -> bool {
+    !is_remote(path) && archive::split_entry(path).is_none()
+}
+
+/// The command line's [`ImageStore`]: plain files, object storage URIs and
+/// entries of zip archives.
+pub struct Storage;
+
+impl ImageStore for Storage {
+    /// Reads the whole file, object or archive entry into memory.
+    fn read(&self, path: &str) -> Result<Vec<u8>, ImageDataErrors> {
+        if let Some((archive, entry)) = archive::split_entry(path) {
+            return archive::read_entry(archive, entry);
+        }
+        if !is_remote(path) {
+            return std::fs::read(path).map_err(ImageDataErrors::UnableToReadImageFromPath);
+        }
+        backend::read(path).map_err(|e| ImageDataErrors::UnableToReadObject(format!("{}: {}", path, e)))
+    }
+
+    /// Writes `bytes` to the file or object at `path`, replacing what was there.
+    fn write(&self, path: &str, bytes: Vec<u8>) -> Result<(), ImageDataErrors> {
+        if archive::split_entry(path).is_some() {
+            return Err(ImageDataErrors::UnableToWriteArchive(format!("{}: use `--zip-output` to write archives", path)));
+        }
+        if !is_remote(path) {
+            if let Some(parent) = Path::new(path).parent() {
+                std::fs::create_dir_all(parent).map_err(ImageDataErrors::UnableToCreateDirectory)?;
+            }
+            return std::fs::write(path, bytes).map_err(|e| ImageDataErrors::UnableToSaveImage(image::ImageError::IoError(e)));
+        }
+        backend::write(path, bytes).map_err(|e| ImageDataErrors::UnableToWriteObject(format!("{}: {}", path, e)))
+    }
+}
+
+/// Resolves `file` against the directory holding `base`, which may itself be
+/// a URI, so relative paths in a remote manifest stay in its bucket.
+pub fn resolve(base: &str, file: &str) -> String {
+    if is_remote(file) || Path::new(file).is_absolute() {
+        return file.to_string();
+    }
+    match base.rsplit_once('/') {
+        Some((dir, _)) if is_remote(base) => format!("{}/{}", dir, file),
+        _ => Path::new(base).parent().unwrap_or_else(|| Path::new("")).join(file).to_string_lossy().into_owned(),
+    }
+}
+
+#[cfg(feature = "cloud")]
+mod backend {
+    use std::sync::OnceLock;
+
+    use object_store::aws::AmazonS3Builder;
+    use object_store::gcp::GoogleCloudStorageBuilder;
+    use object_store::path::Path;
+    use object_store::{ObjectStore, PutPayload};
+
+    /// Credentials and regions come from the usual `AWS_*` and `GOOGLE_*`
+    /// environment variables, as the providers' own tools read them.
+    fn store(uri: &str) -> object_store::Result<(Box<dyn ObjectStore>, Path)> {
+        let (scheme, rest) = uri.split_once("://").unwrap_or(("", uri));
+        let key = rest.split_once('/').map_or("", |(_, key)| key);
+        let store: Box<dyn ObjectStore> = match scheme {
+            "gs" => Box::new(GoogleCloudStorageBuilder::from_env().with_url(uri).build()?),
+            _ => Box::new(AmazonS3Builder::from_env().with_url(uri).build()?),
+        };
+        Ok((store, Path::from(key)))
+    }
+
+    /// Object storage clients are async; one runtime serves every call.
+    fn runtime() -> &'static tokio::runtime::Runtime {
+        static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+        RUNTIME.get_or_init(|| {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("the storage runtime starts")
+        })
+    }
+
+    pub fn read(uri: &str) -> object_store::Result<Vec<u8>> {
+        let (store, path) = store(uri)?;
+        runtime().block_on(async { Ok(store.get(&path).await?.bytes().await?.to_vec()) })
+    }
+
+    pub fn write(uri: &str, bytes: Vec<u8>) -> object_store::Result<()> {
+        let (store, path) = store(uri)?;
+        runtime().block_on(store.put(&path, PutPayload::from(bytes))).map(|_| ())
+    }
+}
+
+#[cfg(not(feature = "cloud"))]
+mod backend {
+    const MISSING_FEATURE: &str = "object storage needs a build with the `cloud` feature";
+
+    pub fn read(_uri: &str) -> Result<Vec<u8>, &'static str> {
+        Err(MISSING_FEATURE)
+    }
+
+    pub fn write(_uri: &str, _bytes: Vec<u8>) -> Result<(), &'static str> {
+        Err(MISSING_FEATURE)
+    }
+}
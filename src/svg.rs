@@ -0,0 +1,85 @@
+//! Rasterising SVG inputs with resvg, behind the `svg` feature, so drawings
+//! such as logos can be combined with photos directly.
+
+use image::DynamicImage;
+
+use crate::ImageDataErrors;
+
+/// How large an SVG is rasterised.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SvgOptions {
+    /// Dots per inch; 96 draws one image pixel per SVG pixel.
+    pub dpi: f32,
+    /// A box the drawing is scaled to fit, keeping its aspect ratio, in place of the DPI.
+    pub fit: Option<(u32, u32)>,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        SvgOptions { dpi: 96.0, fit: None }
+    }
+}
+
+/// Whether a path names an SVG drawing, plain or gzipped.
+pub fn is_svg(path: &str) -> bool {
+    let extension = std::path::Path::new(path).extension().and_then(|extension| extension.to_str());
+    extension.is_some_and(|extension| extension.eq_ignore_ascii_case("svg") || extension.eq_ignore_ascii_case("svgz"))
+}
+
+#[cfg(all(feature = "svg", not(target_arch = "wasm32")))]
+pub fn rasterize(bytes: &[u8], options: &SvgOptions) -> Result<DynamicImage, ImageDataErrors> {
+    use std::sync::{Arc, OnceLock};
+
+    use image::error::{DecodingError, ImageFormatHint};
+    use image::{ImageError, RgbaImage};
+    use resvg::{tiny_skia, usvg};
+
+    // Loading the system fonts takes a while, so every drawing shares them.
+    static FONTS: OnceLock<Arc<usvg::fontdb::Database>> = OnceLock::new();
+
+    let invalid = |message: String| {
+        ImageDataErrors::UnableToDecodeImage(ImageError::Decoding(DecodingError::new(
+            ImageFormatHint::Name("SVG".to_string()),
+            message,
+        )))
+    };
+
+    let parse_options = usvg::Options {
+        fontdb: FONTS
+            .get_or_init(|| {
+                let mut fonts = usvg::fontdb::Database::new();
+                fonts.load_system_fonts();
+                Arc::new(fonts)
+            })
+            .clone(),
+        ..usvg::Options::default()
+    };
+    let tree = usvg::Tree::from_data(bytes, &parse_options).map_err(|e| invalid(e.to_string()))?;
+
+    let size = tree.size();
+    let scale = match options.fit {
+        Some((width, height)) => (width as f32 / size.width()).min(height as f32 / size.height()),
+        None => options.dpi / 96.0,
+    };
+    let width = (size.width() * scale).round().max(1.0) as u32;
+    let height = (size.height() * scale).round().max(1.0) as u32;
+    let mut pixmap = tiny_skia::Pixmap::new(width, height)
+        .ok_or_else(|| invalid(format!("cannot rasterise at {}x{}", width, height)))?;
+    resvg::render(&tree, tiny_skia::Transform::from_scale(scale, scale), &mut pixmap.as_mut());
+
+    // tiny-skia keeps premultiplied alpha; images here are straight.
+    let data = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let pixel = pixel.demultiply();
+            [pixel.red(), pixel.green(), pixel.blue(), pixel.alpha()]
+        })
+        .collect();
+    Ok(DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, data).expect("the pixmap matches its size")))
+}
+
+#[cfg(not(all(feature = "svg", not(target_arch = "wasm32"))))]
+pub fn rasterize(_bytes: &[u8], _options: &SvgOptions) -> Result<DynamicImage, ImageDataErrors> {
+    Err(ImageDataErrors::InvalidArgument("SVG inputs need a build with the `svg` feature".to_string()))
+}
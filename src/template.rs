@@ -0,0 +1,210 @@
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::ImageDataErrors;
+
+const TOKENS: &[&str] = &["stem1", "stem2", "ext", "mode", "date", "width", "height", "index"];
+
+/// An output file name such as `{stem1}_{stem2}_{mode}.{ext}`, checked for
+/// unknown tokens when parsed.
+#[derive(Debug, Clone)]
+pub struct NameTemplate(String);
+
+/// What the tokens of a template expand to for one job.
+pub struct TemplateValues<'a> {
+    pub image_1: &'a str,
+    pub image_2: &'a str,
+    pub mode: &'a str,
+    pub width: u32,
+    pub height: u32,
+    pub index: usize,
+}
+
+impl NameTemplate {
+    pub fn parse(template: &str) -> Result<Self, ImageDataErrors> {
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| ImageDataErrors::InvalidArgument(format!("unclosed `{{` in name template `{}`", template)))?;
+            let token = &rest[start + 1..start + end];
+            if !TOKENS.contains(&token) {
+                return Err(ImageDataErrors::InvalidArgument(format!(
+                    "unknown token `{{{}}}` in name template, expected one of {}",
+                    token,
+                    TOKENS.join(", ")
+                )));
+            }
+            rest = &rest[start + end + 1..];
+        }
+        Ok(NameTemplate(template.to_string()))
+    }
+
+    /// Whether rendered names depend on the day they are rendered on.
+    pub fn uses_date(&self) -> bool {
+        self.0.contains("{date}")
+    }
+
+    /// The name for `values`, with every token substituted in one pass, so
+    /// text substituted for one token is never taken for another.
+    pub fn render(&self, values: &TemplateValues) -> String {
+        let stem = |path: &str| Path::new(path).file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        let mut name = String::new();
+        let mut rest = self.0.as_str();
+        while let Some(start) = rest.find('{') {
+            let end = start + rest[start..].find('}').expect("templates are checked when parsed");
+            name.push_str(&rest[..start]);
+            match &rest[start + 1..end] {
+                "stem1" => name.push_str(&stem(values.image_1)),
+                "stem2" => name.push_str(&stem(values.image_2)),
+                "ext" => name.push_str(&Path::new(values.image_1).extension().unwrap_or_default().to_string_lossy()),
+                "mode" => name.push_str(values.mode),
+                "date" => name.push_str(&today()),
+                "width" => name.push_str(&values.width.to_string()),
+                "height" => name.push_str(&values.height.to_string()),
+                "index" => name.push_str(&values.index.to_string()),
+                token => unreachable!("unknown token `{}` in a checked template", token),
+            }
+            rest = &rest[end + 1..];
+        }
+        name.push_str(rest);
+        name
+    }
+}
+
+/// What batch outputs are named after in place of their input's file name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameBy {
+    /// The earlier of the two inputs' Exif capture times.
+    ExifDate,
+}
+
+impl NameBy {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        match value {
+            "exif-date" => Ok(NameBy::ExifDate),
+            _ => Err(ImageDataErrors::InvalidArgument(format!("invalid name source `{}`, expected exif-date", value))),
+        }
+    }
+}
+
+/// The current UTC date as `YYYY-MM-DD`.
+fn today() -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs() / 86_400) as i64;
+
+    // Days since the epoch to a civil date, after Howard Hinnant's `civil_from_days`.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// A file name for numbered frames such as `frame_%03d.png`, with one
+/// printf-style `%d` placeholder, optionally zero-padded to a width.
+#[derive(Debug, Clone)]
+pub struct FramePattern {
+    prefix: String,
+    width: usize,
+    suffix: String,
+}
+
+impl FramePattern {
+    pub fn parse(pattern: &str) -> Result<Self, ImageDataErrors> {
+        let invalid = || {
+            ImageDataErrors::InvalidArgument(format!("frame pattern `{}` needs one placeholder such as %03d", pattern))
+        };
+        let (prefix, rest) = pattern.split_once('%').ok_or_else(invalid)?;
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let suffix = rest[digits..].strip_prefix('d').ok_or_else(invalid)?;
+        if suffix.contains('%') {
+            return Err(invalid());
+        }
+        // `%3d` pads with spaces in printf, which makes for awkward file names; pad with zeros either way.
+        let width = rest[..digits].parse().unwrap_or(0);
+        Ok(FramePattern { prefix: prefix.to_string(), width, suffix: suffix.to_string() })
+    }
+
+    pub fn render(&self, number: usize) -> String {
+        format!("{}{:0width$}{}", self.prefix, number, self.suffix, width = self.width)
+    }
+}
+
+/// A tile file name such as `tile_{x}_{y}.png`, with one `{x}` and one
+/// `{y}` placeholder, in either order, for the tile's column and row.
+#[derive(Debug, Clone)]
+pub struct TilePattern {
+    /// The text before, between and after the placeholders.
+    parts: [String; 3],
+    /// Whether `{y}` comes before `{x}`.
+    y_first: bool,
+}
+
+impl TilePattern {
+    pub fn parse(pattern: &str) -> Result<Self, ImageDataErrors> {
+        let invalid = || {
+            ImageDataErrors::InvalidArgument(format!("tile pattern `{}` needs one {{x}} and one {{y}}", pattern))
+        };
+        let (x, y) = (pattern.find("{x}").ok_or_else(invalid)?, pattern.find("{y}").ok_or_else(invalid)?);
+        if pattern.matches("{x}").count() > 1 || pattern.matches("{y}").count() > 1 {
+            return Err(invalid());
+        }
+        let (first, second) = (x.min(y), x.max(y));
+        if second < first + 3 {
+            return Err(invalid());
+        }
+        let parts = [&pattern[..first], &pattern[first + 3..second], &pattern[second + 3..]].map(str::to_string);
+        Ok(TilePattern { parts, y_first: y < x })
+    }
+
+    /// The column and row a file named `name` holds, if it matches.
+    pub fn matches(&self, name: &str) -> Option<(u32, u32)> {
+        let number = |text: &str| {
+            let digits = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+            Some((text[..digits].parse::<u32>().ok()?, digits))
+        };
+        let rest = name.strip_prefix(self.parts[0].as_str())?;
+        let (first, digits) = number(rest)?;
+        let rest = rest[digits..].strip_prefix(self.parts[1].as_str())?;
+        let (second, digits) = number(rest)?;
+        if rest[digits..] != self.parts[2] {
+            return None;
+        }
+        Some(if self.y_first { (second, first) } else { (first, second) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values<'a>(image_1: &'a str, image_2: &'a str) -> TemplateValues<'a> {
+        TemplateValues { image_1, image_2, mode: "diff", width: 640, height: 480, index: 7 }
+    }
+
+    #[test]
+    fn renders_every_token() {
+        let template = NameTemplate::parse("{stem1}_{stem2}_{mode}_{width}x{height}_{index}.{ext}").unwrap();
+        assert_eq!(template.render(&values("in/cat.jpg", "dog.png")), "cat_dog_diff_640x480_7.jpg");
+        let template = NameTemplate::parse("{date}.png").unwrap();
+        assert_eq!(template.render(&values("a.png", "b.png")), format!("{}.png", today()));
+    }
+
+    #[test]
+    fn substitutes_in_one_pass() {
+        let template = NameTemplate::parse("{stem1}-{mode}.png").unwrap();
+        assert_eq!(template.render(&values("{mode}.png", "{stem1}.png")), "{mode}-diff.png");
+    }
+
+    #[test]
+    fn rejects_unknown_tokens() {
+        assert!(NameTemplate::parse("{stem}.png").is_err());
+        assert!(NameTemplate::parse("{stem1.png").is_err());
+    }
+}
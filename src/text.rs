@@ -0,0 +1,34 @@
+use font8x8::legacy::BASIC_LEGACY;
+use image::RgbaImage;
+
+use crate::canvas::blend_over;
+
+/// How wide `text` is when drawn at `scale`, in pixels.
+pub(crate) fn text_width(text: &str, scale: u32) -> u32 {
+    text.chars().count() as u32 * 8 * scale
+}
+
+/// Draws `text` with its top-left corner at `(left, top)` in an 8x8 pixel
+/// font, each font pixel `scale` wide. Characters outside ASCII show as `?`,
+/// and whatever falls outside the image is left out.
+pub(crate) fn draw_text(image: &mut RgbaImage, text: &str, left: i64, top: i64, scale: u32, colour: [u8; 4]) {
+    let (width, height) = (image.width() as i64, image.height() as i64);
+    let scale = scale as i64;
+    for (index, character) in text.chars().enumerate() {
+        let glyph = BASIC_LEGACY[if character.is_ascii() { character as usize } else { '?' as usize }];
+        let glyph_left = left + index as i64 * 8 * scale;
+        for (row, bits) in glyph.iter().enumerate() {
+            // The lowest bit is the leftmost pixel.
+            for column in (0..8).filter(|column| bits & (1 << column) != 0) {
+                for y in 0..scale {
+                    for x in 0..scale {
+                        let (x, y) = (glyph_left + column * scale + x, top + row as i64 * scale + y);
+                        if (0..width).contains(&x) && (0..height).contains(&y) {
+                            blend_over(&mut image.get_pixel_mut(x as u32, y as u32).0, colour);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
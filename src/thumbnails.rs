@@ -0,0 +1,127 @@
+//! A thumbnail cache on disk for previews, so browsing a folder a second
+//! time shows its images at once instead of decoding each again. Entries are
+//! keyed by the image's path, modification time and size, and the thumbnail
+//! size, so edited files are never shown stale; the least recently used are
+//! evicted once the cache outgrows its capacity.
+
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use image::imageops::FilterType;
+use image::RgbaImage;
+use sha2::{Digest, Sha256};
+
+use crate::ImageDataErrors;
+
+/// Thumbnails of images, kept as PNGs in a directory of their own.
+#[derive(Debug, Clone)]
+pub struct ThumbnailCache {
+    dir: PathBuf,
+    capacity: u64,
+}
+
+impl ThumbnailCache {
+    /// How many bytes of thumbnails are kept unless [`with_capacity`](Self::with_capacity) says otherwise.
+    pub const DEFAULT_CAPACITY: u64 = 256 * 1024 * 1024;
+
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        ThumbnailCache { dir: dir.into(), capacity: Self::DEFAULT_CAPACITY }
+    }
+
+    /// The cache in the user's cache directory: `$XDG_CACHE_HOME`, or
+    /// `~/.cache`, or `%LOCALAPPDATA%` on Windows. `None` when none is set.
+    pub fn in_user_cache() -> Option<Self> {
+        let base = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+            .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))?;
+        Some(ThumbnailCache::new(base.join("combiner").join("thumbnails")))
+    }
+
+    /// Keeps at most `bytes` of thumbnails, evicting the least recently used.
+    pub fn with_capacity(mut self, bytes: u64) -> Self {
+        self.capacity = bytes;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// A thumbnail of the image at `path` that fits in `size`x`size`, keeping
+    /// its aspect ratio. Cached thumbnails are read back; others are made,
+    /// decoding JPEGs at a reduced scale, and stored. Failing to store one is
+    /// only logged, since the thumbnail itself is still good.
+    pub fn get(&self, path: &str, size: u32) -> Result<RgbaImage, ImageDataErrors> {
+        let metadata = std::fs::metadata(path).map_err(ImageDataErrors::UnableToReadImageFromPath)?;
+        let entry = self.dir.join(format!("{}.png", key(path, &metadata, size)));
+        if let Ok(cached) = image::open(&entry) {
+            log::debug!("reusing the thumbnail of {}", path);
+            // Marks it as recently used.
+            if let Err(e) = std::fs::File::options().append(true).open(&entry).and_then(|file| file.set_modified(SystemTime::now())) {
+                log::debug!("cannot touch {}: {}", entry.display(), e);
+            }
+            return Ok(cached.to_rgba8());
+        }
+
+        let bytes = std::fs::read(path).map_err(ImageDataErrors::UnableToReadImageFromPath)?;
+        let (image, _) = crate::decode_image_bytes_covering(&bytes, (size, size))?;
+        let thumbnail = image.resize(size, size, FilterType::Triangle).to_rgba8();
+        if let Err(e) = self.store(&entry, &thumbnail) {
+            log::warn!("cannot cache the thumbnail of {}: {}", path, e);
+        }
+        Ok(thumbnail)
+    }
+
+    /// Removes every cached thumbnail.
+    pub fn clear(&self) -> std::io::Result<()> {
+        match std::fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn store(&self, entry: &Path, thumbnail: &RgbaImage) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        // Written aside and renamed into place, so readers never see half a file.
+        let partial = entry.with_extension("partial");
+        thumbnail.save_with_format(&partial, image::ImageFormat::Png).map_err(std::io::Error::other)?;
+        std::fs::rename(&partial, entry)?;
+        self.evict()
+    }
+
+    /// Deletes the least recently used thumbnails until the rest fit in the capacity.
+    fn evict(&self) -> std::io::Result<()> {
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_file() && entry.path().extension().is_some_and(|extension| extension == "png") {
+                entries.push((metadata.modified().unwrap_or(UNIX_EPOCH), metadata.len(), entry.path()));
+            }
+        }
+        let mut total: u64 = entries.iter().map(|(_, len, _)| len).sum();
+        entries.sort();
+        for (_, len, path) in entries {
+            if total <= self.capacity {
+                break;
+            }
+            std::fs::remove_file(&path)?;
+            total -= len;
+        }
+        Ok(())
+    }
+}
+
+/// The name of the thumbnail of `path` at `size`: a hash of where the image
+/// is, when it was last modified, how large it is and the thumbnail size.
+fn key(path: &str, metadata: &std::fs::Metadata, size: u32) -> String {
+    let absolute = std::fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    let modified = metadata.modified().ok().and_then(|time| time.duration_since(UNIX_EPOCH).ok()).unwrap_or_default();
+    let mut hasher = Sha256::new();
+    hasher.update(absolute.to_string_lossy().as_bytes());
+    hasher.update(modified.as_nanos().to_le_bytes());
+    hasher.update(metadata.len().to_le_bytes());
+    hasher.update(size.to_le_bytes());
+    hasher.finalize()[..16].iter().map(|byte| format!("{:02x}", byte)).collect()
+}
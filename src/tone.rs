@@ -0,0 +1,210 @@
+//! Levels and curves: tone adjustments that both come down to a 1D lookup
+//! table, mapping each 8-bit value of the red, green and blue channels alike
+//! to another. Alpha is left alone.
+
+use std::fmt;
+
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+
+use crate::ImageDataErrors;
+
+/// A 1D lookup table of the output value for every 8-bit input value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToneTable([u8; 256]);
+
+impl ToneTable {
+    pub fn identity() -> Self {
+        ToneTable(std::array::from_fn(|value| value as u8))
+    }
+
+    /// The table mapping `value` to `map(value)`, both from 0 to 1.
+    fn from_fn(map: impl Fn(f32) -> f32) -> Self {
+        ToneTable(std::array::from_fn(|value| (map(value as f32 / 255.0).clamp(0.0, 1.0) * 255.0).round() as u8))
+    }
+
+    /// This table followed by `next`.
+    pub fn then(&self, next: &ToneTable) -> Self {
+        ToneTable(self.0.map(|value| next.0[usize::from(value)]))
+    }
+
+    /// Maps the colour channels of RGBA `pixels` in place.
+    pub fn apply(&self, pixels: &mut [u8]) {
+        for pixel in pixels.chunks_exact_mut(4) {
+            for channel in &mut pixel[..3] {
+                *channel = self.0[usize::from(*channel)];
+            }
+        }
+    }
+}
+
+/// `black,white[,gamma]`, as `10,245` or `10,245,1.2`: values up to `black`
+/// become black, values from `white` become white, and those between are
+/// stretched across the range with the midtones raised by `gamma` above 1.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Levels {
+    pub black: u8,
+    pub white: u8,
+    pub gamma: f32,
+}
+
+impl Levels {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        let invalid = || ImageDataErrors::InvalidArgument(format!("invalid levels `{}`, expected e.g. 10,245 or 10,245,1.2", value));
+        let parts: Vec<&str> = value.split(',').map(str::trim).collect();
+        let (black, white, gamma) = match parts[..] {
+            [black, white] => (black, white, "1"),
+            [black, white, gamma] => (black, white, gamma),
+            _ => return Err(invalid()),
+        };
+        let levels = Levels {
+            black: black.parse().map_err(|_| invalid())?,
+            white: white.parse().map_err(|_| invalid())?,
+            gamma: gamma.parse().map_err(|_| invalid())?,
+        };
+        if levels.black >= levels.white || !(levels.gamma > 0.0 && levels.gamma.is_finite()) {
+            return Err(invalid());
+        }
+        Ok(levels)
+    }
+
+    pub fn table(&self) -> ToneTable {
+        let (black, white) = (f32::from(self.black) / 255.0, f32::from(self.white) / 255.0);
+        ToneTable::from_fn(|value| ((value - black) / (white - black)).clamp(0.0, 1.0).powf(1.0 / self.gamma))
+    }
+}
+
+impl fmt::Display for Levels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{},{}", self.black, self.white, self.gamma)
+    }
+}
+
+impl TryFrom<String> for Levels {
+    type Error = ImageDataErrors;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Levels::parse(&value)
+    }
+}
+
+impl From<Levels> for String {
+    fn from(levels: Levels) -> Self {
+        levels.to_string()
+    }
+}
+
+/// A tone curve through `input,output` points, written as
+/// `0,0 128,150 255,255`. Between points it is a monotone cubic, so it never
+/// overshoots them; before the first and after the last it stays level.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Curve {
+    points: Vec<(u8, u8)>,
+}
+
+impl Curve {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        let invalid = |reason: &str| ImageDataErrors::InvalidArgument(format!("invalid curve `{}`: {}", value, reason));
+        let points = value
+            .split_whitespace()
+            .map(|point| {
+                let (input, output) = point.split_once(',').ok_or_else(|| invalid("points are written as input,output"))?;
+                let level = |level: &str| level.parse::<u8>().map_err(|_| invalid("levels go from 0 to 255"));
+                Ok((level(input)?, level(output)?))
+            })
+            .collect::<Result<Vec<_>, ImageDataErrors>>()?;
+        if points.len() < 2 {
+            return Err(invalid("a curve needs at least two points"));
+        }
+        if points.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return Err(invalid("inputs must increase from point to point"));
+        }
+        Ok(Curve { points })
+    }
+
+    /// The curve as a table, by Fritsch-Carlson monotone cubic interpolation.
+    pub fn table(&self) -> ToneTable {
+        let points: Vec<(f32, f32)> = self.points.iter().map(|&(x, y)| (f32::from(x) / 255.0, f32::from(y) / 255.0)).collect();
+        let slopes: Vec<f32> = points.windows(2).map(|pair| (pair[1].1 - pair[0].1) / (pair[1].0 - pair[0].0)).collect();
+        // The tangent at each point: the mean of the slopes around it, or
+        // flat at turning points, then limited so no segment overshoots.
+        let mut tangents: Vec<f32> = (0..points.len())
+            .map(|i| match (i.checked_sub(1).map(|before| slopes[before]), slopes.get(i)) {
+                (Some(before), Some(&after)) if before * after > 0.0 => (before + after) / 2.0,
+                (Some(_), Some(_)) => 0.0,
+                (Some(before), None) => before,
+                (None, Some(&after)) => after,
+                (None, None) => 0.0,
+            })
+            .collect();
+        for (i, &slope) in slopes.iter().enumerate() {
+            if slope == 0.0 {
+                tangents[i] = 0.0;
+                tangents[i + 1] = 0.0;
+                continue;
+            }
+            let (a, b) = (tangents[i] / slope, tangents[i + 1] / slope);
+            let length = a.hypot(b);
+            if length > 3.0 {
+                tangents[i] = 3.0 * a / length * slope;
+                tangents[i + 1] = 3.0 * b / length * slope;
+            }
+        }
+        ToneTable::from_fn(|x| {
+            let (first, last) = (points[0], points[points.len() - 1]);
+            if x <= first.0 {
+                return first.1;
+            }
+            if x >= last.0 {
+                return last.1;
+            }
+            let i = points.windows(2).position(|pair| x < pair[1].0).unwrap_or(points.len() - 2);
+            let ((x0, y0), (x1, y1)) = (points[i], points[i + 1]);
+            let h = x1 - x0;
+            let t = (x - x0) / h;
+            let (t2, t3) = (t * t, t * t * t);
+            (2.0 * t3 - 3.0 * t2 + 1.0) * y0
+                + (t3 - 2.0 * t2 + t) * h * tangents[i]
+                + (-2.0 * t3 + 3.0 * t2) * y1
+                + (t3 - t2) * h * tangents[i + 1]
+        })
+    }
+}
+
+impl fmt::Display for Curve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let points: Vec<String> = self.points.iter().map(|(input, output)| format!("{},{}", input, output)).collect();
+        write!(f, "{}", points.join(" "))
+    }
+}
+
+impl TryFrom<String> for Curve {
+    type Error = ImageDataErrors;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Curve::parse(&value)
+    }
+}
+
+impl From<Curve> for String {
+    fn from(curve: Curve) -> Self {
+        curve.to_string()
+    }
+}
+
+/// `image` with `levels` then `curve` applied, as 8-bit RGBA when either is given.
+pub(crate) fn adjust(image: DynamicImage, levels: Option<Levels>, curve: Option<&Curve>) -> DynamicImage {
+    if levels.is_none() && curve.is_none() {
+        return image;
+    }
+    let table = levels.map_or_else(ToneTable::identity, |levels| levels.table());
+    let table = match curve {
+        Some(curve) => table.then(&curve.table()),
+        None => table,
+    };
+    let mut image = image.into_rgba8();
+    table.apply(&mut image);
+    DynamicImage::ImageRgba8(image)
+}
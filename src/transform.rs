@@ -0,0 +1,97 @@
+use image::{DynamicImage, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::ImageDataErrors;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Flip {
+    Horizontal,
+    Vertical,
+    Both,
+}
+
+impl Flip {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        match value {
+            "horizontal" => Ok(Flip::Horizontal),
+            "vertical" => Ok(Flip::Vertical),
+            "both" => Ok(Flip::Both),
+            _ => Err(ImageDataErrors::InvalidArgument(format!("unknown flip `{}`", value))),
+        }
+    }
+}
+
+/// Rotates `image` clockwise by `degrees` and then flips it. Quarter turns
+/// are exact; other angles are sampled bilinearly onto a transparent canvas
+/// grown to hold the whole rotated image.
+pub(crate) fn orient(image: DynamicImage, degrees: f64, flip: Option<Flip>) -> DynamicImage {
+    let degrees = degrees.rem_euclid(360.0);
+    let image = match degrees {
+        _ if degrees == 0.0 => image,
+        _ if degrees == 90.0 => image.rotate90(),
+        _ if degrees == 180.0 => image.rotate180(),
+        _ if degrees == 270.0 => image.rotate270(),
+        _ => DynamicImage::ImageRgba8(rotate(&image.to_rgba8(), degrees)),
+    };
+    match flip {
+        Some(Flip::Horizontal) => image.fliph(),
+        Some(Flip::Vertical) => image.flipv(),
+        Some(Flip::Both) => image.rotate180(),
+        None => image,
+    }
+}
+
+fn rotate(image: &RgbaImage, degrees: f64) -> RgbaImage {
+    let (width, height) = (image.width() as f64, image.height() as f64);
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let rotated_width = (width * cos.abs() + height * sin.abs()).round().max(1.0);
+    let rotated_height = (width * sin.abs() + height * cos.abs()).round().max(1.0);
+    log::debug!("rotating {}x{} by {} degrees onto {}x{}", width, height, degrees, rotated_width, rotated_height);
+
+    RgbaImage::from_fn(rotated_width as u32, rotated_height as u32, |x, y| {
+        // Map each output pixel centre back into the source.
+        let dx = x as f64 + 0.5 - rotated_width / 2.0;
+        let dy = y as f64 + 0.5 - rotated_height / 2.0;
+        let source_x = dx * cos + dy * sin + width / 2.0 - 0.5;
+        let source_y = -dx * sin + dy * cos + height / 2.0 - 0.5;
+        sample(image, source_x, source_y)
+    })
+}
+
+/// Bilinearly samples `image` at a point, treating everything outside it as
+/// transparent so rotated edges fade out smoothly.
+pub(crate) fn sample(image: &RgbaImage, x: f64, y: f64) -> Rgba<u8> {
+    let (left, top) = (x.floor(), y.floor());
+    let (fx, fy) = (x - left, y - top);
+    let texel = |x: f64, y: f64| -> [f64; 4] {
+        if x < 0.0 || y < 0.0 || x >= image.width() as f64 || y >= image.height() as f64 {
+            return [0.0; 4];
+        }
+        let [red, green, blue, alpha] = image.get_pixel(x as u32, y as u32).0.map(|channel| channel as f64);
+        // Premultiplied, so transparent neighbours do not darken the edges.
+        [red * alpha, green * alpha, blue * alpha, alpha]
+    };
+    let corners = [
+        (texel(left, top), (1.0 - fx) * (1.0 - fy)),
+        (texel(left + 1.0, top), fx * (1.0 - fy)),
+        (texel(left, top + 1.0), (1.0 - fx) * fy),
+        (texel(left + 1.0, top + 1.0), fx * fy),
+    ];
+    let mut mixed = [0.0; 4];
+    for (texel, weight) in corners {
+        for (channel, value) in mixed.iter_mut().zip(texel) {
+            *channel += value * weight;
+        }
+    }
+    let alpha = mixed[3];
+    if alpha <= 0.0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+    Rgba([
+        (mixed[0] / alpha).round() as u8,
+        (mixed[1] / alpha).round() as u8,
+        (mixed[2] / alpha).round() as u8,
+        alpha.round() as u8,
+    ])
+}
@@ -0,0 +1,44 @@
+use image::{DynamicImage, GenericImageView};
+
+/// The box `(x, y, width, height)` left once the margins matching the
+/// top-left pixel are cut away. A transparent corner trims every fully
+/// transparent pixel whatever its colour; an opaque one trims its exact
+/// colour. `None` when there is nothing to trim, or nothing would be left.
+pub fn trim_bounds(width: u32, height: u32, pixel: impl Fn(u32, u32) -> [u8; 4]) -> Option<(u32, u32, u32, u32)> {
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let corner = pixel(0, 0);
+    let is_margin = |x, y| {
+        let pixel = pixel(x, y);
+        if corner[3] == 0 { pixel[3] == 0 } else { pixel == corner }
+    };
+
+    let (mut left, mut top, mut right, mut bottom) = (width, height, 0, 0);
+    for y in 0..height {
+        for x in 0..width {
+            if !is_margin(x, y) {
+                left = left.min(x);
+                top = top.min(y);
+                right = right.max(x);
+                bottom = bottom.max(y);
+            }
+        }
+    }
+    if left > right || (left, top, right, bottom) == (0, 0, width - 1, height - 1) {
+        return None;
+    }
+    Some((left, top, right - left + 1, bottom - top + 1))
+}
+
+/// Crops the margins of a decoded input, keeping its pixel type.
+pub fn trim_image(image: DynamicImage) -> DynamicImage {
+    let (width, height) = image.dimensions();
+    match trim_bounds(width, height, |x, y| image.get_pixel(x, y).0) {
+        Some((x, y, width, height)) => {
+            log::debug!("trimmed an input to {}x{} at {},{}", width, height, x, y);
+            image.crop_imm(x, y, width, height)
+        }
+        None => image,
+    }
+}
@@ -0,0 +1,69 @@
+//! Single frames of videos as inputs, written `video.mp4@00:01:23`, taken
+//! with the `ffmpeg` command behind the `video` feature, so screenshots from
+//! videos can be diffed or composited without extracting them by hand.
+
+use image::DynamicImage;
+
+use crate::ImageDataErrors;
+
+const VIDEO_EXTENSIONS: &[&str] = &["mp4", "m4v", "mov", "mkv", "webm", "avi", "mpg", "mpeg", "ts"];
+
+/// Splits `video.mp4@00:01:23` into the video and the time of the frame in
+/// seconds. The time may be `HH:MM:SS`, `MM:SS` or seconds, each with a
+/// fraction. Paths of other files give `None`.
+pub fn split_timestamp(path: &str) -> Option<(&str, f64)> {
+    let (video, timestamp) = path.rsplit_once('@')?;
+    let extension = std::path::Path::new(video).extension().and_then(|extension| extension.to_str())?;
+    if !VIDEO_EXTENSIONS.iter().any(|known| extension.eq_ignore_ascii_case(known)) {
+        return None;
+    }
+    let mut seconds = 0.0;
+    let parts: Vec<&str> = timestamp.split(':').collect();
+    if parts.len() > 3 {
+        return None;
+    }
+    for (index, part) in parts.iter().enumerate() {
+        // Only the seconds may have a fraction.
+        let value: f64 = if index + 1 == parts.len() { part.parse().ok()? } else { part.parse::<u32>().ok()?.into() };
+        if !(value.is_finite() && value >= 0.0) {
+            return None;
+        }
+        seconds = seconds * 60.0 + value;
+    }
+    Some((video, seconds))
+}
+
+/// Decodes the frame shown `seconds` into `video`, a local path or a URL
+/// ffmpeg can open. The `ffmpeg` on the `PATH` is used unless `FFMPEG` names
+/// another.
+#[cfg(feature = "video")]
+pub fn extract_frame(video: &str, seconds: f64) -> Result<DynamicImage, ImageDataErrors> {
+    use image::error::{DecodingError, ImageFormatHint};
+    use image::ImageError;
+
+    let failed = |message: String| {
+        ImageDataErrors::UnableToDecodeImage(ImageError::Decoding(DecodingError::new(
+            ImageFormatHint::Name("video".to_string()),
+            format!("{}@{}: {}", video, seconds, message),
+        )))
+    };
+    let ffmpeg = std::env::var("FFMPEG").unwrap_or_else(|_| "ffmpeg".to_string());
+    // Seeking before `-i` jumps to the nearest keyframe, then decodes up to the exact time.
+    let output = std::process::Command::new(&ffmpeg)
+        .args(["-nostdin", "-v", "error", "-ss", &format!("{:.3}", seconds), "-i", video])
+        .args(["-frames:v", "1", "-f", "image2pipe", "-c:v", "png", "pipe:1"])
+        .output()
+        .map_err(|e| failed(format!("cannot run {}: {}", ffmpeg, e)))?;
+    if !output.status.success() {
+        return Err(failed(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    if output.stdout.is_empty() {
+        return Err(failed("no frame at that time, which may be past the end".to_string()));
+    }
+    image::load_from_memory_with_format(&output.stdout, image::ImageFormat::Png).map_err(ImageDataErrors::UnableToDecodeImage)
+}
+
+#[cfg(not(feature = "video"))]
+pub fn extract_frame(_video: &str, _seconds: f64) -> Result<DynamicImage, ImageDataErrors> {
+    Err(ImageDataErrors::InvalidArgument("video inputs need a build with the `video` feature".to_string()))
+}
@@ -0,0 +1,141 @@
+use std::fmt;
+
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+
+use crate::canvas::blend_over;
+use crate::hooks::Hooks;
+use crate::transform::sample;
+use crate::ImageDataErrors;
+
+/// The four corners an image is warped onto, in the order of its top-left,
+/// top-right, bottom-right and bottom-left corners, written as
+/// `"x1,y1 x2,y2 x3,y3 x4,y4"`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Quad(pub [(f64, f64); 4]);
+
+impl Quad {
+    pub fn parse(value: &str) -> Result<Self, ImageDataErrors> {
+        let corner = |corner: &str| {
+            let (x, y) = corner.split_once(',')?;
+            Some((x.trim().parse().ok()?, y.trim().parse().ok()?))
+        };
+        let corners: Option<Vec<(f64, f64)>> = value.split_whitespace().map(corner).collect();
+        match corners.as_deref() {
+            Some(&[a, b, c, d]) if [a, b, c, d].iter().all(|(x, y): &(f64, f64)| x.is_finite() && y.is_finite()) => {
+                Ok(Quad([a, b, c, d]))
+            }
+            _ => Err(ImageDataErrors::InvalidArgument(format!(
+                "invalid quadrilateral `{}`, expected \"x1,y1 x2,y2 x3,y3 x4,y4\"",
+                value
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Quad {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let corners: Vec<String> = self.0.iter().map(|(x, y)| format!("{},{}", x, y)).collect();
+        write!(f, "{}", corners.join(" "))
+    }
+}
+
+impl TryFrom<String> for Quad {
+    type Error = ImageDataErrors;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Quad::parse(&value)
+    }
+}
+
+impl From<Quad> for String {
+    fn from(quad: Quad) -> Self {
+        quad.to_string()
+    }
+}
+
+/// A projective transform as a row-major 3x3 matrix.
+type Homography = [f64; 9];
+
+/// Maps `image_2` onto `quad` of `image_1` with a perspective transform,
+/// sampling it bilinearly, and composites it over `image_1`.
+pub(crate) fn warp_images(
+    image_1: &DynamicImage,
+    image_2: &DynamicImage,
+    quad: &Quad,
+    hooks: Hooks,
+) -> Result<(u32, u32, Vec<u8>), ImageDataErrors> {
+    let mut output = image_1.to_rgba8();
+    let layer = image_2.to_rgba8();
+    let (width, height) = output.dimensions();
+    let inverse = invert(&square_to_quad(quad)).ok_or_else(|| {
+        ImageDataErrors::InvalidArgument(format!("the quadrilateral `{}` is degenerate", quad))
+    })?;
+
+    // Only the pixels within the quadrilateral's bounding box can be covered.
+    let clamp = |value: f64, limit: u32| value.max(0.0).min(limit as f64) as u32;
+    let xs = quad.0.map(|(x, _)| x);
+    let ys = quad.0.map(|(_, y)| y);
+    let left = clamp(xs.iter().copied().fold(f64::INFINITY, f64::min).floor(), width);
+    let right = clamp(xs.iter().copied().fold(f64::NEG_INFINITY, f64::max).ceil(), width);
+    let top = clamp(ys.iter().copied().fold(f64::INFINITY, f64::min).floor(), height);
+    let bottom = clamp(ys.iter().copied().fold(f64::NEG_INFINITY, f64::max).ceil(), height);
+
+    for y in top..bottom {
+        hooks.step("combining", (y - top) as usize, (bottom - top) as usize)?;
+        for x in left..right {
+            let Some((u, v)) = project(&inverse, x as f64 + 0.5, y as f64 + 0.5) else { continue };
+            if !(-0.01..=1.01).contains(&u) || !(-0.01..=1.01).contains(&v) {
+                continue;
+            }
+            let source = sample(&layer, u * layer.width() as f64 - 0.5, v * layer.height() as f64 - 0.5);
+            blend_over(&mut output.get_pixel_mut(x, y).0, source.0);
+        }
+    }
+    hooks.step("combining", 1, 1)?;
+    Ok((width, height, output.into_raw()))
+}
+
+fn project(matrix: &Homography, x: f64, y: f64) -> Option<(f64, f64)> {
+    let w = matrix[6] * x + matrix[7] * y + matrix[8];
+    if w.abs() < f64::EPSILON {
+        return None;
+    }
+    Some(((matrix[0] * x + matrix[1] * y + matrix[2]) / w, (matrix[3] * x + matrix[4] * y + matrix[5]) / w))
+}
+
+/// The transform taking the unit square's corners onto `quad`'s, in closed
+/// form as derived by Heckbert.
+fn square_to_quad(quad: &Quad) -> Homography {
+    let [(x0, y0), (x1, y1), (x2, y2), (x3, y3)] = quad.0;
+    let (sx, sy) = (x0 - x1 + x2 - x3, y0 - y1 + y2 - y3);
+    if sx.abs() < f64::EPSILON && sy.abs() < f64::EPSILON {
+        // A parallelogram only needs an affine transform.
+        return [x1 - x0, x3 - x0, x0, y1 - y0, y3 - y0, y0, 0.0, 0.0, 1.0];
+    }
+    let (dx1, dx2, dy1, dy2) = (x1 - x2, x3 - x2, y1 - y2, y3 - y2);
+    let denominator = dx1 * dy2 - dx2 * dy1;
+    let g = (sx * dy2 - dx2 * sy) / denominator;
+    let h = (dx1 * sy - sx * dy1) / denominator;
+    [x1 - x0 + g * x1, x3 - x0 + h * x3, x0, y1 - y0 + g * y1, y3 - y0 + h * y3, y0, g, h, 1.0]
+}
+
+fn invert(m: &Homography) -> Option<Homography> {
+    let cofactors = [
+        m[4] * m[8] - m[5] * m[7],
+        m[2] * m[7] - m[1] * m[8],
+        m[1] * m[5] - m[2] * m[4],
+        m[5] * m[6] - m[3] * m[8],
+        m[0] * m[8] - m[2] * m[6],
+        m[2] * m[3] - m[0] * m[5],
+        m[3] * m[7] - m[4] * m[6],
+        m[1] * m[6] - m[0] * m[7],
+        m[0] * m[4] - m[1] * m[3],
+    ];
+    let determinant = m[0] * cofactors[0] + m[1] * cofactors[3] + m[2] * cofactors[6];
+    if !determinant.is_finite() || determinant.abs() < f64::EPSILON {
+        return None;
+    }
+    Some(cofactors.map(|cofactor| cofactor / determinant))
+}
@@ -0,0 +1,49 @@
+use image::ImageFormat;
+use serde::Deserialize;
+use wasm_bindgen::prelude::*;
+
+use crate::options::{CombineOptions, DiffStyle, Mode};
+use crate::{combine_bytes, ImageDataErrors};
+
+/// The options object passed from JavaScript; every field may be left out.
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct Options {
+    mode: Option<String>,
+    threshold: Option<u8>,
+    diff_style: Option<String>,
+    format: Option<String>,
+}
+
+/// Combines two encoded images, such as the contents of two `File`s, and
+/// returns the encoded result. `options` is an object with optional `mode`,
+/// `threshold`, `diffStyle` and `format` fields, as on the command line.
+#[wasm_bindgen]
+pub fn combine(image_1: &[u8], image_2: &[u8], options: JsValue) -> Result<Vec<u8>, JsError> {
+    let options: Options = if options.is_undefined() || options.is_null() {
+        Options::default()
+    } else {
+        serde_wasm_bindgen::from_value(options)?
+    };
+    let format = options
+        .format
+        .as_deref()
+        .map(|format| {
+            ImageFormat::from_extension(format)
+                .ok_or_else(|| ImageDataErrors::InvalidArgument(format!("unknown format `{}`", format)))
+        })
+        .transpose()?;
+
+    let mut combine_options = CombineOptions::default();
+    if let Some(mode) = &options.mode {
+        combine_options.mode = Mode::parse(mode)?;
+    }
+    if let Some(threshold) = options.threshold {
+        combine_options.threshold = threshold;
+    }
+    if let Some(style) = &options.diff_style {
+        combine_options.diff_style = DiffStyle::parse(style)?;
+    }
+
+    Ok(combine_bytes(image_1, image_2, &combine_options, format)?.bytes)
+}
@@ -0,0 +1,114 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use crate::args::Selection;
+use crate::batch::{self, Job, Outcome};
+use crate::webhook::{self, Finished};
+use crate::ImageDataErrors;
+
+/// How long the folder has to stay quiet before collected changes are processed,
+/// so a file is combined once it has been fully written rather than on every write.
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// Processes what is already in `input_dir`, then keeps combining selected
+/// files as they are created or modified there until the process is stopped.
+/// Given a `notify_url`, each of those rounds is reported there once done.
+pub fn watch(
+    input_dir: &str,
+    output_dir: &str,
+    selection: &Selection,
+    image_2: &str,
+    notify_url: Option<&str>,
+    mut combine: impl FnMut(&Job) -> Result<Outcome, ImageDataErrors>,
+) -> Result<(), ImageDataErrors> {
+    let recursive_mode = if selection.recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+    std::fs::create_dir_all(output_dir).map_err(ImageDataErrors::UnableToCreateDirectory)?;
+    let output_root = Path::new(output_dir).canonicalize().ok();
+    let input_root = Path::new(input_dir).canonicalize().map_err(ImageDataErrors::UnableToReadDirectory)?;
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(ImageDataErrors::UnableToWatch)?;
+    watcher
+        .watch(Path::new(input_dir), recursive_mode)
+        .map_err(ImageDataErrors::UnableToWatch)?;
+
+    let existing = batch::directory_jobs(input_dir, output_dir, selection, image_2)?;
+    let mut processed = existing.len();
+    if !existing.is_empty() {
+        batch::run(&existing, false, notify_url, &mut combine)?;
+    }
+    log::info!("watching {} for new images", input_dir);
+
+    let mut pending: BTreeSet<PathBuf> = BTreeSet::new();
+    loop {
+        let event = if pending.is_empty() {
+            receiver.recv().map_err(|_| ImageDataErrors::WatcherStopped)?
+        } else {
+            match receiver.recv_timeout(SETTLE_TIME) {
+                Ok(event) => event,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    let paths: Vec<PathBuf> = std::mem::take(&mut pending)
+                        .into_iter()
+                        .filter_map(|path| watched_input(&path, input_dir, &input_root, output_root.as_deref()))
+                        .filter(|path| selection.accepts(input_dir, path))
+                        .collect();
+                    process(&paths, input_dir, output_dir, image_2, notify_url, &mut processed, &mut combine);
+                    continue;
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Err(ImageDataErrors::WatcherStopped),
+            }
+        };
+
+        match event {
+            Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                pending.extend(event.paths);
+            }
+            Ok(_) => {}
+            Err(e) => log::warn!("watch error: {}", e),
+        }
+    }
+}
+
+/// Maps an event path back under `input_dir` as the user spelled it, ignoring
+/// our own results when the output directory lives inside the input tree.
+fn watched_input(path: &Path, input_dir: &str, input_root: &Path, output_root: Option<&Path>) -> Option<PathBuf> {
+    let path = path.canonicalize().ok()?;
+    if output_root.is_some_and(|root| path.starts_with(root)) {
+        return None;
+    }
+    let relative = path.strip_prefix(input_root).ok()?;
+    Some(Path::new(input_dir).join(relative))
+}
+
+fn process(
+    paths: &[PathBuf],
+    input_dir: &str,
+    output_dir: &str,
+    image_2: &str,
+    notify_url: Option<&str>,
+    processed: &mut usize,
+    combine: &mut impl FnMut(&Job) -> Result<Outcome, ImageDataErrors>,
+) {
+    let started = Instant::now();
+    let mut finished = Vec::new();
+    for path in paths.iter().filter(|path| batch::is_input_file(path)) {
+        *processed += 1;
+        let job = batch::file_job(path, input_dir, output_dir, image_2, *processed);
+        let job_started = Instant::now();
+        let result = combine(&job);
+        match &result {
+            Ok(Outcome::Written(output)) => println!("{} -> {}", path.display(), output),
+            Ok(Outcome::UpToDate(output)) => log::info!("{} is up to date", output),
+            Err(e) => log::warn!("skipping {}: {}", path.display(), e),
+        }
+        finished.push((job, result, job_started.elapsed()));
+    }
+    if let Some(url) = notify_url.filter(|_| !finished.is_empty()) {
+        let finished: Vec<Finished> =
+            finished.iter().map(|(job, result, duration)| Finished { job, result, duration: *duration }).collect();
+        webhook::notify(url, &finished, started.elapsed());
+    }
+}
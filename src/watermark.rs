@@ -0,0 +1,199 @@
+use image::RgbaImage;
+
+/// The side of the blocks the watermark is spread over, as in JPEG.
+const BLOCK: usize = 8;
+/// The mid-frequency DCT coefficients carrying the watermark: low enough to
+/// survive JPEG quantisation, high enough not to show as blotches.
+const COEFFICIENTS: [(usize, usize); 6] = [(1, 2), (2, 1), (2, 2), (1, 3), (3, 1), (3, 2)];
+/// How many standard deviations the correlation must reach to count as a
+/// detection. Unmarked images stay below it all but one time in a million.
+const DETECTION_THRESHOLD: f64 = 4.75;
+
+/// Strong enough to survive re-encoding as JPEG at quality 75.
+pub const DEFAULT_STRENGTH: f32 = 6.0;
+
+/// The outcome of looking for a watermark.
+#[derive(Debug, Clone, Copy)]
+pub struct Detection {
+    /// How far the correlation with the key's pattern lies from what an
+    /// unmarked image gives, in standard deviations.
+    pub z_score: f64,
+    /// The probability of an unmarked image correlating at least as well by
+    /// chance; the smaller, the surer the detection.
+    pub false_match_probability: f64,
+    pub detected: bool,
+}
+
+/// Adds an invisible watermark derived from `key` to the brightness of
+/// `image`. Each 8x8 block has a pseudo-random pattern of ±`strength` added
+/// to its mid-frequency DCT coefficients, which mild JPEG recompression
+/// leaves largely intact.
+pub fn embed(image: &mut RgbaImage, key: &str, strength: f32) {
+    let mut pattern = Pattern::new(key);
+    for_each_block(image.width(), image.height(), |left, top| {
+        let mut block = luma_block(image, left, top);
+        let original = block;
+        let mut coefficients = dct(&block);
+        for (u, v) in COEFFICIENTS {
+            coefficients[v * BLOCK + u] += pattern.next_sign() * strength as f64;
+        }
+        block = idct(&coefficients);
+        // Shift all three channels alike, which changes brightness alone.
+        for y in 0..BLOCK {
+            for x in 0..BLOCK {
+                let delta = block[y * BLOCK + x] - original[y * BLOCK + x];
+                let pixel = image.get_pixel_mut((left + x) as u32, (top + y) as u32);
+                for channel in &mut pixel.0[..3] {
+                    *channel = (*channel as f64 + delta).round().clamp(0.0, 255.0) as u8;
+                }
+            }
+        }
+    });
+}
+
+/// Looks for the watermark `key` would have embedded.
+pub fn detect(image: &RgbaImage, key: &str) -> Detection {
+    let mut pattern = Pattern::new(key);
+    let (mut sum, mut sum_of_squares) = (0.0, 0.0);
+    for_each_block(image.width(), image.height(), |left, top| {
+        let coefficients = dct(&luma_block(image, left, top));
+        for (u, v) in COEFFICIENTS {
+            let product = coefficients[v * BLOCK + u] * pattern.next_sign();
+            sum += product;
+            sum_of_squares += product * product;
+        }
+    });
+    let z_score = if sum_of_squares > 0.0 { sum / sum_of_squares.sqrt() } else { 0.0 };
+    Detection { z_score, false_match_probability: normal_tail(z_score), detected: z_score >= DETECTION_THRESHOLD }
+}
+
+/// Calls `visit` with the top-left corner of every whole block, row by row.
+fn for_each_block(width: u32, height: u32, mut visit: impl FnMut(usize, usize)) {
+    for top in (0..height as usize / BLOCK).map(|row| row * BLOCK) {
+        for left in (0..width as usize / BLOCK).map(|column| column * BLOCK) {
+            visit(left, top);
+        }
+    }
+}
+
+fn luma_block(image: &RgbaImage, left: usize, top: usize) -> [f64; BLOCK * BLOCK] {
+    let mut block = [0.0; BLOCK * BLOCK];
+    for (i, value) in block.iter_mut().enumerate() {
+        let [red, green, blue, _] = image.get_pixel((left + i % BLOCK) as u32, (top + i / BLOCK) as u32).0;
+        *value = 0.299 * red as f64 + 0.587 * green as f64 + 0.114 * blue as f64;
+    }
+    block
+}
+
+/// The orthonormal DCT-II basis: `basis[k * BLOCK + n]` weighs sample `n`
+/// for frequency `k`.
+fn basis() -> [f64; BLOCK * BLOCK] {
+    let mut basis = [0.0; BLOCK * BLOCK];
+    for k in 0..BLOCK {
+        let scale = if k == 0 { (1.0 / BLOCK as f64).sqrt() } else { (2.0 / BLOCK as f64).sqrt() };
+        for n in 0..BLOCK {
+            basis[k * BLOCK + n] =
+                scale * (std::f64::consts::PI * (2 * n + 1) as f64 * k as f64 / (2 * BLOCK) as f64).cos();
+        }
+    }
+    basis
+}
+
+fn dct(block: &[f64; BLOCK * BLOCK]) -> [f64; BLOCK * BLOCK] {
+    let basis = basis();
+    let mut output = [0.0; BLOCK * BLOCK];
+    for v in 0..BLOCK {
+        for u in 0..BLOCK {
+            let mut sum = 0.0;
+            for y in 0..BLOCK {
+                for x in 0..BLOCK {
+                    sum += basis[v * BLOCK + y] * basis[u * BLOCK + x] * block[y * BLOCK + x];
+                }
+            }
+            output[v * BLOCK + u] = sum;
+        }
+    }
+    output
+}
+
+fn idct(coefficients: &[f64; BLOCK * BLOCK]) -> [f64; BLOCK * BLOCK] {
+    let basis = basis();
+    let mut output = [0.0; BLOCK * BLOCK];
+    for y in 0..BLOCK {
+        for x in 0..BLOCK {
+            let mut sum = 0.0;
+            for v in 0..BLOCK {
+                for u in 0..BLOCK {
+                    sum += basis[v * BLOCK + y] * basis[u * BLOCK + x] * coefficients[v * BLOCK + u];
+                }
+            }
+            output[y * BLOCK + x] = sum;
+        }
+    }
+    output
+}
+
+/// The key's pseudo-random sequence of signs, from xorshift64* seeded with
+/// the key's FNV-1a hash.
+struct Pattern(u64);
+
+impl Pattern {
+    fn new(key: &str) -> Self {
+        let hash = key.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3));
+        Pattern(hash.max(1))
+    }
+
+    fn next_sign(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        if self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 63 == 1 { 1.0 } else { -1.0 }
+    }
+}
+
+/// The probability of a standard normal variable exceeding `z`, from the
+/// Abramowitz and Stegun approximation of `erfc`, which keeps its relative
+/// accuracy far out in the tail.
+fn normal_tail(z: f64) -> f64 {
+    let x = z.abs() / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.327_591_1 * x);
+    let polynomial = t * (0.254_829_592 + t * (-0.284_496_736 + t * (1.421_413_741 + t * (-1.453_152_027 + t * 1.061_405_429))));
+    let erfc = polynomial * (-x * x).exp();
+    if z >= 0.0 { 0.5 * erfc } else { 1.0 - 0.5 * erfc }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{ColorType, DynamicImage};
+
+    fn photo() -> RgbaImage {
+        RgbaImage::from_fn(128, 96, |x, y| {
+            let shade = 60 + (x * 3 + y * 2) % 120 + (x * y) % 7;
+            image::Rgba([shade as u8, (shade + 20) as u8, (shade + 10) as u8, 255])
+        })
+    }
+
+    #[test]
+    fn detects_its_own_key_only() {
+        let mut marked = photo();
+        embed(&mut marked, "secret", DEFAULT_STRENGTH);
+        assert!(detect(&marked, "secret").detected);
+        assert!(!detect(&marked, "another").detected);
+        assert!(!detect(&photo(), "secret").detected);
+        assert!(detect(&marked, "secret").false_match_probability < 1e-6);
+    }
+
+    #[test]
+    fn survives_jpeg_recompression() {
+        let mut marked = photo();
+        embed(&mut marked, "secret", DEFAULT_STRENGTH);
+        let mut bytes = Vec::new();
+        JpegEncoder::new_with_quality(&mut bytes, 75)
+            .encode(&DynamicImage::ImageRgba8(marked).to_rgb8(), 128, 96, ColorType::Rgb8)
+            .unwrap();
+        let recompressed = image::load_from_memory(&bytes).unwrap().to_rgba8();
+        assert!(detect(&recompressed, "secret").detected);
+    }
+}
@@ -0,0 +1,83 @@
+//! `--notify-url`: a JSON summary POSTed when a batch finishes, so asset
+//! pipelines can start their next step without polling the output directory.
+//! Under `--watch` one is sent for the files already there, then one for each
+//! group of changes processed.
+
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::batch::{Job, Outcome};
+use crate::ImageDataErrors;
+
+/// How long the receiver has to answer, so a stalled one cannot hold up a watch.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A job that ran, how it went and how long it took.
+pub struct Finished<'a> {
+    pub job: &'a Job,
+    pub result: &'a Result<Outcome, ImageDataErrors>,
+    pub duration: Duration,
+}
+
+/// POSTs the summary of `finished`, which took `elapsed` in all, to `url`. A
+/// receiver that cannot be reached is only warned about: the outputs are
+/// written either way.
+pub fn notify(url: &str, finished: &[Finished], elapsed: Duration) {
+    let body = summary(finished, elapsed).to_string();
+    let sent = ureq::post(url)
+        .config()
+        .timeout_global(Some(TIMEOUT))
+        .build()
+        .header("Content-Type", "application/json")
+        .send(body);
+    match sent {
+        Ok(_) => log::info!("notified {}", url),
+        Err(e) => log::warn!("unable to notify {}: {}", url, e),
+    }
+}
+
+/// The counts of the summary table printed after a batch, followed by each
+/// job with its status, named as in the table, and its duration.
+fn summary(finished: &[Finished], elapsed: Duration) -> Value {
+    let count = |status: &str| finished.iter().filter(|finished| status_of(finished.result) == status).count();
+    let jobs: Vec<Value> = finished
+        .iter()
+        .map(|finished| {
+            let mut job = json!({
+                "image_1": finished.job.image_1,
+                "image_2": finished.job.image_2,
+                "output": match finished.result {
+                    Ok(Outcome::Written(output) | Outcome::UpToDate(output)) => output,
+                    Err(_) => &finished.job.output,
+                },
+                "status": status_of(finished.result),
+                "duration_ms": millis(finished.duration),
+            });
+            if let Err(e) = finished.result {
+                job["error"] = e.to_string().into();
+            }
+            job
+        })
+        .collect();
+    json!({
+        "processed": finished.len(),
+        "succeeded": count("ok"),
+        "skipped": count("skipped"),
+        "failed": count("failed"),
+        "duration_ms": millis(elapsed),
+        "jobs": jobs,
+    })
+}
+
+fn status_of(result: &Result<Outcome, ImageDataErrors>) -> &'static str {
+    match result {
+        Ok(Outcome::Written(_)) => "ok",
+        Ok(Outcome::UpToDate(_)) => "skipped",
+        Err(_) => "failed",
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}